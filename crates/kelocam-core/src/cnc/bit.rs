@@ -1 +1,578 @@
-pub trait ToolBit {}
+use serde::{Deserialize, Serialize};
+
+/// A rotating cutter mounted in the spindle. All lengths are in millimeters, all angles in
+/// degrees. Heights are measured along the tool axis, starting at the lowest point of the tip.
+pub trait ToolBit {
+    /// The cutting diameter of this bit.
+    fn diameter(&self) -> f32;
+    /// The length of the cutting (fluted) part of this bit.
+    fn flute_length(&self) -> f32;
+    /// The diameter of the shank that gets clamped into the collet.
+    fn shank_diameter(&self) -> f32;
+    /// The number of flutes (cutting edges) of this bit.
+    fn flutes(&self) -> u32;
+
+    /// The cutter radius at the specified height above the tip. Returns zero below the tip and
+    /// the full cutting radius above the tip profile.
+    fn radius_at(&self, height: f32) -> f32;
+
+    /// The height above the tip at which the cutter reaches the specified radius. This is the
+    /// inverse of `radius_at`. Returns None if the radius lies outside of the cutter.
+    fn height_at(&self, radius: f32) -> Option<f32>;
+
+    /// The cutting radius of this bit.
+    fn radius(&self) -> f32 {
+        self.diameter() / 2.0
+    }
+
+    /// The height of the tip profile, i.e. the height at which the cutter reaches its full
+    /// cutting radius. This is zero for flat end mills.
+    fn tip_height(&self) -> f32 {
+        self.height_at(self.radius()).unwrap_or(0.0)
+    }
+}
+
+/// A flat end mill. Cuts flat bottoms and square shoulders.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlatEndMill {
+    pub diameter: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl FlatEndMill {
+    pub fn new(diameter: f32, flute_length: f32, shank_diameter: f32, flutes: u32) -> Self {
+        Self {
+            diameter,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for FlatEndMill {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        if height < 0.0 {
+            0.0
+        } else {
+            self.radius()
+        }
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        (radius <= self.radius()).then_some(0.0)
+    }
+}
+
+/// A ball nose end mill. The tip is a half sphere with the cutting radius.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BallNose {
+    pub diameter: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl BallNose {
+    pub fn new(diameter: f32, flute_length: f32, shank_diameter: f32, flutes: u32) -> Self {
+        Self {
+            diameter,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for BallNose {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        let radius = self.radius();
+        if height < 0.0 {
+            0.0
+        } else if height < radius {
+            (radius * radius - (radius - height) * (radius - height)).sqrt()
+        } else {
+            radius
+        }
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        let r = self.radius();
+        (radius <= r).then(|| r - (r * r - radius * radius).sqrt())
+    }
+}
+
+/// A bull nose end mill. Like a flat end mill, but with a rounded corner of the specified radius.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BullNose {
+    pub diameter: f32,
+    pub corner_radius: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl BullNose {
+    pub fn new(
+        diameter: f32,
+        corner_radius: f32,
+        flute_length: f32,
+        shank_diameter: f32,
+        flutes: u32,
+    ) -> Self {
+        Self {
+            diameter,
+            corner_radius,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for BullNose {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        let rc = self.corner_radius;
+        if height < 0.0 {
+            0.0
+        } else if height < rc {
+            self.radius() - rc + (rc * rc - (rc - height) * (rc - height)).sqrt()
+        } else {
+            self.radius()
+        }
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        let rc = self.corner_radius;
+        let flat = self.radius() - rc;
+        if radius > self.radius() {
+            None
+        } else if radius <= flat {
+            Some(0.0)
+        } else {
+            let d = radius - flat;
+            Some(rc - (rc * rc - d * d).sqrt())
+        }
+    }
+}
+
+/// A V-bit (engraving bit) with a pointed tip and the specified included angle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VBit {
+    pub diameter: f32,
+    /// The included angle of the cutting cone.
+    pub angle: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl VBit {
+    pub fn new(
+        diameter: f32,
+        angle: f32,
+        flute_length: f32,
+        shank_diameter: f32,
+        flutes: u32,
+    ) -> Self {
+        Self {
+            diameter,
+            angle,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for VBit {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        cone_radius_at(0.0, self.angle, self.radius(), height)
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        cone_height_at(0.0, self.angle, self.radius(), radius)
+    }
+}
+
+/// A twist drill with a conical point of the specified point angle (usually 118°).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Drill {
+    pub diameter: f32,
+    /// The included angle of the drill point.
+    pub point_angle: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl Drill {
+    pub fn new(
+        diameter: f32,
+        point_angle: f32,
+        flute_length: f32,
+        shank_diameter: f32,
+        flutes: u32,
+    ) -> Self {
+        Self {
+            diameter,
+            point_angle,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for Drill {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        cone_radius_at(0.0, self.point_angle, self.radius(), height)
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        cone_height_at(0.0, self.point_angle, self.radius(), radius)
+    }
+}
+
+/// A chamfer mill. Like a V-bit, but with a flat tip of the specified diameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChamferMill {
+    pub diameter: f32,
+    pub tip_diameter: f32,
+    /// The included angle of the cutting cone.
+    pub angle: f32,
+    pub flute_length: f32,
+    pub shank_diameter: f32,
+    pub flutes: u32,
+}
+
+impl ChamferMill {
+    pub fn new(
+        diameter: f32,
+        tip_diameter: f32,
+        angle: f32,
+        flute_length: f32,
+        shank_diameter: f32,
+        flutes: u32,
+    ) -> Self {
+        Self {
+            diameter,
+            tip_diameter,
+            angle,
+            flute_length,
+            shank_diameter,
+            flutes,
+        }
+    }
+}
+
+impl ToolBit for ChamferMill {
+    fn diameter(&self) -> f32 {
+        self.diameter
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.flute_length
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.shank_diameter
+    }
+
+    fn flutes(&self) -> u32 {
+        self.flutes
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        cone_radius_at(self.tip_diameter / 2.0, self.angle, self.radius(), height)
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        cone_height_at(self.tip_diameter / 2.0, self.angle, self.radius(), radius)
+    }
+}
+
+/// The radius of a truncated cone with the tip radius and included angle at the specified height.
+fn cone_radius_at(tip_radius: f32, angle: f32, radius: f32, height: f32) -> f32 {
+    if height < 0.0 {
+        0.0
+    } else {
+        (tip_radius + height * (angle.to_radians() / 2.0).tan()).min(radius)
+    }
+}
+
+/// The height of a truncated cone with the tip radius and included angle at the specified radius.
+fn cone_height_at(tip_radius: f32, angle: f32, radius: f32, r: f32) -> Option<f32> {
+    if r > radius {
+        None
+    } else if r <= tip_radius {
+        Some(0.0)
+    } else {
+        Some((r - tip_radius) / (angle.to_radians() / 2.0).tan())
+    }
+}
+
+/// Any of the supported tool bits. Use this when the concrete bit type is only known at runtime,
+/// e.g. when it was loaded from a tool library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Bit {
+    Flat(FlatEndMill),
+    Ball(BallNose),
+    BullNose(BullNose),
+    VBit(VBit),
+    Drill(Drill),
+    Chamfer(ChamferMill),
+}
+
+impl Bit {
+    /// Returns the underlying bit as a trait object.
+    pub fn as_tool_bit(&self) -> &dyn ToolBit {
+        match self {
+            Self::Flat(bit) => bit,
+            Self::Ball(bit) => bit,
+            Self::BullNose(bit) => bit,
+            Self::VBit(bit) => bit,
+            Self::Drill(bit) => bit,
+            Self::Chamfer(bit) => bit,
+        }
+    }
+
+    /// A short, human readable name of the bit type.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Flat(_) => "Flat end mill",
+            Self::Ball(_) => "Ball nose",
+            Self::BullNose(_) => "Bull nose",
+            Self::VBit(_) => "V-bit",
+            Self::Drill(_) => "Drill",
+            Self::Chamfer(_) => "Chamfer mill",
+        }
+    }
+}
+
+impl ToolBit for Bit {
+    fn diameter(&self) -> f32 {
+        self.as_tool_bit().diameter()
+    }
+
+    fn flute_length(&self) -> f32 {
+        self.as_tool_bit().flute_length()
+    }
+
+    fn shank_diameter(&self) -> f32 {
+        self.as_tool_bit().shank_diameter()
+    }
+
+    fn flutes(&self) -> u32 {
+        self.as_tool_bit().flutes()
+    }
+
+    fn radius_at(&self, height: f32) -> f32 {
+        self.as_tool_bit().radius_at(height)
+    }
+
+    fn height_at(&self, radius: f32) -> Option<f32> {
+        self.as_tool_bit().height_at(radius)
+    }
+}
+
+impl From<FlatEndMill> for Bit {
+    fn from(bit: FlatEndMill) -> Self {
+        Self::Flat(bit)
+    }
+}
+
+impl From<BallNose> for Bit {
+    fn from(bit: BallNose) -> Self {
+        Self::Ball(bit)
+    }
+}
+
+impl From<BullNose> for Bit {
+    fn from(bit: BullNose) -> Self {
+        Self::BullNose(bit)
+    }
+}
+
+impl From<VBit> for Bit {
+    fn from(bit: VBit) -> Self {
+        Self::VBit(bit)
+    }
+}
+
+impl From<Drill> for Bit {
+    fn from(bit: Drill) -> Self {
+        Self::Drill(bit)
+    }
+}
+
+impl From<ChamferMill> for Bit {
+    fn from(bit: ChamferMill) -> Self {
+        Self::Chamfer(bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn flat_and_ball_profiles() {
+        let flat = FlatEndMill::new(6.0, 20.0, 6.0, 2);
+        assert_eq!(flat.radius_at(-0.1), 0.0);
+        assert_eq!(flat.radius_at(0.0), 3.0);
+        assert_eq!(flat.height_at(3.0), Some(0.0));
+        assert_eq!(flat.height_at(3.1), None);
+        assert_eq!(flat.tip_height(), 0.0);
+
+        let ball = BallNose::new(6.0, 20.0, 6.0, 2);
+        assert_eq!(ball.radius_at(0.0), 0.0);
+        assert!(close(ball.radius_at(3.0), 3.0));
+        assert!(close(ball.radius_at(10.0), 3.0));
+        assert!(close(ball.height_at(3.0).unwrap(), 3.0));
+        assert!(close(ball.tip_height(), 3.0));
+        // radius_at and height_at are inverse
+        for height in [0.5, 1.0, 2.0, 2.9] {
+            assert!(close(
+                ball.height_at(ball.radius_at(height)).unwrap(),
+                height
+            ));
+        }
+    }
+
+    #[test]
+    fn bull_nose_profile() {
+        let bull = BullNose::new(10.0, 1.0, 20.0, 10.0, 3);
+        assert_eq!(bull.height_at(4.0), Some(0.0));
+        assert!(close(bull.height_at(5.0).unwrap(), 1.0));
+        assert!(close(bull.radius_at(0.0), 4.0));
+        assert!(close(bull.radius_at(1.0), 5.0));
+        assert!(close(bull.tip_height(), 1.0));
+        for height in [0.1, 0.5, 0.9] {
+            assert!(close(
+                bull.height_at(bull.radius_at(height)).unwrap(),
+                height
+            ));
+        }
+    }
+
+    #[test]
+    fn cone_profiles() {
+        // 90° makes the radius grow as fast as the height
+        let vbit = VBit::new(6.0, 90.0, 10.0, 6.0, 1);
+        assert_eq!(vbit.radius_at(0.0), 0.0);
+        assert!(close(vbit.radius_at(2.0), 2.0));
+        assert!(close(vbit.radius_at(5.0), 3.0));
+        assert!(close(vbit.tip_height(), 3.0));
+        assert_eq!(vbit.height_at(3.5), None);
+
+        let drill = Drill::new(5.0, 118.0, 30.0, 5.0, 2);
+        let expected = 2.5 / (59.0f32).to_radians().tan();
+        assert!(close(drill.tip_height(), expected));
+
+        let chamfer = ChamferMill::new(12.0, 2.0, 90.0, 10.0, 6.0, 2);
+        assert!(close(chamfer.radius_at(0.0), 1.0));
+        assert_eq!(chamfer.height_at(0.5), Some(0.0));
+        assert!(close(chamfer.height_at(3.0).unwrap(), 2.0));
+        assert!(close(chamfer.tip_height(), 5.0));
+    }
+
+    #[test]
+    fn bit_enum() {
+        let bit = Bit::from(BallNose::new(6.0, 20.0, 6.0, 2));
+        assert_eq!(bit.kind(), "Ball nose");
+        assert_eq!(bit.diameter(), 6.0);
+        assert!(close(bit.tip_height(), 3.0));
+
+        let text = toml::to_string(&bit).unwrap();
+        assert!(text.contains("type = \"ball\""));
+        assert_eq!(toml::from_str::<Bit>(&text).unwrap(), bit);
+    }
+}
@@ -0,0 +1,164 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Machine, Material, ToolBit};
+
+/// Cutting parameters for a tool. Speeds are in revolutions per minute, feeds in millimeters per
+/// minute and lengths in millimeters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedsSpeeds {
+    /// The spindle speed in RPM.
+    pub spindle_speed: f32,
+    /// The horizontal cutting feed rate.
+    pub feed_rate: f32,
+    /// The vertical feed rate used when plunging into the material.
+    pub plunge_rate: f32,
+    /// The horizontal distance between two adjacent passes.
+    pub stepover: f32,
+    /// The vertical distance between two adjacent passes.
+    pub depth_of_cut: f32,
+}
+
+impl FeedsSpeeds {
+    pub fn new(
+        spindle_speed: f32,
+        feed_rate: f32,
+        plunge_rate: f32,
+        stepover: f32,
+        depth_of_cut: f32,
+    ) -> Self {
+        Self {
+            spindle_speed,
+            feed_rate,
+            plunge_rate,
+            stepover,
+            depth_of_cut,
+        }
+    }
+
+    /// Derive feeds and speeds for cutting the material with the bit on the machine.
+    ///
+    /// The spindle speed is derived from the surface speed of the material and clamped to the
+    /// spindle range of the machine. The feed rate follows from that spindle speed and the chip
+    /// load of the material. If the feed exceeds the limits of the machine, the spindle speed is
+    /// lowered accordingly to keep the chip load (and therefore avoid rubbing the bit). A spindle
+    /// that can't turn that slowly, or has a fixed speed, cuts with the maximum feed and a chip
+    /// load below the recommended one.
+    pub fn calculate<T>(bit: &T, material: Material, machine: &Machine) -> Self
+    where
+        T: ToolBit + ?Sized,
+    {
+        let properties = material.properties();
+        let diameter = bit.diameter();
+        let flutes = bit.flutes().max(1) as f32;
+        let chip_load = properties.chip_load_at(diameter);
+        let max_feed = machine.max_feed.x.min(machine.max_feed.y);
+
+        let mut spindle_speed = if machine.variable_spindle {
+            machine.clamp_spindle(properties.surface_speed * 1000.0 / (PI * diameter))
+        } else {
+            machine.spindle_max
+        };
+        if machine.variable_spindle && spindle_speed * flutes * chip_load > max_feed {
+            spindle_speed = machine.clamp_spindle(max_feed / (flutes * chip_load));
+        }
+        let feed_rate = (spindle_speed * flutes * chip_load).min(max_feed);
+
+        let plunge_rate = (feed_rate * properties.plunge_factor).min(machine.max_feed.z);
+        let stepover = diameter * properties.stepover_factor;
+        let depth_of_cut = (diameter * properties.depth_factor).min(bit.flute_length());
+
+        Self::new(
+            spindle_speed,
+            feed_rate,
+            plunge_rate,
+            stepover,
+            depth_of_cut,
+        )
+    }
+
+    /// The chip load (in millimeters per tooth) resulting from these feeds and speeds.
+    pub fn chip_load<T>(&self, bit: &T) -> f32
+    where
+        T: ToolBit + ?Sized,
+    {
+        self.feed_rate / (self.spindle_speed * bit.flutes().max(1) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::FlatEndMill;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn chip_load_from_surface_speed() {
+        // A quarter inch bit in acrylic needs fewer revolutions than the spindle maximum
+        let bit = FlatEndMill::new(6.35, 20.0, 6.35, 1);
+        let material = Material::Acrylic;
+        let properties = material.properties();
+        let feeds = FeedsSpeeds::calculate(&bit, material, &Machine::default());
+
+        let rpm = properties.surface_speed * 1000.0 / (PI * 6.35);
+        assert!(rpm > 8000.0 && rpm < 24000.0, "{}", rpm);
+        assert!(close(feeds.spindle_speed, rpm));
+        assert!(close(feeds.chip_load(&bit), properties.chip_load_at(6.35)));
+        assert!(close(
+            feeds.plunge_rate,
+            feeds.feed_rate * properties.plunge_factor
+        ));
+        assert!(close(feeds.stepover, 6.35 * properties.stepover_factor));
+    }
+
+    #[test]
+    fn feed_limited_by_machine() {
+        let bit = FlatEndMill::new(6.35, 20.0, 6.35, 2);
+        let chip_load = Material::Softwood.properties().chip_load_at(6.35);
+
+        // The spindle slows down to keep the chip load at the maximum feed
+        let machine = Machine {
+            spindle_min: 5000.0,
+            ..Machine::default()
+        };
+        let feeds = FeedsSpeeds::calculate(&bit, Material::Softwood, &machine);
+        assert_eq!(feeds.feed_rate, 3000.0);
+        assert!(close(feeds.chip_load(&bit), chip_load));
+
+        // Unless it can't turn slowly enough
+        let machine = Machine::default();
+        let feeds = FeedsSpeeds::calculate(&bit, Material::Softwood, &machine);
+        assert_eq!(feeds.spindle_speed, 8000.0);
+        assert_eq!(feeds.feed_rate, 3000.0);
+        assert!(feeds.chip_load(&bit) < chip_load);
+    }
+
+    #[test]
+    fn fixed_spindle() {
+        let machine = Machine {
+            variable_spindle: false,
+            spindle_max: 10000.0,
+            ..Machine::default()
+        };
+
+        // The feed follows from the fixed speed
+        let bit = FlatEndMill::new(1.0, 4.0, 3.175, 2);
+        let material = Material::Softwood;
+        let feeds = FeedsSpeeds::calculate(&bit, material, &machine);
+        assert_eq!(feeds.spindle_speed, 10000.0);
+        assert!(close(
+            feeds.feed_rate,
+            10000.0 * 2.0 * material.properties().chip_load_at(1.0)
+        ));
+
+        // And is capped by the machine
+        let bit = FlatEndMill::new(12.7, 30.0, 12.7, 2);
+        let feeds = FeedsSpeeds::calculate(&bit, material, &machine);
+        assert_eq!(feeds.spindle_speed, 10000.0);
+        assert_eq!(feeds.feed_rate, 3000.0);
+    }
+}
@@ -0,0 +1,584 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{BallNose, Bit, BullNose, ChamferMill, Drill, FeedsSpeeds, FlatEndMill, VBit};
+
+/// A numbered tool in a tool library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    /// The tool number used by the machine (e.g. `T3`).
+    pub number: u32,
+    pub name: String,
+    pub bit: Bit,
+    /// The default feeds and speeds used when this tool is selected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feeds: Option<FeedsSpeeds>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+}
+
+impl Tool {
+    pub fn new(number: u32, name: String, bit: Bit) -> Self {
+        Self {
+            number,
+            name,
+            bit,
+            feeds: None,
+            notes: String::new(),
+        }
+    }
+}
+
+/// A collection of tools, ordered by their tool number. Tool numbers are unique.
+///
+/// Libraries are stored as TOML files, with one `[[tool]]` table per tool.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolLibrary {
+    #[serde(default, rename = "tool")]
+    tools: Vec<Tool>,
+}
+
+impl ToolLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a tool into this library. Returns the tool previously stored under the same
+    /// tool number (if any).
+    pub fn insert(&mut self, tool: Tool) -> Option<Tool> {
+        match self.tools.binary_search_by_key(&tool.number, |t| t.number) {
+            Ok(i) => Some(std::mem::replace(&mut self.tools[i], tool)),
+            Err(i) => {
+                self.tools.insert(i, tool);
+                None
+            }
+        }
+    }
+
+    /// Removes the tool with the specified tool number from this library.
+    pub fn remove(&mut self, number: u32) -> Option<Tool> {
+        let i = self.position(number)?;
+        Some(self.tools.remove(i))
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Tool> {
+        self.position(number).map(|i| &self.tools[i])
+    }
+
+    pub fn get_mut(&mut self, number: u32) -> Option<&mut Tool> {
+        self.position(number).map(|i| &mut self.tools[i])
+    }
+
+    /// Iterate over the tools ordered by their tool number.
+    pub fn iter(&self) -> impl Iterator<Item = &Tool> {
+        self.tools.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    fn position(&self, number: u32) -> Option<usize> {
+        self.tools.binary_search_by_key(&number, |t| t.number).ok()
+    }
+
+    /// Parse a library from its TOML representation.
+    pub fn from_toml(source: &str) -> Result<Self, LibraryError> {
+        let parsed: Self =
+            toml::from_str(source).map_err(|e| LibraryError::Parse(e.to_string()))?;
+
+        // Restore the ordering invariant, the file might have been edited by hand
+        let mut library = Self::new();
+        for tool in parsed.tools {
+            let number = tool.number;
+            if library.insert(tool).is_some() {
+                return Err(LibraryError::DuplicateTool(number));
+            }
+        }
+        Ok(library)
+    }
+
+    /// Serialize this library into its TOML representation.
+    pub fn to_toml(&self) -> Result<String, LibraryError> {
+        let mut value =
+            toml::Value::try_from(self).map_err(|e| LibraryError::Parse(e.to_string()))?;
+        round_floats(&mut value);
+        toml::to_string_pretty(&value).map_err(|e| LibraryError::Parse(e.to_string()))
+    }
+
+    /// Load a library from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LibraryError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Save this library to a TOML file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LibraryError> {
+        Ok(fs::write(path, self.to_toml()?)?)
+    }
+
+    /// Import tools from a CSV export of another CAM package. The first row must be a header;
+    /// columns are matched by name (case insensitive), unknown columns are ignored. Recognized
+    /// columns are the tool number, name, type, diameter, flute length, shank diameter, flute
+    /// count, corner radius, angle, tip diameter, rpm, feed, plunge, stepover, depth of cut and
+    /// notes. Fields are separated by commas, or by semicolons if the header contains any, in
+    /// which case numbers may use a decimal comma. Lengths and feeds in inches are converted to
+    /// millimeters. The imported tools are inserted into this library, replacing tools with the
+    /// same number. Returns the number of imported tools.
+    pub fn import_csv(&mut self, source: &str) -> Result<usize, LibraryError> {
+        let mut rows = source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let (_, header) = rows.next().ok_or(LibraryError::Csv {
+            line: 1,
+            message: "missing header".into(),
+        })?;
+        let delimiter = if header.contains(';') { ';' } else { ',' };
+        let columns: Vec<Option<Column>> = split_csv(header, delimiter)
+            .iter()
+            .map(|name| Column::from_header(name))
+            .collect();
+
+        let mut count = 0;
+        for (i, row) in rows {
+            let line = i + 1;
+            let mut record = Record::default();
+            for (column, value) in columns.iter().zip(split_csv(row, delimiter)) {
+                if let Some(column) = column {
+                    record
+                        .set(*column, value.trim())
+                        .map_err(|message| LibraryError::Csv { line, message })?;
+                }
+            }
+
+            let tool = record
+                .into_tool()
+                .map_err(|message| LibraryError::Csv { line, message })?;
+            self.insert(tool);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+/// Replaces all floats with the shortest decimal representation of their f32 value. Without this,
+/// a diameter of 3.175 would end up as 3.174999952316284 in the library file.
+fn round_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => *f = (*f as f32).to_string().parse().unwrap_or(*f),
+        toml::Value::Array(array) => array.iter_mut().for_each(round_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| round_floats(v)),
+        _ => {}
+    }
+}
+
+/// Splits a CSV line into its fields at the delimiter. Supports quoted fields with escaped
+/// (doubled) quotes.
+fn split_csv(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    Number,
+    Name,
+    Kind,
+    Diameter,
+    FluteLength,
+    ShankDiameter,
+    Flutes,
+    CornerRadius,
+    Angle,
+    TipDiameter,
+    SpindleSpeed,
+    FeedRate,
+    PlungeRate,
+    Stepover,
+    DepthOfCut,
+    Notes,
+}
+
+impl Column {
+    fn from_header(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        Some(match name.as_str() {
+            "number" | "tool" | "tool number" | "tool_number" | "t" | "#" => Self::Number,
+            "name" | "description" | "tool name" => Self::Name,
+            "type" | "kind" | "tool type" | "shape" => Self::Kind,
+            "diameter" | "dia" | "cutting diameter" => Self::Diameter,
+            "flute length" | "flute_length" | "cutting length" | "length of cut" => {
+                Self::FluteLength
+            }
+            "shank" | "shank diameter" | "shank_diameter" => Self::ShankDiameter,
+            "flutes" | "flute count" | "number of flutes" => Self::Flutes,
+            "corner radius" | "corner_radius" => Self::CornerRadius,
+            "angle" | "included angle" | "tip angle" | "point angle" | "v angle" => Self::Angle,
+            "tip diameter" | "tip_diameter" => Self::TipDiameter,
+            "rpm" | "spindle speed" | "spindle_speed" | "speed" => Self::SpindleSpeed,
+            "feed" | "feed rate" | "feed_rate" | "feedrate" => Self::FeedRate,
+            "plunge" | "plunge rate" | "plunge_rate" => Self::PlungeRate,
+            "stepover" | "step over" => Self::Stepover,
+            "depth of cut" | "depth_of_cut" | "stepdown" | "step down" | "doc" => Self::DepthOfCut,
+            "notes" | "note" | "comment" | "comments" => Self::Notes,
+            _ => return None,
+        })
+    }
+}
+
+/// The raw values of a CSV row.
+#[derive(Default)]
+struct Record {
+    number: Option<u32>,
+    name: String,
+    kind: String,
+    diameter: Option<f32>,
+    flute_length: Option<f32>,
+    shank_diameter: Option<f32>,
+    flutes: Option<u32>,
+    corner_radius: Option<f32>,
+    angle: Option<f32>,
+    tip_diameter: Option<f32>,
+    spindle_speed: Option<f32>,
+    feed_rate: Option<f32>,
+    plunge_rate: Option<f32>,
+    stepover: Option<f32>,
+    depth_of_cut: Option<f32>,
+    notes: String,
+}
+
+impl Record {
+    fn set(&mut self, column: Column, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        /// Splits a value into its number and unit, e.g. "6,35 mm" into "6.35" and "mm". Leading
+        /// tool letters like in "T3" are skipped.
+        fn split(value: &str) -> (String, String) {
+            let value = value.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+            let end = value
+                .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '+')))
+                .unwrap_or(value.len());
+            let (number, unit) = value.split_at(end);
+            (number.trim().replace(',', "."), unit.trim().to_lowercase())
+        }
+
+        fn number<T: std::str::FromStr>(value: &str) -> Result<Option<T>, String> {
+            split(value)
+                .0
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid number '{}'", value))
+        }
+
+        /// A number in millimeters, or in the unit of the `units` table it is given in.
+        fn convert(value: &str, units: &[(&str, f32)]) -> Result<Option<f32>, String> {
+            let (_, unit) = split(value);
+            let scale = units
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, scale)| *scale)
+                .ok_or_else(|| format!("unknown unit in '{}'", value))?;
+            Ok(number::<f32>(value)?.map(|n| n * scale))
+        }
+
+        let length = |value: &str| {
+            convert(
+                value,
+                &[
+                    ("", 1.0),
+                    ("mm", 1.0),
+                    ("in", 25.4),
+                    ("inch", 25.4),
+                    ("\"", 25.4),
+                ],
+            )
+        };
+        let feed = |value: &str| {
+            convert(
+                value,
+                &[("", 1.0), ("mm/min", 1.0), ("in/min", 25.4), ("ipm", 25.4)],
+            )
+        };
+
+        match column {
+            Column::Number => self.number = number(value)?,
+            Column::Name => self.name = value.into(),
+            Column::Kind => self.kind = value.to_lowercase(),
+            Column::Diameter => self.diameter = length(value)?,
+            Column::FluteLength => self.flute_length = length(value)?,
+            Column::ShankDiameter => self.shank_diameter = length(value)?,
+            Column::Flutes => self.flutes = number(value)?,
+            Column::CornerRadius => self.corner_radius = length(value)?,
+            Column::Angle => self.angle = number(value)?,
+            Column::TipDiameter => self.tip_diameter = length(value)?,
+            Column::SpindleSpeed => self.spindle_speed = number(value)?,
+            Column::FeedRate => self.feed_rate = feed(value)?,
+            Column::PlungeRate => self.plunge_rate = feed(value)?,
+            Column::Stepover => self.stepover = length(value)?,
+            Column::DepthOfCut => self.depth_of_cut = length(value)?,
+            Column::Notes => self.notes = value.into(),
+        }
+        Ok(())
+    }
+
+    fn into_tool(self) -> Result<Tool, String> {
+        let number = self.number.ok_or("missing tool number")?;
+        let diameter = self.diameter.ok_or("missing diameter")?;
+        let flute_length = self.flute_length.unwrap_or(diameter * 3.0);
+        let shank_diameter = self.shank_diameter.unwrap_or(diameter);
+        let flutes = self.flutes.unwrap_or(2);
+
+        let kind = self.kind.as_str();
+        // An explicit type wins over a corner radius, which some exports fill in for every tool
+        let corner_radius = self.corner_radius.filter(|r| *r > 0.0);
+        let bit: Bit = if kind.contains("bull")
+            || (corner_radius.is_some() && (kind.is_empty() || kind == "end mill"))
+        {
+            let corner_radius = corner_radius.ok_or("missing corner radius")?;
+            BullNose::new(
+                diameter,
+                corner_radius,
+                flute_length,
+                shank_diameter,
+                flutes,
+            )
+            .into()
+        } else if kind.contains("ball") {
+            BallNose::new(diameter, flute_length, shank_diameter, flutes).into()
+        } else if kind.contains("chamfer") {
+            let angle = self.angle.ok_or("missing angle")?;
+            let tip_diameter = self.tip_diameter.unwrap_or(0.0);
+            ChamferMill::new(
+                diameter,
+                tip_diameter,
+                angle,
+                flute_length,
+                shank_diameter,
+                flutes,
+            )
+            .into()
+        } else if kind.contains("drill") {
+            let point_angle = self.angle.unwrap_or(118.0);
+            Drill::new(diameter, point_angle, flute_length, shank_diameter, flutes).into()
+        } else if kind.starts_with('v') || kind.contains("engrav") {
+            let angle = self.angle.ok_or("missing angle")?;
+            VBit::new(diameter, angle, flute_length, shank_diameter, flutes).into()
+        } else if kind.is_empty() || kind.contains("flat") || kind.contains("end") {
+            FlatEndMill::new(diameter, flute_length, shank_diameter, flutes).into()
+        } else {
+            return Err(format!("unknown tool type '{}'", self.kind));
+        };
+
+        let feeds = match (self.spindle_speed, self.feed_rate) {
+            (Some(spindle_speed), Some(feed_rate)) => Some(FeedsSpeeds::new(
+                spindle_speed,
+                feed_rate,
+                self.plunge_rate.unwrap_or(feed_rate / 3.0),
+                self.stepover.unwrap_or(diameter * 0.4),
+                self.depth_of_cut.unwrap_or(diameter * 0.5),
+            )),
+            _ => None,
+        };
+
+        let name = if self.name.is_empty() {
+            format!("{} mm {}", diameter, bit.kind())
+        } else {
+            self.name
+        };
+
+        Ok(Tool {
+            number,
+            name,
+            bit,
+            feeds,
+            notes: self.notes,
+        })
+    }
+}
+
+/// An error that occured while loading, saving or importing a tool library.
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(io::Error),
+    /// The library file could not be parsed or serialized.
+    Parse(String),
+    /// The library file contains the same tool number twice.
+    DuplicateTool(u32),
+    /// A row of a CSV import is invalid.
+    Csv {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(message) => write!(f, "invalid tool library: {}", message),
+            Self::DuplicateTool(number) => write!(f, "duplicate tool number T{}", number),
+            Self::Csv { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+impl From<io::Error> for LibraryError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::ToolBit;
+
+    #[test]
+    fn csv_import() {
+        let csv = "Tool,Description,Type,Diameter,Corner Radius,Flutes,RPM,Feed,Notes\n\
+                   T1,\"Quarter inch, flat\",Flat End Mill,0.25 in,0,2,18000,40 in/min,\n\
+                   T2,,Ball End Mill,6 mm,3,2,,,\"say \"\"hi\"\"\"\n\
+                   \n\
+                   T3,,End Mill,6,1,3,,,\n";
+        let mut library = ToolLibrary::new();
+        assert_eq!(library.import_csv(csv).unwrap(), 3);
+
+        let t1 = library.get(1).unwrap();
+        assert_eq!(t1.name, "Quarter inch, flat");
+        assert!(matches!(t1.bit, Bit::Flat(_)));
+        assert!((t1.bit.diameter() - 6.35).abs() < 1e-4);
+        let feeds = t1.feeds.as_ref().unwrap();
+        assert_eq!(feeds.spindle_speed, 18000.0);
+        assert!((feeds.feed_rate - 1016.0).abs() < 1e-3);
+
+        // A ball end mill stays one, even though its corner radius is given
+        let t2 = library.get(2).unwrap();
+        assert!(matches!(t2.bit, Bit::Ball(_)));
+        assert_eq!(t2.name, "6 mm Ball nose");
+        assert_eq!(t2.notes, "say \"hi\"");
+        assert_eq!(t2.feeds, None);
+
+        // A generic end mill with a corner radius is a bull nose
+        let Bit::BullNose(t3) = &library.get(3).unwrap().bit else {
+            panic!("expected a bull nose");
+        };
+        assert_eq!(t3.corner_radius, 1.0);
+        assert_eq!(t3.flutes, 3);
+    }
+
+    #[test]
+    fn csv_import_semicolons() {
+        let csv = "Number;Name;Type;Diameter;Angle\n7;Engraver;V-Bit;6,35;60°\n";
+        let mut library = ToolLibrary::new();
+        assert_eq!(library.import_csv(csv).unwrap(), 1);
+        let Bit::VBit(bit) = &library.get(7).unwrap().bit else {
+            panic!("expected a v-bit");
+        };
+        assert_eq!(bit.diameter, 6.35);
+        assert_eq!(bit.angle, 60.0);
+    }
+
+    #[test]
+    fn csv_import_errors() {
+        let mut library = ToolLibrary::new();
+        let error = |csv: &str, library: &mut ToolLibrary| library.import_csv(csv).unwrap_err();
+
+        assert!(matches!(
+            error("", &mut library),
+            LibraryError::Csv { line: 1, .. }
+        ));
+        for (csv, expected) in [
+            ("T,Diameter\n1,6\n2,\n", "line 3: missing diameter"),
+            ("T,Diameter\n1,abc\n", "line 2: invalid number 'abc'"),
+            (
+                "T,Diameter\n1,6 furlong\n",
+                "line 2: unknown unit in '6 furlong'",
+            ),
+            (
+                "T,Type,Diameter\n1,Router,6\n",
+                "line 2: unknown tool type 'router'",
+            ),
+            ("T,Type,Diameter\n1,V-Bit,6\n", "line 2: missing angle"),
+        ] {
+            assert_eq!(error(csv, &mut library).to_string(), expected, "{}", csv);
+        }
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let mut library = ToolLibrary::new();
+        let mut tool = Tool::new(
+            3,
+            "1/8\" flat".into(),
+            FlatEndMill::new(3.175, 12.0, 3.175, 2).into(),
+        );
+        tool.feeds = Some(FeedsSpeeds::new(18000.0, 800.0, 300.0, 1.2, 1.0));
+        library.insert(tool);
+        library.insert(Tool::new(
+            1,
+            "Ball".into(),
+            BallNose::new(6.0, 20.0, 6.0, 2).into(),
+        ));
+
+        let toml = library.to_toml().unwrap();
+        assert!(toml.contains("diameter = 3.175\n"), "{}", toml);
+        let loaded = ToolLibrary::from_toml(&toml).unwrap();
+        assert_eq!(loaded, library);
+        assert_eq!(loaded.iter().map(|t| t.number).collect::<Vec<_>>(), [1, 3]);
+    }
+
+    #[test]
+    fn toml_import() {
+        let toml = r#"
+            [[tool]]
+            number = 5
+            name = "Drill"
+            bit = { type = "drill", diameter = 3.0, point_angle = 118.0, flute_length = 20.0, shank_diameter = 3.0, flutes = 2 }
+
+            [[tool]]
+            number = 2
+            name = "Flat"
+            bit = { type = "flat", diameter = 6.0, flute_length = 20.0, shank_diameter = 6.0, flutes = 2 }
+        "#;
+        let library = ToolLibrary::from_toml(toml).unwrap();
+        assert_eq!(library.iter().map(|t| t.number).collect::<Vec<_>>(), [2, 5]);
+        assert!(matches!(library.get(5).unwrap().bit, Bit::Drill(_)));
+
+        let duplicate = format!("{}{}", toml, toml.replace("number = 2", "number = 9"));
+        assert!(matches!(
+            ToolLibrary::from_toml(&duplicate),
+            Err(LibraryError::DuplicateTool(5))
+        ));
+        assert!(matches!(
+            ToolLibrary::from_toml("[[tool]]\nnumber = 1"),
+            Err(LibraryError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,235 @@
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::primitives::Axis;
+
+/// The unit system a machine is programmed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Units {
+    #[default]
+    Millimeters,
+    Inches,
+}
+
+impl Units {
+    pub const MM_PER_INCH: f32 = 25.4;
+
+    /// Convert a length in millimeters into this unit system.
+    pub fn from_mm(&self, value: f32) -> f32 {
+        match self {
+            Self::Millimeters => value,
+            Self::Inches => value / Self::MM_PER_INCH,
+        }
+    }
+
+    /// Convert a length in this unit system into millimeters.
+    pub fn to_mm(&self, value: f32) -> f32 {
+        match self {
+            Self::Millimeters => value,
+            Self::Inches => value * Self::MM_PER_INCH,
+        }
+    }
+}
+
+/// A CNC machine. Lengths are in millimeters, feeds in millimeters per minute and spindle speeds
+/// in RPM, regardless of the units the machine is programmed in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Machine {
+    pub name: String,
+    /// The units the controller expects in programs.
+    pub units: Units,
+    /// The minimum reachable coordinate per axis. Like toolpaths, this uses work coordinates
+    /// with the origin at the center of the top of the stock.
+    pub travel_min: Vector3<f32>,
+    /// The maximum reachable coordinate per axis.
+    pub travel_max: Vector3<f32>,
+    /// The maximum rapid (G0) feed per axis.
+    pub max_rapid: Vector3<f32>,
+    /// The maximum cutting (G1) feed per axis.
+    pub max_feed: Vector3<f32>,
+    pub spindle_min: f32,
+    pub spindle_max: f32,
+    /// Whether the spindle speed can be controlled (e.g. using PWM). Machines with a fixed speed
+    /// spindle (like a trim router) can only turn it on or off.
+    pub variable_spindle: bool,
+    /// Whether the machine can change tools by itself (M6).
+    pub tool_changer: bool,
+}
+
+impl Default for Machine {
+    /// A typical hobby router with a 300 x 400 x 80 mm work envelope. The cutter reaches 60 mm
+    /// below and 20 mm above the top of the stock.
+    fn default() -> Self {
+        Self {
+            name: "Generic router".into(),
+            units: Units::Millimeters,
+            travel_min: Vector3::new(-150.0, -200.0, -60.0),
+            travel_max: Vector3::new(150.0, 200.0, 20.0),
+            max_rapid: Vector3::new(5000.0, 5000.0, 1000.0),
+            max_feed: Vector3::new(3000.0, 3000.0, 800.0),
+            spindle_min: 8000.0,
+            spindle_max: 24000.0,
+            variable_spindle: true,
+            tool_changer: false,
+        }
+    }
+}
+
+impl Machine {
+    /// The size of the work envelope.
+    pub fn envelope_size(&self) -> Vector3<f32> {
+        self.travel_max - self.travel_min
+    }
+
+    /// Returns whether the point lies inside of the work envelope.
+    pub fn contains(&self, point: &Vector3<f32>) -> bool {
+        (0..3).all(|i| (self.travel_min[i]..=self.travel_max[i]).contains(&point[i]))
+    }
+
+    /// Check whether any of the points leave the work envelope. Returns the first violation.
+    pub fn check_envelope<'a, I>(&self, points: I) -> Option<EnvelopeViolation>
+    where
+        I: IntoIterator<Item = &'a Vector3<f32>>,
+    {
+        points.into_iter().find_map(|point| {
+            [Axis::X, Axis::Y, Axis::Z]
+                .into_iter()
+                .enumerate()
+                .find_map(|(i, axis)| {
+                    let overtravel = if point[i] < self.travel_min[i] {
+                        point[i] - self.travel_min[i]
+                    } else if point[i] > self.travel_max[i] {
+                        point[i] - self.travel_max[i]
+                    } else {
+                        return None;
+                    };
+
+                    Some(EnvelopeViolation {
+                        point: *point,
+                        axis,
+                        overtravel,
+                    })
+                })
+        })
+    }
+
+    /// The maximum cutting feed when moving in the specified direction, limited by the maximum
+    /// feed of every axis involved.
+    pub fn max_feed_along(&self, direction: &Vector3<f32>) -> f32 {
+        Self::limit_along(&self.max_feed, direction)
+    }
+
+    /// The maximum rapid feed when moving in the specified direction, limited by the maximum
+    /// rapid of every axis involved.
+    pub fn max_rapid_along(&self, direction: &Vector3<f32>) -> f32 {
+        Self::limit_along(&self.max_rapid, direction)
+    }
+
+    fn limit_along(limits: &Vector3<f32>, direction: &Vector3<f32>) -> f32 {
+        let direction = direction.normalize();
+        let mut feed = f32::INFINITY;
+        for i in 0..3 {
+            if direction[i].abs() > f32::EPSILON {
+                feed = feed.min(limits[i] / direction[i].abs());
+            }
+        }
+        feed
+    }
+
+    /// Clamp a spindle speed to the range supported by this machine. The maximum wins if a
+    /// loaded machine has its limits the wrong way round.
+    pub fn clamp_spindle(&self, rpm: f32) -> f32 {
+        rpm.max(self.spindle_min).min(self.spindle_max)
+    }
+}
+
+/// A point that lies outside of the work envelope of a machine.
+#[derive(Debug, Clone)]
+pub struct EnvelopeViolation {
+    pub point: Vector3<f32>,
+    /// The first axis whose travel limits are exceeded.
+    pub axis: Axis,
+    /// The distance by which the limit is exceeded. Negative if the minimum is exceeded.
+    pub overtravel: f32,
+}
+
+impl std::fmt::Display for EnvelopeViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({:.3}, {:.3}, {:.3}) exceeds the {:?} travel limits by {:.3} mm",
+            self.point.x, self.point.y, self.point.z, self.axis, self.overtravel
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(Units::Millimeters.to_mm(2.0), 2.0);
+        assert_eq!(Units::Millimeters.from_mm(2.0), 2.0);
+        assert_eq!(Units::Inches.to_mm(2.0), 50.8);
+        assert_eq!(Units::Inches.from_mm(50.8), 2.0);
+    }
+
+    #[test]
+    fn envelope() {
+        let machine = Machine::default();
+        assert_eq!(machine.envelope_size(), Vector3::new(300.0, 400.0, 80.0));
+        assert!(machine.contains(&Vector3::new(150.0, -200.0, -60.0)));
+        assert!(!machine.contains(&Vector3::new(0.0, 0.0, 20.1)));
+
+        let inside = [Vector3::zeros(), Vector3::new(150.0, 200.0, 20.0)];
+        assert!(machine.check_envelope(&inside).is_none());
+
+        let points = [
+            Vector3::zeros(),
+            Vector3::new(0.0, -201.0, 90.0),
+            Vector3::new(160.0, 0.0, 0.0),
+        ];
+        let violation = machine.check_envelope(&points).unwrap();
+        assert_eq!(violation.point, points[1]);
+        assert_eq!(violation.axis, Axis::Y);
+        assert_eq!(violation.overtravel, -1.0);
+        assert_eq!(
+            violation.to_string(),
+            "(0.000, -201.000, 90.000) exceeds the Y travel limits by -1.000 mm"
+        );
+    }
+
+    #[test]
+    fn feed_limits() {
+        let machine = Machine::default();
+        assert_eq!(machine.max_feed_along(&Vector3::new(2.0, 0.0, 0.0)), 3000.0);
+        assert_eq!(
+            machine.max_rapid_along(&Vector3::new(0.0, 0.0, -1.0)),
+            1000.0
+        );
+
+        // Moving diagonally, both axes run at their maximum feed
+        let diagonal = machine.max_feed_along(&Vector3::new(1.0, 1.0, 0.0));
+        assert!((diagonal - 3000.0 * 2f32.sqrt()).abs() < 0.1);
+        // The Z axis limits steep moves
+        let steep = machine.max_feed_along(&Vector3::new(1.0, 0.0, -1.0));
+        assert!((steep - 800.0 * 2f32.sqrt()).abs() < 0.1);
+    }
+
+    #[test]
+    fn spindle() {
+        let machine = Machine::default();
+        assert_eq!(machine.clamp_spindle(1000.0), 8000.0);
+        assert_eq!(machine.clamp_spindle(12000.0), 12000.0);
+        assert_eq!(machine.clamp_spindle(30000.0), 24000.0);
+
+        let swapped = Machine {
+            spindle_min: 24000.0,
+            spindle_max: 8000.0,
+            ..machine
+        };
+        assert_eq!(swapped.clamp_spindle(12000.0), 8000.0);
+    }
+}
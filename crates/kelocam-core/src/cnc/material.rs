@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+/// A stock material with known cutting properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Material {
+    Softwood,
+    Hardwood,
+    Mdf,
+    Acrylic,
+    Aluminium6061,
+    Brass,
+    Hdpe,
+}
+
+/// Cutting properties of a material for carbide tooling.
+#[derive(Debug, Clone)]
+pub struct MaterialProperties {
+    pub name: &'static str,
+    /// The recommended surface (cutting) speed in meters per minute.
+    pub surface_speed: f32,
+    /// The recommended chip load in millimeters per tooth, tabulated per cutter diameter in
+    /// millimeters. The table is sorted by diameter.
+    pub chip_load: &'static [(f32, f32)],
+    /// The depth of cut as a fraction of the cutter diameter.
+    pub depth_factor: f32,
+    /// The stepover as a fraction of the cutter diameter.
+    pub stepover_factor: f32,
+    /// The plunge rate as a fraction of the feed rate.
+    pub plunge_factor: f32,
+}
+
+impl MaterialProperties {
+    /// The chip load for a cutter of the specified diameter. Values between table entries are
+    /// linearly interpolated, values outside of the table are clamped.
+    pub fn chip_load_at(&self, diameter: f32) -> f32 {
+        let table = self.chip_load;
+        let first = table[0];
+        let last = table[table.len() - 1];
+
+        if diameter <= first.0 {
+            return first.1;
+        }
+
+        for window in table.windows(2) {
+            let (d0, c0) = window[0];
+            let (d1, c1) = window[1];
+            if diameter <= d1 {
+                return c0 + (c1 - c0) * (diameter - d0) / (d1 - d0);
+            }
+        }
+
+        last.1
+    }
+}
+
+impl Material {
+    pub const ALL: [Material; 7] = [
+        Self::Softwood,
+        Self::Hardwood,
+        Self::Mdf,
+        Self::Acrylic,
+        Self::Aluminium6061,
+        Self::Brass,
+        Self::Hdpe,
+    ];
+
+    pub fn properties(&self) -> &'static MaterialProperties {
+        match self {
+            Self::Softwood => &SOFTWOOD,
+            Self::Hardwood => &HARDWOOD,
+            Self::Mdf => &MDF,
+            Self::Acrylic => &ACRYLIC,
+            Self::Aluminium6061 => &ALUMINIUM_6061,
+            Self::Brass => &BRASS,
+            Self::Hdpe => &HDPE,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.properties().name
+    }
+}
+
+// The values below are conservative starting points for hobby machines, based on the usual
+// manufacturer chip load charts for carbide tooling.
+
+const SOFTWOOD: MaterialProperties = MaterialProperties {
+    name: "Softwood",
+    surface_speed: 500.0,
+    chip_load: &[(1.0, 0.02), (3.175, 0.08), (6.35, 0.2), (12.7, 0.45)],
+    depth_factor: 1.0,
+    stepover_factor: 0.45,
+    plunge_factor: 0.5,
+};
+
+const HARDWOOD: MaterialProperties = MaterialProperties {
+    name: "Hardwood",
+    surface_speed: 450.0,
+    chip_load: &[(1.0, 0.015), (3.175, 0.06), (6.35, 0.17), (12.7, 0.4)],
+    depth_factor: 0.5,
+    stepover_factor: 0.4,
+    plunge_factor: 0.4,
+};
+
+const MDF: MaterialProperties = MaterialProperties {
+    name: "MDF",
+    surface_speed: 500.0,
+    chip_load: &[(1.0, 0.02), (3.175, 0.08), (6.35, 0.23), (12.7, 0.5)],
+    depth_factor: 1.0,
+    stepover_factor: 0.45,
+    plunge_factor: 0.5,
+};
+
+const ACRYLIC: MaterialProperties = MaterialProperties {
+    name: "Acrylic",
+    surface_speed: 250.0,
+    chip_load: &[(1.0, 0.015), (3.175, 0.06), (6.35, 0.15), (12.7, 0.3)],
+    depth_factor: 0.5,
+    stepover_factor: 0.4,
+    plunge_factor: 0.3,
+};
+
+const ALUMINIUM_6061: MaterialProperties = MaterialProperties {
+    name: "Aluminium 6061",
+    surface_speed: 200.0,
+    chip_load: &[(1.0, 0.005), (3.175, 0.025), (6.35, 0.05), (12.7, 0.1)],
+    depth_factor: 0.2,
+    stepover_factor: 0.3,
+    plunge_factor: 0.3,
+};
+
+const BRASS: MaterialProperties = MaterialProperties {
+    name: "Brass",
+    surface_speed: 150.0,
+    chip_load: &[(1.0, 0.005), (3.175, 0.02), (6.35, 0.04), (12.7, 0.08)],
+    depth_factor: 0.2,
+    stepover_factor: 0.3,
+    plunge_factor: 0.3,
+};
+
+const HDPE: MaterialProperties = MaterialProperties {
+    name: "HDPE",
+    surface_speed: 300.0,
+    chip_load: &[(1.0, 0.02), (3.175, 0.08), (6.35, 0.2), (12.7, 0.4)],
+    depth_factor: 1.0,
+    stepover_factor: 0.45,
+    plunge_factor: 0.5,
+};
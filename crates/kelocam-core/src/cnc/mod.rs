@@ -1,5 +1,13 @@
 //! This crate offers traits and structs to represent CNC tooling & CNC machines.
 
 pub mod bit;
+pub mod feeds;
+pub mod library;
+pub mod machine;
+pub mod material;
 
-pub use bit::ToolBit;
+pub use bit::{BallNose, Bit, BullNose, ChamferMill, Drill, FlatEndMill, ToolBit, VBit};
+pub use feeds::FeedsSpeeds;
+pub use library::{LibraryError, Tool, ToolLibrary};
+pub use machine::{EnvelopeViolation, Machine, Units};
+pub use material::{Material, MaterialProperties};
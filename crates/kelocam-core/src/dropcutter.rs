@@ -0,0 +1,400 @@
+//! Drop-cutter: the lowest height a cutter can sit at without gouging a mesh.
+//!
+//! The cutter is dropped along the Z axis onto every triangle below it and rests at the highest
+//! contact. The contact with a triangle is found by testing its vertices, its facet and its
+//! edges. Every cutter profile is convex, so the contact height along the facet and along the
+//! edges is a concave function, which is maximized numerically. This works for every `ToolBit`
+//! without special casing the cutter shapes.
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::cnc::ToolBit;
+use crate::primitives::{BoundingBox, Mesh, Triangle};
+
+/// The number of golden section iterations, which narrows the interval to about 1e-5 of its size.
+const ITERATIONS: usize = 24;
+
+/// Computes drop-cutter heights for a mesh. The triangles are sorted into a grid of buckets,
+/// so that only the triangles near the cutter get tested.
+pub struct DropCutter<'a> {
+    bit: &'a dyn ToolBit,
+    triangles: &'a [Triangle],
+    origin: Vector2<f32>,
+    size: f32,
+    width: usize,
+    height: usize,
+    buckets: Vec<Vec<usize>>,
+}
+
+impl<'a> DropCutter<'a> {
+    pub fn new(mesh: &'a Mesh, bit: &'a dyn ToolBit) -> Self {
+        let radius = bit.radius();
+        let (min, max) = if mesh.triangles.is_empty() {
+            (Vector3::zeros(), Vector3::zeros())
+        } else {
+            mesh.bb_min_max()
+        };
+
+        let origin = min.xy() - Vector2::from_element(radius);
+        let extent = max.xy() - min.xy() + Vector2::from_element(radius * 2.0);
+        // Aim for a few triangles per bucket, but keep the grid reasonably small
+        let area = extent.x * extent.y / mesh.triangles.len().max(1) as f32;
+        let size = (area.sqrt() * 2.0).max(extent.max() / 512.0).max(1e-3);
+        let width = (extent.x / size).floor() as usize + 1;
+        let height = (extent.y / size).floor() as usize + 1;
+
+        let mut buckets = vec![Vec::new(); width * height];
+        for (i, triangle) in mesh.triangles.iter().enumerate() {
+            let (min, max) = triangle.bb_min_max();
+            let from = (min.xy() - origin).add_scalar(-radius) / size;
+            let to = (max.xy() - origin).add_scalar(radius) / size;
+            let x0 = from.x.floor().max(0.0) as usize;
+            let y0 = from.y.floor().max(0.0) as usize;
+            let x1 = (to.x.floor().max(0.0) as usize).min(width - 1);
+            let y1 = (to.y.floor().max(0.0) as usize).min(height - 1);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    buckets[y * width + x].push(i);
+                }
+            }
+        }
+
+        // Test the highest triangles first, lower triangles can not lift the cutter any further
+        for bucket in buckets.iter_mut() {
+            bucket.sort_by(|a, b| {
+                let a = mesh.triangles[*a].bb_max().z;
+                let b = mesh.triangles[*b].bb_max().z;
+                b.total_cmp(&a)
+            });
+        }
+
+        Self {
+            bit,
+            triangles: &mesh.triangles,
+            origin,
+            size,
+            width,
+            height,
+            buckets,
+        }
+    }
+
+    /// The lowest Z of the cutter tip above the point, where the cutter touches but does not
+    /// gouge the mesh. None if no triangle lies below the cutter.
+    pub fn height(&self, point: &Vector2<f32>) -> Option<f32> {
+        self.contact(point).map(|(height, _)| height)
+    }
+
+    /// Like `height`, but also returns the triangle the cutter rests on.
+    pub fn contact(&self, point: &Vector2<f32>) -> Option<(f32, &'a Triangle)> {
+        let mut contact: Option<(f32, &'a Triangle)> = None;
+        for triangle in self.nearby(point) {
+            if contact.map_or(false, |(height, _)| triangle.bb_max().z <= height) {
+                break;
+            }
+            if let Some(z) = drop_triangle(self.bit, triangle, point) {
+                if contact.map_or(true, |(height, _)| z > height) {
+                    contact = Some((z, triangle));
+                }
+            }
+        }
+        contact
+    }
+
+    /// The triangles the cutter with its axis at the point may touch, the highest first.
+    pub(crate) fn nearby(&self, point: &Vector2<f32>) -> impl Iterator<Item = &'a Triangle> + '_ {
+        let local = (point - self.origin) / self.size;
+        let (x, y) = (local.x as usize, local.y as usize);
+        let inside = local.x >= 0.0 && local.y >= 0.0 && x < self.width && y < self.height;
+        let bucket = if inside {
+            self.buckets[y * self.width + x].as_slice()
+        } else {
+            &[]
+        };
+        let triangles = self.triangles;
+        bucket.iter().map(move |i| &triangles[*i])
+    }
+}
+
+/// The lowest Z of the cutter tip above the point, where the cutter touches the triangle.
+/// None if the triangle does not lie below the cutter.
+pub fn drop_triangle(bit: &dyn ToolBit, triangle: &Triangle, point: &Vector2<f32>) -> Option<f32> {
+    let radius = bit.radius();
+    let (min, max) = triangle.bb_min_max();
+    if point.x < min.x - radius
+        || point.x > max.x + radius
+        || point.y < min.y - radius
+        || point.y > max.y + radius
+    {
+        return None;
+    }
+
+    // The height of the cutter profile at a distance from the axis
+    let profile = |distance: f32| bit.height_at(distance.min(radius)).unwrap_or(0.0);
+    let vertices = [triangle.a, triangle.b, triangle.c];
+    let mut height: Option<f32> = None;
+    let mut contact = |z: f32| height = Some(height.map_or(z, |height| height.max(z)));
+
+    for vertex in vertices.iter() {
+        let distance = (vertex.xy() - point).magnitude();
+        if distance <= radius {
+            contact(vertex.z - profile(distance));
+        }
+    }
+
+    if let Some(facet) = facet_contact(bit, triangle) {
+        let p = point + facet.offset;
+        if contains(&vertices, &p) {
+            contact(triangle.a.z + facet.gradient.dot(&(p - triangle.a.xy())) - facet.height);
+        }
+    }
+
+    // The edges, clipped to the cutter disk
+    for i in 0..3 {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % 3];
+        let Some((t0, t1)) = clip(&a.xy(), &b.xy(), point, radius) else {
+            continue;
+        };
+        let along = |t: f32| {
+            let p = a.lerp(&b, t);
+            p.z - profile((p.xy() - point).magnitude())
+        };
+        let t = maximize(t0, t1, along);
+        contact(along(t));
+    }
+
+    height
+}
+
+/// The contact of the cutter with the plane of a triangle, relative to the cutter tip.
+pub(crate) struct FacetContact {
+    /// The slope of the plane along X and Y.
+    pub gradient: Vector2<f32>,
+    /// The offset of the contact point from the cutter axis.
+    pub offset: Vector2<f32>,
+    /// The height of the contact point above the cutter tip.
+    pub height: f32,
+}
+
+/// Where the cutter touches the plane of the triangle. The contact lies in the direction of the
+/// steepest ascent, where the slope of the cutter profile matches the slope of the plane.
+/// None for vertical triangles.
+pub(crate) fn facet_contact(bit: &dyn ToolBit, triangle: &Triangle) -> Option<FacetContact> {
+    let radius = bit.radius();
+    let profile = |distance: f32| bit.height_at(distance.min(radius)).unwrap_or(0.0);
+
+    let normal = (triangle.b - triangle.a).cross(&(triangle.c - triangle.a));
+    if normal.z.abs() <= 1e-9 * normal.magnitude_squared() {
+        return None;
+    }
+
+    let gradient = -normal.xy() / normal.z;
+    let slope = gradient.magnitude();
+    let distance = if slope > 1e-9 {
+        maximize(0.0, radius, |r| slope * r - profile(r))
+    } else {
+        0.0
+    };
+    let direction = gradient.try_normalize(1e-9).unwrap_or_else(Vector2::x);
+    Some(FacetContact {
+        gradient,
+        offset: direction * distance,
+        height: profile(distance),
+    })
+}
+
+/// Whether the point lies inside of the triangle, projected onto the XY plane.
+pub(crate) fn contains(vertices: &[Vector3<f32>; 3], p: &Vector2<f32>) -> bool {
+    let side = |a: &Vector3<f32>, b: &Vector3<f32>| (b.xy() - a.xy()).perp(&(p - a.xy()));
+    let sides = [
+        side(&vertices[0], &vertices[1]),
+        side(&vertices[1], &vertices[2]),
+        side(&vertices[2], &vertices[0]),
+    ];
+    sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+}
+
+/// The parameter range of the segment from a to b that lies inside of the circle.
+fn clip(
+    a: &Vector2<f32>,
+    b: &Vector2<f32>,
+    center: &Vector2<f32>,
+    radius: f32,
+) -> Option<(f32, f32)> {
+    let d = b - a;
+    let f = a - center;
+    let qa = d.magnitude_squared();
+    let qb = 2.0 * f.dot(&d);
+    let qc = f.magnitude_squared() - radius * radius;
+    if qa < 1e-12 {
+        return None;
+    }
+
+    let discriminant = qb * qb - 4.0 * qa * qc;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let t0 = ((-qb - root) / (2.0 * qa)).max(0.0);
+    let t1 = ((-qb + root) / (2.0 * qa)).min(1.0);
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// The argument maximizing the concave function within the interval, using a golden section
+/// search.
+pub(crate) fn maximize<F>(mut low: f32, mut high: f32, f: F) -> f32
+where
+    F: Fn(f32) -> f32,
+{
+    const RATIO: f32 = 0.618_034;
+
+    let (start, end) = (low, high);
+    let mut x1 = high - RATIO * (high - low);
+    let mut x2 = low + RATIO * (high - low);
+    let mut f1 = f(x1);
+    let mut f2 = f(x2);
+    for _ in 0..ITERATIONS {
+        if f1 < f2 {
+            low = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = low + RATIO * (high - low);
+            f2 = f(x2);
+        } else {
+            high = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = high - RATIO * (high - low);
+            f1 = f(x1);
+        }
+    }
+
+    // The maximum often lies exactly at the ends, e.g. for flat cutters
+    [start, end, (low + high) * 0.5]
+        .into_iter()
+        .max_by(|a, b| f(*a).total_cmp(&f(*b)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::UnitVector3;
+
+    use super::*;
+    use crate::cnc::{BallNose, FlatEndMill, VBit};
+    use crate::testing::cuboid;
+
+    fn triangle(a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> Triangle {
+        let (a, b, c) = (Vector3::from(a), Vector3::from(b), Vector3::from(c));
+        Triangle::new(a, b, c, UnitVector3::new_normalize((b - a).cross(&(c - a))))
+    }
+
+    fn close(a: Option<f32>, b: f32) -> bool {
+        a.map_or(false, |a| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn flat_triangle() {
+        let flat = FlatEndMill::new(6.0, 20.0, 6.0, 2);
+        let ball = BallNose::new(6.0, 20.0, 6.0, 2);
+        let t = triangle([0.0, 0.0, 2.0], [10.0, 0.0, 2.0], [0.0, 10.0, 2.0]);
+
+        // Above the facet
+        assert!(close(
+            drop_triangle(&flat, &t, &Vector2::new(2.0, 2.0)),
+            2.0
+        ));
+        assert!(close(
+            drop_triangle(&ball, &t, &Vector2::new(2.0, 2.0)),
+            2.0
+        ));
+
+        // Next to an edge, the flat cutter rests on the edge, the ball sinks in
+        let beside = Vector2::new(5.0, -2.0);
+        assert!(close(drop_triangle(&flat, &t, &beside), 2.0));
+        let sunk = 2.0 - 3.0 + (9.0f32 - 4.0).sqrt();
+        assert!(close(drop_triangle(&ball, &t, &beside), sunk));
+
+        // Next to a vertex
+        let corner = Vector2::new(-2.0, -2.0);
+        assert!(close(drop_triangle(&flat, &t, &corner), 2.0));
+        let sunk = 2.0 - 3.0 + (9.0f32 - 8.0).sqrt();
+        assert!(close(drop_triangle(&ball, &t, &corner), sunk));
+
+        // Too far away
+        assert_eq!(drop_triangle(&flat, &t, &Vector2::new(5.0, -3.5)), None);
+    }
+
+    #[test]
+    fn sloped_triangles() {
+        // A 45 degree slope rising along X
+        let slope = [
+            triangle([0.0, -10.0, 0.0], [10.0, -10.0, 10.0], [0.0, 10.0, 0.0]),
+            triangle([10.0, -10.0, 10.0], [10.0, 10.0, 10.0], [0.0, 10.0, 0.0]),
+        ];
+        let point = Vector2::new(5.0, 0.0);
+        let drop = |bit: &dyn ToolBit| {
+            slope
+                .iter()
+                .filter_map(|t| drop_triangle(bit, t, &point))
+                .reduce(f32::max)
+        };
+
+        // The ball touches the slope off its axis, the tip hovers above the surface
+        let ball = BallNose::new(6.0, 20.0, 6.0, 2);
+        assert!(close(drop(&ball), 5.0 + 3.0 * (2f32.sqrt() - 1.0)));
+
+        // The flat cutter rests on its rim
+        let flat = FlatEndMill::new(6.0, 20.0, 6.0, 2);
+        assert!(close(drop(&flat), 8.0));
+
+        // A 90 degree V-bit fits the 45 degree slope exactly
+        let vbit = VBit::new(6.0, 90.0, 20.0, 6.0, 2);
+        assert!(close(drop(&vbit), 5.0));
+    }
+
+    #[test]
+    fn mesh() {
+        let mesh = cuboid(
+            Vector3::new(-10.0, -10.0, -5.0),
+            Vector3::new(10.0, 10.0, 0.0),
+        );
+        let bit = BallNose::new(4.0, 20.0, 4.0, 2);
+        let drop = DropCutter::new(&mesh, &bit);
+
+        assert!(close(drop.height(&Vector2::new(0.0, 0.0)), 0.0));
+        assert!(close(drop.height(&Vector2::new(9.9, -9.9)), 0.0));
+        assert_eq!(drop.height(&Vector2::new(12.5, 0.0)), None);
+        assert_eq!(drop.height(&Vector2::new(100.0, 0.0)), None);
+        let (height, triangle) = drop.contact(&Vector2::new(3.0, 4.0)).unwrap();
+        assert_eq!(height, 0.0);
+        assert_eq!(triangle.normal.z, 1.0);
+
+        // The buckets find the same contacts as testing every triangle
+        for i in 0..40 {
+            let point = Vector2::new(i as f32 * 0.61 - 12.0, i as f32 * 0.37 - 7.0);
+            let brute = mesh
+                .triangles
+                .iter()
+                .filter_map(|t| drop_triangle(&bit, t, &point))
+                .reduce(f32::max);
+            assert_eq!(drop.height(&point), brute, "{:?}", point);
+        }
+
+        let empty = Mesh::new(Vec::new());
+        assert_eq!(
+            DropCutter::new(&empty, &bit).height(&Vector2::zeros()),
+            None
+        );
+    }
+
+    #[test]
+    fn golden_section() {
+        let x = maximize(0.0, 1.0, |x| -(x - 0.3) * (x - 0.3));
+        assert!((x - 0.3).abs() < 1e-4);
+        // Maxima at the ends of the interval are found exactly
+        assert_eq!(maximize(0.0, 1.0, |x| x), 1.0);
+        assert_eq!(maximize(0.0, 1.0, |x| -x), 0.0);
+    }
+}
@@ -1,2 +1,12 @@
 pub mod cnc;
+pub mod dropcutter;
+pub mod offset;
+pub mod operations;
 pub mod primitives;
+pub mod pushcutter;
+pub mod simulation;
+
+#[cfg(test)]
+mod testing;
+
+pub use kelocam_toolpath as toolpath;
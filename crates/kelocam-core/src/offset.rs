@@ -0,0 +1,585 @@
+//! 2D polygon offsetting.
+//!
+//! Every ring gets offset on its own first, producing a raw offset curve which may intersect
+//! itself and other curves. The raw curves are then cleaned up by splitting them at all
+//! intersections and keeping only the edges that separate regions with a positive winding
+//! number from the rest. This removes self intersections, merges overlapping parts and lets
+//! parts split into separate islands or vanish entirely.
+//!
+//! Computations are performed in double precision to keep the cleanup robust.
+
+use std::collections::HashMap;
+
+use nalgebra::Vector2;
+
+use crate::primitives::{Path2, Polygon};
+
+type Point = Vector2<f64>;
+
+/// The shape of the corners created when offsetting convex corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Join {
+    /// Corners are rounded with arcs. The arcs deviate at most `tolerance` from the exact arc.
+    Round { tolerance: f32 },
+    /// Corners are extended to a sharp point. If the point is further than `limit` times the
+    /// offset distance away from the original corner, the corner gets beveled instead.
+    Miter { limit: f32 },
+}
+
+impl Default for Join {
+    fn default() -> Self {
+        Self::Round { tolerance: 0.01 }
+    }
+}
+
+/// Offsets the polygons. Positive distances grow, negative distances shrink the polygons.
+/// Overlapping results are merged.
+pub fn offset(polygons: &[Polygon], distance: f32, join: Join) -> Vec<Polygon> {
+    let raw: Vec<Vec<Point>> = polygons
+        .iter()
+        .flat_map(Polygon::rings)
+        .map(|ring| dedup(ring.points.iter().map(|p| p.cast()).collect()))
+        .filter(|ring| ring.len() >= 3)
+        .map(|ring| raw_offset(&ring, distance as f64, join))
+        .collect();
+
+    to_polygons(union_raw(&raw))
+}
+
+/// Merges the polygons and removes self intersections. This is an offset by zero.
+pub fn union(polygons: &[Polygon]) -> Vec<Polygon> {
+    let raw: Vec<Vec<Point>> = polygons
+        .iter()
+        .flat_map(Polygon::rings)
+        .map(|ring| dedup(ring.points.iter().map(|p| p.cast()).collect()))
+        .filter(|ring| ring.len() >= 3)
+        .collect();
+
+    to_polygons(union_raw(&raw))
+}
+
+fn to_polygons(rings: Vec<Vec<Point>>) -> Vec<Polygon> {
+    Polygon::from_paths(
+        rings
+            .into_iter()
+            .map(|ring| Path2::new(ring.iter().map(|p| p.cast()).collect()))
+            .collect(),
+    )
+}
+
+/// Removes consecutive duplicates (including the closing point).
+fn dedup(mut ring: Vec<Point>) -> Vec<Point> {
+    const EPSILON: f64 = 1e-12;
+    ring.dedup_by(|a, b| (*a - *b).magnitude_squared() < EPSILON);
+    while ring.len() > 1 && (ring[0] - ring[ring.len() - 1]).magnitude_squared() < EPSILON {
+        ring.pop();
+    }
+    ring
+}
+
+/// The normal on the right side of the direction.
+fn right(direction: Point) -> Point {
+    Point::new(direction.y, -direction.x).normalize()
+}
+
+/// Offsets every edge of the ring to its right side by the distance and connects the offset
+/// edges using the join. Concave corners are connected through the original corner, the
+/// resulting loops get removed by the cleanup.
+fn raw_offset(ring: &[Point], distance: f64, join: Join) -> Vec<Point> {
+    let len = ring.len();
+    let mut raw = Vec::with_capacity(len * 2);
+
+    for i in 0..len {
+        let prev = ring[(i + len - 1) % len];
+        let point = ring[i];
+        let next = ring[(i + 1) % len];
+
+        let e1 = point - prev;
+        let e2 = next - point;
+        let n1 = right(e1);
+        let n2 = right(e2);
+        let a = point + n1 * distance;
+        let b = point + n2 * distance;
+
+        let cross = e1.perp(&e2) / (e1.magnitude() * e2.magnitude());
+        let dot = e1.dot(&e2);
+
+        if cross.abs() < 1e-9 && dot > 0.0 {
+            // Collinear
+            raw.push(a);
+        } else if cross * distance > 0.0 || (cross.abs() < 1e-9 && dot < 0.0) {
+            // The offset edges diverge, fill the gap using the join
+            let angle = if cross.abs() < 1e-9 {
+                std::f64::consts::PI * distance.signum()
+            } else {
+                n1.perp(&n2).atan2(n1.dot(&n2))
+            };
+
+            raw.push(a);
+            match join {
+                Join::Round { tolerance } => {
+                    let ratio =
+                        (1.0 - tolerance.max(1e-6) as f64 / distance.abs()).clamp(-1.0, 1.0);
+                    let step = (2.0 * ratio.acos()).max(1e-3);
+                    let steps = (angle.abs() / step).ceil() as usize;
+                    for s in 1..steps {
+                        let t = angle * s as f64 / steps as f64;
+                        let (sin, cos) = t.sin_cos();
+                        let n = Point::new(n1.x * cos - n1.y * sin, n1.x * sin + n1.y * cos);
+                        raw.push(point + n * distance);
+                    }
+                }
+                Join::Miter { limit } => {
+                    let bisector = n1 + n2;
+                    let length = bisector.magnitude();
+                    // The bisector of two unit normals has a length of 2 * cos(angle / 2)
+                    if length > 1e-9 && 2.0 / length <= limit as f64 {
+                        raw.push(point + bisector * (distance * 2.0 / (length * length)));
+                    }
+                }
+            }
+            raw.push(b);
+        } else {
+            raw.push(a);
+            raw.push(point);
+            raw.push(b);
+        }
+    }
+
+    dedup(raw)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    a: Point,
+    b: Point,
+}
+
+impl Segment {
+    fn min_x(&self) -> f64 {
+        self.a.x.min(self.b.x)
+    }
+
+    fn max_x(&self) -> f64 {
+        self.a.x.max(self.b.x)
+    }
+}
+
+/// Computes the boundary of the region with a positive winding number of the rings. The
+/// resulting rings have the region on their left side.
+fn union_raw(rings: &[Vec<Point>]) -> Vec<Vec<Point>> {
+    let mut segments = Vec::new();
+    for ring in rings.iter() {
+        for i in 0..ring.len() {
+            let a = ring[i];
+            let b = ring[(i + 1) % ring.len()];
+            if a != b {
+                segments.push(Segment { a, b });
+            }
+        }
+    }
+
+    if segments.is_empty() {
+        return Vec::new();
+    }
+
+    // Tolerances are relative to the size of the input
+    let mut min = Point::from_element(f64::INFINITY);
+    let mut max = Point::from_element(f64::NEG_INFINITY);
+    for segment in segments.iter() {
+        min = min.inf(&segment.a.inf(&segment.b));
+        max = max.sup(&segment.a.sup(&segment.b));
+    }
+    let scale = (max - min).magnitude().max(1e-9);
+    let epsilon = scale * 1e-9;
+
+    // Split the segments at all intersections
+    let mut splits: Vec<Vec<(f64, Point)>> = vec![Vec::new(); segments.len()];
+    let mut order: Vec<usize> = (0..segments.len()).collect();
+    order.sort_by(|a, b| segments[*a].min_x().total_cmp(&segments[*b].min_x()));
+
+    for (k, &i) in order.iter().enumerate() {
+        let s1 = segments[i];
+        for &j in order[k + 1..].iter() {
+            let s2 = segments[j];
+            if s2.min_x() > s1.max_x() + epsilon {
+                break;
+            }
+            intersect(&s1, &s2, epsilon, |t, u, point| {
+                if t > 0.0 && t < 1.0 {
+                    splits[i].push((t, point));
+                }
+                if u > 0.0 && u < 1.0 {
+                    splits[j].push((u, point));
+                }
+            });
+        }
+    }
+
+    // Build the planar graph, merging vertices which are (almost) identical
+    let quantum = scale * 1e-8;
+    let mut vertices: Vec<Point> = Vec::new();
+    let mut vertex_ids: HashMap<(i64, i64), usize> = HashMap::new();
+    let mut vertex = |point: Point| -> usize {
+        let key = (
+            (point.x / quantum).round() as i64,
+            (point.y / quantum).round() as i64,
+        );
+        *vertex_ids.entry(key).or_insert_with(|| {
+            vertices.push(point);
+            vertices.len() - 1
+        })
+    };
+
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+    for (segment, mut splits) in segments.iter().zip(splits) {
+        splits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut from = vertex(segment.a);
+        for (_, point) in splits.into_iter().chain(std::iter::once((1.0, segment.b))) {
+            let to = vertex(point);
+            if to != from {
+                edges.push((from, to));
+                from = to;
+            }
+        }
+    }
+
+    // Group the segments into horizontal bands. The winding number of a point only depends on
+    // the segments crossing its height, which are all in the band of the point.
+    let count = ((segments.len() as f64).sqrt() * 4.0).ceil() as usize;
+    let height = (max.y - min.y).max(epsilon) / count as f64;
+    let band = |y: f64| (((y - min.y) / height).max(0.0) as usize).min(count - 1);
+    let mut bands: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for (i, segment) in segments.iter().enumerate() {
+        let (low, high) = (segment.a.y.min(segment.b.y), segment.a.y.max(segment.b.y));
+        for bucket in bands[band(low)..=band(high)].iter_mut() {
+            bucket.push(i);
+        }
+    }
+
+    // Keep the edges separating the positive region from the rest
+    let winding = |point: Point| -> i32 {
+        let mut winding = 0;
+        for &i in bands[band(point.y)].iter() {
+            let (a, b) = (segments[i].a, segments[i].b);
+            let side = (b - a).perp(&(point - a));
+            if a.y <= point.y {
+                if b.y > point.y && side > 0.0 {
+                    winding += 1;
+                }
+            } else if b.y <= point.y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+        winding
+    };
+
+    let mut boundary: Vec<(usize, usize)> = Vec::new();
+    for &(from, to) in edges.iter() {
+        let a = vertices[from];
+        let b = vertices[to];
+        let middle = (a + b) * 0.5;
+        let normal = right(b - a) * (scale * 1e-7);
+        let inside_left = winding(middle - normal) > 0;
+        let inside_right = winding(middle + normal) > 0;
+        if inside_left && !inside_right {
+            boundary.push((from, to));
+        } else if inside_right && !inside_left {
+            boundary.push((to, from));
+        }
+    }
+    boundary.sort_unstable();
+    boundary.dedup();
+
+    link(&vertices, &boundary)
+        .into_iter()
+        .map(simplify)
+        .filter(|ring| ring.len() >= 3 && signed_area(ring).abs() > epsilon * scale)
+        .collect()
+}
+
+/// Computes the intersection of two segments, calling back with the segment parameters and the
+/// intersection point. Collinear overlapping segments call back with each endpoint lying on the
+/// other segment.
+fn intersect<F>(s1: &Segment, s2: &Segment, epsilon: f64, mut callback: F)
+where
+    F: FnMut(f64, f64, Point),
+{
+    let r = s1.b - s1.a;
+    let s = s2.b - s2.a;
+    let qp = s2.a - s1.a;
+    let denom = r.perp(&s);
+    let rl = r.magnitude();
+    let sl = s.magnitude();
+
+    if denom.abs() <= epsilon * (rl + sl) {
+        // Parallel, only collinear segments can overlap
+        if (qp.perp(&r) / rl).abs() > epsilon {
+            return;
+        }
+
+        let rr = r.dot(&r);
+        let ss = s.dot(&s);
+        for point in [s2.a, s2.b] {
+            let t = (point - s1.a).dot(&r) / rr;
+            if t > 0.0 && t < 1.0 && (t * rl).min((1.0 - t) * rl) > epsilon {
+                callback(t, -1.0, point);
+            }
+        }
+        for point in [s1.a, s1.b] {
+            let u = (point - s2.a).dot(&s) / ss;
+            if u > 0.0 && u < 1.0 && (u * sl).min((1.0 - u) * sl) > epsilon {
+                callback(-1.0, u, point);
+            }
+        }
+        return;
+    }
+
+    let t = qp.perp(&s) / denom;
+    let u = qp.perp(&r) / denom;
+
+    // Tolerances in parameter space
+    let et = epsilon / rl;
+    let eu = epsilon / sl;
+    if t < -et || t > 1.0 + et || u < -eu || u > 1.0 + eu {
+        return;
+    }
+
+    // Snap intersections close to endpoints onto the endpoints
+    let (t, point) = if t <= et {
+        (0.0, s1.a)
+    } else if t >= 1.0 - et {
+        (1.0, s1.b)
+    } else if u <= eu {
+        (t, s2.a)
+    } else if u >= 1.0 - eu {
+        (t, s2.b)
+    } else {
+        (t, s1.a + r * t)
+    };
+    let u = if u <= eu {
+        0.0
+    } else if u >= 1.0 - eu {
+        1.0
+    } else {
+        u
+    };
+
+    callback(t, u, point);
+}
+
+/// Links the directed boundary edges into closed rings. At vertices with multiple outgoing
+/// edges, the sharpest left turn is taken, which separates regions touching in a single point.
+fn link(vertices: &[Point], edges: &[(usize, usize)]) -> Vec<Vec<Point>> {
+    let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, (from, _)) in edges.iter().enumerate() {
+        outgoing.entry(*from).or_default().push(i);
+    }
+
+    let mut used = vec![false; edges.len()];
+    let mut rings = Vec::new();
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+
+        let start = edges[first].0;
+        let mut ring = Vec::new();
+        let mut edge = first;
+        let closed = loop {
+            used[edge] = true;
+            let (from, to) = edges[edge];
+            ring.push(vertices[from]);
+
+            if to == start {
+                break true;
+            }
+
+            let incoming = vertices[to] - vertices[from];
+            let next = outgoing.get(&to).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .filter(|candidate| !used[**candidate])
+                    .map(|candidate| {
+                        let outgoing = vertices[edges[*candidate].1] - vertices[to];
+                        let angle = incoming.perp(&outgoing).atan2(incoming.dot(&outgoing));
+                        (*candidate, angle)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(candidate, _)| candidate)
+            });
+
+            match next {
+                Some(next) => edge = next,
+                None => break false,
+            }
+        };
+
+        if closed {
+            rings.push(ring);
+        }
+    }
+
+    rings
+}
+
+/// Removes collinear points from a ring.
+fn simplify(ring: Vec<Point>) -> Vec<Point> {
+    let mut ring = dedup(ring);
+    let mut i = 0;
+    while ring.len() >= 3 && i < ring.len() {
+        let len = ring.len();
+        let a = ring[(i + len - 1) % len];
+        let b = ring[i];
+        let c = ring[(i + 1) % len];
+        let ab = b - a;
+        let bc = c - b;
+        if ab.perp(&bc).abs() <= 1e-12 * ab.magnitude() * bc.magnitude() && ab.dot(&bc) > 0.0 {
+            ring.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    ring
+}
+
+fn signed_area(ring: &[Point]) -> f64 {
+    let len = ring.len();
+    (0..len)
+        .map(|i| ring[i].perp(&ring[(i + 1) % len]))
+        .sum::<f64>()
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn polygon(points: &[(f32, f32)]) -> Polygon {
+        let points = points.iter().map(|(x, y)| Vector2::new(*x, *y)).collect();
+        Polygon::new(Path2::new(points), Vec::new())
+    }
+
+    fn square(min: f32, max: f32) -> Polygon {
+        polygon(&[(min, min), (max, min), (max, max), (min, max)])
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let input = [square(0.0, 20.0)];
+
+        let grown = offset(&input, 2.0, Join::Round { tolerance: 0.001 });
+        assert_eq!(grown.len(), 1);
+        assert!(grown[0].exterior.is_ccw());
+        assert!((grown[0].area() - (400.0 + 80.0 * 2.0 + PI * 4.0)).abs() < 0.05);
+
+        let shrunk = offset(&input, -2.0, Join::default());
+        assert_eq!(shrunk.len(), 1);
+        assert!(close(shrunk[0].area(), 16.0 * 16.0));
+        let (min, max) = shrunk[0].exterior.bb_min_max();
+        assert!(close(min.x, 2.0) && close(max.y, 18.0));
+    }
+
+    #[test]
+    fn joins() {
+        let input = [square(0.0, 20.0)];
+
+        let miter = offset(&input, 2.0, Join::Miter { limit: 2.0 });
+        assert_eq!(miter[0].exterior.points.len(), 4);
+        assert!(close(miter[0].area(), 24.0 * 24.0));
+
+        // A right angle needs a limit of sqrt(2), below that the corners get beveled
+        let bevel = offset(&input, 2.0, Join::Miter { limit: 1.2 });
+        assert_eq!(bevel[0].exterior.points.len(), 8);
+        assert!(close(bevel[0].area(), 24.0 * 24.0 - 4.0 * 2.0));
+
+        let coarse = offset(&input, 2.0, Join::Round { tolerance: 0.5 });
+        let fine = offset(&input, 2.0, Join::Round { tolerance: 0.01 });
+        assert!(coarse[0].exterior.points.len() < fine[0].exterior.points.len());
+    }
+
+    #[test]
+    fn holes() {
+        let frame = [Polygon::new(
+            square(-10.0, 10.0).exterior,
+            vec![square(-3.0, 3.0).exterior],
+        )];
+
+        // Growing the frame narrows the hole
+        let grown = offset(&frame, 1.0, Join::Miter { limit: 2.0 });
+        assert_eq!(grown.len(), 1);
+        assert_eq!(grown[0].holes.len(), 1);
+        assert!(!grown[0].holes[0].is_ccw());
+        assert!(close(grown[0].area(), 22.0 * 22.0 - 4.0 * 4.0));
+
+        // Until it closes
+        let closed = offset(&frame, 3.5, Join::Miter { limit: 2.0 });
+        assert_eq!(closed.len(), 1);
+        assert!(closed[0].holes.is_empty());
+    }
+
+    #[test]
+    fn self_intersections() {
+        // A pentagram winds around its center twice
+        let star: Vec<(f32, f32)> = (0..5)
+            .map(|i| {
+                let angle = PI / 2.0 + i as f32 * 4.0 * PI / 5.0;
+                (10.0 * angle.cos(), 10.0 * angle.sin())
+            })
+            .collect();
+        let merged = union(&[polygon(&star)]);
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].holes.is_empty());
+        assert_eq!(merged[0].exterior.points.len(), 10);
+        assert!(merged[0].exterior.contains(&Vector2::zeros()));
+
+        // Overlapping polygons merge
+        let merged = union(&[square(0.0, 10.0), square(5.0, 15.0)]);
+        assert_eq!(merged.len(), 1);
+        assert!(close(merged[0].area(), 175.0));
+    }
+
+    #[test]
+    fn split_and_vanish() {
+        // Two squares connected by a narrow bridge
+        let dumbbell = [polygon(&[
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 4.0),
+            (15.0, 4.0),
+            (15.0, 0.0),
+            (25.0, 0.0),
+            (25.0, 10.0),
+            (15.0, 10.0),
+            (15.0, 6.0),
+            (10.0, 6.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+        ])];
+        assert_eq!(offset(&dumbbell, -0.5, Join::default()).len(), 1);
+
+        let split = offset(&dumbbell, -1.5, Join::default());
+        assert_eq!(split.len(), 2);
+        for part in split.iter() {
+            // The squares shrink to 7 x 7, with a small bump where the bridge was
+            assert!(part.area() > 49.0 && part.area() < 50.0, "{}", part.area());
+        }
+
+        // A small island vanishes entirely
+        let parts = offset(
+            &[square(0.0, 20.0), square(30.0, 33.0)],
+            -2.0,
+            Join::default(),
+        );
+        assert_eq!(parts.len(), 1);
+        assert!(close(parts[0].area(), 16.0 * 16.0));
+        assert!(offset(&[square(0.0, 3.0)], -1.5, Join::default()).is_empty());
+    }
+}
@@ -0,0 +1,816 @@
+use std::f32::consts::{PI, TAU};
+
+use nalgebra::{Rotation2, Vector2, Vector3};
+
+use crate::cnc::{Bit, FeedsSpeeds, ToolBit};
+use crate::offset::{self, Join};
+use crate::primitives::{BoundingBox, Mesh, Path2, Polygon};
+use crate::toolpath::{ArcDirection, Toolpath};
+
+use super::{ring_passes, slice_regions, step_levels, Operation};
+
+/// Limits the work done for a single level, in case the clearing does not converge.
+const MAX_PASSES: usize = 10_000;
+const MAX_STEPS: usize = 200_000;
+
+/// An adaptive clearing operation. Like a pocket, the model describes the volume to be removed.
+/// The remaining material is tracked while clearing, and the cutter is steered along the
+/// boundary of the cleared area so that its radial engagement stays at the configured maximum.
+/// Where the engagement can not be limited by steering (e.g. in tight corners or slots), the
+/// cutter advances using trochoidal loops. The cutter enters the material along a helix.
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    /// The maximum radial engagement (i.e. width of cut) of the cutter.
+    pub max_engagement: f32,
+    /// The maximum depth of a single pass.
+    pub step_down: f32,
+    pub feed_rate: f32,
+    pub plunge_rate: f32,
+    /// The angle (in degrees) of the helix the cutter descends along into the material.
+    pub ramp_angle: f32,
+    /// The height above the top of the model used for rapid moves.
+    pub clearance: f32,
+}
+
+/// A single continuous cut of the adaptive clearing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptivePass {
+    /// The positions of the cutter center.
+    pub points: Vec<Vector2<f32>>,
+    /// Whether the cutter can feed to the start of this pass from the end of the previous pass
+    /// directly, without hitting any material.
+    pub linked: bool,
+    /// The radius of the helix around the start of this pass along which the cutter enters the
+    /// material. None if the cutter can plunge, because the start has been cleared already or
+    /// there is no room for a helix.
+    pub helix: Option<f32>,
+}
+
+impl Adaptive {
+    pub fn new(feeds: &FeedsSpeeds) -> Self {
+        Self {
+            max_engagement: feeds.stepover,
+            step_down: feeds.depth_of_cut,
+            feed_rate: feeds.feed_rate,
+            plunge_rate: feeds.plunge_rate,
+            ramp_angle: 3.0,
+            clearance: 5.0,
+        }
+    }
+
+    /// The passes the cutter center follows for clearing the regions, in cutting order. The
+    /// clearing is finished by a pass along the outline of the regions.
+    pub fn passes(&self, regions: &[Polygon], radius: f32) -> Vec<AdaptivePass> {
+        let area = offset::offset(regions, -radius, Join::default());
+        if area.is_empty() {
+            return Vec::new();
+        }
+
+        let engagement = self.max_engagement.clamp(1e-2, radius * 2.0);
+        let (min, max) = regions
+            .iter()
+            .map(|region| region.exterior.bb_min_max())
+            .reduce(|a, b| (a.0.inf(&b.0), a.1.sup(&b.1)))
+            .unwrap_or_default();
+        let extent = (max - min).max();
+        let resolution = (engagement / 4.0).max(extent / 1000.0).min(radius / 4.0);
+        let margin = Vector2::from_element(radius + engagement + resolution * 2.0);
+
+        let mut material = Grid::new(min - margin, max + margin, resolution);
+        material.fill(regions);
+
+        // Keep the cutter center a cell inside the area, so that the raster can not let it
+        // gouge the walls
+        let mut allowed = Grid::new(min - margin, max + margin, resolution);
+        allowed.fill(&offset::offset(
+            regions,
+            -radius - resolution,
+            Join::default(),
+        ));
+
+        let mut clearing = Clearing {
+            material,
+            allowed,
+            radius,
+            engagement,
+            step: resolution * 2.0,
+        };
+        let mut passes = clearing.clear();
+
+        // Clean up the cusps left along the outline
+        let mut position = passes.last().and_then(|pass| pass.points.last().copied());
+        let rings = area
+            .into_iter()
+            .flat_map(|Polygon { exterior, holes }| std::iter::once(exterior).chain(holes))
+            .collect();
+        let bounds = offset::offset(regions, -radius + 1e-2, Join::default());
+        for points in ring_passes(rings, &mut position, true) {
+            let linked = passes
+                .last()
+                .and_then(|pass| pass.points.last())
+                .map_or(false, |end| {
+                    bounds
+                        .iter()
+                        .any(|bound| bound.contains_segment(end, &points[0]))
+                });
+            passes.push(AdaptivePass {
+                points,
+                linked,
+                helix: None,
+            });
+        }
+
+        passes
+    }
+}
+
+impl Operation for Adaptive {
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath {
+        let mut toolpath = Toolpath::new();
+        if mesh.triangles.is_empty() {
+            return toolpath;
+        }
+
+        let (min, max) = mesh.bb_min_max();
+        let safe_z = max.z + self.clearance;
+
+        // The material above the level has been cleared by the previous level
+        let mut top = max.z;
+        for z in step_levels(max.z, min.z, self.step_down) {
+            let regions = slice_regions(mesh, z);
+            for pass in self.passes(&regions, bit.radius()) {
+                let start = Vector3::new(pass.points[0].x, pass.points[0].y, z);
+                match toolpath.position().copied() {
+                    Some(position) if pass.linked && position.z == z => {
+                        toolpath.feed(start, self.feed_rate);
+                    }
+                    position => {
+                        if let Some(position) = position {
+                            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+                        }
+                        match pass.helix {
+                            Some(radius) => self.helix(&mut toolpath, &start, radius, top, safe_z),
+                            None => {
+                                toolpath.rapid(Vector3::new(start.x, start.y, safe_z));
+                                toolpath.plunge(start, self.plunge_rate);
+                            }
+                        }
+                    }
+                }
+
+                for point in pass.points.iter().skip(1) {
+                    toolpath.feed(Vector3::new(point.x, point.y, z), self.feed_rate);
+                }
+            }
+            top = z;
+        }
+
+        if let Some(position) = toolpath.position().copied() {
+            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+        }
+        toolpath.tagged("Adaptive")
+    }
+}
+
+impl Adaptive {
+    /// Descends from the top to the point along a counter clockwise helix around it, made of
+    /// half circles so that every arc has a distinct end point.
+    fn helix(
+        &self,
+        toolpath: &mut Toolpath,
+        center: &Vector3<f32>,
+        radius: f32,
+        top: f32,
+        safe_z: f32,
+    ) {
+        let side = |sign: f32, z: f32| Vector3::new(center.x + radius * sign, center.y, z);
+        toolpath.rapid(side(1.0, safe_z));
+        toolpath.plunge(side(1.0, top), self.plunge_rate);
+
+        let pitch = PI * radius * self.ramp_angle.clamp(0.1, 90.0).to_radians().tan();
+        let halves = ((top - center.z) / pitch).ceil().max(1.0) as usize;
+        for i in 1..=halves {
+            let z = top + (center.z - top) * i as f32 / halves as f32;
+            let sign = if i % 2 == 1 { -1.0 } else { 1.0 };
+            toolpath.arc(
+                side(sign, z),
+                center.xy(),
+                ArcDirection::CounterClockwise,
+                self.feed_rate,
+            );
+        }
+        toolpath.feed(*center, self.feed_rate);
+    }
+}
+
+/// The state of clearing a single level.
+struct Clearing {
+    /// The material remaining in the regions.
+    material: Grid,
+    /// Where the cutter center may go.
+    allowed: Grid,
+    radius: f32,
+    /// The target radial engagement.
+    engagement: f32,
+    /// The distance the cutter advances in a single step.
+    step: f32,
+}
+
+impl Clearing {
+    fn clear(&mut self) -> Vec<AdaptivePass> {
+        let resolution = self.material.resolution;
+        let clearance = self.allowed.distance(false);
+
+        // Material out of reach along the walls is left for the final pass along the outline
+        let reachable = self.allowed.distance(true);
+        for (cell, distance) in self.material.cells.iter_mut().zip(reachable) {
+            *cell &= distance <= self.radius - resolution * 0.5;
+        }
+
+        let mut passes: Vec<AdaptivePass> = Vec::new();
+        for _ in 0..MAX_PASSES {
+            let position = passes.last().and_then(|pass| pass.points.last().copied());
+            let reach = self.material.distance(true);
+
+            // Continue along the boundary of the cleared area, where the cutter fits without
+            // touching material but has enough material within a step to cut
+            let mut candidates: Vec<Vector2<f32>> = (0..reach.len())
+                .filter(|i| {
+                    self.allowed.cells[*i]
+                        && reach[*i] >= self.radius - resolution
+                        && reach[*i] <= self.radius + self.step * 0.5
+                })
+                .map(|i| self.material.center(i))
+                .collect();
+            if let Some(position) = position {
+                candidates.sort_by(|a, b| {
+                    let da = (a - position).magnitude_squared();
+                    let db = (b - position).magnitude_squared();
+                    da.total_cmp(&db)
+                });
+            }
+            let minimum = (self.engagement * self.step * 2.0 / (resolution * resolution)) as usize;
+            let start = candidates.into_iter().find(|candidate| {
+                self.material.count(candidate, self.radius + self.step) >= minimum.max(1)
+            });
+
+            let pass = match start {
+                Some(start) => {
+                    let linked = position
+                        .map_or(false, |position| self.link_clear(&reach, &position, &start));
+                    let mut points = vec![start];
+                    let heading = self.material_direction(&start);
+                    let heading = Vector2::new(-heading.y, heading.x);
+                    let cut = self.follow(&mut points, heading);
+
+                    if cut == 0 {
+                        // The material can not be reached from here, give up on it
+                        self.material.clear(&start, self.radius + self.step);
+                        continue;
+                    }
+                    AdaptivePass {
+                        points,
+                        linked,
+                        helix: None,
+                    }
+                }
+                None => {
+                    // Enter the material where there is the most room, ignoring single crumbs
+                    let mut entries: Vec<usize> = (0..reach.len())
+                        .filter(|i| self.allowed.cells[*i] && self.material.cells[*i])
+                        .collect();
+                    entries.sort_by(|a, b| clearance[*b].total_cmp(&clearance[*a]));
+                    let entry = entries.into_iter().find(|i| {
+                        self.material.count(&self.material.center(*i), self.radius) >= minimum
+                    });
+                    let Some(entry) = entry else {
+                        break;
+                    };
+
+                    // Enter along a helix around the center, if there is room for it
+                    let center = self.material.center(entry);
+                    let room = clearance[entry] - resolution;
+                    let helix = Some(room.min(self.radius * 0.5)).filter(|r| *r >= resolution);
+                    if let Some(helix) = helix {
+                        self.material.clear(&center, self.radius + helix);
+                    }
+
+                    let mut points = Vec::new();
+                    let heading = self.spiral(&mut points, center, clearance[entry]);
+                    self.follow(&mut points, heading);
+                    AdaptivePass {
+                        points,
+                        linked: false,
+                        helix,
+                    }
+                }
+            };
+
+            passes.push(pass);
+        }
+
+        passes
+    }
+
+    /// Whether the cutter can move along the line without hitting material or leaving the
+    /// allowed area.
+    fn link_clear(&self, reach: &[f32], a: &Vector2<f32>, b: &Vector2<f32>) -> bool {
+        let resolution = self.material.resolution;
+        let steps = ((b - a).magnitude() / resolution).ceil() as usize;
+        (0..=steps).all(|i| {
+            let point = a.lerp(b, i as f32 / steps.max(1) as f32);
+            self.allowed.get(&point)
+                && self
+                    .material
+                    .index(&point)
+                    .map_or(false, |i| reach[i] >= self.radius - resolution)
+        })
+    }
+
+    /// The direction from the point towards the nearby material.
+    fn material_direction(&self, point: &Vector2<f32>) -> Vector2<f32> {
+        let mut direction = Vector2::zeros();
+        let radius = self.radius + self.engagement + self.material.resolution;
+        self.material.for_disk(point, radius, |grid, i| {
+            if grid.cells[i] {
+                direction += grid.center(i) - point;
+            }
+        });
+        direction.try_normalize(1e-6).unwrap_or_else(Vector2::x)
+    }
+
+    /// Starts at the center and spirals outwards counter clockwise, with the engagement as
+    /// pitch. Returns the heading at the end of the spiral.
+    fn spiral(
+        &mut self,
+        points: &mut Vec<Vector2<f32>>,
+        center: Vector2<f32>,
+        room: f32,
+    ) -> Vector2<f32> {
+        let outer = (room - self.material.resolution).min(self.radius);
+        points.push(center);
+        self.material.clear(&center, self.radius);
+
+        if outer < self.engagement * 0.5 {
+            return Vector2::x();
+        }
+
+        let mut angle: f32 = 0.0;
+        let end = outer / self.engagement * TAU + TAU;
+        while angle < end {
+            let radius = (self.engagement * angle / TAU).min(outer);
+            angle += self.step / radius.max(self.step);
+            let radius = (self.engagement * angle.min(end) / TAU).min(outer);
+            let (sin, cos) = angle.min(end).sin_cos();
+            let point = center + Vector2::new(cos, sin) * radius;
+            self.material.clear(&point, self.radius);
+            points.push(point);
+        }
+
+        let (sin, cos) = end.sin_cos();
+        Vector2::new(-sin, cos)
+    }
+
+    /// The radial engagement when moving from the position (which has been cut already) to the
+    /// point, or None if the cutter may not move there.
+    fn engagement_at(&self, position: &Vector2<f32>, point: &Vector2<f32>) -> Option<f32> {
+        if !self.allowed.get(point) {
+            return None;
+        }
+
+        let resolution = self.material.resolution;
+        let cells = self.material.count(point, self.radius);
+        Some(cells as f32 * resolution * resolution / (point - position).magnitude())
+    }
+
+    /// Follows the boundary of the cleared area, keeping the material on the right side (climb
+    /// milling) and steering so that the engagement matches the target. Returns the number of
+    /// cells cut.
+    fn follow(&mut self, points: &mut Vec<Vector2<f32>>, mut heading: Vector2<f32>) -> usize {
+        const SAMPLES: usize = 24;
+        const MAX_TURN: f32 = PI * 5.0 / 6.0;
+
+        let mut position = *points.last().unwrap();
+        let mut cut = self.material.clear(&position, self.radius);
+
+        for _ in 0..MAX_STEPS {
+            let direction = |angle: f32| Rotation2::new(angle) * heading;
+            let sample = |angle: f32| {
+                let point = position + direction(angle) * self.step;
+                self.engagement_at(&position, &point)
+            };
+
+            // Scan from the left (the cleared side) to the right (the material side) for the
+            // direction where the engagement reaches the target
+            let angles: Vec<f32> = (0..=SAMPLES)
+                .map(|i| MAX_TURN - 2.0 * MAX_TURN * i as f32 / SAMPLES as f32)
+                .collect();
+            let engagements: Vec<Option<f32>> = angles.iter().map(|angle| sample(*angle)).collect();
+
+            // Pairs of adjacent directions, where the first has too little engagement and the
+            // second too much. Crossings with the material on the right side are preferred.
+            let valid: Vec<(usize, f32)> = engagements
+                .iter()
+                .enumerate()
+                .filter_map(|(i, engagement)| engagement.map(|e| (i, e)))
+                .collect();
+            let target = self.engagement;
+            let crossing = valid
+                .windows(2)
+                .find(|pair| {
+                    pair[1].0 == pair[0].0 + 1 && pair[0].1 < target && pair[1].1 >= target
+                })
+                .map(|pair| (pair[0].0, pair[1].0))
+                .or_else(|| {
+                    valid
+                        .windows(2)
+                        .find(|pair| {
+                            pair[1].0 == pair[0].0 + 1 && pair[0].1 >= target && pair[1].1 < target
+                        })
+                        .map(|pair| (pair[1].0, pair[0].0))
+                });
+
+            let angle = if let Some((low, high)) = crossing {
+                // Bisect between the directions with too little and too much engagement
+                let (mut low, mut high) = (angles[low], angles[high]);
+                for _ in 0..6 {
+                    let middle = (low + high) * 0.5;
+                    match sample(middle) {
+                        Some(engagement) if engagement < target => low = middle,
+                        _ => high = middle,
+                    }
+                }
+                low
+            } else if valid.iter().all(|(_, engagement)| *engagement >= target) {
+                // Every direction is overloaded
+                if let Some((end, cells)) = self.trochoid(points, position, heading) {
+                    cut += cells;
+                    position = end;
+                    continue;
+                }
+
+                match valid.iter().min_by(|a, b| a.1.total_cmp(&b.1)) {
+                    Some((i, _)) => angles[*i],
+                    None => break,
+                }
+            } else {
+                // No direction reaches the target exactly. Take the direction with the most
+                // engagement below the target, or the least overloaded one if that cuts too little.
+                let (below, above): (Vec<&(usize, f32)>, Vec<_>) = valid
+                    .iter()
+                    .partition(|(_, engagement)| *engagement < target);
+                let most = below.iter().max_by(|a, b| a.1.total_cmp(&b.1));
+                let least = above.iter().min_by(|a, b| a.1.total_cmp(&b.1));
+                match (most, least) {
+                    (Some((i, engagement)), _) if *engagement >= target * 0.2 => angles[*i],
+                    (_, Some((i, _))) => angles[*i],
+                    _ => break,
+                }
+            };
+
+            heading = direction(angle);
+            position += heading * self.step;
+            cut += self.material.clear(&position, self.radius);
+            points.push(position);
+        }
+
+        cut
+    }
+
+    /// Advances in the heading using a counter clockwise loop, which cuts the engagement per
+    /// loop. Returns the final position and the number of cells cut, the heading stays the same.
+    fn trochoid(
+        &mut self,
+        points: &mut Vec<Vector2<f32>>,
+        position: Vector2<f32>,
+        heading: Vector2<f32>,
+    ) -> Option<(Vector2<f32>, usize)> {
+        let left = Vector2::new(-heading.y, heading.x);
+
+        for fraction in [1.0, 0.5, 0.25] {
+            let radius = self.engagement.min(self.radius * 0.5) * fraction;
+            if radius < self.material.resolution * 2.0 {
+                break;
+            }
+
+            let center = position + left * radius;
+            let start = (position - center).y.atan2((position - center).x);
+            let count = ((TAU * radius / self.step).ceil() as usize).max(8);
+            let loop_points: Vec<Vector2<f32>> = (1..=count)
+                .map(|i| {
+                    let t = i as f32 / count as f32;
+                    let (sin, cos) = (start + TAU * t).sin_cos();
+                    center + heading * (self.engagement * t) + Vector2::new(cos, sin) * radius
+                })
+                .collect();
+
+            if loop_points.iter().all(|point| self.allowed.get(point)) {
+                let cells = loop_points
+                    .iter()
+                    .map(|point| self.material.clear(point, self.radius))
+                    .sum();
+                let end = *loop_points.last().unwrap();
+                points.extend(loop_points);
+                return Some((end, cells));
+            }
+        }
+
+        None
+    }
+}
+
+/// A raster of boolean cells, e.g. the material remaining.
+struct Grid {
+    origin: Vector2<f32>,
+    resolution: f32,
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl Grid {
+    fn new(min: Vector2<f32>, max: Vector2<f32>, resolution: f32) -> Self {
+        let width = ((max.x - min.x) / resolution).ceil() as usize + 1;
+        let height = ((max.y - min.y) / resolution).ceil() as usize + 1;
+        Self {
+            origin: min,
+            resolution,
+            width,
+            height,
+            cells: vec![false; width * height],
+        }
+    }
+
+    /// Sets the cells whose center lies inside of the polygons.
+    fn fill(&mut self, polygons: &[Polygon]) {
+        let rings: Vec<&Path2> = polygons.iter().flat_map(Polygon::rings).collect();
+        for y in 0..self.height {
+            let py = self.origin.y + y as f32 * self.resolution;
+
+            let mut xs = Vec::new();
+            for ring in rings.iter() {
+                let len = ring.points.len();
+                for i in 0..len {
+                    let a = ring.points[i];
+                    let b = ring.points[(i + 1) % len];
+                    if (a.y <= py) != (b.y <= py) {
+                        xs.push(a.x + (py - a.y) / (b.y - a.y) * (b.x - a.x));
+                    }
+                }
+            }
+            xs.sort_by(f32::total_cmp);
+
+            for pair in xs.chunks_exact(2) {
+                let from = ((pair[0] - self.origin.x) / self.resolution)
+                    .ceil()
+                    .max(0.0) as usize;
+                let to = ((pair[1] - self.origin.x) / self.resolution).floor();
+                if to < 0.0 {
+                    continue;
+                }
+                for x in from..=(to as usize).min(self.width - 1) {
+                    self.cells[y * self.width + x] = true;
+                }
+            }
+        }
+    }
+
+    fn center(&self, index: usize) -> Vector2<f32> {
+        let x = (index % self.width) as f32;
+        let y = (index / self.width) as f32;
+        self.origin + Vector2::new(x, y) * self.resolution
+    }
+
+    fn index(&self, point: &Vector2<f32>) -> Option<usize> {
+        let x = ((point.x - self.origin.x) / self.resolution).round();
+        let y = ((point.y - self.origin.y) / self.resolution).round();
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    fn get(&self, point: &Vector2<f32>) -> bool {
+        self.index(point).map_or(false, |i| self.cells[i])
+    }
+
+    /// Calls back with every cell whose center lies inside of the disk.
+    fn for_disk<F>(&self, center: &Vector2<f32>, radius: f32, mut callback: F)
+    where
+        F: FnMut(&Self, usize),
+    {
+        let local = (center - self.origin) / self.resolution;
+        let radius = radius / self.resolution;
+        let y0 = (local.y - radius).ceil().max(0.0) as usize;
+        let y1 = (local.y + radius).floor().min(self.height as f32 - 1.0);
+        if y1 < 0.0 {
+            return;
+        }
+
+        for y in y0..=y1 as usize {
+            let dy = y as f32 - local.y;
+            let dx = (radius * radius - dy * dy).max(0.0).sqrt();
+            let x0 = (local.x - dx).ceil().max(0.0) as usize;
+            let x1 = (local.x + dx).floor().min(self.width as f32 - 1.0);
+            if x1 < 0.0 {
+                continue;
+            }
+            for x in x0..=x1 as usize {
+                callback(self, y * self.width + x);
+            }
+        }
+    }
+
+    /// The number of set cells inside of the disk.
+    fn count(&self, center: &Vector2<f32>, radius: f32) -> usize {
+        let mut count = 0;
+        self.for_disk(center, radius, |grid, i| {
+            if grid.cells[i] {
+                count += 1;
+            }
+        });
+        count
+    }
+
+    /// Unsets the cells inside of the disk. Returns the number of cells that have been set.
+    fn clear(&mut self, center: &Vector2<f32>, radius: f32) -> usize {
+        let mut indices = Vec::new();
+        self.for_disk(center, radius, |grid, i| {
+            if grid.cells[i] {
+                indices.push(i);
+            }
+        });
+        for i in indices.iter() {
+            self.cells[*i] = false;
+        }
+        indices.len()
+    }
+
+    /// The distance of every cell to the closest cell with the value. Uses the exact euclidean
+    /// distance transform by Felzenszwalb and Huttenlocher.
+    fn distance(&self, value: bool) -> Vec<f32> {
+        const FAR: f64 = 1e12;
+
+        let size = self.width.max(self.height);
+        let mut f = vec![0.0; size];
+        let mut d = vec![0.0; size];
+        let mut v = vec![0; size];
+        let mut z = vec![0.0; size + 1];
+
+        let mut squared: Vec<f64> = self
+            .cells
+            .iter()
+            .map(|cell| if *cell == value { 0.0 } else { FAR })
+            .collect();
+
+        for x in 0..self.width {
+            for y in 0..self.height {
+                f[y] = squared[y * self.width + x];
+            }
+            transform(&f[..self.height], &mut d, &mut v, &mut z);
+            for y in 0..self.height {
+                squared[y * self.width + x] = d[y];
+            }
+        }
+
+        for y in 0..self.height {
+            let row = y * self.width..(y + 1) * self.width;
+            f[..self.width].copy_from_slice(&squared[row.clone()]);
+            transform(&f[..self.width], &mut d, &mut v, &mut z);
+            squared[row].copy_from_slice(&d[..self.width]);
+        }
+
+        squared
+            .into_iter()
+            .map(|squared| squared.sqrt() as f32 * self.resolution)
+            .collect()
+    }
+}
+
+/// The one dimensional squared distance transform of the sampled function.
+fn transform(f: &[f64], d: &mut [f64], v: &mut [usize], z: &mut [f64]) {
+    let n = f.len();
+    let intersection = |q: usize, p: usize| {
+        ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2 * (q - p)) as f64
+    };
+
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+    for q in 1..n {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, d) in d.iter_mut().enumerate().take(n) {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - v[k] as f64;
+        *d = offset * offset + f[v[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::FlatEndMill;
+    use crate::testing::cuboid;
+    use crate::toolpath::Move;
+
+    fn adaptive() -> Adaptive {
+        Adaptive::new(&FeedsSpeeds::new(18000.0, 1000.0, 300.0, 1.0, 2.0))
+    }
+
+    fn square(half: f32) -> Polygon {
+        let points = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, y)| Vector2::new(x * half, y * half))
+            .collect();
+        Polygon::new(Path2::new(points), Vec::new())
+    }
+
+    #[test]
+    fn clears_the_region() {
+        let passes = adaptive().passes(&[square(10.0)], 3.0);
+        assert!(!passes.is_empty());
+
+        // Entered along a helix, finished along the outline
+        assert!(passes[0].helix.is_some());
+        assert!(!passes[0].linked);
+        let outline = passes.last().unwrap();
+        assert_eq!(outline.points.first(), outline.points.last());
+        assert!(outline.points.iter().any(|p| (p.x - 7.0).abs() < 1e-3));
+
+        // The cutter stays inside of the region
+        let points: Vec<&Vector2<f32>> = passes.iter().flat_map(|pass| &pass.points).collect();
+        for point in points.iter() {
+            assert!(point.x.abs() <= 7.0 + 1e-3 && point.y.abs() <= 7.0 + 1e-3);
+        }
+
+        // And cuts every point of it
+        for i in -9..=9 {
+            for j in -9..=9 {
+                let sample = Vector2::new(i as f32, j as f32);
+                let covered = points
+                    .iter()
+                    .any(|point| (*point - sample).magnitude() <= 3.0 + 0.2);
+                assert!(covered, "{:?}", sample);
+            }
+        }
+    }
+
+    #[test]
+    fn no_room() {
+        // Narrower than the cutter
+        assert!(adaptive().passes(&[square(2.0)], 3.0).is_empty());
+    }
+
+    #[test]
+    fn helix_entry() {
+        let mesh = cuboid(
+            Vector3::new(-10.0, -10.0, -4.0),
+            Vector3::new(10.0, 10.0, 0.0),
+        );
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let operation = adaptive();
+        let toolpath = operation.generate(&mesh, &bit);
+        assert_eq!(toolpath.operation(0), Some("Adaptive"));
+        assert_eq!(toolpath.position().unwrap().z, 5.0);
+
+        // Plunges only go down to the top of the level, the rest is a helix
+        let mut top = 0.0;
+        let mut position = Vector3::zeros();
+        let mut entries = 0;
+        for instruction in toolpath.moves.iter() {
+            match instruction {
+                Move::Plunge { to, .. } => {
+                    assert_eq!(to.z, top, "plunge into the material");
+                    entries += 1;
+                }
+                Move::Arc { to, .. } => {
+                    assert!(to.z < position.z);
+                    // Descending at most at the ramp angle
+                    let run = (to.xy() - position.xy()).magnitude() * PI / 2.0;
+                    let angle = ((position.z - to.z) / run).atan().to_degrees();
+                    assert!(angle <= operation.ramp_angle + 1e-3, "{}", angle);
+                }
+                Move::Feed { to, .. } if to.z < top => top = to.z,
+                _ => {}
+            }
+            position = *instruction.to().unwrap_or(&position);
+        }
+        assert_eq!(top, -4.0);
+        // A single entry per level
+        assert_eq!(entries, 2);
+    }
+}
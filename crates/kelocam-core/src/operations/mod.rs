@@ -0,0 +1,358 @@
+//! Toolpath generating operations. All operations expect the mesh in millimeters, with the Z axis
+//! pointing up (away from the machine bed).
+
+pub mod adaptive;
+pub mod pocket;
+pub mod profile;
+pub mod raster;
+pub mod waterline;
+
+pub use adaptive::{Adaptive, AdaptivePass};
+pub use pocket::{Pocket, PocketStrategy};
+pub use profile::{Profile, ProfileSide};
+pub use raster::Raster;
+pub use waterline::Waterline;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::cnc::{Bit, EnvelopeViolation, Machine};
+use crate::primitives::{Axis, BoundingBox, Mesh, Path2, Plane, Polygon};
+use crate::toolpath::Toolpath;
+
+/// An operation turns a model into cutter motion.
+pub trait Operation {
+    /// Generate the toolpath for machining the mesh using the bit.
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath;
+
+    /// Generate the toolpath like `generate`, but refuse toolpaths that leave the work envelope
+    /// of the machine.
+    fn generate_for(
+        &self,
+        mesh: &Mesh,
+        bit: &Bit,
+        machine: &Machine,
+    ) -> Result<Toolpath, EnvelopeViolation> {
+        let toolpath = self.generate(mesh, bit);
+        match check_envelope(&toolpath, machine) {
+            Some(violation) => Err(violation),
+            None => Ok(toolpath),
+        }
+    }
+}
+
+/// Check whether the toolpath leaves the work envelope of the machine. Returns the first
+/// violation.
+pub fn check_envelope(toolpath: &Toolpath, machine: &Machine) -> Option<EnvelopeViolation> {
+    machine.check_envelope(&swept_points(toolpath))
+}
+
+/// The points the cutter passes through, including the points along arcs. Arcs can bulge out
+/// beyond their endpoints, so checking the targets of the moves is not enough.
+fn swept_points(toolpath: &Toolpath) -> Vec<Vector3<f32>> {
+    const TOLERANCE: f32 = 0.01;
+
+    let mut points = Vec::new();
+    let mut position: Option<Vector3<f32>> = None;
+    for instruction in toolpath.moves.iter() {
+        let Some(to) = instruction.to() else {
+            continue;
+        };
+        match position {
+            Some(from) => points.extend(instruction.interpolate(&from, TOLERANCE)),
+            None => points.push(*to),
+        }
+        position = Some(*to);
+    }
+    points
+}
+
+/// Computes the Z levels for cutting from the top down to the bottom in steps of at most
+/// `step_down`. The levels are ordered top-down and always include the bottom.
+pub fn step_levels(top: f32, bottom: f32, step_down: f32) -> Vec<f32> {
+    let mut levels = Vec::new();
+    if top <= bottom {
+        return levels;
+    }
+
+    // The small bias avoids an additional, paper thin level caused by rounding errors
+    let steps = ((top - bottom) / step_down.max(1e-3) - 1e-4).ceil() as usize;
+    for i in 1..steps {
+        levels.push(top - i as f32 * step_down);
+    }
+    levels.push(bottom);
+    levels
+}
+
+/// The regions of the model at the Z level. Holes in the regions are islands of the model.
+pub fn slice_regions(mesh: &Mesh, z: f32) -> Vec<Polygon> {
+    const EPSILON: f32 = 1e-3;
+
+    let (min, max) = mesh.bb_min_max();
+    // Slicing exactly at the top or bottom face does not yield any outlines
+    let z = z.clamp(min.z + EPSILON, max.z - EPSILON);
+    let plane = Plane::new(Vector3::new(0.0, 0.0, z), Axis::Z_VEC);
+
+    Polygon::from_paths(mesh.slice(&plane).iter().map(Path2::from).collect())
+}
+
+/// Cuts the passes at the Z level one after another. Consecutive passes are linked by feeding
+/// directly if the straight move stays inside of the bounds, otherwise the cutter retracts to
+/// the safe height and plunges again.
+pub(crate) fn cut_passes(
+    toolpath: &mut Toolpath,
+    passes: &[Vec<Vector2<f32>>],
+    bounds: &[Polygon],
+    z: f32,
+    safe_z: f32,
+    feed_rate: f32,
+    plunge_rate: f32,
+) {
+    for pass in passes.iter().filter(|pass| !pass.is_empty()) {
+        let start = Vector3::new(pass[0].x, pass[0].y, z);
+
+        let position = toolpath.position().copied();
+        let linked = position.map_or(false, |position| {
+            position.z == z
+                && bounds
+                    .iter()
+                    .any(|bound| bound.contains_segment(&position.xy(), &pass[0]))
+        });
+
+        if linked {
+            if position != Some(start) {
+                toolpath.feed(start, feed_rate);
+            }
+        } else {
+            if let Some(position) = position {
+                toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+            }
+            toolpath.rapid(Vector3::new(start.x, start.y, safe_z));
+            toolpath.plunge(start, plunge_rate);
+        }
+
+        for point in pass.iter().skip(1) {
+            toolpath.feed(Vector3::new(point.x, point.y, z), feed_rate);
+        }
+    }
+}
+
+/// Turns the rings into closed passes. The closest ring is cut next, starting at its closest
+/// point to the current position. The rings have to be oriented with the area to clear on their
+/// left side.
+pub(crate) fn ring_passes(
+    mut rings: Vec<Path2>,
+    position: &mut Option<Vector2<f32>>,
+    climb: bool,
+) -> Vec<Vec<Vector2<f32>>> {
+    let mut passes = Vec::new();
+    while !rings.is_empty() {
+        let current = position.unwrap_or(rings[0].points[0]);
+        let (index, start) = rings
+            .iter()
+            .enumerate()
+            .map(|(i, ring)| (i, ring.closest_point(&current)))
+            .min_by(|a, b| {
+                let da = (rings[a.0].points[a.1] - current).magnitude_squared();
+                let db = (rings[b.0].points[b.1] - current).magnitude_squared();
+                da.total_cmp(&db)
+            })
+            .unwrap();
+
+        let mut pass = rings.swap_remove(index).points;
+        pass.rotate_left(start);
+
+        // The area lies on the left side of the rings and the remaining material outside of
+        // the area, i.e. on the right, as required for climb milling
+        if !climb {
+            pass[1..].reverse();
+        }
+        pass.push(pass[0]);
+
+        *position = Some(pass[0]);
+        passes.push(pass);
+    }
+    passes
+}
+
+/// Fills the area enclosed by the rings with lines parallel to the X axis, at most `stepover`
+/// apart. The lines are ordered so that the closest line is cut next, which alternates the
+/// direction of adjacent lines. Areas without height in Y get no lines.
+pub(crate) fn hatch(rings: &[Path2], stepover: f32) -> Vec<[Vector2<f32>; 2]> {
+    const EPSILON: f32 = 1e-4;
+
+    let Some((min, max)) = rings
+        .iter()
+        .map(Path2::bb_min_max)
+        .reduce(|a, b| (a.0.inf(&b.0), a.1.sup(&b.1)))
+        .filter(|(min, max)| max.y - min.y >= 2.0 * EPSILON)
+    else {
+        return Vec::new();
+    };
+
+    // Distribute the lines evenly, with the outermost lines touching the area
+    let height = max.y - min.y;
+    let count = (height / stepover).ceil().max(1.0) as usize;
+    let mut segments = Vec::new();
+    for i in 0..=count {
+        let y = (min.y + height * i as f32 / count as f32).clamp(min.y + EPSILON, max.y - EPSILON);
+
+        let mut xs = Vec::new();
+        for ring in rings.iter() {
+            let len = ring.points.len();
+            for j in 0..len {
+                let a = ring.points[j];
+                let b = ring.points[(j + 1) % len];
+                if (a.y <= y) != (b.y <= y) {
+                    xs.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+                }
+            }
+        }
+        xs.sort_by(f32::total_cmp);
+
+        for pair in xs.chunks_exact(2) {
+            segments.push([Vector2::new(pair[0], y), Vector2::new(pair[1], y)]);
+        }
+    }
+
+    let mut ordered = Vec::new();
+    let mut position = segments.first().map(|segment| segment[0]);
+    while let Some(current) = position {
+        let closest = segments
+            .iter()
+            .enumerate()
+            .flat_map(|(i, segment)| {
+                [(i, false), (i, true)].map(|(i, flip)| {
+                    let start = if flip { segment[1] } else { segment[0] };
+                    (i, flip, (start - current).magnitude_squared())
+                })
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+
+        match closest {
+            Some((i, flip, _)) => {
+                let mut segment = segments.swap_remove(i);
+                if flip {
+                    segment.reverse();
+                }
+                position = Some(segment[1]);
+                ordered.push(segment);
+            }
+            None => break,
+        }
+    }
+
+    ordered
+}
+
+/// Removes points which deviate at most `tolerance` from the straight line between their
+/// neighbours (Douglas-Peucker). Keeps the first and the last point.
+pub(crate) fn simplify(points: &[Vector3<f32>], tolerance: f32) -> Vec<Vector3<f32>> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let a = points[first];
+        let direction = (points[last] - a).try_normalize(1e-9);
+        let farthest = (first + 1..last)
+            .map(|i| {
+                let offset = points[i] - a;
+                let distance = match direction {
+                    Some(direction) => (offset - direction * offset.dot(&direction)).magnitude(),
+                    None => offset.magnitude(),
+                };
+                (i, distance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                stack.push((first, i));
+                stack.push((i, last));
+            }
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::FlatEndMill;
+    use crate::toolpath::{ArcDirection, Move};
+
+    /// An operation which always generates the same toolpath.
+    struct Fixed(Toolpath);
+
+    impl Operation for Fixed {
+        fn generate(&self, _mesh: &Mesh, _bit: &Bit) -> Toolpath {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn envelope_includes_arcs() {
+        let machine = Machine::default();
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let mesh = Mesh::new(Vec::new());
+
+        // A half circle from (0, -190) to (0, 190) around the origin, which bulges out to X 190
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::new(0.0, -190.0, 10.0));
+        toolpath.arc(
+            Vector3::new(0.0, 190.0, 10.0),
+            Vector2::zeros(),
+            ArcDirection::CounterClockwise,
+            1000.0,
+        );
+        let violation = Fixed(toolpath.clone())
+            .generate_for(&mesh, &bit, &machine)
+            .unwrap_err();
+        assert_eq!(violation.axis, Axis::X);
+        assert!(violation.overtravel > 0.0);
+        assert!((violation.point.xy().magnitude() - 190.0).abs() < 0.1);
+
+        // Clockwise, the same arc bulges out beyond the negative X limit
+        toolpath.moves[1] = Move::Arc {
+            to: Vector3::new(0.0, 190.0, 10.0),
+            plane: Default::default(),
+            center: Vector2::new(0.0, 0.0),
+            direction: ArcDirection::Clockwise,
+            feed: 1000.0,
+        };
+        let violation = Fixed(toolpath)
+            .generate_for(&mesh, &bit, &machine)
+            .unwrap_err();
+        assert_eq!(violation.axis, Axis::X);
+        assert!(violation.overtravel < 0.0);
+
+        let mut inside = Toolpath::new();
+        inside.rapid(Vector3::new(0.0, -100.0, 10.0));
+        inside.arc(
+            Vector3::new(0.0, 100.0, 10.0),
+            Vector2::zeros(),
+            ArcDirection::CounterClockwise,
+            1000.0,
+        );
+        assert!(Fixed(inside).generate_for(&mesh, &bit, &machine).is_ok());
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(step_levels(0.0, -3.0, 1.0), [-1.0, -2.0, -3.0]);
+        assert_eq!(step_levels(0.0, -2.5, 1.0), [-1.0, -2.0, -2.5]);
+        assert_eq!(step_levels(0.0, -1.0, 2.0), [-1.0]);
+        assert!(step_levels(-1.0, -1.0, 1.0).is_empty());
+    }
+}
@@ -0,0 +1,318 @@
+use nalgebra::{Rotation2, Vector2, Vector3};
+
+use crate::cnc::{Bit, FeedsSpeeds, ToolBit};
+use crate::offset::{self, Join};
+use crate::primitives::{BoundingBox, Mesh, Path2, Polygon};
+use crate::toolpath::Toolpath;
+
+use super::{cut_passes, hatch, ring_passes, slice_regions, step_levels, Operation};
+
+/// The pattern used for clearing the inside of a pocket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PocketStrategy {
+    /// Rings parallel to the outline, cut from the inside out.
+    ContourParallel,
+    /// Parallel lines at the angle (in degrees, counter clockwise from the X axis) cut in
+    /// alternating directions, followed by a pass along the outline.
+    Zigzag { angle: f32 },
+}
+
+/// A 2.5D pocket operation. The model describes the volume to be removed, e.g. the recess for
+/// an inlay. Slices the model at every step down level and clears the regions, leaving the
+/// islands (holes in the regions) standing.
+#[derive(Debug, Clone)]
+pub struct Pocket {
+    pub strategy: PocketStrategy,
+    /// The horizontal distance between two adjacent passes.
+    pub stepover: f32,
+    /// The maximum depth of a single pass.
+    pub step_down: f32,
+    pub feed_rate: f32,
+    pub plunge_rate: f32,
+    /// The height above the top of the model used for rapid moves.
+    pub clearance: f32,
+    /// Whether to climb mill (assuming a clockwise spindle) or to conventional mill.
+    pub climb: bool,
+}
+
+impl Pocket {
+    pub fn new(strategy: PocketStrategy, feeds: &FeedsSpeeds) -> Self {
+        Self {
+            strategy,
+            stepover: feeds.stepover,
+            step_down: feeds.depth_of_cut,
+            feed_rate: feeds.feed_rate,
+            plunge_rate: feeds.plunge_rate,
+            clearance: 5.0,
+            climb: true,
+        }
+    }
+
+    /// The passes the cutter center follows for clearing the regions, in cutting order. Closed
+    /// passes end at their starting point.
+    pub fn passes(&self, regions: &[Polygon], radius: f32) -> Vec<Vec<Vector2<f32>>> {
+        let area = offset::offset(regions, -radius, Join::default());
+        let stepover = self.stepover.max(1e-2);
+
+        match self.strategy {
+            PocketStrategy::ContourParallel => self.contour_parallel(&area, stepover),
+            PocketStrategy::Zigzag { angle } => self.zigzag(&area, stepover, angle),
+        }
+    }
+
+    fn contour_parallel(&self, area: &[Polygon], stepover: f32) -> Vec<Vec<Vector2<f32>>> {
+        let mut levels = Vec::new();
+        let mut current = area.to_vec();
+        while !current.is_empty() {
+            let distance = -(levels.len() as f32 + 1.0) * stepover;
+            levels.push(current);
+            current = offset::offset(area, distance, Join::default());
+        }
+
+        // Start in the center and work outwards
+        let mut passes = Vec::new();
+        let mut position = None;
+        for polygons in levels.into_iter().rev() {
+            let rings = polygons
+                .into_iter()
+                .flat_map(|Polygon { exterior, holes }| std::iter::once(exterior).chain(holes))
+                .collect();
+            passes.extend(ring_passes(rings, &mut position, self.climb));
+        }
+        passes
+    }
+
+    fn zigzag(&self, area: &[Polygon], stepover: f32, angle: f32) -> Vec<Vec<Vector2<f32>>> {
+        // Rotate the area, so that the lines run along the X axis
+        let rotation = Rotation2::new(angle.to_radians());
+        let inverse = rotation.inverse();
+        let rings: Vec<Path2> = area
+            .iter()
+            .flat_map(Polygon::rings)
+            .map(|ring| Path2::new(ring.points.iter().map(|point| inverse * point).collect()))
+            .collect();
+
+        let mut passes: Vec<Vec<Vector2<f32>>> = hatch(&rings, stepover)
+            .into_iter()
+            .map(|segment| segment.to_vec())
+            .collect();
+        let mut position = passes.last().map(|pass| pass[1]);
+
+        // Clean up the scallops left along the outline
+        passes.extend(ring_passes(rings, &mut position, self.climb));
+
+        for pass in passes.iter_mut() {
+            for point in pass.iter_mut() {
+                *point = rotation * *point;
+            }
+        }
+        passes
+    }
+}
+
+impl Operation for Pocket {
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath {
+        // Allows linking passes along the outline of the area without retracting
+        const LINK_TOLERANCE: f32 = 1e-2;
+
+        let mut toolpath = Toolpath::new();
+        if mesh.triangles.is_empty() {
+            return toolpath;
+        }
+
+        let (min, max) = mesh.bb_min_max();
+        let safe_z = max.z + self.clearance;
+        let radius = bit.radius();
+
+        for z in step_levels(max.z, min.z, self.step_down) {
+            let regions = slice_regions(mesh, z);
+            let passes = self.passes(&regions, radius);
+            let bounds = offset::offset(&regions, -radius + LINK_TOLERANCE, Join::default());
+            cut_passes(
+                &mut toolpath,
+                &passes,
+                &bounds,
+                z,
+                safe_z,
+                self.feed_rate,
+                self.plunge_rate,
+            );
+        }
+
+        if let Some(position) = toolpath.position().copied() {
+            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+        }
+        toolpath.tagged("Pocket")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::{FlatEndMill, Machine};
+    use crate::testing::{cuboid, frame};
+    use crate::toolpath::Move;
+
+    fn pocket(strategy: PocketStrategy) -> Pocket {
+        Pocket::new(
+            strategy,
+            &FeedsSpeeds::new(18000.0, 1000.0, 300.0, 2.0, 2.0),
+        )
+    }
+
+    fn square(half: f32) -> Polygon {
+        let points = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, y)| Vector2::new(x * half, y * half))
+            .collect();
+        Polygon::new(Path2::new(points), Vec::new())
+    }
+
+    fn assert_within(passes: &[Vec<Vector2<f32>>], half: f32) {
+        for point in passes.iter().flatten() {
+            assert!(
+                point.x.abs() <= half + 1e-3 && point.y.abs() <= half + 1e-3,
+                "{:?}",
+                point
+            );
+        }
+    }
+
+    /// The distance of the point from a square island around the origin. Round joins and links
+    /// along the outline may come closer to the island by a few hundredths.
+    fn island_distance(point: &Vector2<f32>, half: f32) -> f32 {
+        point.abs().map(|c| (c - half).max(0.0)).magnitude()
+    }
+
+    #[test]
+    fn contour_parallel() {
+        let passes = pocket(PocketStrategy::ContourParallel).passes(&[square(10.0)], 3.0);
+        // Rings 2 mm apart from the outline at 7 mm inwards
+        assert_eq!(passes.len(), 4);
+        assert_within(&passes, 7.0);
+        for pass in passes.iter() {
+            assert_eq!(pass.first(), pass.last());
+        }
+
+        // From the inside out
+        let sizes: Vec<f32> = passes
+            .iter()
+            .map(|pass| Path2::new(pass.clone()).signed_area().abs())
+            .collect();
+        assert!(
+            sizes.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            sizes
+        );
+        let outer = passes.last().unwrap();
+        assert!(outer.iter().any(|p| (p.x - 7.0).abs() < 1e-3));
+
+        // Climb milling runs the rings counter clockwise, with the material on the right
+        assert!(Path2::new(outer.clone()).signed_area() > 0.0);
+        let mut conventional = pocket(PocketStrategy::ContourParallel);
+        conventional.climb = false;
+        let passes = conventional.passes(&[square(10.0)], 3.0);
+        assert!(Path2::new(passes.last().unwrap().clone()).signed_area() < 0.0);
+    }
+
+    #[test]
+    fn zigzag() {
+        for angle in [0.0f32, 90.0] {
+            let passes = pocket(PocketStrategy::Zigzag { angle }).passes(&[square(10.0)], 3.0);
+            assert_within(&passes, 7.0);
+
+            // Lines along the angle, followed by a pass along the outline
+            let (lines, outline) = passes.split_at(passes.len() - 1);
+            assert!(lines.len() >= 8, "{}", lines.len());
+            let direction = Vector2::new(angle.to_radians().cos(), angle.to_radians().sin());
+            for line in lines.iter() {
+                assert_eq!(line.len(), 2);
+                let along = (line[1] - line[0]).normalize();
+                assert!(along.dot(&direction).abs() > 0.999, "{:?}", line);
+            }
+            // Adjacent lines run in opposite directions
+            assert!((lines[0][1] - lines[0][0]).dot(&(lines[1][1] - lines[1][0])) < 0.0);
+            assert_eq!(outline[0].first(), outline[0].last());
+        }
+    }
+
+    #[test]
+    fn islands() {
+        let region = [Polygon::new(
+            square(20.0).exterior,
+            vec![square(5.0).exterior],
+        )];
+        for strategy in [
+            PocketStrategy::ContourParallel,
+            PocketStrategy::Zigzag { angle: 30.0 },
+        ] {
+            let passes = pocket(strategy).passes(&region, 3.0);
+            assert!(!passes.is_empty());
+            // The cutter stays clear of the island
+            for point in passes.iter().flatten() {
+                assert!(
+                    island_distance(point, 5.0) >= 3.0 - 0.02,
+                    "{:?} {:?}",
+                    strategy,
+                    point
+                );
+            }
+        }
+
+        // Pockets narrower than the cutter stay uncut
+        for strategy in [
+            PocketStrategy::ContourParallel,
+            PocketStrategy::Zigzag { angle: 0.0 },
+        ] {
+            assert!(pocket(strategy).passes(&[square(2.0)], 3.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn generate() {
+        let mesh = cuboid(
+            Vector3::new(-10.0, -10.0, -4.0),
+            Vector3::new(10.0, 10.0, 0.0),
+        );
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let toolpath = pocket(PocketStrategy::ContourParallel).generate(&mesh, &bit);
+
+        assert_eq!(toolpath.operation(0), Some("Pocket"));
+        assert_eq!(toolpath.position().unwrap().z, 5.0);
+        // One plunge per level, the rings are linked without retracting
+        let plunges: Vec<f32> = toolpath
+            .moves
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Move::Plunge { to, .. } => Some(to.z),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(plunges, [-2.0, -4.0]);
+
+        // The cutter stays clear of islands on every level
+        let mesh = frame(40.0, 10.0, -2.0, 0.0);
+        let toolpath = pocket(PocketStrategy::ContourParallel).generate(&mesh, &bit);
+        assert!(!toolpath.is_empty());
+        for to in toolpath.positions().filter(|to| to.z < 0.0) {
+            assert!(island_distance(&to.xy(), 5.0) >= 3.0 - 0.02, "{:?}", to);
+        }
+    }
+
+    #[test]
+    fn fits_default_machine() {
+        // Cuts below the top of the stock at Z0 stay inside the envelope
+        let mesh = cuboid(
+            Vector3::new(-20.0, -20.0, -12.0),
+            Vector3::new(20.0, 20.0, 0.0),
+        );
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let toolpath = pocket(PocketStrategy::ContourParallel)
+            .generate_for(&mesh, &bit, &Machine::default())
+            .unwrap();
+        assert_eq!(
+            toolpath.positions().map(|to| to.z).fold(0.0, f32::min),
+            -12.0
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use nalgebra::Vector3;
+
+use crate::cnc::{Bit, FeedsSpeeds, ToolBit};
+use crate::offset::{self, Join};
+use crate::primitives::{BoundingBox, Mesh, Path2, Polygon};
+use crate::toolpath::Toolpath;
+
+use super::{slice_regions, step_levels, Operation};
+
+/// The side of the outline the cutter travels on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileSide {
+    /// The cutter stays outside of the model, e.g. for cutting a part out of a sheet.
+    Outside,
+    /// The cutter stays inside of the model, e.g. when the model describes a cutout.
+    Inside,
+    /// The cutter center follows the outline.
+    On,
+}
+
+/// A 2.5D profile (contour) operation. Slices the model at every step down level, offsets the
+/// outlines by the cutter radius and follows them, starting at the top.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub side: ProfileSide,
+    /// The maximum depth of a single pass.
+    pub step_down: f32,
+    pub feed_rate: f32,
+    pub plunge_rate: f32,
+    /// The height above the top of the model used for rapid moves.
+    pub clearance: f32,
+    /// The depth to cut below the bottom of the model, e.g. for cutting through the stock.
+    pub extra_depth: f32,
+    /// Whether to climb mill (assuming a clockwise spindle) or to conventional mill.
+    pub climb: bool,
+}
+
+impl Profile {
+    pub fn new(side: ProfileSide, feeds: &FeedsSpeeds) -> Self {
+        Self {
+            side,
+            step_down: feeds.depth_of_cut,
+            feed_rate: feeds.feed_rate,
+            plunge_rate: feeds.plunge_rate,
+            clearance: 5.0,
+            extra_depth: 0.0,
+            climb: true,
+        }
+    }
+
+    /// The closed loops the cutter center follows at the Z level. Loops that vanish when they
+    /// get offset (e.g. holes smaller than the cutter) are skipped. The loops are oriented with
+    /// the model on their left side.
+    pub fn contours(&self, mesh: &Mesh, radius: f32, z: f32) -> Vec<Path2> {
+        let outlines = slice_regions(mesh, z);
+
+        // Positive offsets move away from the model
+        let distance = match self.side {
+            ProfileSide::Outside => radius,
+            ProfileSide::Inside => -radius,
+            ProfileSide::On => 0.0,
+        };
+
+        offset::offset(&outlines, distance, Join::default())
+            .into_iter()
+            .flat_map(|polygon| {
+                let Polygon { exterior, holes } = polygon;
+                std::iter::once(exterior).chain(holes)
+            })
+            .collect()
+    }
+}
+
+impl Operation for Profile {
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath {
+        let mut toolpath = Toolpath::new();
+        if mesh.triangles.is_empty() {
+            return toolpath;
+        }
+
+        let (min, max) = mesh.bb_min_max();
+        let safe_z = max.z + self.clearance;
+        let mut position = Vector3::new(0.0, 0.0, safe_z);
+
+        for z in step_levels(max.z, min.z - self.extra_depth, self.step_down) {
+            let mut contours = self.contours(mesh, bit.radius(), z);
+
+            // Cut holes before outer outlines, so that the part stays in place as long as possible
+            contours.sort_by_key(Path2::is_ccw);
+
+            let mut remaining = contours;
+            while !remaining.is_empty() {
+                // Pick the closest contour of the same kind next
+                let ccw = remaining[0].is_ccw();
+                let (index, start) = remaining
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, contour)| contour.is_ccw() == ccw)
+                    .map(|(i, contour)| (i, contour.closest_point(&position.xy())))
+                    .min_by(|a, b| {
+                        let da = (remaining[a.0].points[a.1] - position.xy()).magnitude_squared();
+                        let db = (remaining[b.0].points[b.1] - position.xy()).magnitude_squared();
+                        da.total_cmp(&db)
+                    })
+                    .unwrap();
+
+                let mut contour = remaining.remove(index).to_path3(z).points;
+                contour.rotate_left(start);
+
+                // The model lies on the left, climb milling requires the material on the right
+                let reverse = match self.side {
+                    ProfileSide::Outside | ProfileSide::On => self.climb,
+                    ProfileSide::Inside => !self.climb,
+                };
+                if reverse {
+                    contour[1..].reverse();
+                }
+
+                let start = contour[0];
+                if let Some(position) = toolpath.position().copied() {
+                    toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+                }
+                toolpath.rapid(Vector3::new(start.x, start.y, safe_z));
+                toolpath.plunge(start, self.plunge_rate);
+                for point in contour.iter().skip(1) {
+                    toolpath.feed(*point, self.feed_rate);
+                }
+                toolpath.feed(start, self.feed_rate);
+
+                position = start;
+            }
+        }
+
+        if !toolpath.is_empty() {
+            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+        }
+        toolpath.tagged("Profile")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::FlatEndMill;
+    use crate::testing::{cuboid, frame};
+    use crate::toolpath::Move;
+
+    fn profile(side: ProfileSide) -> Profile {
+        Profile::new(side, &FeedsSpeeds::new(18000.0, 1000.0, 300.0, 2.0, 2.0))
+    }
+
+    fn assert_square(path: &Path2, half: f32) {
+        let (min, max) = path.bb_min_max();
+        for value in [-min.x, -min.y, max.x, max.y] {
+            assert!((value - half).abs() < 1e-3, "{:?} {:?}", min, max);
+        }
+    }
+
+    /// The rings cut at the Z level, as signed areas.
+    fn rings_at(toolpath: &Toolpath, z: f32) -> Vec<f32> {
+        let mut rings = Vec::new();
+        let mut ring = Vec::new();
+        for instruction in toolpath.moves.iter() {
+            match instruction {
+                Move::Plunge { to, .. } if to.z == z => ring = vec![to.xy()],
+                Move::Feed { to, .. } if to.z == z => ring.push(to.xy()),
+                _ => {
+                    if ring.len() > 1 {
+                        assert_eq!(ring.first(), ring.last(), "open ring");
+                        rings.push(Path2::new(std::mem::take(&mut ring)).signed_area());
+                    }
+                    ring.clear();
+                }
+            }
+        }
+        rings
+    }
+
+    #[test]
+    fn contour_sides() {
+        let mesh = cuboid(
+            Vector3::new(-10.0, -10.0, 0.0),
+            Vector3::new(10.0, 10.0, 6.0),
+        );
+        for (side, half) in [
+            (ProfileSide::Outside, 13.0),
+            (ProfileSide::Inside, 7.0),
+            (ProfileSide::On, 10.0),
+        ] {
+            let contours = profile(side).contours(&mesh, 3.0, 3.0);
+            assert_eq!(contours.len(), 1, "{:?}", side);
+            assert!(contours[0].is_ccw());
+            assert_square(&contours[0], half);
+        }
+    }
+
+    #[test]
+    fn contour_holes() {
+        let mesh = frame(40.0, 20.0, 0.0, 6.0);
+        let contours = profile(ProfileSide::Outside).contours(&mesh, 3.0, 3.0);
+        assert_eq!(contours.len(), 2);
+        let hole = contours.iter().find(|c| !c.is_ccw()).unwrap();
+        assert_square(hole, 7.0);
+
+        // Holes smaller than the cutter are skipped
+        let mesh = frame(40.0, 4.0, 0.0, 6.0);
+        let contours = profile(ProfileSide::Outside).contours(&mesh, 3.0, 3.0);
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].is_ccw());
+    }
+
+    #[test]
+    fn levels_and_direction() {
+        let mesh = cuboid(
+            Vector3::new(-10.0, -10.0, 0.0),
+            Vector3::new(10.0, 10.0, 6.0),
+        );
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let mut operation = profile(ProfileSide::Outside);
+        operation.extra_depth = 0.5;
+        let toolpath = operation.generate(&mesh, &bit);
+
+        assert_eq!(toolpath.operation(0), Some("Profile"));
+        for z in [4.0, 2.0, 0.0, -0.5] {
+            let rings = rings_at(&toolpath, z);
+            assert_eq!(rings.len(), 1, "z {}", z);
+            // Climb milling outside runs clockwise
+            assert!(rings[0] < 0.0);
+        }
+        assert_eq!(toolpath.position().unwrap().z, 11.0);
+
+        operation.climb = false;
+        let toolpath = operation.generate(&mesh, &bit);
+        assert!(rings_at(&toolpath, 0.0)[0] > 0.0);
+
+        // Inside, climb milling runs counter clockwise
+        let toolpath = profile(ProfileSide::Inside).generate(&mesh, &bit);
+        assert!(rings_at(&toolpath, 0.0)[0] > 0.0);
+    }
+
+    #[test]
+    fn holes_first() {
+        let mesh = frame(40.0, 20.0, 0.0, 2.0);
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let toolpath = profile(ProfileSide::Outside).generate(&mesh, &bit);
+        let rings = rings_at(&toolpath, 0.0);
+        assert_eq!(rings.len(), 2);
+        // The hole has the smaller area
+        assert!(rings[0].abs() < rings[1].abs());
+
+        assert!(profile(ProfileSide::Outside)
+            .generate(&Mesh::new(Vec::new()), &bit)
+            .is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+use nalgebra::{Rotation2, Vector2, Vector3};
+
+use crate::cnc::{Bit, FeedsSpeeds};
+use crate::dropcutter::DropCutter;
+use crate::offset::{self, Join};
+use crate::primitives::{BoundingBox, Mesh, Path2, Polygon};
+use crate::toolpath::Toolpath;
+
+use super::{hatch, simplify, Operation};
+
+/// A 3D parallel (raster) finishing operation, e.g. for relief carvings and molds. The cutter
+/// follows parallel lines across the model and gets dropped onto the surface along them. Where
+/// no part of the model lies below the cutter, it stays at the bottom of the model.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    /// The direction of the lines in degrees, counter clockwise from the X axis.
+    pub angle: f32,
+    /// The horizontal distance between two adjacent lines.
+    pub stepover: f32,
+    /// The distance between the points sampled along a line.
+    pub resolution: f32,
+    /// The maximum deviation from the sampled points when merging them into straight moves.
+    pub tolerance: f32,
+    /// The area the cutter center stays inside of. Defaults to the bounding box of the model.
+    pub boundary: Option<Vec<Polygon>>,
+    pub feed_rate: f32,
+    pub plunge_rate: f32,
+    /// The height above the top of the model used for rapid moves.
+    pub clearance: f32,
+}
+
+impl Raster {
+    pub fn new(feeds: &FeedsSpeeds) -> Self {
+        Self {
+            angle: 0.0,
+            stepover: feeds.stepover,
+            resolution: 0.1,
+            tolerance: 0.005,
+            boundary: None,
+            feed_rate: feeds.feed_rate,
+            plunge_rate: feeds.plunge_rate,
+            clearance: 5.0,
+        }
+    }
+
+    /// The lines the cutter center follows in the XY plane, in cutting order.
+    pub fn lines(&self, boundary: &[Polygon]) -> Vec<[Vector2<f32>; 2]> {
+        // Rotate the boundary, so that the lines run along the X axis
+        let rotation = Rotation2::new(self.angle.to_radians());
+        let inverse = rotation.inverse();
+        let rings: Vec<Path2> = boundary
+            .iter()
+            .flat_map(Polygon::rings)
+            .map(|ring| Path2::new(ring.points.iter().map(|point| inverse * point).collect()))
+            .collect();
+
+        hatch(&rings, self.stepover.max(1e-2))
+            .into_iter()
+            .map(|line| line.map(|point| rotation * point))
+            .collect()
+    }
+
+    /// Drops the cutter onto the model along the line. The points are at most `resolution`
+    /// apart, before merging points on straight sections.
+    fn drop_line(
+        &self,
+        drop: &DropCutter,
+        a: &Vector2<f32>,
+        b: &Vector2<f32>,
+        floor: f32,
+    ) -> Vec<Vector3<f32>> {
+        let steps = ((b - a).magnitude() / self.resolution.max(1e-3))
+            .ceil()
+            .max(1.0) as usize;
+        let points: Vec<Vector3<f32>> = (0..=steps)
+            .map(|i| {
+                let point = a.lerp(b, i as f32 / steps as f32);
+                let z = drop.height(&point).map_or(floor, |z| z.max(floor));
+                Vector3::new(point.x, point.y, z)
+            })
+            .collect();
+
+        simplify(&points, self.tolerance)
+    }
+}
+
+impl Operation for Raster {
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath {
+        // Allows linking lines along the boundary without retracting
+        const LINK_TOLERANCE: f32 = 1e-2;
+
+        let mut toolpath = Toolpath::new();
+        if mesh.triangles.is_empty() {
+            return toolpath;
+        }
+
+        let (min, max) = mesh.bb_min_max();
+        let safe_z = max.z + self.clearance;
+        let boundary = self.boundary.clone().unwrap_or_else(|| {
+            vec![Polygon::new(
+                Path2::new(vec![
+                    Vector2::new(min.x, min.y),
+                    Vector2::new(max.x, min.y),
+                    Vector2::new(max.x, max.y),
+                    Vector2::new(min.x, max.y),
+                ]),
+                Vec::new(),
+            )]
+        });
+        let bounds = offset::offset(&boundary, LINK_TOLERANCE, Join::default());
+
+        let drop = DropCutter::new(mesh, bit);
+        let mut previous: Option<Vector2<f32>> = None;
+        for [a, b] in self.lines(&boundary) {
+            let points = self.drop_line(&drop, &a, &b, min.z);
+
+            // Follow the surface to the next line if it stays inside of the boundary
+            let link = previous.filter(|previous| {
+                bounds
+                    .iter()
+                    .any(|bound| bound.contains_segment(previous, &a))
+            });
+            match link {
+                Some(previous) => {
+                    for point in self.drop_line(&drop, &previous, &a, min.z).iter().skip(1) {
+                        toolpath.feed(*point, self.feed_rate);
+                    }
+                }
+                None => {
+                    if let Some(position) = toolpath.position().copied() {
+                        toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+                    }
+                    toolpath.rapid(Vector3::new(a.x, a.y, safe_z));
+                    toolpath.plunge(points[0], self.plunge_rate);
+                }
+            }
+
+            for point in points.iter().skip(1) {
+                toolpath.feed(*point, self.feed_rate);
+            }
+            previous = Some(b);
+        }
+
+        if let Some(position) = toolpath.position().copied() {
+            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+        }
+        toolpath.tagged("Raster")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::BallNose;
+    use crate::testing::cuboid;
+    use crate::toolpath::Move;
+
+    fn raster() -> Raster {
+        Raster::new(&FeedsSpeeds::new(18000.0, 1000.0, 300.0, 1.0, 2.0))
+    }
+
+    fn square(half: f32) -> Vec<Polygon> {
+        let points = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|(x, y)| Vector2::new(x * half, y * half))
+            .collect();
+        vec![Polygon::new(Path2::new(points), Vec::new())]
+    }
+
+    #[test]
+    fn lines() {
+        let mut operation = raster();
+        operation.stepover = 2.0;
+        // The rotated square is wider along the diagonal
+        for (angle, count) in [(0.0f32, 11), (45.0, 16), (90.0, 11)] {
+            operation.angle = angle;
+            let lines = operation.lines(&square(10.0));
+            assert!(
+                lines.len() == count || lines.len() == count + 1,
+                "{}",
+                angle
+            );
+
+            let direction = Vector2::new(angle.to_radians().cos(), angle.to_radians().sin());
+            for [a, b] in lines.iter() {
+                assert!((b - a).normalize().dot(&direction).abs() > 0.999);
+                assert!(a.x.abs() <= 10.0 + 1e-3 && a.y.abs() <= 10.0 + 1e-3);
+            }
+            // Adjacent lines run in opposite directions
+            assert!((lines[0][1] - lines[0][0]).dot(&(lines[1][1] - lines[1][0])) < 0.0);
+        }
+    }
+
+    #[test]
+    fn degenerate_boundaries() {
+        let mesh = cuboid(
+            Vector3::new(-20.0, -20.0, -5.0),
+            Vector3::new(20.0, 20.0, 0.0),
+        );
+        let bit = BallNose::new(4.0, 20.0, 4.0, 2).into();
+        let mut operation = raster();
+        operation.boundary = Some(Vec::new());
+        assert!(operation.generate(&mesh, &bit).is_empty());
+
+        // A boundary without height in Y
+        let flat = Path2::new(vec![Vector2::new(-10.0, 0.0), Vector2::new(10.0, 0.0)]);
+        assert!(operation
+            .lines(&[Polygon::new(flat, Vec::new())])
+            .is_empty());
+    }
+
+    #[test]
+    fn follows_the_surface() {
+        // A raised block in the middle of a base plate
+        let mut mesh = cuboid(
+            Vector3::new(-20.0, -20.0, -5.0),
+            Vector3::new(20.0, 20.0, 0.0),
+        );
+        let block = cuboid(Vector3::new(-5.0, -5.0, 0.0), Vector3::new(5.0, 5.0, 3.0));
+        mesh.triangles.extend(block.triangles);
+
+        let bit = BallNose::new(4.0, 20.0, 4.0, 2).into();
+        let mut operation = raster();
+        operation.boundary = Some(square(15.0));
+        let toolpath = operation.generate(&mesh, &bit);
+
+        assert_eq!(toolpath.operation(0), Some("Raster"));
+        assert_eq!(toolpath.position().unwrap().z, 8.0);
+
+        // A single plunge, the lines are linked along the boundary
+        let plunges = toolpath
+            .moves
+            .iter()
+            .filter(|m| matches!(m, Move::Plunge { .. }))
+            .count();
+        assert_eq!(plunges, 1);
+
+        for to in toolpath.positions().filter(|to| to.z < 8.0) {
+            assert!(to.x.abs() <= 15.0 + 1e-3 && to.y.abs() <= 15.0 + 1e-3);
+            let expected = if to.x.abs() <= 5.0 && to.y.abs() <= 5.0 {
+                3.0
+            } else if to.x.abs() > 7.001 || to.y.abs() > 7.001 {
+                0.0
+            } else {
+                // Rolling over the edge of the block
+                assert!(to.z >= 0.0 && to.z <= 3.0, "{:?}", to);
+                continue;
+            };
+            assert!((to.z - expected).abs() < 1e-3, "{:?}", to);
+        }
+        let top = toolpath.positions().filter(|to| to.z == 3.0).count();
+        assert!(top > 0);
+    }
+}
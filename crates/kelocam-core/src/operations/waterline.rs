@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::cnc::{Bit, FeedsSpeeds, ToolBit};
+use crate::dropcutter::DropCutter;
+use crate::primitives::{BoundingBox, Mesh, Path2, Triangle};
+use crate::pushcutter::{elements, push_triangle, Element};
+use crate::toolpath::Toolpath;
+
+use super::{cut_passes, simplify, step_levels, Operation};
+
+/// A 3D waterline (constant Z) finishing operation, for steep walls which come out badly with
+/// raster finishing. At every step down level, the cutter follows the contour where it touches
+/// the model without gouging it.
+///
+/// The contours are found by pushing the cutter along a grid of lines (fibers) in X and Y
+/// direction and recording where it collides with the model. The grid cells are then contoured
+/// with marching squares, using the exact collision points on the fibers.
+#[derive(Debug, Clone)]
+pub struct Waterline {
+    /// The maximum depth of a single pass.
+    pub step_down: f32,
+    /// The distance between two adjacent fibers.
+    pub resolution: f32,
+    /// The maximum deviation from the contour when merging points into straight moves.
+    pub tolerance: f32,
+    /// Only cut where the surface is at least this steep, in degrees from the horizontal. Zero
+    /// cuts the contours completely.
+    pub min_slope: f32,
+    pub feed_rate: f32,
+    pub plunge_rate: f32,
+    /// The height above the top of the model used for rapid moves.
+    pub clearance: f32,
+    /// Whether to climb mill (assuming a clockwise spindle) or to conventional mill.
+    pub climb: bool,
+}
+
+impl Waterline {
+    pub fn new(feeds: &FeedsSpeeds) -> Self {
+        Self {
+            step_down: feeds.depth_of_cut,
+            resolution: 0.2,
+            tolerance: 0.005,
+            min_slope: 0.0,
+            feed_rate: feeds.feed_rate,
+            plunge_rate: feeds.plunge_rate,
+            clearance: 5.0,
+            climb: true,
+        }
+    }
+
+    /// The closed loops the cutter center follows at the Z level, oriented with the model on
+    /// their left side.
+    pub fn contours(&self, mesh: &Mesh, bit: &dyn ToolBit, z: f32) -> Vec<Path2> {
+        let drop = DropCutter::new(mesh, bit);
+        self.contours_with(mesh, bit, &drop, z)
+    }
+
+    fn contours_with(
+        &self,
+        mesh: &Mesh,
+        bit: &dyn ToolBit,
+        drop: &DropCutter,
+        z: f32,
+    ) -> Vec<Path2> {
+        if mesh.triangles.is_empty() {
+            return Vec::new();
+        }
+
+        let resolution = self.resolution.max(1e-3);
+        let (min, max) = mesh.bb_min_max();
+        let margin = Vector2::from_element(bit.radius() + resolution * 2.0);
+        let origin = min.xy() - margin;
+        let size = max.xy() - min.xy() + margin * 2.0;
+        let width = (size.x / resolution).ceil() as usize + 1;
+        let height = (size.y / resolution).ceil() as usize + 1;
+
+        // Only triangles reaching above the cutter tip can collide with it
+        let triangles = mesh
+            .triangles
+            .iter()
+            .filter(|triangle| triangle.bb_max().z > z);
+        let elements = elements(bit, triangles, z);
+
+        let rows = Fibers::push(bit, &elements, z, origin, resolution, height, width, false);
+        let columns = Fibers::push(bit, &elements, z, origin, resolution, width, height, true);
+
+        let inside: Vec<bool> = (0..width * height)
+            .map(|index| rows.contains(index / width, (index % width) as f32 * resolution))
+            .collect();
+        let point = |i: usize, j: usize| origin + Vector2::new(i as f32, j as f32) * resolution;
+
+        // The crossing of the contour with a grid edge. Horizontal edges lie on the rows,
+        // vertical edges on the columns.
+        let horizontal = |i: usize, j: usize| {
+            let x = rows.crossing(j, i as f32 * resolution, (i + 1) as f32 * resolution);
+            (
+                j * width + i,
+                point(i, j) + Vector2::new(x - i as f32 * resolution, 0.0),
+            )
+        };
+        let vertical = |i: usize, j: usize| {
+            let y = columns.crossing(i, j as f32 * resolution, (j + 1) as f32 * resolution);
+            (
+                width * height + j * width + i,
+                point(i, j) + Vector2::new(0.0, y - j as f32 * resolution),
+            )
+        };
+
+        // Marching squares. The contour segments run from edges where the corners go from
+        // inside to outside (counter clockwise around the cell) to edges where they go from
+        // outside to inside, which keeps the model on their left side.
+        let mut points: HashMap<usize, Vector2<f32>> = HashMap::new();
+        let mut next: HashMap<usize, usize> = HashMap::new();
+        for j in 0..height - 1 {
+            for i in 0..width - 1 {
+                let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+                let states = corners.map(|(i, j)| inside[j * width + i]);
+                if states.iter().all(|state| *state) || states.iter().all(|state| !*state) {
+                    continue;
+                }
+
+                let edges = [
+                    horizontal(i, j),
+                    vertical(i + 1, j),
+                    horizontal(i, j + 1),
+                    vertical(i, j),
+                ];
+                let crossings: Vec<(usize, bool)> = (0..4)
+                    .filter(|k| states[*k] != states[(k + 1) % 4])
+                    .map(|k| (k, states[k]))
+                    .collect();
+
+                // Resolve ambiguous cells by checking whether the center is inside
+                let center = point(i, j) + Vector2::from_element(resolution * 0.5);
+                let connected =
+                    crossings.len() == 4 && drop.height(&center).map_or(false, |height| height > z);
+
+                for (n, (k, leaving)) in crossings.iter().enumerate() {
+                    if !leaving {
+                        continue;
+                    }
+                    let len = crossings.len();
+                    let (other, _) = if connected {
+                        crossings[(n + 1) % len]
+                    } else {
+                        crossings[(n + len - 1) % len]
+                    };
+                    let (from, from_point) = edges[*k];
+                    let (to, to_point) = edges[other];
+                    points.insert(from, from_point);
+                    points.insert(to, to_point);
+                    next.insert(from, to);
+                }
+            }
+        }
+
+        // Link the segments into loops
+        let mut loops = Vec::new();
+        while let Some(&start) = next.keys().next() {
+            let mut ring = Vec::new();
+            let mut current = start;
+            while let Some(following) = next.remove(&current) {
+                ring.push(Vector3::new(points[&current].x, points[&current].y, z));
+                current = following;
+            }
+            if current != start || ring.len() < 3 {
+                continue;
+            }
+
+            ring.push(ring[0]);
+            let mut ring = simplify(&ring, self.tolerance);
+            ring.pop();
+            if ring.len() >= 3 {
+                loops.push(Path2::new(ring.iter().map(|point| point.xy()).collect()));
+            }
+        }
+        loops
+    }
+
+    /// The passes the cutter center follows at the Z level, in cutting order. Closed passes end
+    /// at their starting point.
+    fn passes(
+        &self,
+        mesh: &Mesh,
+        bit: &dyn ToolBit,
+        drop: &DropCutter,
+        z: f32,
+        position: &mut Option<Vector2<f32>>,
+    ) -> Vec<Vec<Vector2<f32>>> {
+        // Whether the cutter touches a steep triangle at the point of the contour. The touching
+        // triangles are found by pushing the cutter across the contour at the level. Dropping
+        // the cutter would find the triangle it rests on instead, e.g. the top next to a wall.
+        let min_normal = self.min_slope.clamp(0.0, 90.0).to_radians().cos();
+        let resolution = self.resolution.max(1e-3);
+        let tolerance = 1e-3 / (2.0 * resolution);
+        let steep = |points: &[Vector2<f32>], i: usize| {
+            let len = points.len();
+            let tangent = points[(i + 1) % len] - points[(i + len - 1) % len];
+            let Some(tangent) = tangent.try_normalize(1e-9) else {
+                return true;
+            };
+            let across = Vector2::new(-tangent.y, tangent.x) * resolution;
+            let (a, b) = (points[i] - across, points[i] + across);
+
+            // The cutter touches the triangles whose collisions start or end at the point, in
+            // the middle of the fiber
+            let touching: Vec<&Triangle> = drop
+                .nearby(&points[i])
+                .filter(|triangle| triangle.bb_max().z > z)
+                .filter(|triangle| {
+                    push_triangle(bit, triangle, z, &a, &b).map_or(false, |(start, end)| {
+                        start <= 0.5 + tolerance && end >= 0.5 - tolerance
+                    })
+                })
+                .collect();
+            touching.is_empty()
+                || touching.iter().any(|triangle| {
+                    let normal = (triangle.b - triangle.a).cross(&(triangle.c - triangle.a));
+                    normal.z.abs() <= min_normal * normal.magnitude() + 1e-6
+                })
+        };
+
+        let mut closed = Vec::new();
+        let mut open = Vec::new();
+        for contour in self.contours_with(mesh, bit, drop, z) {
+            let mut points = contour.points;
+            // The model lies on the left, climb milling requires the material on the right
+            if self.climb {
+                points.reverse();
+            }
+
+            let flags: Vec<bool> = (0..points.len())
+                .map(|i| self.min_slope <= 0.0 || steep(&points, i))
+                .collect();
+            match flags.iter().position(|flag| !flag) {
+                None => closed.push(points),
+                Some(shallow) => {
+                    // Split the contour into the steep sections
+                    points.rotate_left(shallow);
+                    let mut flags = flags;
+                    flags.rotate_left(shallow);
+
+                    let mut section: Vec<Vector2<f32>> = Vec::new();
+                    for (point, flag) in points.iter().zip(flags) {
+                        if flag {
+                            section.push(*point);
+                        } else if section.len() >= 2 {
+                            open.push(std::mem::take(&mut section));
+                        } else {
+                            section.clear();
+                        }
+                    }
+                    if section.len() >= 2 {
+                        open.push(section);
+                    }
+                }
+            }
+        }
+
+        // Cut the closest pass next, closed passes start at their closest point
+        let mut passes = Vec::new();
+        while !closed.is_empty() || !open.is_empty() {
+            let current = position
+                .or_else(|| closed.first().or(open.first()).map(|pass| pass[0]))
+                .unwrap();
+            let distance = |point: &Vector2<f32>| (point - current).magnitude_squared();
+
+            let nearest_closed = closed
+                .iter()
+                .enumerate()
+                .flat_map(|(i, pass)| pass.iter().enumerate().map(move |(j, p)| (i, j, p)))
+                .min_by(|a, b| distance(a.2).total_cmp(&distance(b.2)))
+                .map(|(i, j, p)| (i, j, distance(p)));
+            let nearest_open = open
+                .iter()
+                .enumerate()
+                .map(|(i, pass)| (i, distance(&pass[0])))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            let pass = match (nearest_closed, nearest_open) {
+                (Some((i, j, d)), open_candidate)
+                    if open_candidate.map_or(true, |(_, other)| d <= other) =>
+                {
+                    let mut pass = closed.swap_remove(i);
+                    pass.rotate_left(j);
+                    pass.push(pass[0]);
+                    pass
+                }
+                (_, Some((i, _))) => open.swap_remove(i),
+                _ => break,
+            };
+
+            *position = pass.last().copied();
+            passes.push(pass);
+        }
+        passes
+    }
+}
+
+impl Operation for Waterline {
+    fn generate(&self, mesh: &Mesh, bit: &Bit) -> Toolpath {
+        let mut toolpath = Toolpath::new();
+        if mesh.triangles.is_empty() {
+            return toolpath;
+        }
+
+        let (min, max) = mesh.bb_min_max();
+        let safe_z = max.z + self.clearance;
+        let drop = DropCutter::new(mesh, bit);
+
+        let mut position = None;
+        for z in step_levels(max.z, min.z, self.step_down) {
+            let passes = self.passes(mesh, bit, &drop, z, &mut position);
+            cut_passes(
+                &mut toolpath,
+                &passes,
+                &[],
+                z,
+                safe_z,
+                self.feed_rate,
+                self.plunge_rate,
+            );
+        }
+
+        if let Some(position) = toolpath.position().copied() {
+            toolpath.rapid(Vector3::new(position.x, position.y, safe_z));
+        }
+        toolpath.tagged("Waterline")
+    }
+}
+
+/// The collisions of the cutter along parallel fibers, as sorted and merged intervals. The
+/// positions are measured from the start of the fibers.
+struct Fibers {
+    intervals: Vec<Vec<(f32, f32)>>,
+}
+
+impl Fibers {
+    /// Pushes the cutter along `count` fibers of the length, which are `resolution` apart.
+    /// Fibers run along X, or along Y if `transposed`.
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        bit: &dyn ToolBit,
+        elements: &[Element],
+        z: f32,
+        origin: Vector2<f32>,
+        resolution: f32,
+        count: usize,
+        length: usize,
+        transposed: bool,
+    ) -> Self {
+        // Swap the axes for fibers along Y
+        let swap = |v: Vector2<f32>| if transposed { v.yx() } else { v };
+        let origin = swap(origin);
+        let length = (length - 1) as f32 * resolution;
+
+        let mut intervals = vec![Vec::new(); count];
+        for element in elements {
+            let (min, max) = element.bb_min_max(bit);
+            let (min, max) = (swap(min), swap(max));
+            let first = ((min.y - origin.y) / resolution).ceil().max(0.0);
+            let last = ((max.y - origin.y) / resolution).floor();
+            if last < first {
+                continue;
+            }
+
+            let fibers = intervals
+                .iter_mut()
+                .enumerate()
+                .take(last as usize + 1)
+                .skip(first as usize);
+            for (index, fiber) in fibers {
+                let offset = origin.y + index as f32 * resolution;
+                let a = swap(Vector2::new(origin.x, offset));
+                let b = swap(Vector2::new(origin.x + length, offset));
+                if let Some((start, end)) = element.push(bit, z, &a, &b) {
+                    fiber.push((start * length, end * length));
+                }
+            }
+        }
+
+        for fiber in intervals.iter_mut() {
+            fiber.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut merged: Vec<(f32, f32)> = Vec::new();
+            for (start, end) in fiber.drain(..) {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *fiber = merged;
+        }
+
+        Self { intervals }
+    }
+
+    /// Whether the cutter collides at the position along the fiber.
+    fn contains(&self, fiber: usize, position: f32) -> bool {
+        self.intervals[fiber]
+            .iter()
+            .any(|(start, end)| *start < position && position < *end)
+    }
+
+    /// The boundary of the collisions between the positions. Takes the closest boundary if
+    /// rounding errors moved it slightly outside, or the middle if there is none.
+    fn crossing(&self, fiber: usize, from: f32, to: f32) -> f32 {
+        let distance = |position: &f32| (from - position).max(position - to).max(0.0);
+        self.intervals[fiber]
+            .iter()
+            .flat_map(|(start, end)| [*start, *end])
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .map_or((from + to) * 0.5, |position| position.clamp(from, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::FlatEndMill;
+    use crate::testing::cuboid;
+    use nalgebra::UnitVector3;
+
+    fn waterline() -> Waterline {
+        let mut operation = Waterline::new(&FeedsSpeeds::new(18000.0, 1000.0, 300.0, 2.0, 2.0));
+        operation.resolution = 0.25;
+        operation.tolerance = 0.01;
+        operation
+    }
+
+    fn block() -> Mesh {
+        cuboid(
+            Vector3::new(-10.0, -10.0, 0.0),
+            Vector3::new(10.0, 10.0, 6.0),
+        )
+    }
+
+    /// A square pyramid with the slope in degrees, on a base of 20 by 20.
+    fn pyramid(slope: f32) -> Mesh {
+        let apex = Vector3::new(0.0, 0.0, 10.0 * slope.to_radians().tan());
+        let base = [(-10.0, -10.0), (10.0, -10.0), (10.0, 10.0), (-10.0, 10.0)]
+            .map(|(x, y)| Vector3::new(x, y, 0.0));
+        let triangle = |a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>| {
+            Triangle::new(a, b, c, UnitVector3::new_normalize((b - a).cross(&(c - a))))
+        };
+        let mut triangles: Vec<Triangle> = (0..4)
+            .map(|i| triangle(base[i], base[(i + 1) % 4], apex))
+            .collect();
+        triangles.push(triangle(base[0], base[2], base[1]));
+        triangles.push(triangle(base[0], base[3], base[2]));
+        Mesh::new(triangles)
+    }
+
+    fn passes(operation: &Waterline, mesh: &Mesh, bit: &Bit, z: f32) -> Vec<Vec<Vector2<f32>>> {
+        let drop = DropCutter::new(mesh, bit);
+        operation.passes(mesh, bit, &drop, z, &mut None)
+    }
+
+    #[test]
+    fn contours() {
+        let bit: Bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let contours = waterline().contours(&block(), &bit, 3.0);
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].is_ccw());
+
+        // The corners are rounded by the cutter
+        let (min, max) = contours[0].bb_min_max();
+        for value in [-min.x, -min.y, max.x, max.y] {
+            assert!((value - 13.0).abs() < 0.05, "{:?} {:?}", min, max);
+        }
+        assert!(contours[0]
+            .points
+            .iter()
+            .all(|point| point.abs().max() >= 10.0 - 1e-3));
+        assert!(waterline().contours(&block(), &bit, 7.0).is_empty());
+    }
+
+    #[test]
+    fn steep_walls() {
+        // Next to a vertical wall the cutter rests on the top, which must not count as shallow
+        let bit: Bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let mut operation = waterline();
+        operation.min_slope = 80.0;
+        let passes = passes(&operation, &block(), &bit, 3.0);
+        assert_eq!(passes.len(), 1);
+        assert_eq!(passes[0].first(), passes[0].last());
+        assert!(passes[0].len() > 4);
+    }
+
+    #[test]
+    fn shallow_slopes() {
+        let bit: Bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let mut operation = waterline();
+        let mesh = pyramid(20.0);
+        assert_eq!(passes(&operation, &mesh, &bit, 1.0).len(), 1);
+
+        operation.min_slope = 45.0;
+        assert!(passes(&operation, &mesh, &bit, 1.0).is_empty());
+        let mesh = pyramid(60.0);
+        assert_eq!(passes(&operation, &mesh, &bit, 5.0).len(), 1);
+    }
+
+    #[test]
+    fn levels_and_direction() {
+        let bit: Bit = FlatEndMill::new(6.0, 20.0, 6.0, 2).into();
+        let toolpath = waterline().generate(&block(), &bit);
+        assert_eq!(toolpath.operation(0), Some("Waterline"));
+        assert_eq!(toolpath.position().unwrap().z, 11.0);
+
+        for z in [4.0, 2.0, 0.0] {
+            let ring: Vec<Vector2<f32>> = toolpath
+                .moves
+                .iter()
+                .filter_map(|instruction| instruction.to())
+                .filter(|to| to.z == z)
+                .map(|to| to.xy())
+                .collect();
+            assert!(ring.len() > 4, "z {}", z);
+            // Climb milling outside runs clockwise
+            assert!(Path2::new(ring).signed_area() < 0.0, "z {}", z);
+        }
+
+        assert!(waterline()
+            .generate(&Mesh::new(Vec::new()), &bit)
+            .is_empty());
+    }
+}
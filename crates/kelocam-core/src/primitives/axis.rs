@@ -1,6 +1,6 @@
 use nalgebra::{UnitVector3, Vector3};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Axis {
     X,
     Y,
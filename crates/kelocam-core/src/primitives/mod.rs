@@ -3,7 +3,9 @@ pub mod geometry;
 pub mod line;
 pub mod mesh;
 pub mod path;
+pub mod path2;
 pub mod plane;
+pub mod polygon;
 pub mod ray;
 pub mod sphere;
 pub mod square;
@@ -15,7 +17,9 @@ pub use geometry::Geometry;
 pub use line::Line;
 pub use mesh::Mesh;
 pub use path::Path3;
+pub use path2::Path2;
 pub use plane::Plane;
+pub use polygon::Polygon;
 pub use ray::Ray;
 pub use sphere::Sphere;
 pub use square::Square;
@@ -0,0 +1,110 @@
+use nalgebra::{Vector2, Vector3};
+
+use super::Path3;
+
+/// A closed 2D path (a ring) in the XY plane.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path2 {
+    pub points: Vec<Vector2<f32>>,
+}
+
+impl Path2 {
+    pub fn new(points: Vec<Vector2<f32>>) -> Self {
+        Self { points }
+    }
+
+    /// Lift this path into 3D space at the specified height.
+    pub fn to_path3(&self, z: f32) -> Path3 {
+        Path3::new(
+            self.points
+                .iter()
+                .map(|point| Vector3::new(point.x, point.y, z))
+                .collect(),
+        )
+    }
+
+    /// The signed area enclosed by this path. Positive for counter clockwise paths.
+    pub fn signed_area(&self) -> f32 {
+        let len = self.points.len();
+        let mut area = 0.0;
+        for i in 0..len {
+            let a = self.points[i];
+            let b = self.points[(i + 1) % len];
+            area += a.x * b.y - b.x * a.y;
+        }
+        area * 0.5
+    }
+
+    /// Returns whether this path runs counter clockwise.
+    pub fn is_ccw(&self) -> bool {
+        self.signed_area() > 0.0
+    }
+
+    pub fn reverse(&mut self) {
+        self.points.reverse();
+    }
+
+    /// The total length of this path, including the closing segment.
+    pub fn length(&self) -> f32 {
+        let len = self.points.len();
+        (0..len)
+            .map(|i| (self.points[(i + 1) % len] - self.points[i]).magnitude())
+            .sum()
+    }
+
+    /// The winding number of this path around the point. Zero if the point lies outside.
+    pub fn winding_number(&self, point: &Vector2<f32>) -> i32 {
+        let len = self.points.len();
+        let mut winding = 0;
+        for i in 0..len {
+            let a = self.points[i];
+            let b = self.points[(i + 1) % len];
+            let side = (b - a).perp(&(point - a));
+            if a.y <= point.y {
+                if b.y > point.y && side > 0.0 {
+                    winding += 1;
+                }
+            } else if b.y <= point.y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+        winding
+    }
+
+    /// Returns whether the point lies inside of this path.
+    pub fn contains(&self, point: &Vector2<f32>) -> bool {
+        self.winding_number(point) != 0
+    }
+
+    /// Compute the minimum and maximum coordinate of the bounding box.
+    pub fn bb_min_max(&self) -> (Vector2<f32>, Vector2<f32>) {
+        let mut min = Vector2::from_element(f32::INFINITY);
+        let mut max = Vector2::from_element(f32::NEG_INFINITY);
+        for point in self.points.iter() {
+            min = min.inf(point);
+            max = max.sup(point);
+        }
+        (min, max)
+    }
+
+    /// The index of the point closest to the position.
+    pub fn closest_point(&self, position: &Vector2<f32>) -> usize {
+        self.points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let da = (*a - position).magnitude_squared();
+                let db = (*b - position).magnitude_squared();
+                da.total_cmp(&db)
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+}
+
+impl From<&Path3> for Path2 {
+    /// Projects the path onto the XY plane.
+    fn from(path: &Path3) -> Self {
+        Self::new(path.points.iter().map(|point| point.xy()).collect())
+    }
+}
@@ -0,0 +1,121 @@
+use nalgebra::Vector2;
+
+use super::Path2;
+use crate::offset::{self, Join};
+
+/// A polygon with holes (islands) in the XY plane. The exterior runs counter clockwise, the
+/// holes run clockwise, so that the polygon always lies on the left side of its rings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub exterior: Path2,
+    pub holes: Vec<Path2>,
+}
+
+impl Polygon {
+    /// Creates a new polygon. Fixes the orientation of the rings if necessary.
+    pub fn new(mut exterior: Path2, mut holes: Vec<Path2>) -> Self {
+        if !exterior.is_ccw() {
+            exterior.reverse();
+        }
+        for hole in holes.iter_mut() {
+            if hole.is_ccw() {
+                hole.reverse();
+            }
+        }
+        Self { exterior, holes }
+    }
+
+    /// Groups rings into polygons based on their nesting, regardless of their orientation.
+    /// Rings nested at an even depth become exteriors, rings nested at an odd depth become holes
+    /// of the ring directly containing them.
+    pub fn from_paths(paths: Vec<Path2>) -> Vec<Self> {
+        let paths: Vec<Path2> = paths
+            .into_iter()
+            .filter(|path| path.points.len() >= 3 && path.signed_area().abs() > f32::EPSILON)
+            .collect();
+
+        // The depth and direct parent of every ring
+        let mut parents = vec![None; paths.len()];
+        let mut depths = vec![0; paths.len()];
+        for (i, path) in paths.iter().enumerate() {
+            let area = path.signed_area().abs();
+            let mut parent: Option<usize> = None;
+            for (j, other) in paths.iter().enumerate() {
+                if i == j || other.signed_area().abs() <= area || !other.contains(&path.points[0]) {
+                    continue;
+                }
+                depths[i] += 1;
+                // The direct parent is the smallest ring containing this one
+                if parent.map_or(true, |p| {
+                    other.signed_area().abs() < paths[p].signed_area().abs()
+                }) {
+                    parent = Some(j);
+                }
+            }
+            parents[i] = parent;
+        }
+
+        let mut polygons: Vec<Option<Self>> = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (depths[i] % 2 == 0).then(|| Self::new(path.clone(), Vec::new())))
+            .collect();
+
+        for (i, path) in paths.into_iter().enumerate() {
+            if depths[i] % 2 == 1 {
+                if let Some(Some(polygon)) = parents[i].map(|p| polygons[p].as_mut()) {
+                    let mut hole = path;
+                    if hole.is_ccw() {
+                        hole.reverse();
+                    }
+                    polygon.holes.push(hole);
+                }
+            }
+        }
+
+        polygons.into_iter().flatten().collect()
+    }
+
+    /// The area of this polygon, i.e. the area of the exterior minus the area of the holes.
+    pub fn area(&self) -> f32 {
+        self.rings().map(Path2::signed_area).sum()
+    }
+
+    /// Iterate over the exterior and the holes of this polygon.
+    pub fn rings(&self) -> impl Iterator<Item = &Path2> {
+        std::iter::once(&self.exterior).chain(self.holes.iter())
+    }
+
+    /// Returns whether the point lies inside of this polygon (and not inside of a hole).
+    pub fn contains(&self, point: &Vector2<f32>) -> bool {
+        self.rings()
+            .map(|ring| ring.winding_number(point))
+            .sum::<i32>()
+            > 0
+    }
+
+    /// Returns whether the straight line between the points lies inside of this polygon. The
+    /// line may touch the rings, but must not cross them.
+    pub fn contains_segment(&self, a: &Vector2<f32>, b: &Vector2<f32>) -> bool {
+        if !self.contains(&((a + b) * 0.5)) {
+            return false;
+        }
+
+        let side = |p: &Vector2<f32>, q: &Vector2<f32>, r: &Vector2<f32>| (q - p).perp(&(r - p));
+        !self.rings().any(|ring| {
+            let len = ring.points.len();
+            (0..len).any(|i| {
+                let p = &ring.points[i];
+                let q = &ring.points[(i + 1) % len];
+                side(p, q, a) * side(p, q, b) < 0.0 && side(a, b, p) * side(a, b, q) < 0.0
+            })
+        })
+    }
+
+    /// Offsets this polygon. Positive distances grow, negative distances shrink the polygon.
+    /// The result may consist of multiple polygons (when parts get separated) or no polygon at
+    /// all (when the polygon vanishes).
+    pub fn offset(&self, distance: f32, join: Join) -> Vec<Self> {
+        offset::offset(std::slice::from_ref(self), distance, join)
+    }
+}
@@ -0,0 +1,249 @@
+//! Push-cutter: where a cutter at a fixed height collides with a mesh while it gets pushed along
+//! a line in the XY plane (a fiber).
+//!
+//! Only the part of a triangle above the cutter tip can collide, so the triangle gets clipped at
+//! the tip height first. The cutter collides with an edge where the edge passes through the
+//! cutter, i.e. where its horizontal distance to the axis is less than the cutter radius at the
+//! height of the edge. With the facet it collides where the drop-cutter contact lies above the
+//! tip. The collisions with a triangle form a convex region, so they cover a single interval of
+//! the fiber.
+
+use std::collections::HashSet;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::cnc::ToolBit;
+use crate::dropcutter::{contains, facet_contact, maximize, FacetContact};
+use crate::primitives::{Axis, Line, Plane, Triangle};
+
+/// The height the mesh has to reach above the cutter tip for a collision. Merely touching the
+/// mesh is not a collision.
+const EPSILON: f32 = 1e-4;
+
+/// The interval of the fiber from a to b where the cutter with its tip at the height collides
+/// with the triangle. The interval is given as parameters along the fiber, with 0 at a and 1 at
+/// b, and may extend beyond the fiber.
+pub fn push_triangle(
+    bit: &dyn ToolBit,
+    triangle: &Triangle,
+    z: f32,
+    a: &Vector2<f32>,
+    b: &Vector2<f32>,
+) -> Option<(f32, f32)> {
+    elements(bit, std::iter::once(triangle), z)
+        .iter()
+        .filter_map(|element| element.push(bit, z, a, b))
+        .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+}
+
+/// A part of the mesh the cutter can collide with at a fixed height.
+pub(crate) enum Element {
+    /// An edge, clipped to the part above the cutter tip.
+    Edge([Vector3<f32>; 2]),
+    /// The inside of a triangle and the contact of the cutter with its plane.
+    Facet([Vector3<f32>; 3], FacetContact),
+}
+
+/// The elements of the triangles the cutter with its tip at the height can collide with. Edges
+/// shared by multiple triangles are only included once.
+pub(crate) fn elements<'a, I>(bit: &dyn ToolBit, triangles: I, z: f32) -> Vec<Element>
+where
+    I: IntoIterator<Item = &'a Triangle>,
+{
+    let plane = Plane::new(Vector3::new(0.0, 0.0, z + EPSILON), Axis::Z_VEC);
+    let key = |p: &Vector3<f32>| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+
+    let mut edges = HashSet::new();
+    let mut elements = Vec::new();
+    for triangle in triangles {
+        let vertices = [triangle.a, triangle.b, triangle.c];
+
+        // Clip the triangle to the part above the cutter tip
+        let mut clipped = Vec::new();
+        for i in 0..3 {
+            let p = vertices[i];
+            let q = vertices[(i + 1) % 3];
+            if p.z >= plane.origin.z {
+                clipped.push(p);
+            }
+            if (p.z >= plane.origin.z) != (q.z >= plane.origin.z) {
+                if let Some(point) = plane.intersect(&Line::new(p, q)) {
+                    clipped.push(point);
+                }
+            }
+        }
+        if clipped.is_empty() {
+            continue;
+        }
+
+        for i in 0..clipped.len() {
+            let (p, q) = (clipped[i], clipped[(i + 1) % clipped.len()]);
+            let (kp, kq) = (key(&p), key(&q));
+            if edges.insert(if kp < kq { (kp, kq) } else { (kq, kp) }) {
+                elements.push(Element::Edge([p, q]));
+            }
+        }
+
+        if let Some(facet) = facet_contact(bit, triangle) {
+            // The contact can not lie higher than the highest vertex
+            let top = vertices.iter().map(|v| v.z).fold(f32::MIN, f32::max);
+            if top - facet.height > plane.origin.z {
+                elements.push(Element::Facet(vertices, facet));
+            }
+        }
+    }
+    elements
+}
+
+impl Element {
+    /// The bounding box of the positions of the cutter axis which may collide with this element.
+    pub fn bb_min_max(&self, bit: &dyn ToolBit) -> (Vector2<f32>, Vector2<f32>) {
+        match self {
+            Self::Edge([p, q]) => {
+                let radius = Vector2::from_element(bit.radius());
+                (p.xy().inf(&q.xy()) - radius, p.xy().sup(&q.xy()) + radius)
+            }
+            // The contact point has to lie inside of the triangle
+            Self::Facet([a, b, c], facet) => (
+                a.xy().inf(&b.xy()).inf(&c.xy()) - facet.offset,
+                a.xy().sup(&b.xy()).sup(&c.xy()) - facet.offset,
+            ),
+        }
+    }
+
+    /// The interval of the fiber from a to b where the cutter with its tip at the height collides
+    /// with this element, see `push_triangle`.
+    pub fn push(
+        &self,
+        bit: &dyn ToolBit,
+        z: f32,
+        a: &Vector2<f32>,
+        b: &Vector2<f32>,
+    ) -> Option<(f32, f32)> {
+        let length = (b - a).magnitude();
+        if length < 1e-9 {
+            return None;
+        }
+        let along = (b - a) / length;
+        let across = Vector2::new(-along.y, along.x);
+
+        match self {
+            Self::Edge([p, q]) => {
+                // The cutter covers a disk around its axis, whose radius depends on the height of
+                // the edge above the tip
+                let point = |s: f32| p.lerp(q, s);
+                let radius = |s: f32| bit.radius_at(point(s).z - z);
+                let offset = |s: f32| across.dot(&(point(s).xy() - a));
+                // How far the disk reaches over the fiber, this is concave along the edge
+                let reach = |s: f32| radius(s) - offset(s).abs();
+
+                let (start, end) = match (reach(0.0) > 0.0, reach(1.0) > 0.0) {
+                    (true, true) => (0.0, 1.0),
+                    (inside_start, inside_end) => {
+                        let peak = maximize(0.0, 1.0, reach);
+                        if reach(peak) <= 0.0 {
+                            return None;
+                        }
+                        let start = if inside_start {
+                            0.0
+                        } else {
+                            bisect(0.0, peak, reach)
+                        };
+                        let end = if inside_end {
+                            1.0
+                        } else {
+                            bisect(1.0, peak, reach)
+                        };
+                        (start, end)
+                    }
+                };
+
+                let chord = |s: f32| (radius(s).powi(2) - offset(s).powi(2)).max(0.0).sqrt();
+                let position = |s: f32| along.dot(&(point(s).xy() - a));
+                let front = |s: f32| position(s) + chord(s);
+                let back = |s: f32| chord(s) - position(s);
+                let front = front(maximize(start, end, front));
+                let back = -back(maximize(start, end, back));
+                Some((back / length, front / length))
+            }
+            Self::Facet(vertices, facet) => {
+                // The contact point has to lie inside of the triangle and above the tip. Its
+                // height changes linearly along the fiber.
+                let plane =
+                    |p: Vector2<f32>| vertices[0].z + facet.gradient.dot(&(p - vertices[0].xy()));
+                let height = |t: f32| plane(a + along * t + facet.offset) - facet.height - z;
+                let (h0, h1) = (height(0.0), height(1.0));
+                let (mut from, mut to) = (f32::NEG_INFINITY, f32::INFINITY);
+                if (h1 - h0).abs() < 1e-9 {
+                    if h0 <= EPSILON {
+                        return None;
+                    }
+                } else {
+                    let t = (EPSILON - h0) / (h1 - h0);
+                    if h1 > h0 {
+                        from = t;
+                    } else {
+                        to = t;
+                    }
+                }
+
+                // The contact point runs along the fiber shifted by the offset
+                let (start, end) =
+                    clip_triangle(vertices, &(a + facet.offset), &(b + facet.offset))?;
+                let (from, to) = (from.max(start * length), to.min(end * length));
+                (from < to).then_some((from / length, to / length))
+            }
+        }
+    }
+}
+
+/// The parameter range of the infinite line through a and b that lies inside of the triangle,
+/// projected onto the XY plane.
+fn clip_triangle(
+    vertices: &[Vector3<f32>; 3],
+    a: &Vector2<f32>,
+    b: &Vector2<f32>,
+) -> Option<(f32, f32)> {
+    let ccw = (vertices[1].xy() - vertices[0].xy()).perp(&(vertices[2].xy() - vertices[0].xy()));
+    let sign = ccw.signum();
+
+    let (mut from, mut to) = (f32::NEG_INFINITY, f32::INFINITY);
+    for i in 0..3 {
+        let p = vertices[i].xy();
+        let q = vertices[(i + 1) % 3].xy();
+        // The distance to the left of the edge (for counter clockwise triangles) is linear
+        let side = |point: &Vector2<f32>| (q - p).perp(&(point - p)) * sign;
+        let (da, db) = (side(a), side(b));
+        if (db - da).abs() < 1e-12 {
+            if da < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = -da / (db - da);
+        if db > da {
+            from = from.max(t);
+        } else {
+            to = to.min(t);
+        }
+    }
+
+    (from < to && contains(vertices, &a.lerp(b, (from + to) * 0.5))).then_some((from, to))
+}
+
+/// The root of the function between the points, where it is not positive at the first point and
+/// positive at the second point.
+fn bisect<F>(mut outside: f32, mut inside: f32, f: F) -> f32
+where
+    F: Fn(f32) -> f32,
+{
+    for _ in 0..24 {
+        let middle = (outside + inside) * 0.5;
+        if f(middle) > 0.0 {
+            inside = middle;
+        } else {
+            outside = middle;
+        }
+    }
+    inside
+}
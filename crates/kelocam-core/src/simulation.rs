@@ -0,0 +1,452 @@
+//! Stock removal simulation on a heightmap, for checking that a toolpath cuts the intended part
+//! before running it on the machine.
+//!
+//! The stock is a rectangular block sampled on a grid of points. Every point stores the height
+//! of the stock surface above it, like a column of material along the Z axis (a dexel). Cutting
+//! a move lowers the points below the swept cutter to the surface of the cutter. A heightmap
+//! can't have overhangs, which is fine for what a 3-axis machine cuts from the top.
+
+use std::ops::Range;
+
+use nalgebra::{UnitVector3, Vector2, Vector3};
+
+use crate::cnc::ToolBit;
+use crate::dropcutter::maximize;
+use crate::primitives::{Mesh, Triangle};
+use crate::toolpath::Toolpath;
+
+/// The stock of a simulated job.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    /// The corner of the stock with the smallest coordinates.
+    min: Vector3<f32>,
+    /// The height of the top of the uncut stock.
+    top: f32,
+    /// The distance between neighbouring points along X and Y.
+    spacing: Vector2<f32>,
+    /// The number of points along X and Y.
+    columns: usize,
+    rows: usize,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// An uncut block of stock between the corners. The points of the grid are at most
+    /// `resolution` apart and lie on the sides of the block. The block has to have a size along
+    /// every axis.
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>, resolution: f32) -> Self {
+        let size = max - min;
+        let count = |length: f32| (length / resolution).ceil().max(1.0) as usize + 1;
+        let (columns, rows) = (count(size.x), count(size.y));
+        Self {
+            min,
+            top: max.z,
+            spacing: Vector2::new(size.x / (columns - 1) as f32, size.y / (rows - 1) as f32),
+            columns,
+            rows,
+            heights: vec![max.z; columns * rows],
+        }
+    }
+
+    /// The number of points along X and Y.
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// The height of the stock surface at the grid point closest to the point. None outside of
+    /// the stock.
+    pub fn height(&self, point: &Vector2<f32>) -> Option<f32> {
+        let local = (point - self.min.xy()).component_div(&self.spacing);
+        let (i, j) = (local.x.round(), local.y.round());
+        if i < 0.0 || j < 0.0 || i as usize >= self.columns || j as usize >= self.rows {
+            return None;
+        }
+        Some(self.at(i as usize, j as usize))
+    }
+
+    /// Cuts the stock with the bit moving in a straight line.
+    pub fn cut(&mut self, bit: &dyn ToolBit, from: &Vector3<f32>, to: &Vector3<f32>) {
+        // The stock only gets lower
+        if from.z.min(to.z) >= self.top {
+            return;
+        }
+
+        let radius = Vector2::from_element(bit.radius());
+        let low = from.xy().inf(&to.xy()) - radius - self.min.xy();
+        let high = from.xy().sup(&to.xy()) + radius - self.min.xy();
+        for j in indices(low.y / self.spacing.y, high.y / self.spacing.y, self.rows) {
+            for i in indices(
+                low.x / self.spacing.x,
+                high.x / self.spacing.x,
+                self.columns,
+            ) {
+                let point = self.point(i, j).xy();
+                if let Some(z) = lowest(bit, from, to, &point) {
+                    let height = &mut self.heights[j * self.columns + i];
+                    *height = height.min(z.max(self.min.z));
+                }
+            }
+        }
+    }
+
+    /// Cuts the stock with the moves of the toolpath. Rapid moves cut as well, like they would
+    /// on the machine. Arcs are split into straight moves which deviate at most `tolerance` from
+    /// them. The cutter comes down from above to the target of the first move.
+    pub fn cut_toolpath(&mut self, bit: &dyn ToolBit, toolpath: &Toolpath, tolerance: f32) {
+        let mut position: Option<Vector3<f32>> = None;
+        for instruction in toolpath.moves.iter() {
+            let Some(to) = instruction.to() else {
+                continue;
+            };
+            let mut from = position.unwrap_or_else(|| Vector3::new(to.x, to.y, to.z.max(self.top)));
+            for to in instruction.interpolate(&from, tolerance) {
+                self.cut(bit, &from, &to);
+                from = to;
+            }
+            position = Some(from);
+        }
+    }
+
+    /// A closed mesh of the stock, e.g. for rendering. Runs of flat quads along X are merged, so
+    /// the uncut parts of the stock take only a few triangles.
+    pub fn mesh(&self) -> Mesh {
+        let mut triangles = Vec::new();
+        let (columns, rows) = (self.columns, self.rows);
+
+        // The top, made of the quads between four neighbouring points
+        for j in 0..rows - 1 {
+            let flat = |i: usize, z: f32| {
+                [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)]
+                    .iter()
+                    .all(|(i, j)| self.at(*i, *j) == z)
+            };
+            let mut i = 0;
+            while i < columns - 1 {
+                let z = self.at(i, j);
+                let mut end = i + 1;
+                if flat(i, z) {
+                    while end < columns - 1 && flat(end, z) {
+                        end += 1;
+                    }
+                }
+                quad(
+                    [
+                        self.point(i, j),
+                        self.point(end, j),
+                        self.point(end, j + 1),
+                        self.point(i, j + 1),
+                    ],
+                    &mut triangles,
+                );
+                i = end;
+            }
+        }
+
+        // The sides, counter clockwise seen from above
+        let front: Vec<_> = (0..columns).map(|i| (i, 0)).collect();
+        let right: Vec<_> = (0..rows).map(|j| (columns - 1, j)).collect();
+        let back: Vec<_> = (0..columns).rev().map(|i| (i, rows - 1)).collect();
+        let left: Vec<_> = (0..rows).rev().map(|j| (0, j)).collect();
+        for side in [front, right, back, left] {
+            let height = |k: usize| self.at(side[k].0, side[k].1);
+            let point = |k: usize| self.point(side[k].0, side[k].1);
+            let bottom = |k: usize| Vector3::new(point(k).x, point(k).y, self.min.z);
+            let mut k = 0;
+            while k < side.len() - 1 {
+                let mut end = k + 1;
+                while end < side.len() - 1
+                    && height(end) == height(k)
+                    && height(end + 1) == height(k)
+                {
+                    end += 1;
+                }
+                quad(
+                    [bottom(k), bottom(end), point(end), point(k)],
+                    &mut triangles,
+                );
+                k = end;
+            }
+        }
+
+        // The bottom
+        let (first, last) = (self.point(0, 0), self.point(columns - 1, rows - 1));
+        let (x0, y0, x1, y1) = (first.x, first.y, last.x, last.y);
+        let z = self.min.z;
+        quad(
+            [
+                Vector3::new(x0, y0, z),
+                Vector3::new(x0, y1, z),
+                Vector3::new(x1, y1, z),
+                Vector3::new(x1, y0, z),
+            ],
+            &mut triangles,
+        );
+
+        Mesh::new(triangles)
+    }
+
+    /// A coarser heightmap with the points about `factor` times as far apart. Every point takes
+    /// the lowest height around it, so that narrow cuts stay visible.
+    pub fn decimated(&self, factor: usize) -> Self {
+        let max = self.point(self.columns - 1, self.rows - 1);
+        let max = Vector3::new(max.x, max.y, self.top);
+        let resolution = self.spacing.max() * factor.max(1) as f32;
+        let mut coarse = Self::new(self.min, max, resolution);
+
+        let half = coarse.spacing * 0.5;
+        for j in 0..coarse.rows {
+            for i in 0..coarse.columns {
+                let center = coarse.point(i, j).xy() - self.min.xy();
+                let (low, high) = (center - half, center + half);
+                let rows = indices(low.y / self.spacing.y, high.y / self.spacing.y, self.rows);
+                let lowest = rows
+                    .flat_map(|y| {
+                        let columns = indices(
+                            low.x / self.spacing.x,
+                            high.x / self.spacing.x,
+                            self.columns,
+                        );
+                        columns.map(move |x| (x, y))
+                    })
+                    .map(|(x, y)| self.at(x, y))
+                    .fold(self.top, f32::min);
+                coarse.heights[j * coarse.columns + i] = lowest;
+            }
+        }
+        coarse
+    }
+
+    /// The mesh of the stock with at most about `max_triangles` triangles, e.g. to fit into a
+    /// vertex buffer. The heightmap gets decimated until the mesh is small enough.
+    pub fn mesh_within(&self, max_triangles: usize) -> Mesh {
+        let mut mesh = self.mesh();
+        let mut factor = 1;
+        while mesh.triangles.len() > max_triangles && (self.columns > 2 || self.rows > 2) {
+            // The number of triangles shrinks with the square of the factor
+            let ratio = (mesh.triangles.len() as f32 / max_triangles as f32).sqrt();
+            factor = ((factor as f32 * ratio).ceil() as usize).max(factor + 1);
+            mesh = self.decimated(factor).mesh();
+            if factor >= self.columns.max(self.rows) {
+                break;
+            }
+        }
+        mesh
+    }
+
+    /// The height of the stock surface at the indices of the grid.
+    fn at(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.columns + i]
+    }
+
+    /// The point of the stock surface at the indices of the grid.
+    fn point(&self, i: usize, j: usize) -> Vector3<f32> {
+        Vector3::new(
+            self.min.x + i as f32 * self.spacing.x,
+            self.min.y + j as f32 * self.spacing.y,
+            self.at(i, j),
+        )
+    }
+}
+
+/// The indices of the grid points between the coordinates, in multiples of the spacing.
+fn indices(low: f32, high: f32, count: usize) -> Range<usize> {
+    let start = low.ceil().max(0.0) as usize;
+    let end = (high.floor() + 1.0).clamp(0.0, count as f32) as usize;
+    start..end.max(start)
+}
+
+/// The lowest height of the cutter surface above the point while the cutter moves along the
+/// line. None if the cutter does not pass above the point.
+fn lowest(
+    bit: &dyn ToolBit,
+    from: &Vector3<f32>,
+    to: &Vector3<f32>,
+    point: &Vector2<f32>,
+) -> Option<f32> {
+    let radius = bit.radius();
+    let (offset, direction) = (from.xy() - point, to.xy() - from.xy());
+    // The height of the cutter surface above the point, at a part of the way
+    let surface = |t: f32| {
+        let distance = (offset + direction * t).magnitude().min(radius);
+        from.z + (to.z - from.z) * t + bit.height_at(distance).unwrap_or(0.0)
+    };
+
+    // The part of the way on which the point lies within the radius of the cutter
+    let length = direction.magnitude_squared();
+    let (start, end) = if length < 1e-12 {
+        if offset.magnitude() > radius {
+            return None;
+        }
+        (0.0, 1.0)
+    } else {
+        let b = offset.dot(&direction);
+        let discriminant = b * b - length * (offset.magnitude_squared() - radius * radius);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        (
+            ((-b - root) / length).max(0.0),
+            ((-b + root) / length).min(1.0),
+        )
+    };
+    if start > end {
+        return None;
+    }
+
+    if from.z == to.z {
+        // The cutter is lowest above the point where its axis comes closest
+        let closest = if length < 1e-12 {
+            0.0
+        } else {
+            -offset.dot(&direction) / length
+        };
+        return Some(surface(closest.clamp(start, end)));
+    }
+    // The profiles of all cutters are convex, so is the height of the surface along the way
+    Some(surface(maximize(start, end, |t| -surface(t))))
+}
+
+/// Adds the triangles of the quad with the corners counter clockwise seen from outside.
+/// Triangles without area, e.g. of sides cut down to the bottom, are left out.
+fn quad(corners: [Vector3<f32>; 4], triangles: &mut Vec<Triangle>) {
+    let [a, b, c, d] = corners;
+    for (a, b, c) in [(a, b, c), (a, c, d)] {
+        let normal = (b - a).cross(&(c - a));
+        if normal.magnitude_squared() > 1e-12 {
+            triangles.push(Triangle::new(a, b, c, UnitVector3::new_normalize(normal)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cnc::{BallNose, FlatEndMill};
+
+    fn stock() -> Heightmap {
+        Heightmap::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(40.0, 20.0, 10.0),
+            0.5,
+        )
+    }
+
+    /// The volume enclosed by the mesh and the sum of its normals weighted by area, which
+    /// vanishes for closed meshes.
+    fn volume_and_normals(mesh: &Mesh) -> (f32, Vector3<f32>) {
+        mesh.triangles
+            .iter()
+            .fold((0.0, Vector3::zeros()), |(volume, normals), triangle| {
+                let (a, b, c) = (triangle.a, triangle.b, triangle.c);
+                let area = (b - a).cross(&(c - a)) * 0.5;
+                (volume + a.dot(&b.cross(&c)) / 6.0, normals + area)
+            })
+    }
+
+    #[test]
+    fn slot() {
+        let mut stock = stock();
+        assert_eq!(stock.size(), (81, 41));
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2);
+        stock.cut(
+            &bit,
+            &Vector3::new(5.0, 10.0, 7.0),
+            &Vector3::new(35.0, 10.0, 7.0),
+        );
+
+        let height = |x: f32, y: f32| stock.height(&Vector2::new(x, y)).unwrap();
+        for (x, y, z) in [
+            (20.0, 10.0, 7.0),
+            (20.0, 13.0, 7.0),
+            (20.0, 7.0, 7.0),
+            (20.0, 13.5, 10.0),
+            (2.0, 10.0, 7.0),
+            (1.5, 10.0, 10.0),
+            (38.0, 10.0, 7.0),
+            (38.5, 10.0, 10.0),
+        ] {
+            assert_eq!(height(x, y), z, "{} {}", x, y);
+        }
+        assert_eq!(stock.height(&Vector2::new(41.0, 10.0)), None);
+
+        // Moves above the stock don't cut
+        let before = stock.clone();
+        stock.cut(
+            &bit,
+            &Vector3::new(0.0, 0.0, 10.0),
+            &Vector3::new(40.0, 20.0, 12.0),
+        );
+        assert_eq!(stock, before);
+    }
+
+    #[test]
+    fn groove() {
+        let mut stock = stock();
+        let bit = BallNose::new(6.0, 20.0, 6.0, 2);
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::new(-5.0, 10.0, 5.0));
+        toolpath.feed(Vector3::new(45.0, 10.0, 5.0), 1000.0);
+        stock.cut_toolpath(&bit, &toolpath, 0.01);
+
+        // The profile of the ball across the groove
+        for offset in [0.0f32, 1.0, 1.5, 2.5, -2.5] {
+            let expected = 5.0 + 3.0 - (9.0 - offset * offset).sqrt();
+            let height = stock.height(&Vector2::new(20.0, 10.0 + offset)).unwrap();
+            assert!((height - expected).abs() < 1e-4, "{} {}", offset, height);
+        }
+        assert_eq!(stock.height(&Vector2::new(20.0, 13.5)), Some(10.0));
+        // The cutter comes down from above at the first target
+        assert_eq!(stock.height(&Vector2::new(0.0, 10.0)), Some(5.0));
+    }
+
+    #[test]
+    fn closed_mesh() {
+        let mut stock = stock();
+        let (volume, normals) = volume_and_normals(&stock.mesh());
+        assert!((volume - 8000.0).abs() < 1e-1, "{}", volume);
+        assert!(normals.magnitude() < 1e-2);
+        // Uncut, the top is merged into one quad per row
+        assert_eq!(stock.mesh().triangles.len(), 2 * 40 + 2 * 2 * 2 + 2);
+
+        // A slot through the stock, 3 mm deep and open to both sides
+        let bit = FlatEndMill::new(6.0, 20.0, 6.0, 2);
+        stock.cut(
+            &bit,
+            &Vector3::new(-5.0, 10.0, 7.0),
+            &Vector3::new(45.0, 10.0, 7.0),
+        );
+        let mesh = stock.mesh();
+        let (volume, normals) = volume_and_normals(&mesh);
+        assert!(normals.magnitude() < 1e-2, "{:?}", normals);
+        // The grid rounds the width of the slot to 6 mm plus half the spacing on either side
+        let removed = 8000.0 - volume;
+        assert!((removed - 40.0 * 6.5 * 3.0).abs() < 1.0, "{}", removed);
+    }
+
+    #[test]
+    fn decimation() {
+        let mut stock = stock();
+        let bit = FlatEndMill::new(0.6, 20.0, 1.0, 2);
+        let mut toolpath = Toolpath::new();
+        for i in 0..20 {
+            let y = i as f32 + 0.5;
+            toolpath.rapid(Vector3::new(0.0, y, 12.0));
+            toolpath.plunge(Vector3::new(0.0, y, 9.0), 300.0);
+            toolpath.feed(Vector3::new(40.0, y, 9.0), 1000.0);
+            toolpath.rapid(Vector3::new(40.0, y, 12.0));
+        }
+        stock.cut_toolpath(&bit, &toolpath, 0.01);
+
+        // The grooves are narrower than the spacing of the coarse points, but still show up
+        let coarse = stock.decimated(4);
+        assert_eq!(coarse.size(), (21, 11));
+        assert_eq!(coarse.height(&Vector2::new(20.0, 10.0)), Some(9.0));
+
+        let full = stock.mesh().triangles.len();
+        let mesh = stock.mesh_within(full / 10);
+        assert!(mesh.triangles.len() <= full / 10);
+        assert!(volume_and_normals(&mesh).1.magnitude() < 1e-2);
+        assert_eq!(stock.mesh_within(full).triangles.len(), full);
+    }
+}
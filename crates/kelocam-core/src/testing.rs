@@ -0,0 +1,83 @@
+//! Simple meshes shared by the tests of the operations.
+
+use nalgebra::{UnitVector3, Vector2, Vector3};
+
+use crate::primitives::{Mesh, Triangle};
+
+/// A closed box between the corners.
+pub fn cuboid(min: Vector3<f32>, max: Vector3<f32>) -> Mesh {
+    let outline = [
+        Vector2::new(min.x, min.y),
+        Vector2::new(max.x, min.y),
+        Vector2::new(max.x, max.y),
+        Vector2::new(min.x, max.y),
+    ];
+    prism(&outline, None, min.z, max.z)
+}
+
+/// A closed square frame around the origin, e.g. a part with a square pocket through it.
+pub fn frame(outer: f32, inner: f32, bottom: f32, top: f32) -> Mesh {
+    let square = |size: f32| {
+        let half = size / 2.0;
+        [
+            Vector2::new(-half, -half),
+            Vector2::new(half, -half),
+            Vector2::new(half, half),
+            Vector2::new(-half, half),
+        ]
+    };
+    prism(&square(outer), Some(&square(inner)), bottom, top)
+}
+
+/// A closed prism between the Z levels. The convex outline runs counter clockwise. The hole
+/// (if any) runs counter clockwise as well and has as many corners as the outline, every corner
+/// facing the corresponding corner of the outline.
+pub fn prism(
+    outline: &[Vector2<f32>],
+    hole: Option<&[Vector2<f32>]>,
+    bottom: f32,
+    top: f32,
+) -> Mesh {
+    let mut triangles = Vec::new();
+    let mut add = |a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>| {
+        let normal = UnitVector3::new_normalize((b - a).cross(&(c - a)));
+        triangles.push(Triangle::new(a, b, c, normal));
+    };
+    let at = |point: &Vector2<f32>, z: f32| Vector3::new(point.x, point.y, z);
+
+    // The walls, facing away from the material
+    let len = outline.len();
+    for i in 0..len {
+        let (a, b) = (&outline[i], &outline[(i + 1) % len]);
+        add(at(a, bottom), at(b, bottom), at(b, top));
+        add(at(a, bottom), at(b, top), at(a, top));
+        if let Some(hole) = hole {
+            let (a, b) = (&hole[i], &hole[(i + 1) % len]);
+            add(at(b, bottom), at(a, bottom), at(a, top));
+            add(at(b, bottom), at(a, top), at(b, top));
+        }
+    }
+
+    // The top and bottom faces
+    match hole {
+        None => {
+            for i in 1..len - 1 {
+                let (a, b, c) = (&outline[0], &outline[i], &outline[i + 1]);
+                add(at(a, top), at(b, top), at(c, top));
+                add(at(a, bottom), at(c, bottom), at(b, bottom));
+            }
+        }
+        Some(hole) => {
+            for i in 0..len {
+                let j = (i + 1) % len;
+                let (a, b, c, d) = (&outline[i], &outline[j], &hole[j], &hole[i]);
+                add(at(a, top), at(b, top), at(c, top));
+                add(at(a, top), at(c, top), at(d, top));
+                add(at(a, bottom), at(c, bottom), at(b, bottom));
+                add(at(a, bottom), at(d, bottom), at(c, bottom));
+            }
+        }
+    }
+
+    Mesh::new(triangles)
+}
@@ -3,7 +3,10 @@ use egui::{ScrollArea, Vec2};
 use nalgebra::{UnitVector3, Vector3};
 use std::sync::Arc;
 
-use kelocam_core::primitives::{BoundingBox, Plane};
+use kelocam_core::cnc::{Machine, ToolBit};
+use kelocam_core::primitives::{BoundingBox, Mesh, Plane};
+use kelocam_core::simulation::Heightmap;
+use kelocam_toolpath::Toolpath;
 
 pub mod camera;
 pub mod icons;
@@ -27,6 +30,22 @@ pub struct Editor {
 
     pub action: Option<Action>,
 
+    /// The machine whose work envelope is shown in the viewport (if any).
+    pub machine: Option<Machine>,
+
+    /// The toolpath previewed in the viewport (if any).
+    pub toolpath: Option<Toolpath>,
+
+    /// The G-code program the toolpath was read from (if any). Jobs run it unchanged instead of
+    /// posting the toolpath, which lacks the codes the parser ignores.
+    pub program: Option<String>,
+
+    /// The position of the tool on a connected machine, shown as a marker (if any).
+    pub tool_position: Option<Vector3<f32>>,
+
+    /// The stock left by a simulated job in scene units, see `Editor::simulate` (if any).
+    pub stock: Option<Mesh>,
+
     pub state: State,
     pub log: Log,
 }
@@ -83,6 +102,49 @@ impl Editor {
         })
     }
 
+    /// Cuts the stock with the toolpath and the bit and shows what is left of it. The stock is
+    /// the block around the objects, or below the cutting moves with its top at zero if there
+    /// are none. The mesh takes at most half of the object vertex buffer.
+    pub fn simulate(&mut self, bit: &dyn ToolBit) {
+        self.stock = None;
+        let Some(toolpath) = &self.toolpath else {
+            return;
+        };
+
+        // The objects are in scene units, the toolpath in millimeters
+        let bounds = (self.state.objects.values())
+            .filter(|object| !object.mesh.triangles.is_empty())
+            .map(|object| object.mesh.bb_min_max())
+            .reduce(|(a, b), (c, d)| (a.inf(&c), b.sup(&d)))
+            .map(|(min, max)| (min / renderer::SCENE_SCALE, max / renderer::SCENE_SCALE));
+        let bounds = bounds.or_else(|| {
+            let cuts = (toolpath.moves.iter())
+                .filter(|m| m.is_cutting())
+                .filter_map(|m| m.to());
+            let (min, max) = cuts.fold(
+                (Vector3::repeat(f32::MAX), Vector3::repeat(f32::MIN)),
+                |(min, max), point| (min.inf(point), max.sup(point)),
+            );
+            let margin = Vector3::new(bit.radius(), bit.radius(), 1.0);
+            (min.x <= max.x).then(|| {
+                (
+                    min - margin,
+                    Vector3::new(max.x + margin.x, max.y + margin.y, 0.0),
+                )
+            })
+        });
+        let Some((min, max)) = bounds.filter(|(min, max)| (max - min).min() > 0.0) else {
+            return;
+        };
+
+        let resolution = ((max - min).xy().max() / 400.0).max(0.05);
+        let mut heightmap = Heightmap::new(min, max, resolution);
+        heightmap.cut_toolpath(bit, toolpath, resolution * 0.1);
+        let mut mesh = heightmap.mesh_within(renderer::object::MAX_VERTICIES / 2 / 3);
+        mesh.scale(renderer::SCENE_SCALE);
+        self.stock = Some(mesh);
+    }
+
     pub fn move_delta(&self, plane: &Plane, before: Vec2, after: Vec2) -> Option<Vector3<f32>> {
         Some(
             plane.intersect(&self.camera.screen_ray(after.x, after.y))?
@@ -282,7 +344,62 @@ impl Editor {
             }
         }
 
-        // Generate visual camera center
+        // Generate simulated stock, if the objects left enough room
+        let room = renderer::object::MAX_VERTICIES.saturating_sub(object_verticies.len());
+        if let Some(stock) = self
+            .stock
+            .as_ref()
+            .filter(|s| 3 * s.triangles.len() <= room)
+        {
+            renderer::object::generate(&stock.triangles, [0.8, 0.6, 0.4], &mut object_verticies);
+        }
+
+        // Generate work envelope
+        if let Some(machine) = &self.machine {
+            renderer::grid::generate_envelope(
+                machine,
+                [0.3, 0.6, 1.0, 0.6],
+                3.0 / self.camera.height,
+                &mut path_verticies,
+                &mut path_indicies,
+            );
+        }
+
+        // Generate toolpath preview
+        if let Some(toolpath) = &self.toolpath {
+            renderer::path::generate_toolpath(
+                toolpath,
+                [1.0, 0.8, 0.2, 0.5],
+                [0.3, 1.0, 0.5, 0.9],
+                2.0 / self.camera.height,
+                &mut path_verticies,
+                &mut path_indicies,
+            );
+        }
+
+        // Generate tool marker, an arrow pointing at the tip of the tool
+        if let Some(position) = &self.tool_position {
+            // The position is in millimeters like the toolpath, the size is 1.5 millimeters at
+            // a zoom of 1
+            let position = position.scale(renderer::SCENE_SCALE);
+            let scale = 1.5 * renderer::SCENE_SCALE / self.camera.zoom;
+            let color = [1.0, 0.3, 0.3, 0.9];
+            renderer::entity::generate_arrow(
+                scale * 0.3,
+                &(position + Vector3::new(0.0, 0.0, scale * 0.3)),
+                &-Vector3::z_axis(),
+                color,
+                &mut entity_verticies,
+            );
+            renderer::entity::generate_cube(
+                scale * 0.15,
+                &(position + Vector3::new(0.0, 0.0, scale * 0.375)),
+                color,
+                &mut entity_verticies,
+            );
+        }
+
+        // Generate visual camera center, its verticies are reserved by the toolpath preview
         {
             let o = self.camera.position.xzy();
             let scale = 1.0 / self.camera.zoom * 10.0 / self.camera.height;
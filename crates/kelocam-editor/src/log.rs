@@ -29,6 +29,8 @@ pub struct Log {
     pub cursor: usize,
     before: usize,
     after: usize,
+    /// Notices for the user, e.g. problems with an opened file. They are not undoable.
+    pub notices: Vec<String>,
 }
 
 impl Log {
@@ -45,9 +47,15 @@ impl Log {
             cursor: 0,
             before: 0,
             after: 0,
+            notices: Vec::new(),
         }
     }
 
+    /// Adds a notice for the user.
+    pub fn notify<S: Into<String>>(&mut self, notice: S) {
+        self.notices.push(notice.into());
+    }
+
     /// Pushes an action. This returns the action that was overwritten (if any).
     pub fn push(&mut self, mut message: Message) -> Option<Message> {
         if matches!(message, Message::None) {
@@ -1,6 +1,49 @@
 use eframe::wgpu;
+use nalgebra::Vector3;
 use std::sync::Arc;
 
+use kelocam_core::cnc::Machine;
+
+use super::path;
+
+/// Generates the outline of the work envelope of a machine as a wireframe box.
+pub fn generate_envelope(
+    machine: &Machine,
+    color: [f32; 4],
+    thickness: f32,
+    verticies: &mut Vec<path::Vertex>,
+    indicies: &mut Vec<path::Index>,
+) {
+    let min = machine.travel_min.scale(super::SCENE_SCALE);
+    let max = machine.travel_max.scale(super::SCENE_SCALE);
+
+    let corners = [
+        Vector3::new(min.x, min.y, min.z),
+        Vector3::new(max.x, min.y, min.z),
+        Vector3::new(max.x, max.y, min.z),
+        Vector3::new(min.x, max.y, min.z),
+    ];
+    let height = Vector3::new(0.0, 0.0, max.z - min.z);
+
+    path::generate_closed(&corners, color, thickness, verticies, indicies);
+    path::generate_closed(
+        &corners.map(|corner| corner + height),
+        color,
+        thickness,
+        verticies,
+        indicies,
+    );
+    for corner in corners.iter() {
+        path::generate_open(
+            &[*corner, corner + height],
+            color,
+            thickness,
+            verticies,
+            indicies,
+        );
+    }
+}
+
 pub struct Renderer {
     pipeline: wgpu::RenderPipeline,
 }
@@ -2,3 +2,7 @@ pub mod entity;
 pub mod grid;
 pub mod object;
 pub mod path;
+
+/// Scene units per millimeter. Meshes get scaled by the same factor when they are imported
+/// (see `Triangle::from_stl`), so CNC related geometry has to be scaled by it before rendering.
+pub const SCENE_SCALE: f32 = 0.1;
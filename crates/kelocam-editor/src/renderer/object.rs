@@ -5,6 +5,9 @@ use eframe::wgpu;
 use kelocam_core::primitives::Triangle;
 
 pub const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
+
+/// The capacity of the vertex buffer.
+pub const MAX_VERTICIES: usize = 100_000;
 pub const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
     array_stride: VERTEX_SIZE as u64,
     attributes: &[
@@ -73,7 +76,7 @@ impl Renderer {
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("object"),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            size: 100000 * VERTEX_SIZE as u64,
+            size: (MAX_VERTICIES * VERTEX_SIZE) as u64,
             mapped_at_creation: false,
         });
 
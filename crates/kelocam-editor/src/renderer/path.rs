@@ -4,9 +4,18 @@ use nalgebra::Vector3;
 
 use eframe::wgpu;
 
+use kelocam_toolpath::{Move, Toolpath};
+
 pub type Index = u32;
 pub const INDEX_SIZE: usize = std::mem::size_of::<Index>();
 
+/// The capacity of the buffers. Paths exceeding it are not drawn completely.
+pub const MAX_VERTICIES: usize = 200_000;
+pub const MAX_INDICIES: usize = 3 * MAX_VERTICIES;
+/// The verticies kept free by `generate_toolpath` for the paths drawn after the toolpath, i.e.
+/// the three lines of the camera center with 4 verticies per point.
+pub const RESERVED_VERTICIES: usize = 3 * 2 * 4;
+
 pub const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
 pub const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
     array_stride: VERTEX_SIZE as u64,
@@ -196,6 +205,54 @@ pub fn generate_open(
     }
 }
 
+/// Generates verticies for the moves of a toolpath, with rapid moves and cutting moves in
+/// different colors. Stops when the buffers are full, leaving `RESERVED_VERTICIES` free.
+pub fn generate_toolpath(
+    toolpath: &Toolpath,
+    rapid_color: [f32; 4],
+    feed_color: [f32; 4],
+    thickness: f32,
+    verticies: &mut Vec<Vertex>,
+    indicies: &mut Vec<Index>,
+) {
+    let mut flush = |points: &mut Vec<Vector3<f32>>, rapid: bool| {
+        if points.len() >= 2
+            && verticies.len() + 4 * points.len() <= MAX_VERTICIES - RESERVED_VERTICIES
+        {
+            let color = if rapid { rapid_color } else { feed_color };
+            generate_open(points, color, thickness, verticies, indicies);
+        }
+        let last = points.pop();
+        points.clear();
+        points.extend(last);
+    };
+
+    let mut points = Vec::new();
+    let mut rapid = true;
+    let mut position: Option<Vector3<f32>> = None;
+    for m in toolpath.moves.iter() {
+        let Some(to) = m.to() else {
+            continue;
+        };
+        let is_rapid = matches!(m, Move::Rapid(_));
+        if is_rapid != rapid {
+            flush(&mut points, rapid);
+            rapid = is_rapid;
+        }
+        // Arcs are drawn as polylines, deviating at most 0.01 mm from the arc
+        match position {
+            Some(from) => points.extend(
+                m.interpolate(&from, 0.01)
+                    .iter()
+                    .map(|p| p.scale(super::SCENE_SCALE)),
+            ),
+            None => points.push(to.scale(super::SCENE_SCALE)),
+        }
+        position = Some(*to);
+    }
+    flush(&mut points, rapid);
+}
+
 pub struct Renderer {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
@@ -212,13 +269,13 @@ impl Renderer {
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("path"),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            size: 20000 * VERTEX_SIZE as u64,
+            size: (MAX_VERTICIES * VERTEX_SIZE) as u64,
             mapped_at_creation: false,
         });
         let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("path"),
             usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-            size: 40000 * std::mem::size_of::<u32>() as u64,
+            size: (MAX_INDICIES * INDEX_SIZE) as u64,
             mapped_at_creation: false,
         });
 
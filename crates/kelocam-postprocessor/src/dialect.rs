@@ -0,0 +1,193 @@
+//! The differences between the G-code dialects of CNC controllers.
+
+/// How arcs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArcFormat {
+    /// G2/G3 with the center relative to the start of the arc (IJ).
+    Center,
+    /// G2/G3 with the radius (R). Full circles get split into two halves, because their radius
+    /// does not define them.
+    Radius,
+    /// The controller does not support arcs, they get approximated by straight moves.
+    Linear,
+}
+
+/// How a tool change is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChange {
+    /// The controller changes the tool itself, or handles the manual change (T1 M6).
+    Automatic,
+    /// Stop the spindle and pause the program using the code (e.g. M0), so the operator can
+    /// change the tool.
+    Pause(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// "(comment)"
+    Parentheses,
+    /// "; comment"
+    Semicolon,
+    /// The controller does not support comments, they are left out.
+    None,
+}
+
+/// The unit of the P word of a dwell (G4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwellUnits {
+    Seconds,
+    Milliseconds,
+}
+
+/// The G-code dialect of a controller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialect {
+    pub name: String,
+    pub arcs: ArcFormat,
+    pub tool_change: ToolChange,
+    pub comments: CommentStyle,
+    pub dwell: DwellUnits,
+    /// The maximum length of a block, including the line number (if any).
+    pub max_line_length: Option<usize>,
+    /// Whether a block may contain multiple G or M codes, like "G21 G90".
+    pub multiple_codes: bool,
+    /// Whether the controller supports plane selection (G17).
+    pub plane_select: bool,
+    /// Whether the controller supports flood coolant (M8) and coolant off (M9).
+    pub flood: bool,
+    /// Whether the controller supports mist coolant (M7).
+    pub mist: bool,
+    /// Whether the controller supports running the spindle counter clockwise (M4).
+    pub spindle_reverse: bool,
+    /// Whether whole numbers need a decimal point, i.e. "X10." instead of "X10". Some controls
+    /// read numbers without a decimal point in their smallest increment (e.g. microns).
+    pub decimal_point: bool,
+    /// Blocks at the start of the program, before the header of the post-processor.
+    pub program_start: Vec<String>,
+    /// Blocks at the end of the program, after the footer of the post-processor.
+    pub program_end: Vec<String>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::linuxcnc()
+    }
+}
+
+impl Dialect {
+    /// GRBL 1.1, as used by most hobby routers. Mist coolant (M7) is disabled in the default
+    /// build and M6 is not supported.
+    pub fn grbl() -> Self {
+        Self {
+            name: "GRBL".into(),
+            arcs: ArcFormat::Center,
+            tool_change: ToolChange::Pause("M0".into()),
+            comments: CommentStyle::Parentheses,
+            dwell: DwellUnits::Seconds,
+            // The line buffer holds 80 characters, including the line break
+            max_line_length: Some(79),
+            multiple_codes: true,
+            plane_select: true,
+            flood: true,
+            mist: false,
+            spindle_reverse: true,
+            decimal_point: false,
+            program_start: Vec::new(),
+            program_end: vec!["M5".into(), "M9".into(), "M2".into()],
+        }
+    }
+
+    /// Marlin firmware with a spindle (e.g. 3D printers converted to routers). Marlin handles one
+    /// command per line, has no coolant or plane selection in the default configuration and
+    /// dwells in milliseconds.
+    pub fn marlin() -> Self {
+        Self {
+            name: "Marlin".into(),
+            arcs: ArcFormat::Center,
+            tool_change: ToolChange::Pause("M0".into()),
+            comments: CommentStyle::Semicolon,
+            dwell: DwellUnits::Milliseconds,
+            max_line_length: Some(95),
+            multiple_codes: false,
+            plane_select: false,
+            flood: false,
+            mist: false,
+            spindle_reverse: true,
+            decimal_point: false,
+            program_start: Vec::new(),
+            program_end: vec!["M5".into()],
+        }
+    }
+
+    /// LinuxCNC, which follows RS274/NGC closely.
+    pub fn linuxcnc() -> Self {
+        Self {
+            name: "LinuxCNC".into(),
+            arcs: ArcFormat::Center,
+            tool_change: ToolChange::Automatic,
+            comments: CommentStyle::Parentheses,
+            dwell: DwellUnits::Seconds,
+            max_line_length: Some(255),
+            multiple_codes: true,
+            plane_select: true,
+            flood: true,
+            mist: true,
+            spindle_reverse: true,
+            decimal_point: false,
+            program_start: Vec::new(),
+            program_end: vec!["M5".into(), "M9".into(), "M2".into()],
+        }
+    }
+
+    /// Mach3. The IJ mode of Mach3 is configurable, so the program selects incremental centers.
+    pub fn mach3() -> Self {
+        Self {
+            name: "Mach3".into(),
+            arcs: ArcFormat::Center,
+            tool_change: ToolChange::Automatic,
+            comments: CommentStyle::Parentheses,
+            dwell: DwellUnits::Seconds,
+            max_line_length: None,
+            multiple_codes: true,
+            plane_select: true,
+            flood: true,
+            mist: true,
+            spindle_reverse: true,
+            decimal_point: false,
+            program_start: vec!["G91.1".into()],
+            program_end: vec!["M5".into(), "M9".into(), "M30".into()],
+        }
+    }
+
+    /// FANUC-style industrial controls. Programs are enclosed by "%" and numbered, whole numbers
+    /// need a decimal point and dwells are in milliseconds.
+    pub fn fanuc() -> Self {
+        Self {
+            name: "FANUC".into(),
+            arcs: ArcFormat::Radius,
+            tool_change: ToolChange::Automatic,
+            comments: CommentStyle::Parentheses,
+            dwell: DwellUnits::Milliseconds,
+            max_line_length: None,
+            multiple_codes: true,
+            plane_select: true,
+            flood: true,
+            mist: true,
+            spindle_reverse: true,
+            decimal_point: true,
+            program_start: vec!["%".into(), "O0001".into()],
+            program_end: vec!["M5".into(), "M9".into(), "M30".into(), "%".into()],
+        }
+    }
+
+    /// All built-in dialects.
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::grbl(),
+            Self::marlin(),
+            Self::linuxcnc(),
+            Self::mach3(),
+            Self::fanuc(),
+        ]
+    }
+}
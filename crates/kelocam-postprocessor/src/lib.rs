@@ -1,14 +1,385 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
+//! Turns toolpaths into G-code programs for CNC controllers.
+
+pub mod dialect;
+pub mod parser;
+pub mod resume;
+pub mod template;
+mod writer;
+
+pub use dialect::{ArcFormat, CommentStyle, Dialect, DwellUnits, ToolChange};
+pub use parser::{ParseError, Parser, Program};
+pub use resume::Resume;
+pub use template::{Template, TemplateError};
+
+use std::fmt;
+
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use kelocam_core::cnc::{Machine, Units};
+use kelocam_toolpath::{Move, Spindle, Toolpath};
+
+use writer::Writer;
+
+/// How the coordinates of moves are interpreted by the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Positioning {
+    /// Coordinates are positions in the work coordinate system (G90).
+    #[default]
+    Absolute,
+    /// Coordinates are distances from the previous position (G91).
+    Incremental,
+}
+
+/// Numbering of the blocks of a program, e.g. "N10 G0 X0".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct LineNumbers {
+    pub start: u32,
+    pub increment: u32,
+}
+
+impl Default for LineNumbers {
+    fn default() -> Self {
+        Self {
+            start: 10,
+            increment: 10,
+        }
+    }
+}
+
+/// An error that occured while posting a toolpath.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostError {
+    /// A block exceeds the maximum line length of the dialect.
+    LineTooLong {
+        line: usize,
+        length: usize,
+        max: usize,
+    },
+    /// The toolpath uses a feature the dialect does not support.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LineTooLong { line, length, max } => write!(
+                f,
+                "line {} is {} characters long, the controller accepts {}",
+                line, length, max
+            ),
+            Self::Unsupported(feature) => write!(f, "the controller does not support {}", feature),
+        }
+    }
+}
+
+impl std::error::Error for PostError {}
+
+/// Converts toolpaths into G-code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessor {
+    pub dialect: Dialect,
+    /// The units of the program. Toolpaths are always in millimeters and get converted.
+    pub units: Units,
+    pub positioning: Positioning,
+    /// The number of decimal places of coordinates and feed rates.
+    pub precision: usize,
+    /// Remove trailing zeros after the decimal point, "X1.500" becomes "X1.5" and "X2.000"
+    /// becomes "X2".
+    pub trim_zeros: bool,
+    pub line_numbers: Option<LineNumbers>,
+    /// Blocks emitted after the start of the program, before the modes are set.
+    pub header: Vec<String>,
+    /// Blocks emitted before the end of the program.
+    pub footer: Vec<String>,
+    /// Leave out words which do not change the modal state of the controller, like a repeated G1
+    /// or an unchanged coordinate.
+    pub modal: bool,
+}
+
+impl Default for PostProcessor {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::default(),
+            units: Units::Millimeters,
+            positioning: Positioning::Absolute,
+            precision: 3,
+            trim_zeros: true,
+            line_numbers: None,
+            header: Vec::new(),
+            footer: Vec::new(),
+            modal: true,
+        }
+    }
+}
+
+impl PostProcessor {
+    pub fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            ..Default::default()
+        }
+    }
+
+    /// A post-processor for programs in the units of the machine.
+    pub fn for_machine(machine: &Machine, dialect: Dialect) -> Self {
+        Self {
+            units: machine.units,
+            ..Self::new(dialect)
+        }
+    }
+
+    /// Generate the G-code program for the toolpath. Incremental programs assume that the
+    /// machine starts at the work origin.
+    pub fn post(&self, toolpath: &Toolpath) -> Result<String, PostError> {
+        let mut writer = Writer::new(self);
+        self.start(&mut writer)?;
+        for m in toolpath.moves.iter() {
+            writer.write(m)?;
+        }
+        self.end(&mut writer)?;
+        Ok(writer.finish())
+    }
+
+    /// Generate the G-code program for the moves of the toolpath from the index on, with a
+    /// preamble which restores the tool, spindle and coolant and brings the cutter back to where
+    /// the moves continue, see `Resume`. The last tool change is repeated, so manual tool
+    /// changes stop to check the tool.
+    pub fn resume(
+        &self,
+        toolpath: &Toolpath,
+        index: usize,
+        resume: &Resume,
+    ) -> Result<String, PostError> {
+        if self.positioning == Positioning::Incremental {
+            return Err(PostError::Unsupported("resuming incremental programs"));
+        }
+        let (done, rest) = toolpath.moves.split_at(index.min(toolpath.moves.len()));
+        let last = |f: fn(&Move) -> Option<Move>| done.iter().rev().find_map(f);
+        let tool = last(|m| matches!(m, Move::ToolChange(_)).then(|| m.clone()));
+        let spindle = last(|m| matches!(m, Move::Spindle(_)).then(|| m.clone()))
+            .filter(|m| *m != Move::Spindle(Spindle::Off));
+        let coolant = last(|m| matches!(m, Move::Coolant(_)).then(|| m.clone()));
+        let feed = done.iter().rev().find_map(Move::feed);
+
+        let mut writer = Writer::new(self);
+        self.start(&mut writer)?;
+        let position = done.iter().rev().find_map(Move::to);
+        let safe_z = position.map_or(resume.safe_z, |position| resume.safe_z.max(position.z));
+        writer.retract(safe_z)?;
+        for m in tool.iter().chain(spindle.iter()) {
+            writer.write(m)?;
+        }
+        if spindle.is_some() && resume.spindle_delay > 0.0 {
+            writer.write(&Move::Dwell(resume.spindle_delay))?;
+        }
+        for m in coolant.iter() {
+            writer.write(m)?;
+        }
+        if let Some(position) = position {
+            writer.write(&Move::Rapid(Vector3::new(position.x, position.y, safe_z)))?;
+            match resume.plunge_feed.or(feed) {
+                Some(feed) if position.z < safe_z => writer.write(&Move::Plunge {
+                    to: *position,
+                    feed,
+                })?,
+                _ => writer.write(&Move::Rapid(*position))?,
+            }
+        }
+
+        for m in rest.iter() {
+            writer.write(m)?;
+        }
+        self.end(&mut writer)?;
+        Ok(writer.finish())
+    }
+
+    /// Writes the start of the program and sets the modes.
+    fn start(&self, writer: &mut Writer) -> Result<(), PostError> {
+        for block in self.dialect.program_start.iter().chain(self.header.iter()) {
+            writer.line(block)?;
+        }
+
+        let mut modes = Vec::new();
+        // Start in the XY plane, the writer switches planes as arcs need them
+        if self.dialect.plane_select {
+            modes.push("G17");
+        }
+        modes.push(match self.units {
+            Units::Millimeters => "G21",
+            Units::Inches => "G20",
+        });
+        modes.push(match self.positioning {
+            Positioning::Absolute => "G90",
+            Positioning::Incremental => "G91",
+        });
+        writer.codes(&modes)
+    }
+
+    /// Writes the end of the program.
+    fn end(&self, writer: &mut Writer) -> Result<(), PostError> {
+        for block in self.footer.iter().chain(self.dialect.program_end.iter()) {
+            writer.line(block)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use kelocam_toolpath::{ArcDirection, ArcPlane};
+    use nalgebra::{Vector2, Vector3};
+
+    fn square() -> Toolpath {
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::new(0.0, 0.0, 5.0));
+        toolpath.plunge(Vector3::new(0.0, 0.0, -1.0), 300.0);
+        toolpath.feed(Vector3::new(10.0, 0.0, -1.0), 1000.0);
+        toolpath.feed(Vector3::new(10.0, 10.0, -1.0), 1000.0);
+        toolpath.rapid(Vector3::new(10.0, 10.0, 5.0));
+        toolpath
+    }
+
+    #[test]
+    fn modal_suppression() {
+        assert_eq!(
+            PostProcessor::default().post(&square()).unwrap(),
+            "G17 G21 G90\nG0 X0 Y0 Z5\nG1 Z-1 F300\nX10 F1000\nY10\nG0 Z5\nM5\nM9\nM2\n"
+        );
+    }
+
+    #[test]
+    fn incremental_inches_with_line_numbers() {
+        let post = PostProcessor {
+            units: Units::Inches,
+            positioning: Positioning::Incremental,
+            precision: 4,
+            trim_zeros: false,
+            line_numbers: Some(LineNumbers::default()),
+            footer: vec!["M0".into()],
+            modal: false,
+            ..Default::default()
+        };
+        let program = post.post(&square()).unwrap();
+        let lines: Vec<_> = program.lines().collect();
+        assert_eq!(lines[0], "N10 G17 G20 G91");
+        assert_eq!(lines[3], "N40 G1 X0.3937 Y0.0000 Z0.0000 F39.3701");
+        assert_eq!(lines[6], "N70 M0");
+        assert_eq!(lines[9], "N100 M2");
+    }
+
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn arcs_use_incremental_centers() {
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::new(10.0, 0.0, 0.0));
+        toolpath.arc(
+            Vector3::new(0.0, 10.0, 0.0),
+            Vector2::zeros(),
+            ArcDirection::CounterClockwise,
+            500.0,
+        );
+        toolpath.arc(
+            Vector3::new(0.0, 10.0, -1.0),
+            Vector2::new(0.0, 5.0),
+            ArcDirection::Clockwise,
+            500.0,
+        );
+        let program = PostProcessor::default().post(&toolpath).unwrap();
+        let lines: Vec<_> = program.lines().collect();
+        assert_eq!(lines[2], "G3 X0 Y10 I-10 J0 F500");
+        assert_eq!(lines[3], "G2 Z-1 I0 J-5");
+    }
+
+    #[test]
+    fn dialects() {
+        let mut toolpath = Toolpath::new();
+        toolpath.tool_change(2);
+        toolpath.comment("Roughing (6 mm end mill)");
+        toolpath.rapid(Vector3::new(10.0, 0.0, 0.0));
+        toolpath.arc(
+            Vector3::new(10.0, 0.0, 0.0),
+            Vector2::zeros(),
+            ArcDirection::CounterClockwise,
+            500.0,
+        );
+        toolpath.dwell(0.5);
+
+        let grbl = PostProcessor::new(Dialect::grbl()).post(&toolpath).unwrap();
+        assert!(grbl
+            .starts_with("G17 G21 G90\nM5\n(Change to tool T2)\nM0\n(Roughing 6 mm end mill)\n"));
+        assert!(grbl.contains("G3 I-10 J0 F500\nG4 P0.5\n"));
+
+        let marlin = PostProcessor::new(Dialect::marlin())
+            .post(&toolpath)
+            .unwrap();
+        assert!(marlin.starts_with("G21\nG90\nM5\n; Change to tool T2\nM0\n"));
+        assert!(marlin.contains("G4 P500\n"));
+
+        let fanuc = PostProcessor::new(Dialect::fanuc())
+            .post(&toolpath)
+            .unwrap();
+        assert!(fanuc.starts_with("%\nO0001\nG17 G21 G90\nT2 M6\n"));
+        assert!(fanuc.contains("G0 X10. Y0. Z0.\nG3 X-10. R10. F500.\nX10. R10.\n"));
+        assert!(fanuc.ends_with("M30\n%\n"));
+
+        let linear = PostProcessor::new(Dialect {
+            arcs: ArcFormat::Linear,
+            ..Dialect::linuxcnc()
+        });
+        let program = linear.post(&toolpath).unwrap();
+        assert!(!program.contains("G3"));
+        assert!(program.lines().filter(|line| line.starts_with('X')).count() > 90);
+    }
+
+    #[test]
+    fn line_length() {
+        let mut toolpath = Toolpath::new();
+        toolpath.comment("x".repeat(200));
+        toolpath.feed(Vector3::new(1.0, 2.0, 3.0), 100.0);
+        let post = PostProcessor {
+            precision: 30,
+            trim_zeros: false,
+            ..PostProcessor::new(Dialect::grbl())
+        };
+        let error = post.post(&toolpath).unwrap_err();
+        assert!(matches!(
+            error,
+            PostError::LineTooLong {
+                line: 3,
+                max: 79,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn arc_planes() {
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::new(0.0, 0.0, 0.0));
+        toolpath.arc_in(
+            ArcPlane::ZX,
+            Vector3::new(5.0, 0.0, -5.0),
+            Vector2::new(-5.0, 0.0),
+            ArcDirection::Clockwise,
+            500.0,
+        );
+        toolpath.arc(
+            Vector3::new(5.0, 0.0, -5.0),
+            Vector2::new(0.0, 0.0),
+            ArcDirection::Clockwise,
+            500.0,
+        );
+
+        let program = PostProcessor::new(Dialect::grbl()).post(&toolpath).unwrap();
+        assert!(program.contains("G18\nG2 X5 Z-5 I0 K-5 F500\nG17\nI-5 J0\n"));
+
+        // Marlin can only select the XY plane
+        let program = PostProcessor::new(Dialect::marlin())
+            .post(&toolpath)
+            .unwrap();
+        assert!(!program.contains("G18"));
+        assert!(program.contains("\nG2 I-5 J0\n"));
     }
 }
@@ -0,0 +1,719 @@
+//! Reads G-code programs back into toolpaths, e.g. to preview programs produced by other
+//! software.
+//!
+//! The parser follows the modal state of the controller (motion mode, units, distance modes,
+//! arc plane, feed rate, spindle, tool), supports arcs given by their center (IJK) or radius
+//! (R), expands the canned drilling cycles G73, G81, G82, G83 and G85 and keeps comments. Words
+//! which do not affect the path of the cutter, like work coordinate systems, are ignored. Words
+//! which would, like coordinate offsets (G92) or cutter compensation, get reported as
+//! warnings.
+//!
+//! The machine is assumed to start at the work origin.
+
+use std::fmt;
+
+use nalgebra::{Vector2, Vector3};
+
+use kelocam_core::cnc::Units;
+use kelocam_toolpath::{ArcDirection, ArcPlane, Coolant, Spindle, Toolpath};
+
+use crate::dialect::DwellUnits;
+
+/// A problem in a line of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub toolpath: Toolpath,
+    /// Words that were ignored although they might change the path of the cutter. Every message
+    /// is only reported for the first line it occurs in.
+    pub warnings: Vec<ParseError>,
+}
+
+/// Reads G-code programs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parser {
+    /// The unit of the P word of dwells (G4) and of drilling cycles with a dwell (G82).
+    pub dwell: DwellUnits,
+    /// How far the chip breaking drilling cycle (G73) retracts after every peck, in millimeters.
+    pub peck_retract: f32,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            dwell: DwellUnits::Seconds,
+            peck_retract: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Cycle {
+    /// G73, drilling with chip breaking.
+    ChipBreak,
+    /// G81, drilling.
+    Drill,
+    /// G82, drilling with a dwell at the bottom.
+    Dwell,
+    /// G83, peck drilling, retracting after every peck.
+    Peck,
+    /// G85, boring, feeding out.
+    Bore,
+}
+
+/// The tool length offset of the controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ToolLength {
+    /// The offset stored for the tool number (G43 H).
+    Table(u32),
+    /// The offset along Z in millimeters (G43.1).
+    Dynamic(f32),
+    /// No offset (G49).
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Motion {
+    Rapid,
+    Linear,
+    Arc(ArcDirection),
+    Cycle(Cycle),
+}
+
+impl Motion {
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Rapid => "G0",
+            Self::Linear => "G1",
+            Self::Arc(ArcDirection::Clockwise) => "G2",
+            Self::Arc(ArcDirection::CounterClockwise) => "G3",
+            Self::Cycle(Cycle::ChipBreak) => "G73",
+            Self::Cycle(Cycle::Drill) => "G81",
+            Self::Cycle(Cycle::Dwell) => "G82",
+            Self::Cycle(Cycle::Peck) => "G83",
+            Self::Cycle(Cycle::Bore) => "G85",
+        }
+    }
+}
+
+/// The modal state of the controller.
+pub(crate) struct State {
+    pub units: Units,
+    pub absolute: bool,
+    /// Whether arc centers are absolute (G90.1) instead of relative to the start (G91.1).
+    pub absolute_centers: bool,
+    pub plane: ArcPlane,
+    /// The work coordinate system times ten, e.g. 591 for G59.1.
+    pub coordinates: Option<u32>,
+    pub motion: Option<Motion>,
+    /// The feed rate in millimeters per minute.
+    pub feed: f32,
+    pub speed: f32,
+    pub tool: u32,
+    /// The tool length offset, None if the program never set it.
+    pub tool_length: Option<ToolLength>,
+    pub spindle: Spindle,
+    pub mist: bool,
+    pub flood: bool,
+    pub position: Vector3<f32>,
+    /// Whether drilling cycles retract to the initial height (G98) instead of the R plane (G99).
+    pub retract_initial: bool,
+    /// The height before the current drilling cycle started.
+    initial: f32,
+    /// The sticky parameters of drilling cycles: R plane, bottom, peck depth and dwell.
+    pub cycle: [Option<f32>; 4],
+    /// Whether the program ended (M2, M30).
+    pub ended: bool,
+}
+
+/// The words of a block, in the order they appear.
+pub(crate) struct Block {
+    /// G codes times ten, e.g. 911 for G91.1.
+    pub g: Vec<u32>,
+    m: Vec<u32>,
+    words: Vec<(char, f32)>,
+}
+
+impl Block {
+    pub fn get(&self, letter: char) -> Option<f32> {
+        self.words
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, value)| *value)
+    }
+}
+
+impl Parser {
+    pub fn parse(&self, source: &str) -> Result<Program, ParseError> {
+        self.run(source.lines()).map(|(program, _)| program)
+    }
+
+    /// Parses the lines, returning the program and the modal state at its end.
+    pub(crate) fn run<'a, I>(&self, lines: I) -> Result<(Program, State), ParseError>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut state = State {
+            units: Units::Millimeters,
+            absolute: true,
+            absolute_centers: false,
+            plane: ArcPlane::XY,
+            coordinates: None,
+            motion: None,
+            feed: 0.0,
+            speed: 0.0,
+            tool: 0,
+            tool_length: None,
+            spindle: Spindle::Off,
+            mist: false,
+            flood: false,
+            position: Vector3::zeros(),
+            retract_initial: true,
+            initial: 0.0,
+            cycle: [None; 4],
+            ended: false,
+        };
+        let mut program = Program {
+            toolpath: Toolpath::new(),
+            warnings: Vec::new(),
+        };
+
+        for (i, text) in lines.enumerate() {
+            let line = i + 1;
+            let error = |message: String| ParseError { line, message };
+            let (block, comments) = tokenize(text).map_err(error)?;
+            for comment in comments {
+                program.toolpath.comment(comment);
+            }
+
+            let mut warn = |message: String| {
+                if !program.warnings.iter().any(|w| w.message == message) {
+                    program.warnings.push(ParseError { line, message });
+                }
+            };
+            state.ended = self
+                .execute(&block, &mut state, &mut program.toolpath, &mut warn)
+                .map_err(error)?;
+            if state.ended {
+                break;
+            }
+        }
+        Ok((program, state))
+    }
+
+    /// Executes the block in the order defined by RS274/NGC. Returns whether the program ends.
+    fn execute<W>(
+        &self,
+        block: &Block,
+        state: &mut State,
+        toolpath: &mut Toolpath,
+        warn: &mut W,
+    ) -> Result<bool, String>
+    where
+        W: FnMut(String),
+    {
+        // Settings which apply to the whole block
+        for g in block.g.iter() {
+            match g {
+                170 => state.plane = ArcPlane::XY,
+                180 => state.plane = ArcPlane::ZX,
+                190 => state.plane = ArcPlane::YZ,
+                200 => state.units = Units::Inches,
+                210 => state.units = Units::Millimeters,
+                900 => state.absolute = true,
+                910 => state.absolute = false,
+                901 => state.absolute_centers = true,
+                911 => state.absolute_centers = false,
+                980 => state.retract_initial = true,
+                990 => state.retract_initial = false,
+                540..=599 => state.coordinates = Some(*g),
+                _ => {}
+            }
+        }
+        if let Some(feed) = block.get('F') {
+            state.feed = state.units.to_mm(feed);
+        }
+        if let Some(speed) = block.get('S') {
+            state.speed = speed;
+        }
+        if let Some(tool) = block.get('T') {
+            state.tool = tool as u32;
+        }
+        for g in block.g.iter() {
+            // Without H, G43 uses the offset of the selected tool
+            state.tool_length = match g {
+                430 => Some(ToolLength::Table(
+                    block.get('H').map_or(state.tool, |h| h as u32),
+                )),
+                431 => Some(ToolLength::Dynamic(
+                    state.units.to_mm(block.get('Z').unwrap_or(0.0)),
+                )),
+                490 => Some(ToolLength::Off),
+                _ => continue,
+            };
+        }
+
+        for m in block.m.iter() {
+            match m {
+                3 => state.spindle = Spindle::Clockwise(state.speed),
+                4 => state.spindle = Spindle::CounterClockwise(state.speed),
+                5 => state.spindle = Spindle::Off,
+                7 => state.mist = true,
+                8 => state.flood = true,
+                9 => (state.mist, state.flood) = (false, false),
+                _ => {}
+            }
+            match m {
+                3..=5 => toolpath.spindle(state.spindle),
+                6 => toolpath.tool_change(state.tool),
+                7 => toolpath.coolant(Coolant::Mist),
+                8 => toolpath.coolant(Coolant::Flood),
+                9 => toolpath.coolant(Coolant::Off),
+                0 | 1 | 2 | 30 | 48 | 49 => {}
+                m => warn(format!("M{} is not supported", m)),
+            }
+        }
+
+        let mut motion = None;
+        for g in block.g.iter() {
+            match g {
+                0 => motion = Some(Motion::Rapid),
+                10 => motion = Some(Motion::Linear),
+                20 => motion = Some(Motion::Arc(ArcDirection::Clockwise)),
+                30 => motion = Some(Motion::Arc(ArcDirection::CounterClockwise)),
+                730 => motion = Some(Motion::Cycle(Cycle::ChipBreak)),
+                810 => motion = Some(Motion::Cycle(Cycle::Drill)),
+                820 => motion = Some(Motion::Cycle(Cycle::Dwell)),
+                830 => motion = Some(Motion::Cycle(Cycle::Peck)),
+                850 => motion = Some(Motion::Cycle(Cycle::Bore)),
+                800 => state.motion = None,
+                40 => {
+                    let seconds = block.get('P').or(block.get('X')).unwrap_or(0.0);
+                    toolpath.dwell(match self.dwell {
+                        DwellUnits::Seconds => seconds,
+                        DwellUnits::Milliseconds => seconds / 1000.0,
+                    });
+                }
+                // Modes which do not change the path of the cutter
+                170
+                | 180
+                | 190
+                | 200
+                | 210
+                | 400
+                | 430
+                | 431
+                | 490
+                | 540..=599
+                | 610
+                | 611
+                | 640
+                | 900
+                | 901
+                | 910
+                | 911
+                | 940
+                | 980
+                | 990 => {}
+                g => warn(format!("G{} is not supported", format_code(*g))),
+            }
+        }
+        if block.g.iter().any(|g| matches!(g, 410 | 420)) {
+            warn("cutter compensation is not supported".into());
+        }
+        if ['A', 'B', 'C', 'U', 'V', 'W']
+            .iter()
+            .any(|letter| block.get(*letter).is_some())
+        {
+            warn("rotary and secondary axes are not supported".into());
+        }
+
+        // A new drilling cycle remembers the height it started at
+        if let Some(Motion::Cycle(_)) = motion {
+            if !matches!(state.motion, Some(Motion::Cycle(_))) {
+                state.initial = state.position.z;
+                state.cycle = [None; 4];
+            }
+        }
+        if let Some(motion) = motion {
+            state.motion = Some(motion);
+        }
+
+        // Motion. Some controls give the duration of dwells as X, which is not a coordinate.
+        // Offsets (G10, G43.1, G92), moves to stored positions (G28, G30) and moves in machine
+        // coordinates (G53) take the axis words as parameters, which are ignored with them.
+        let axes = ['X', 'Y', 'Z'].map(|letter| block.get(letter));
+        let parameters = (block.g.iter())
+            .any(|g| matches!(g, 40 | 100 | 280 | 281 | 300 | 301 | 431 | 530 | 920));
+        let moves = axes.iter().any(Option::is_some) && !parameters;
+        // Drilling cycles drill at the current position when they start
+        let drills = matches!(motion, Some(Motion::Cycle(_)));
+        if moves || drills {
+            match state.motion {
+                None => return Err("coordinates without a motion mode".into()),
+                Some(Motion::Rapid) => {
+                    state.position = target(state, &axes);
+                    toolpath.rapid(state.position);
+                }
+                Some(Motion::Linear) => {
+                    let from = state.position;
+                    state.position = target(state, &axes);
+                    let to = state.position;
+                    if (to.xy() - from.xy()).magnitude() < 1e-6 && to.z < from.z {
+                        toolpath.plunge(to, state.feed);
+                    } else {
+                        toolpath.feed(to, state.feed);
+                    }
+                }
+                Some(Motion::Arc(direction)) => {
+                    let from = state.position;
+                    let to = target(state, &axes);
+                    let center = center(state, block, &from, &to, direction)?;
+                    toolpath.arc_in(state.plane, to, center, direction, state.feed);
+                    state.position = to;
+                }
+                Some(Motion::Cycle(cycle)) => self.cycle(cycle, block, state, toolpath)?,
+            }
+        }
+
+        Ok(block.m.iter().any(|m| matches!(m, 2 | 30)))
+    }
+
+    /// Drills a hole with the cycle at the position of the block.
+    fn cycle(
+        &self,
+        cycle: Cycle,
+        block: &Block,
+        state: &mut State,
+        toolpath: &mut Toolpath,
+    ) -> Result<(), String> {
+        let unit = state.units.to_mm(1.0);
+        for (i, letter) in ['R', 'Z', 'Q', 'P'].into_iter().enumerate() {
+            if let Some(value) = block.get(letter) {
+                state.cycle[i] = Some(value);
+            }
+        }
+        let [Some(r), Some(z)] = [state.cycle[0], state.cycle[1]] else {
+            return Err("drilling cycles need R and Z".into());
+        };
+        // Incremental cycles give R relative to the initial height and Z relative to R
+        let (r, bottom) = if state.absolute {
+            (r * unit, z * unit)
+        } else {
+            (state.initial + r * unit, state.initial + (r + z) * unit)
+        };
+
+        let mut xy = state.position.xy();
+        for (i, letter) in ['X', 'Y'].into_iter().enumerate() {
+            if let Some(value) = block.get(letter) {
+                xy[i] = if state.absolute {
+                    value * unit
+                } else {
+                    xy[i] + value * unit
+                };
+            }
+        }
+
+        let at = |z: f32| Vector3::new(xy.x, xy.y, z);
+        if state.position.z < r {
+            toolpath.rapid(Vector3::new(state.position.x, state.position.y, r));
+        }
+        toolpath.rapid(at(state.position.z.max(r)));
+        toolpath.rapid(at(r));
+
+        let feed = state.feed;
+        match cycle {
+            Cycle::Drill | Cycle::Dwell | Cycle::Bore => {
+                toolpath.plunge(at(bottom), feed);
+                if cycle == Cycle::Dwell {
+                    let dwell = state.cycle[3].unwrap_or(0.0);
+                    toolpath.dwell(match self.dwell {
+                        DwellUnits::Seconds => dwell,
+                        DwellUnits::Milliseconds => dwell / 1000.0,
+                    });
+                }
+                if cycle == Cycle::Bore {
+                    toolpath.feed(at(r), feed);
+                }
+            }
+            Cycle::Peck | Cycle::ChipBreak => {
+                let peck = state.cycle[2].map(|q| q.abs() * unit);
+                let peck = peck.filter(|peck| *peck > 1e-3).unwrap_or(r - bottom);
+                let mut depth = r;
+                while depth > bottom + 1e-6 {
+                    let next = (depth - peck).max(bottom);
+                    if depth < r {
+                        // Return close to the previous depth at rapid speed
+                        let clearance = self.peck_retract.min(r - depth);
+                        toolpath.rapid(at(depth + clearance));
+                    }
+                    toolpath.plunge(at(next), feed);
+                    if next > bottom + 1e-6 {
+                        match cycle {
+                            Cycle::Peck => toolpath.rapid(at(r)),
+                            _ => toolpath.rapid(at(next + self.peck_retract.min(r - next))),
+                        }
+                    }
+                    depth = next;
+                }
+            }
+        }
+
+        let retract = if state.retract_initial {
+            state.initial.max(r)
+        } else {
+            r
+        };
+        toolpath.rapid(at(retract));
+        state.position = at(retract);
+        Ok(())
+    }
+}
+
+/// Formats a G code times ten, e.g. "91.1" for 911.
+pub(crate) fn format_code(code: u32) -> String {
+    match code % 10 {
+        0 => format!("{}", code / 10),
+        fraction => format!("{}.{}", code / 10, fraction),
+    }
+}
+
+/// The target of a move with the axis words, in millimeters.
+fn target(state: &State, axes: &[Option<f32>; 3]) -> Vector3<f32> {
+    let unit = state.units.to_mm(1.0);
+    let mut target = state.position;
+    for (i, value) in axes.iter().enumerate() {
+        if let Some(value) = value {
+            target[i] = if state.absolute {
+                value * unit
+            } else {
+                target[i] + value * unit
+            };
+        }
+    }
+    target
+}
+
+/// The center of an arc in the coordinates of the active plane.
+fn center(
+    state: &State,
+    block: &Block,
+    from: &Vector3<f32>,
+    to: &Vector3<f32>,
+    direction: ArcDirection,
+) -> Result<Vector2<f32>, String> {
+    let unit = state.units.to_mm(1.0);
+    let plane = state.plane;
+    let (start, end) = (plane.project(from), plane.project(to));
+
+    if let Some(radius) = block.get('R') {
+        // The center lies on the bisector of the chord, to the right of it for clockwise arcs of
+        // less than 180 degrees
+        let radius = radius * unit;
+        let chord = end - start;
+        let length = chord.magnitude();
+        if length < 1e-6 {
+            return Err("arcs given by their radius can not be full circles".into());
+        }
+        let height = (radius.powi(2) - (length * 0.5).powi(2)).max(0.0).sqrt();
+        let left = Vector2::new(-chord.y, chord.x) / length;
+        let side = match direction {
+            ArcDirection::Clockwise => -1.0,
+            ArcDirection::CounterClockwise => 1.0,
+        } * radius.signum();
+        return Ok(start + chord * 0.5 + left * height * side);
+    }
+
+    let [a, b, _] = plane.axes();
+    let letters = ['I', 'J', 'K'];
+    let (i, j) = (block.get(letters[a]), block.get(letters[b]));
+    if i.is_none() && j.is_none() {
+        return Err("arcs need a center (IJK) or a radius (R)".into());
+    }
+    let offset = Vector2::new(i.unwrap_or(0.0), j.unwrap_or(0.0)) * unit;
+    Ok(if state.absolute_centers {
+        offset
+    } else {
+        start + offset
+    })
+}
+
+/// Splits a line into its words and comments.
+pub(crate) fn tokenize(line: &str) -> Result<(Block, Vec<String>), String> {
+    let mut comments = Vec::new();
+    let mut code = String::new();
+
+    let mut chars = line.trim().chars().peekable();
+    // Block delete
+    if chars.peek() == Some(&'/') {
+        chars.next();
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != ')').collect();
+                comments.push(comment.trim().to_string());
+            }
+            ';' => {
+                comments.push(chars.by_ref().collect::<String>().trim().to_string());
+            }
+            // Checksums of sent lines
+            '*' => break,
+            '%' => {}
+            c if c.is_whitespace() => {}
+            c => code.push(c.to_ascii_uppercase()),
+        }
+    }
+    comments.retain(|comment| !comment.is_empty());
+
+    let mut block = Block {
+        g: Vec::new(),
+        m: Vec::new(),
+        words: Vec::new(),
+    };
+    let mut chars = code.chars().peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() {
+            return Err(format!("unexpected '{}'", letter));
+        }
+        let mut number = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() || c == '.' || ((c == '-' || c == '+') && number.is_empty()) {
+                number.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let value: f32 = number
+            .parse()
+            .map_err(|_| format!("invalid number '{}' after {}", number, letter))?;
+
+        match letter {
+            'G' => block.g.push((value * 10.0).round() as u32),
+            'M' => block.m.push(value.round() as u32),
+            // Line numbers and program numbers
+            'N' | 'O' => {}
+            letter => block.words.push((letter, value)),
+        }
+    }
+    Ok((block, comments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kelocam_toolpath::Move;
+
+    use crate::{Dialect, PostProcessor};
+
+    #[test]
+    fn round_trip() {
+        let mut toolpath = Toolpath::new();
+        toolpath.tool_change(2);
+        toolpath.spindle(Spindle::Clockwise(18000.0));
+        toolpath.comment("Contour");
+        toolpath.rapid(Vector3::new(10.0, 0.0, 5.0));
+        toolpath.plunge(Vector3::new(10.0, 0.0, -1.0), 300.0);
+        toolpath.arc(
+            Vector3::new(-10.0, 0.0, -1.0),
+            Vector2::zeros(),
+            ArcDirection::CounterClockwise,
+            1200.0,
+        );
+        toolpath.arc_in(
+            ArcPlane::ZX,
+            Vector3::new(-5.0, 0.0, 4.0),
+            Vector2::new(-1.0, -5.0),
+            ArcDirection::Clockwise,
+            1200.0,
+        );
+        toolpath.feed(Vector3::new(-5.0, 3.0, 4.0), 1200.0);
+        toolpath.spindle(Spindle::Off);
+
+        for dialect in [Dialect::linuxcnc(), Dialect::fanuc()] {
+            let program = PostProcessor::new(dialect).post(&toolpath).unwrap();
+            let parsed = Parser::default().parse(&program).unwrap();
+            assert!(parsed.warnings.is_empty());
+
+            // FANUC programs use radius arcs, compare the interpolated paths
+            let path = |toolpath: &Toolpath| {
+                let mut position = Vector3::zeros();
+                let mut points = Vec::new();
+                for m in toolpath.moves.iter() {
+                    points.extend(m.interpolate(&position, 0.01));
+                    position = m.to().copied().unwrap_or(position);
+                }
+                points
+            };
+            let (expected, actual) = (path(&toolpath), path(&parsed.toolpath));
+            assert_eq!(expected.len(), actual.len());
+            for (a, b) in expected.iter().zip(actual.iter()) {
+                assert!((a - b).magnitude() < 1e-3, "{} != {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn drilling_cycles() {
+        let source = "G21 G90 G98\nG0 Z10\nG83 X5 Y5 Z-6 R2 Q2.5 F100\nX10\nG80\n";
+        let program = Parser::default().parse(source).unwrap();
+        let depths: Vec<f32> = (program.toolpath.moves.iter())
+            .filter_map(|m| match m {
+                Move::Plunge { to, .. } => Some(to.z),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, [-0.5, -3.0, -5.5, -6.0, -0.5, -3.0, -5.5, -6.0]);
+        assert_eq!(
+            program.toolpath.position(),
+            Some(&Vector3::new(10.0, 5.0, 10.0))
+        );
+    }
+
+    #[test]
+    fn modal_state_and_errors() {
+        let source = "G20 G91 (inches)\nG0 X1 Y1\nG1 X1 F10 ; feed\nG92 X0\nG1 A5 X1\nM30\nG0 X9";
+        let program = Parser::default().parse(source).unwrap();
+        assert_eq!(
+            program.toolpath.position(),
+            Some(&Vector3::new(76.2, 25.4, 0.0))
+        );
+        assert_eq!(program.toolpath.moves[0], Move::Comment("inches".into()));
+        assert_eq!(program.toolpath.moves[3].feed(), Some(254.0));
+        assert_eq!(program.warnings.len(), 2);
+        assert_eq!(program.warnings[0].line, 4);
+
+        // Axis words of non-modal codes are no coordinates
+        let source =
+            "G21 G90\nG0 X5 Y5 Z5\nG92 X0 Y0\nG28 Z10\nG53 G0 Z0\nG10 L2 P1 X3\nG1 X6 F100";
+        let program = Parser::default().parse(source).unwrap();
+        assert_eq!(program.toolpath.positions().count(), 2);
+        assert_eq!(
+            program.toolpath.position(),
+            Some(&Vector3::new(6.0, 5.0, 5.0))
+        );
+        assert_eq!(program.warnings.len(), 4);
+
+        let error = Parser::default().parse("G0 X1\nG1 X2 Y\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid number '' after Y");
+        let error = Parser::default().parse("X1\n").unwrap_err();
+        assert_eq!(error.line, 1);
+    }
+}
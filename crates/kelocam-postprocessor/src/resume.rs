@@ -0,0 +1,279 @@
+//! Resuming interrupted jobs part way through, e.g. after an error or a power loss, instead of
+//! running them again from the start.
+//!
+//! The modal state of the controller at the resume point (units, work coordinate system, tool
+//! length offset, spindle, coolant, tool and feed rate) is restored by a preamble, which also
+//! brings the cutter back safely: it retracts to a safe height, starts the spindle, moves above
+//! the position the cutter had before the resumed move and plunges to it. The machine has to be
+//! homed and its work offsets have to be the same as before, the preamble moves in work
+//! coordinates.
+
+use kelocam_core::cnc::Units;
+use kelocam_toolpath::{ArcPlane, Spindle};
+
+use crate::dialect::DwellUnits;
+use crate::parser::{format_code, tokenize, Motion, ToolLength};
+use crate::writer::format_number;
+use crate::{ParseError, Parser};
+
+/// The G codes of motion modes, times ten.
+const MOTION_CODES: [u32; 10] = [0, 10, 20, 30, 730, 800, 810, 820, 830, 850];
+
+/// How the preamble of a resumed job brings the cutter back to where the job continues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resume {
+    /// The height the cutter retracts to before moving to the resume position, in millimeters.
+    pub safe_z: f32,
+    /// The feed rate of the plunge to the resume position, in mm/min. Without one, the cutter
+    /// plunges at the feed rate of the program at that point.
+    pub plunge_feed: Option<f32>,
+    /// How long to wait for the spindle to get up to speed, in seconds.
+    pub spindle_delay: f32,
+}
+
+impl Default for Resume {
+    fn default() -> Self {
+        Self {
+            safe_z: 10.0,
+            plunge_feed: None,
+            spindle_delay: 3.0,
+        }
+    }
+}
+
+impl Resume {
+    /// The program continuing at the line, starting at 1. The lines before are only read to
+    /// find the modal state. The preamble selects the tool with a T word but does not change
+    /// it, the tool in the spindle has to be the right one. Fails if the lines before contain
+    /// codes the parser ignores, like G92 offsets, as the preamble can not restore their state.
+    pub fn program(
+        &self,
+        source: &str,
+        line: usize,
+        parser: &Parser,
+    ) -> Result<String, ParseError> {
+        let lines: Vec<&str> = source.lines().collect();
+        let error = |message: String| ParseError { line, message };
+        if line == 0 || line > lines.len() {
+            return Err(error(format!("the program has {} lines", lines.len())));
+        }
+        let (skipped, state) = parser.run(lines[..line - 1].iter().copied())?;
+        if let Some(warning) = skipped.warnings.into_iter().next() {
+            return Err(ParseError {
+                line: warning.line,
+                message: format!("{}, resuming after it is not possible", warning.message),
+            });
+        }
+        if state.ended {
+            return Err(error("the program ends before this line".into()));
+        }
+
+        let number = |value: f32| format_number(value, 4, true, false);
+        let length = |value: f32| number(state.units.from_mm(value));
+        let mut blocks = Vec::new();
+
+        let mut modes = vec![
+            match state.units {
+                Units::Millimeters => "G21".to_string(),
+                Units::Inches => "G20".to_string(),
+            },
+            match state.plane {
+                ArcPlane::XY => "G17".to_string(),
+                ArcPlane::ZX => "G18".to_string(),
+                ArcPlane::YZ => "G19".to_string(),
+            },
+            "G90".to_string(),
+        ];
+        modes.extend(state.coordinates.map(|g| format!("G{}", format_code(g))));
+        blocks.push(modes.join(" "));
+        // The safe height and the position are measured with the offset of the tool
+        match state.tool_length {
+            Some(ToolLength::Table(h)) => blocks.push(format!("G43 H{}", h)),
+            Some(ToolLength::Dynamic(z)) => blocks.push(format!("G43.1 Z{}", length(z))),
+            Some(ToolLength::Off) => blocks.push("G49".into()),
+            None => {}
+        }
+
+        let position = state.position;
+        let safe_z = self.safe_z.max(position.z);
+        blocks.push(format!("G0 Z{}", length(safe_z)));
+
+        if state.tool > 0 {
+            blocks.push(format!("T{}", state.tool));
+        }
+        let spindle = match state.spindle {
+            Spindle::Clockwise(_) => Some("M3"),
+            Spindle::CounterClockwise(_) => Some("M4"),
+            Spindle::Off => None,
+        };
+        if let Some(code) = spindle {
+            blocks.push(format!("{} S{:.0}", code, state.speed));
+            if self.spindle_delay > 0.0 {
+                let dwell = match parser.dwell {
+                    DwellUnits::Seconds => number(self.spindle_delay),
+                    DwellUnits::Milliseconds => format!("{:.0}", self.spindle_delay * 1000.0),
+                };
+                blocks.push(format!("G4 P{}", dwell));
+            }
+        }
+        if state.mist {
+            blocks.push("M7".into());
+        }
+        if state.flood {
+            blocks.push("M8".into());
+        }
+
+        blocks.push(format!(
+            "G0 X{} Y{}",
+            length(position.x),
+            length(position.y)
+        ));
+        let feed = (state.feed > 0.0).then_some(state.feed);
+        let mut plunge_feed = None;
+        if position.z < safe_z {
+            plunge_feed = self.plunge_feed.or(feed);
+            blocks.push(match plunge_feed {
+                Some(feed) => format!("G1 Z{} F{}", length(position.z), length(feed)),
+                // Nothing was cut yet
+                None => format!("G0 Z{}", length(position.z)),
+            });
+        }
+
+        // Back to the modes of the program
+        let mut modes = Vec::new();
+        if !state.absolute {
+            modes.push("G91".to_string());
+        }
+        if state.absolute_centers {
+            modes.push("G90.1".to_string());
+        }
+        if !state.retract_initial {
+            modes.push("G99".to_string());
+        }
+        if let Some(feed) = feed.filter(|feed| plunge_feed != Some(*feed)) {
+            modes.push(format!("F{}", length(feed)));
+        }
+        if !modes.is_empty() {
+            blocks.push(modes.join(" "));
+        }
+
+        // The motion mode is only set by the resumed line if it has a motion code. Drilling
+        // cycles need their sticky words as well.
+        let (block, _) = tokenize(lines[line - 1]).map_err(error)?;
+        let mut first = lines[line - 1].trim().to_string();
+        let axes = ['X', 'Y', 'Z']
+            .iter()
+            .any(|letter| block.get(*letter).is_some());
+        let modal = block.g.iter().any(|g| MOTION_CODES.contains(g));
+        if let Some(motion) = state.motion.filter(|_| axes && !modal) {
+            let mut words = vec![motion.code().to_string()];
+            if let Motion::Cycle(_) = motion {
+                for (letter, value) in ['R', 'Z', 'Q', 'P'].into_iter().zip(state.cycle) {
+                    if let Some(value) = value.filter(|_| block.get(letter).is_none()) {
+                        words.push(format!("{}{}", letter, number(value)));
+                    }
+                }
+            }
+            // Line numbers come first
+            let (number, rest) = match first.split_once(char::is_whitespace) {
+                Some((number, rest)) if number.starts_with(['N', 'n']) => {
+                    (format!("{} ", number), rest.trim_start().to_string())
+                }
+                _ => (String::new(), first.clone()),
+            };
+            first = format!("{}{} {}", number, words.join(" "), rest);
+        }
+        blocks.push(first);
+
+        blocks.extend(lines[line..].iter().map(|line| line.to_string()));
+        let mut program = blocks.join("\n");
+        program.push('\n');
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kelocam_toolpath::{Coolant, Toolpath};
+    use nalgebra::Vector3;
+
+    use crate::{Dialect, PostProcessor};
+
+    const PROGRAM: &str = "G21 G90 G55
+T2 M6
+S12000 M3
+M8
+G0 X0 Y0 Z5
+G1 Z-1 F300
+X10 F800
+Y10
+G81 X20 Y20 R2 Z-5 F100
+X30
+M30
+";
+
+    #[test]
+    fn resume_lines() {
+        let resume = Resume::default();
+        let parser = Parser::default();
+        assert_eq!(
+            resume.program(PROGRAM, 8, &parser).unwrap(),
+            "G21 G17 G90 G55\nG0 Z10\nT2\nM3 S12000\nG4 P3\nM8\nG0 X10 Y0\nG1 Z-1 F800\n\
+             G1 Y10\nG81 X20 Y20 R2 Z-5 F100\nX30\nM30\n"
+        );
+
+        let resume = Resume {
+            plunge_feed: Some(200.0),
+            spindle_delay: 0.0,
+            ..resume
+        };
+        let program = resume.program(PROGRAM, 10, &parser).unwrap();
+        assert!(program.ends_with("G0 X20 Y20\nG1 Z2 F200\nF100\nG81 R2 Z-5 X30\nM30\n"));
+
+        assert_eq!(resume.program(PROGRAM, 12, &parser).unwrap_err().line, 12);
+        assert_eq!(resume.program(PROGRAM, 0, &parser).unwrap_err().line, 0);
+        // Tool length offsets are restored before moving
+        let program = PROGRAM.replace("G0 X0 Y0 Z5", "G43 H2\nG0 X0 Y0 Z5");
+        let resumed = resume.program(&program, 9, &parser).unwrap();
+        assert!(resumed.starts_with("G21 G17 G90 G55\nG43 H2\nG0 Z10\n"));
+        let program = PROGRAM.replace("G0 X0 Y0 Z5", "G20 G43.1 Z0.5\nG21\nG0 X0 Y0 Z5");
+        let resumed = resume.program(&program, 9, &parser).unwrap();
+        assert!(resumed.starts_with("G21 G17 G90 G55\nG43.1 Z12.7\nG0 Z10\n"));
+        let program = PROGRAM.replace("M30", "G49\nM30");
+        assert!(resume
+            .program(&program, 12, &parser)
+            .unwrap()
+            .contains("\nG49\n"));
+
+        let ended = format!("{}G0 X0\n", PROGRAM);
+        assert!(resume.program(&ended, 12, &parser).is_err());
+
+        // The offset set by G92 would be lost
+        let program = PROGRAM.replace("G0 X0 Y0 Z5", "G92 X5 Y5\nG0 X0 Y0 Z5");
+        let error = resume.program(&program, 9, &parser).unwrap_err();
+        assert_eq!(error.line, 5);
+        assert!(error.message.starts_with("G92 is not supported"));
+        assert!(resume.program(&program, 5, &parser).is_ok());
+    }
+
+    #[test]
+    fn resume_toolpath() {
+        let mut toolpath = Toolpath::new();
+        toolpath.tool_change(1);
+        toolpath.spindle(Spindle::Clockwise(10000.0));
+        toolpath.coolant(Coolant::Flood);
+        toolpath.rapid(Vector3::new(0.0, 0.0, 5.0));
+        toolpath.plunge(Vector3::new(0.0, 0.0, -2.0), 300.0);
+        toolpath.feed(Vector3::new(10.0, 0.0, -2.0), 1000.0);
+        toolpath.feed(Vector3::new(10.0, 10.0, -2.0), 1000.0);
+
+        let post = PostProcessor::new(Dialect::linuxcnc());
+        assert_eq!(
+            post.resume(&toolpath, 6, &Resume::default()).unwrap(),
+            "G17 G21 G90\nG0 Z10\nT1 M6\nM3 S10000\nG4 P3\nM8\nX10 Y0\nG1 Z-2 F1000\n\
+             Y10\nM5\nM9\nM2\n"
+        );
+    }
+}
@@ -0,0 +1,742 @@
+//! Post-processors defined by template files, for controllers none of the built-in dialects
+//! covers.
+//!
+//! Templates are TOML files. Every instruction of a toolpath has a template with one or more
+//! blocks, in which variables like `{x}` get replaced by their values:
+//!
+//! ```toml
+//! name = "Old mill"
+//! precision = 2
+//! decimal_point = true
+//! line_numbers = { start = 1, increment = 1 }
+//! header = ["%"]
+//! footer = ["M30", "%"]
+//!
+//! [moves]
+//! rapid = "G00 X{x} Y{y} Z{z}"
+//! feed = "G01 X{x} Y{y} Z{z} F{f}"
+//! arc_cw = "G02 X{x} Y{y} R{r} F{f}"
+//! arc_ccw = "G03 X{x} Y{y} R{r} F{f}"
+//! tool_change = ["M05", "T{t}", "M00"]
+//! spindle_cw = "S{s} M03"
+//! spindle_off = "M05"
+//! comment = "({text})"
+//! ```
+//!
+//! The available variables are `x`, `y`, `z` (the target of moves), `f` (the feed rate), `i`, `j`
+//! (the center of arcs relative to their start), `r` (the radius of arcs, negative for arcs of
+//! more than 180 degrees), `s` (the spindle speed), `t` (the tool number), `seconds`,
+//! `milliseconds` (the duration of dwells) and `text` (comments). Literal braces are written as
+//! `{{` and `}}`.
+//!
+//! With `modal` enabled (the default), words whose variables are all unchanged coordinates or
+//! feed rates are left out, as is the leading code of motion blocks if it repeats the previous
+//! motion code. Blocks whose variable words were all left out are skipped entirely.
+//!
+//! Only `rapid` and `feed` are required. Plunges use the feed template unless `plunge` is
+//! given, arcs without `arc_cw` and `arc_ccw` templates or outside of the XY plane get
+//! approximated by feed moves, comments and coolant without a template are left out. Other
+//! instructions without a template can not be posted.
+
+use std::{fmt, fs, io, path::Path};
+
+use nalgebra::{Vector2, Vector3};
+use serde::Deserialize;
+
+use kelocam_core::cnc::Units;
+use kelocam_toolpath::{ArcDirection, ArcPlane, Coolant, Move, Spindle, Toolpath};
+
+use crate::writer::Writer;
+use crate::{Dialect, LineNumbers, PostError, PostProcessor};
+
+/// The maximum deviation of feed moves approximating an arc, in millimeters.
+const ARC_TOLERANCE: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    X,
+    Y,
+    Z,
+    F,
+    I,
+    J,
+    R,
+    S,
+    T,
+    Seconds,
+    Milliseconds,
+    Text,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "x" => Self::X,
+            "y" => Self::Y,
+            "z" => Self::Z,
+            "f" => Self::F,
+            "i" => Self::I,
+            "j" => Self::J,
+            "r" => Self::R,
+            "s" => Self::S,
+            "t" => Self::T,
+            "seconds" => Self::Seconds,
+            "milliseconds" => Self::Milliseconds,
+            "text" => Self::Text,
+            _ => return None,
+        })
+    }
+
+    /// The index of the modal state of the controller this variable holds (if any).
+    fn modal(&self) -> Option<usize> {
+        match self {
+            Self::X => Some(0),
+            Self::Y => Some(1),
+            Self::Z => Some(2),
+            Self::F => Some(3),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable(Variable),
+}
+
+/// A block of a template, split into words at whitespace.
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    words: Vec<Vec<Segment>>,
+}
+
+impl Block {
+    /// Parses the block, allowing only the specified variables.
+    fn parse(source: &str, allowed: &[Variable]) -> Result<Self, String> {
+        let mut words = Vec::new();
+        for word in source.split_whitespace() {
+            let mut segments = Vec::new();
+            let mut text = String::new();
+            let mut chars = word.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '{' if chars.peek() == Some(&'{') => {
+                        chars.next();
+                        text.push('{');
+                    }
+                    '}' if chars.peek() == Some(&'}') => {
+                        chars.next();
+                        text.push('}');
+                    }
+                    '{' => {
+                        let mut name = String::new();
+                        loop {
+                            match chars.next() {
+                                Some('}') => break,
+                                Some(c) => name.push(c),
+                                None => return Err(format!("unterminated {{{}", name)),
+                            }
+                        }
+                        let variable = Variable::parse(&name)
+                            .ok_or_else(|| format!("unknown variable {{{}}}", name))?;
+                        if !allowed.contains(&variable) {
+                            return Err(format!("variable {{{}}} is not available here", name));
+                        }
+                        if !text.is_empty() {
+                            segments.push(Segment::Text(std::mem::take(&mut text)));
+                        }
+                        segments.push(Segment::Variable(variable));
+                    }
+                    '}' => return Err("unmatched }".into()),
+                    c => text.push(c),
+                }
+            }
+            if !text.is_empty() {
+                segments.push(Segment::Text(text));
+            }
+            words.push(segments);
+        }
+        Ok(Self { words })
+    }
+
+    fn variables(&self) -> impl Iterator<Item = Variable> + '_ {
+        self.words
+            .iter()
+            .flatten()
+            .filter_map(|segment| match segment {
+                Segment::Variable(variable) => Some(*variable),
+                Segment::Text(_) => None,
+            })
+    }
+}
+
+/// One or more blocks in a template file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Lines {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Lines {
+    fn parse(&self, name: &str, allowed: &[Variable]) -> Result<Vec<Block>, TemplateError> {
+        let lines = match self {
+            Self::One(line) => std::slice::from_ref(line),
+            Self::Many(lines) => lines.as_slice(),
+        };
+        lines
+            .iter()
+            .map(|line| {
+                Block::parse(line, allowed).map_err(|message| TemplateError::Template {
+                    name: name.into(),
+                    message,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MoveLines {
+    rapid: Lines,
+    feed: Lines,
+    plunge: Option<Lines>,
+    arc_cw: Option<Lines>,
+    arc_ccw: Option<Lines>,
+    dwell: Option<Lines>,
+    tool_change: Option<Lines>,
+    spindle_cw: Option<Lines>,
+    spindle_ccw: Option<Lines>,
+    spindle_off: Option<Lines>,
+    coolant_mist: Option<Lines>,
+    coolant_flood: Option<Lines>,
+    coolant_off: Option<Lines>,
+    comment: Option<Lines>,
+}
+
+fn default_precision() -> usize {
+    3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateFile {
+    name: String,
+    #[serde(default)]
+    units: Units,
+    #[serde(default = "default_precision")]
+    precision: usize,
+    #[serde(default = "default_true")]
+    trim_zeros: bool,
+    #[serde(default)]
+    decimal_point: bool,
+    #[serde(default = "default_true")]
+    modal: bool,
+    line_numbers: Option<LineNumbers>,
+    max_line_length: Option<usize>,
+    #[serde(default)]
+    header: Vec<String>,
+    #[serde(default)]
+    footer: Vec<String>,
+    moves: MoveLines,
+}
+
+/// The parsed templates of the instructions.
+#[derive(Debug, Clone, PartialEq)]
+struct Moves {
+    rapid: Vec<Block>,
+    feed: Vec<Block>,
+    plunge: Option<Vec<Block>>,
+    arc_cw: Option<Vec<Block>>,
+    arc_ccw: Option<Vec<Block>>,
+    dwell: Option<Vec<Block>>,
+    tool_change: Option<Vec<Block>>,
+    spindle_cw: Option<Vec<Block>>,
+    spindle_ccw: Option<Vec<Block>>,
+    spindle_off: Option<Vec<Block>>,
+    coolant_mist: Option<Vec<Block>>,
+    coolant_flood: Option<Vec<Block>>,
+    coolant_off: Option<Vec<Block>>,
+    comment: Option<Vec<Block>>,
+}
+
+/// A post-processor defined by a template file, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub units: Units,
+    /// The number of decimal places of coordinates and feed rates.
+    pub precision: usize,
+    /// See `PostProcessor::trim_zeros`.
+    pub trim_zeros: bool,
+    /// See `Dialect::decimal_point`.
+    pub decimal_point: bool,
+    /// Leave out unchanged coordinates, feed rates and motion codes.
+    pub modal: bool,
+    pub line_numbers: Option<LineNumbers>,
+    pub max_line_length: Option<usize>,
+    pub header: Vec<String>,
+    pub footer: Vec<String>,
+    moves: Moves,
+}
+
+impl Template {
+    /// Parse a template from its TOML representation.
+    pub fn from_toml(source: &str) -> Result<Self, TemplateError> {
+        use Variable::*;
+
+        let file: TemplateFile =
+            toml::from_str(source).map_err(|e| TemplateError::Parse(e.to_string()))?;
+        let lines = &file.moves;
+        let optional = |lines: &Option<Lines>, name: &str, allowed: &[Variable]| {
+            lines
+                .as_ref()
+                .map(|lines| lines.parse(name, allowed))
+                .transpose()
+        };
+
+        let moves = Moves {
+            rapid: lines.rapid.parse("rapid", &[X, Y, Z])?,
+            feed: lines.feed.parse("feed", &[X, Y, Z, F])?,
+            plunge: optional(&lines.plunge, "plunge", &[X, Y, Z, F])?,
+            arc_cw: optional(&lines.arc_cw, "arc_cw", &[X, Y, Z, F, I, J, R])?,
+            arc_ccw: optional(&lines.arc_ccw, "arc_ccw", &[X, Y, Z, F, I, J, R])?,
+            dwell: optional(&lines.dwell, "dwell", &[Seconds, Milliseconds])?,
+            tool_change: optional(&lines.tool_change, "tool_change", &[T])?,
+            spindle_cw: optional(&lines.spindle_cw, "spindle_cw", &[S])?,
+            spindle_ccw: optional(&lines.spindle_ccw, "spindle_ccw", &[S])?,
+            spindle_off: optional(&lines.spindle_off, "spindle_off", &[])?,
+            coolant_mist: optional(&lines.coolant_mist, "coolant_mist", &[])?,
+            coolant_flood: optional(&lines.coolant_flood, "coolant_flood", &[])?,
+            coolant_off: optional(&lines.coolant_off, "coolant_off", &[])?,
+            comment: optional(&lines.comment, "comment", &[Text])?,
+        };
+        if moves.arc_cw.is_some() != moves.arc_ccw.is_some() {
+            return Err(TemplateError::Template {
+                name: "arc_cw".into(),
+                message: "arc_cw and arc_ccw have to be given together".into(),
+            });
+        }
+
+        Ok(Self {
+            name: file.name,
+            units: file.units,
+            precision: file.precision,
+            trim_zeros: file.trim_zeros,
+            decimal_point: file.decimal_point,
+            modal: file.modal,
+            line_numbers: file.line_numbers,
+            max_line_length: file.max_line_length,
+            header: file.header,
+            footer: file.footer,
+            moves,
+        })
+    }
+
+    /// Load a template from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplateError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Generate the G-code program for the toolpath.
+    pub fn post(&self, toolpath: &Toolpath) -> Result<String, PostError> {
+        // The writer takes care of line numbers, line lengths and number formatting
+        let post = PostProcessor {
+            dialect: Dialect {
+                name: self.name.clone(),
+                max_line_length: self.max_line_length,
+                decimal_point: self.decimal_point,
+                ..Dialect::default()
+            },
+            units: self.units,
+            precision: self.precision,
+            trim_zeros: self.trim_zeros,
+            line_numbers: self.line_numbers,
+            modal: self.modal,
+            ..Default::default()
+        };
+        let mut renderer = Renderer {
+            template: self,
+            writer: Writer::new(&post),
+            scale: self.units.from_mm(1.0),
+            last: None,
+            state: [None, None, None, None],
+            motion: None,
+        };
+
+        for block in self.header.iter() {
+            renderer.writer.line(block)?;
+        }
+        for m in toolpath.moves.iter() {
+            renderer.write(m)?;
+        }
+        for block in self.footer.iter() {
+            renderer.writer.line(block)?;
+        }
+        Ok(renderer.writer.finish())
+    }
+}
+
+/// Fills in the templates, keeping track of the modal state of the controller.
+struct Renderer<'a> {
+    template: &'a Template,
+    writer: Writer<'a>,
+    /// Converts millimeters into program units.
+    scale: f32,
+    /// The position of the cutter after the last move, in millimeters.
+    last: Option<Vector3<f32>>,
+    /// The values of the modal variables as written to the program.
+    state: [Option<String>; 4],
+    /// The leading code of the last motion block.
+    motion: Option<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn write(&mut self, m: &Move) -> Result<(), PostError> {
+        let template = self.template;
+        let moves = &template.moves;
+        let number = |value: f32| self.number(value);
+
+        match m {
+            Move::Rapid(to) => {
+                let values = self.coordinates(to);
+                self.motion(&moves.rapid, &values, to)
+            }
+            Move::Plunge { to, feed } => {
+                let mut values = self.coordinates(to);
+                values.push((Variable::F, number(feed * self.scale)));
+                let blocks = moves.plunge.as_ref().unwrap_or(&moves.feed);
+                self.motion(blocks, &values, to)
+            }
+            Move::Feed { to, feed } => {
+                let mut values = self.coordinates(to);
+                values.push((Variable::F, number(feed * self.scale)));
+                self.motion(&moves.feed, &values, to)
+            }
+            Move::Arc {
+                to,
+                plane,
+                center,
+                direction,
+                feed,
+            } => self.arc(m, to, *plane, center, *direction, *feed),
+            Move::Dwell(seconds) => {
+                let values = [
+                    (Variable::Seconds, number(*seconds)),
+                    (Variable::Milliseconds, format!("{:.0}", seconds * 1000.0)),
+                ];
+                let blocks = moves
+                    .dwell
+                    .as_ref()
+                    .ok_or(PostError::Unsupported("dwell"))?;
+                self.blocks(blocks, &values)
+            }
+            Move::ToolChange(tool) => {
+                let blocks =
+                    (moves.tool_change.as_ref()).ok_or(PostError::Unsupported("tool changes"))?;
+                self.blocks(blocks, &[(Variable::T, tool.to_string())])
+            }
+            Move::Spindle(spindle) => {
+                let (blocks, rpm, feature) = match spindle {
+                    Spindle::Clockwise(rpm) => (&moves.spindle_cw, *rpm, "the spindle"),
+                    Spindle::CounterClockwise(rpm) => (
+                        &moves.spindle_ccw,
+                        *rpm,
+                        "counter clockwise spindle rotation",
+                    ),
+                    Spindle::Off => (&moves.spindle_off, 0.0, "turning off the spindle"),
+                };
+                let blocks = blocks.as_ref().ok_or(PostError::Unsupported(feature))?;
+                self.blocks(blocks, &[(Variable::S, format!("{:.0}", rpm))])
+            }
+            Move::Coolant(coolant) => {
+                let blocks = match coolant {
+                    Coolant::Mist => &moves.coolant_mist,
+                    Coolant::Flood => &moves.coolant_flood,
+                    Coolant::Off => &moves.coolant_off,
+                };
+                match blocks {
+                    Some(blocks) => self.blocks(blocks, &[]),
+                    None => Ok(()),
+                }
+            }
+            Move::Comment(text) => match &moves.comment {
+                Some(blocks) => {
+                    let text = text.replace(['\n', '\r'], " ");
+                    self.blocks(blocks, &[(Variable::Text, text)])
+                }
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn arc(
+        &mut self,
+        m: &Move,
+        to: &Vector3<f32>,
+        plane: ArcPlane,
+        center: &Vector2<f32>,
+        direction: ArcDirection,
+        feed: f32,
+    ) -> Result<(), PostError> {
+        let template = self.template;
+        let moves = &template.moves;
+        let from = self.last.unwrap_or_else(Vector3::zeros);
+        // The templates only cover arcs in the XY plane
+        let (Some(arc_cw), Some(arc_ccw), ArcPlane::XY) = (&moves.arc_cw, &moves.arc_ccw, plane)
+        else {
+            for point in m.interpolate(&from, ARC_TOLERANCE) {
+                self.write(&Move::Feed { to: point, feed })?;
+            }
+            return Ok(());
+        };
+        let blocks = match direction {
+            ArcDirection::Clockwise => arc_cw,
+            ArcDirection::CounterClockwise => arc_ccw,
+        };
+
+        // The radius does not define full circles, split them into two halves
+        let sweep = m.sweep(&from).unwrap_or_default().abs();
+        let radius = blocks
+            .iter()
+            .any(|block| block.variables().any(|v| v == Variable::R));
+        if radius && sweep > std::f32::consts::TAU - 1e-6 {
+            let middle = center * 2.0 - from.xy();
+            let middle = Vector3::new(middle.x, middle.y, (from.z + to.z) * 0.5);
+            for to in [middle, *to] {
+                self.write(&Move::Arc {
+                    to,
+                    plane,
+                    center: *center,
+                    direction,
+                    feed,
+                })?;
+            }
+            return Ok(());
+        }
+
+        let r = (from.xy() - center)
+            .magnitude()
+            .max((to.xy() - center).magnitude())
+            * self.scale;
+        let r = if sweep > std::f32::consts::PI { -r } else { r };
+        let mut values = self.coordinates(to);
+        values.extend([
+            (Variable::F, self.number(feed * self.scale)),
+            (Variable::I, self.number((center.x - from.x) * self.scale)),
+            (Variable::J, self.number((center.y - from.y) * self.scale)),
+            (Variable::R, self.number(r)),
+        ]);
+        self.motion(blocks, &values, to)
+    }
+
+    fn coordinates(&self, to: &Vector3<f32>) -> Vec<(Variable, String)> {
+        vec![
+            (Variable::X, self.number(to.x * self.scale)),
+            (Variable::Y, self.number(to.y * self.scale)),
+            (Variable::Z, self.number(to.z * self.scale)),
+        ]
+    }
+
+    fn motion(
+        &mut self,
+        blocks: &[Block],
+        values: &[(Variable, String)],
+        to: &Vector3<f32>,
+    ) -> Result<(), PostError> {
+        let mut lines = Vec::new();
+        for block in blocks {
+            if let Some(line) = self.render(block, values, true) {
+                lines.push(line);
+            }
+        }
+        self.last = Some(*to);
+        for (variable, value) in values {
+            if let Some(index) = variable.modal() {
+                self.state[index] = Some(value.clone());
+            }
+        }
+        lines.iter().try_for_each(|line| self.writer.line(line))
+    }
+
+    fn blocks(&mut self, blocks: &[Block], values: &[(Variable, String)]) -> Result<(), PostError> {
+        for block in blocks {
+            if let Some(line) = self.render(block, values, false) {
+                self.writer.line(&line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the block, returns None if the block is left out.
+    fn render(
+        &mut self,
+        block: &Block,
+        values: &[(Variable, String)],
+        motion: bool,
+    ) -> Option<String> {
+        let modal = self.template.modal;
+        let value = |variable: Variable| {
+            values
+                .iter()
+                .find(|(v, _)| *v == variable)
+                .map_or("", |(_, value)| value.as_str())
+        };
+
+        let mut words = Vec::new();
+        let mut variables = false;
+        let mut written = false;
+        for (i, word) in block.words.iter().enumerate() {
+            let mut text = String::new();
+            let mut changed = false;
+            let mut has_variables = false;
+            for segment in word {
+                match segment {
+                    Segment::Text(literal) => text.push_str(literal),
+                    Segment::Variable(variable) => {
+                        has_variables = true;
+                        let value = value(*variable);
+                        changed |= variable
+                            .modal()
+                            .map_or(true, |index| self.state[index].as_deref() != Some(value));
+                        text.push_str(value);
+                    }
+                }
+            }
+
+            if has_variables {
+                variables = true;
+                if modal && !changed {
+                    continue;
+                }
+                written = true;
+            } else if motion && i == 0 {
+                // The leading code of a motion block is modal
+                if modal && self.motion.as_deref() == Some(text.as_str()) {
+                    continue;
+                }
+                self.motion = Some(text.clone());
+            }
+            words.push(text);
+        }
+
+        (!variables || written).then(|| words.join(" "))
+    }
+
+    fn number(&self, value: f32) -> String {
+        crate::writer::format_number(
+            value,
+            self.template.precision,
+            self.template.trim_zeros,
+            self.template.decimal_point,
+        )
+    }
+}
+
+/// An error that occured while loading a template.
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(io::Error),
+    /// The template file could not be parsed.
+    Parse(String),
+    /// A template of an instruction is invalid.
+    Template {
+        name: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(message) => write!(f, "invalid post-processor template: {}", message),
+            Self::Template { name, message } => write!(f, "template {}: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<io::Error> for TemplateError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"
+        name = "Old mill"
+        precision = 2
+        decimal_point = true
+        line_numbers = { start = 1, increment = 1 }
+        header = ["%"]
+        footer = ["M30", "%"]
+
+        [moves]
+        rapid = "G00 X{x} Y{y} Z{z}"
+        feed = "G01 X{x} Y{y} Z{z} F{f}"
+        arc_cw = "G02 X{x} Y{y} R{r} F{f}"
+        arc_ccw = "G03 X{x} Y{y} R{r} F{f}"
+        tool_change = ["M05", "T{t}", "M00"]
+        spindle_cw = "S{s} M03"
+        comment = "({text})"
+    "#;
+
+    #[test]
+    fn post_with_template() {
+        let template = Template::from_toml(TEMPLATE).unwrap();
+
+        let mut toolpath = Toolpath::new();
+        toolpath.tool_change(3);
+        toolpath.spindle(Spindle::Clockwise(12000.0));
+        toolpath.comment("Finishing");
+        toolpath.rapid(Vector3::new(10.0, 0.0, 2.0));
+        toolpath.feed(Vector3::new(10.0, 0.0, -1.0), 200.0);
+        toolpath.feed(Vector3::new(20.0, 0.0, -1.0), 200.0);
+        toolpath.arc(
+            Vector3::new(20.0, 0.0, -1.0),
+            Vector2::new(15.0, 0.0),
+            ArcDirection::Clockwise,
+            200.0,
+        );
+
+        assert_eq!(
+            template.post(&toolpath).unwrap(),
+            "%\nN1 M05\nN2 T3\nN3 M00\nN4 S12000 M03\nN5 (Finishing)\nN6 G00 X10. Y0. Z2.\n\
+             N7 G01 Z-1. F200.\nN8 X20.\nN9 G02 X10. R5.\nN10 X20. R5.\nN11 M30\n%\n"
+        );
+        assert!(matches!(
+            template.post(&{
+                let mut toolpath = Toolpath::new();
+                toolpath.dwell(1.0);
+                toolpath
+            }),
+            Err(PostError::Unsupported("dwell"))
+        ));
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let error = Template::from_toml(&TEMPLATE.replace("{t}", "{q}")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "template tool_change: unknown variable {q}"
+        );
+        let error = Template::from_toml(&TEMPLATE.replace("T{t}", "T{s}")).unwrap_err();
+        assert!(matches!(error, TemplateError::Template { .. }));
+        let error = Template::from_toml(&TEMPLATE.replace("T{t}", "T{t")).unwrap_err();
+        assert_eq!(error.to_string(), "template tool_change: unterminated {t");
+        let error = Template::from_toml(&TEMPLATE.replace("rapid", "rapids")).unwrap_err();
+        assert!(matches!(error, TemplateError::Parse(_)));
+    }
+}
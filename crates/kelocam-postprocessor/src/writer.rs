@@ -0,0 +1,353 @@
+use std::f32::consts::{PI, TAU};
+
+use nalgebra::Vector3;
+
+use kelocam_toolpath::{ArcDirection, ArcPlane, Coolant, Move, Spindle};
+
+use crate::dialect::{ArcFormat, CommentStyle, DwellUnits, ToolChange};
+use crate::{Positioning, PostError, PostProcessor};
+
+const AXES: [char; 3] = ['X', 'Y', 'Z'];
+
+/// The maximum deviation of straight moves approximating an arc, in millimeters.
+const ARC_TOLERANCE: f32 = 0.005;
+
+/// Writes the blocks of a program, keeping track of the modal state of the controller.
+pub(crate) struct Writer<'a> {
+    post: &'a PostProcessor,
+    output: String,
+    /// The number of lines written so far.
+    lines: usize,
+    /// The number of the next block (if numbered).
+    number: Option<u32>,
+    /// The active motion mode, e.g. "G1".
+    motion: Option<&'static str>,
+    /// The position of the cutter after the last move, in millimeters.
+    last: Option<Vector3<f32>>,
+    /// The coordinates of the last move as written to the program, in program units. Unknown
+    /// axes have not been written yet.
+    position: [Option<f32>; 3],
+    /// The active feed rate as written to the program.
+    feed: Option<String>,
+    /// The active arc plane.
+    plane: ArcPlane,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(post: &'a PostProcessor) -> Self {
+        Self {
+            post,
+            output: String::new(),
+            lines: 0,
+            number: post.line_numbers.map(|numbers| numbers.start),
+            motion: None,
+            last: None,
+            position: [None; 3],
+            feed: None,
+            plane: ArcPlane::XY,
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.output
+    }
+
+    /// Writes a block to the program, prefixed with its number. Program delimiters ("%") and
+    /// program numbers ("O1234") are not numbered.
+    pub fn line(&mut self, block: &str) -> Result<(), PostError> {
+        let block = block.trim();
+        if block.is_empty() {
+            return Ok(());
+        }
+
+        let mut line = String::new();
+        if !block.starts_with(['%', 'O']) {
+            if let Some(number) = self.number.as_mut() {
+                line.push_str(&format!("N{number} "));
+                *number += self
+                    .post
+                    .line_numbers
+                    .map_or(0, |numbers| numbers.increment);
+            }
+        }
+        line.push_str(block);
+
+        self.lines += 1;
+        if let Some(max) = self.post.dialect.max_line_length {
+            if line.len() > max {
+                return Err(PostError::LineTooLong {
+                    line: self.lines,
+                    length: line.len(),
+                    max,
+                });
+            }
+        }
+        self.output.push_str(&line);
+        self.output.push('\n');
+        Ok(())
+    }
+
+    /// Writes the codes as a single block, or as one block per code if the dialect does not
+    /// allow multiple codes in a block.
+    pub fn codes(&mut self, codes: &[&str]) -> Result<(), PostError> {
+        if self.post.dialect.multiple_codes {
+            self.line(&codes.join(" "))
+        } else {
+            codes.iter().try_for_each(|code| self.line(code))
+        }
+    }
+
+    /// Writes a comment in the style of the dialect. Comments exceeding the maximum line length
+    /// are shortened.
+    pub fn comment(&mut self, text: &str) -> Result<(), PostError> {
+        let (open, close) = match self.post.dialect.comments {
+            CommentStyle::Parentheses => ("(", ")"),
+            CommentStyle::Semicolon => ("; ", ""),
+            CommentStyle::None => return Ok(()),
+        };
+        // Parentheses would end the comment early
+        let mut text: String = text
+            .chars()
+            .filter(|c| !matches!(c, '(' | ')' | '\n' | '\r'))
+            .collect();
+        if let Some(max) = self.post.dialect.max_line_length {
+            let number = self.number.map_or(0, |number| format!("N{number} ").len());
+            let available = max.saturating_sub(number + open.len() + close.len());
+            if let Some((index, _)) = text.trim().char_indices().nth(available) {
+                text.truncate(index);
+            }
+        }
+        self.line(&format!("{open}{}{close}", text.trim()))
+    }
+
+    /// Moves up to the height at rapid speed, wherever the cutter is. Only for absolute
+    /// positioning.
+    pub fn retract(&mut self, z: f32) -> Result<(), PostError> {
+        let z = self.number(self.post.units.from_mm(z));
+        self.position[2] = z.parse().ok();
+        self.motion = Some("G0");
+        self.line(&format!("G0 Z{z}"))
+    }
+
+    /// Writes the blocks for the move.
+    pub fn write(&mut self, m: &Move) -> Result<(), PostError> {
+        let post = self.post;
+        let dialect = &post.dialect;
+        match m {
+            Move::Rapid(to) => self.motion("G0", to, Vec::new(), None),
+            Move::Plunge { to, feed } | Move::Feed { to, feed } => {
+                self.motion("G1", to, Vec::new(), Some(*feed))
+            }
+            Move::Arc { .. } => self.arc(m),
+            Move::Dwell(seconds) => {
+                let dwell = match dialect.dwell {
+                    DwellUnits::Seconds => self.number(*seconds),
+                    DwellUnits::Milliseconds => format!("{:.0}", seconds * 1000.0),
+                };
+                self.line(&format!("G4 P{dwell}"))
+            }
+            Move::ToolChange(tool) => match &dialect.tool_change {
+                ToolChange::Automatic => self.codes(&[&format!("T{tool}"), "M6"]),
+                ToolChange::Pause(code) => {
+                    self.line("M5")?;
+                    self.comment(&format!("Change to tool T{tool}"))?;
+                    self.line(code)
+                }
+            },
+            Move::Spindle(spindle) => match spindle {
+                Spindle::Clockwise(rpm) => self.line(&format!("M3 S{:.0}", rpm)),
+                Spindle::CounterClockwise(rpm) => {
+                    if !dialect.spindle_reverse {
+                        return Err(PostError::Unsupported("counter clockwise spindle (M4)"));
+                    }
+                    self.line(&format!("M4 S{:.0}", rpm))
+                }
+                Spindle::Off => self.line("M5"),
+            },
+            Move::Coolant(coolant) => match coolant {
+                Coolant::Mist if dialect.mist => self.line("M7"),
+                Coolant::Flood if dialect.flood => self.line("M8"),
+                Coolant::Off if dialect.flood || dialect.mist => self.line("M9"),
+                // The operator has to take care of the coolant
+                Coolant::Mist => self.comment("Turn on mist coolant"),
+                Coolant::Flood => self.comment("Turn on flood coolant"),
+                Coolant::Off => self.comment("Turn off coolant"),
+            },
+            Move::Comment(text) => self.comment(text),
+        }
+    }
+
+    fn arc(&mut self, m: &Move) -> Result<(), PostError> {
+        let Move::Arc {
+            to,
+            plane,
+            center,
+            direction,
+            feed,
+        } = m
+        else {
+            return Ok(());
+        };
+        let code = match direction {
+            ArcDirection::Clockwise => "G2",
+            ArcDirection::CounterClockwise => "G3",
+        };
+        let from = self.last.unwrap_or_else(Vector3::zeros);
+        let scale = self.post.units.from_mm(1.0);
+        let [a, b, normal] = plane.axes();
+
+        let format = if self.post.dialect.plane_select || *plane == ArcPlane::XY {
+            self.post.dialect.arcs
+        } else {
+            ArcFormat::Linear
+        };
+        if format != ArcFormat::Linear && *plane != self.plane {
+            self.line(match plane {
+                ArcPlane::XY => "G17",
+                ArcPlane::ZX => "G18",
+                ArcPlane::YZ => "G19",
+            })?;
+            self.plane = *plane;
+        }
+
+        match format {
+            ArcFormat::Center => {
+                let start = self.position.map(|value| value.unwrap_or(0.0));
+                let mut offsets = [(a, center.x), (b, center.y)];
+                offsets.sort_by_key(|(axis, _)| *axis);
+                let words = offsets
+                    .iter()
+                    .map(|(axis, value)| {
+                        let letter = ['I', 'J', 'K'][*axis];
+                        format!("{letter}{}", self.number(value * scale - start[*axis]))
+                    })
+                    .collect();
+                self.motion(code, to, words, Some(*feed))
+            }
+            ArcFormat::Radius => {
+                let sweep = m.sweep(&from).unwrap_or_default().abs();
+
+                // Full circles are split into two halves
+                if sweep > TAU - 1e-6 {
+                    let middle = center * 2.0 - plane.project(&from);
+                    let middle = plane.unproject(&middle, (from[normal] + to[normal]) * 0.5);
+                    for to in [middle, *to] {
+                        self.arc(&Move::Arc {
+                            to,
+                            plane: *plane,
+                            center: *center,
+                            direction: *direction,
+                            feed: *feed,
+                        })?;
+                    }
+                    return Ok(());
+                }
+
+                // Arcs of more than 180 degrees have a negative radius
+                let radius = (plane.project(&from) - center)
+                    .magnitude()
+                    .max((plane.project(to) - center).magnitude());
+                let radius = radius * scale;
+                let radius = if sweep > PI { -radius } else { radius };
+                let words = vec![format!("R{}", self.number(radius))];
+                self.motion(code, to, words, Some(*feed))
+            }
+            ArcFormat::Linear => m
+                .interpolate(&from, ARC_TOLERANCE)
+                .iter()
+                .try_for_each(|point| self.motion("G1", point, Vec::new(), Some(*feed))),
+        }
+    }
+
+    /// Writes a motion block. The words follow the coordinates of the target.
+    fn motion(
+        &mut self,
+        code: &'static str,
+        to: &Vector3<f32>,
+        words: Vec<String>,
+        feed: Option<f32>,
+    ) -> Result<(), PostError> {
+        let modal = self.post.modal;
+        let scale = self.post.units.from_mm(1.0);
+        let from = self.position.map(|value| value.unwrap_or(0.0));
+        self.last = Some(*to);
+
+        let mut block = Vec::new();
+        if !modal || self.motion != Some(code) {
+            block.push(code.to_string());
+        }
+
+        let mut axes = false;
+        for (i, axis) in AXES.into_iter().enumerate() {
+            let target = to[i] * scale;
+            let (value, position) = match self.post.positioning {
+                Positioning::Absolute => {
+                    let value = self.number(target);
+                    let position = value.parse().unwrap_or(target);
+                    (value, position)
+                }
+                // The distance is relative to the rounded position, so rounding errors do not
+                // accumulate
+                Positioning::Incremental => {
+                    let value = self.number(target - from[i]);
+                    let position = from[i] + value.parse().unwrap_or(target - from[i]);
+                    (value, position)
+                }
+            };
+            if !modal || self.position[i] != Some(position) {
+                block.push(format!("{axis}{value}"));
+                axes = true;
+            }
+            self.position[i] = Some(position);
+        }
+
+        // Only arcs may move nowhere, as full circles
+        if !axes && words.is_empty() {
+            return Ok(());
+        }
+        block.extend(words);
+
+        if let Some(feed) = feed {
+            let feed = self.number(feed * scale);
+            if !modal || self.feed.as_ref() != Some(&feed) {
+                block.push(format!("F{feed}"));
+            }
+            self.feed = Some(feed);
+        }
+
+        self.motion = Some(code);
+        self.line(&block.join(" "))
+    }
+
+    /// Formats the number with the precision of the post-processor.
+    pub fn number(&self, value: f32) -> String {
+        format_number(
+            value,
+            self.post.precision,
+            self.post.trim_zeros,
+            self.post.dialect.decimal_point,
+        )
+    }
+}
+
+/// Formats the number with the number of decimal places. See `PostProcessor::trim_zeros` and
+/// `Dialect::decimal_point`.
+pub(crate) fn format_number(
+    value: f32,
+    precision: usize,
+    trim_zeros: bool,
+    decimal_point: bool,
+) -> String {
+    let mut text = format!("{:.*}", precision, value);
+    if trim_zeros && text.contains('.') {
+        text = text.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    // Avoid "-0"
+    if text.starts_with('-') && text[1..].chars().all(|c| matches!(c, '0' | '.')) {
+        text.remove(0);
+    }
+    if decimal_point && !text.contains('.') {
+        text.push('.');
+    }
+    text
+}
@@ -0,0 +1,394 @@
+//! Passes that shorten toolpaths by replacing runs of short feed moves, like the ones produced by
+//! slicing and offsetting, with fewer and longer moves. Controllers plan their motion a few
+//! blocks ahead, so long chains of tiny moves slow them down.
+//!
+//! Only consecutive feed moves with the same feed rate get replaced, and runs never cross the
+//! boundaries of tags. Fit arcs before merging lines, otherwise curves get merged into chords.
+
+use std::f32::consts::TAU;
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::{ArcDirection, ArcPlane, Move, Tag, Toolpath};
+
+/// Replaces runs of feed moves along (almost) straight lines by single moves. The removed
+/// positions deviate at most `tolerance` from the remaining moves.
+pub fn merge_lines(toolpath: &Toolpath, tolerance: f32) -> Toolpath {
+    rebuild(toolpath, |from, points, feed| {
+        let mut moves = Vec::new();
+        let (mut start, mut i) = (*from, 0);
+        while i < points.len() {
+            let mut end = i;
+            while end + 1 < points.len() && straight(&start, &points[i..=end + 1], tolerance) {
+                end += 1;
+            }
+            moves.push(Move::Feed {
+                to: points[end],
+                feed,
+            });
+            start = points[end];
+            i = end + 1;
+        }
+        moves
+    })
+}
+
+/// Whether the points lie on the line from the start to the last point, in order.
+fn straight(start: &Vector3<f32>, points: &[Vector3<f32>], tolerance: f32) -> bool {
+    let Some((end, points)) = points.split_last() else {
+        return true;
+    };
+    let direction = end - start;
+    let length = direction.magnitude();
+    if length < 1e-9 {
+        return false;
+    }
+    let direction = direction / length;
+
+    let mut previous = 0.0;
+    points.iter().all(|point| {
+        let offset = point - start;
+        let along = offset.dot(&direction);
+        let ok = (previous..=length).contains(&along)
+            && (offset - direction * along).magnitude() <= tolerance;
+        previous = along;
+        ok
+    })
+}
+
+/// Replaces runs of feed moves along circular arcs by arc moves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArcFitting {
+    /// The maximum deviation of the replaced moves from the arcs.
+    pub tolerance: f32,
+    /// The planes in which arcs get fitted, in the order of preference.
+    pub planes: Vec<ArcPlane>,
+    /// The minimum number of moves replaced by an arc.
+    pub min_moves: usize,
+    /// Arcs with a larger radius are left as lines, since controllers handle nearly straight
+    /// arcs poorly.
+    pub max_radius: f32,
+}
+
+impl ArcFitting {
+    pub fn new(tolerance: f32) -> Self {
+        Self {
+            tolerance,
+            planes: ArcPlane::ALL.to_vec(),
+            min_moves: 3,
+            max_radius: 1000.0,
+        }
+    }
+
+    pub fn fit(&self, toolpath: &Toolpath) -> Toolpath {
+        let min_moves = self.min_moves.max(2);
+        rebuild(toolpath, |from, points, feed| {
+            let mut moves = Vec::new();
+            let (mut start, mut i) = (*from, 0);
+            while i < points.len() {
+                // Extend the arc as long as the moves fit
+                let mut arc = None;
+                let mut end = i + min_moves - 1;
+                while end < points.len() {
+                    match self.fit_arc(&start, &points[i..=end], feed) {
+                        Some(fitted) => arc = Some((end, fitted)),
+                        None => break,
+                    }
+                    end += 1;
+                }
+
+                let end = match arc {
+                    Some((end, arc)) => {
+                        moves.push(arc);
+                        end
+                    }
+                    None => {
+                        moves.push(Move::Feed {
+                            to: points[i],
+                            feed,
+                        });
+                        i
+                    }
+                };
+                start = points[end];
+                i = end + 1;
+            }
+            moves
+        })
+    }
+
+    /// The arc from the start through the points, if all of them and the lines between them lie
+    /// within the tolerance of the arc.
+    fn fit_arc(&self, start: &Vector3<f32>, points: &[Vector3<f32>], feed: f32) -> Option<Move> {
+        let end = points.last()?;
+        self.planes.iter().find_map(|plane| {
+            let normal = plane.axes()[2];
+            if points
+                .iter()
+                .any(|point| (point[normal] - start[normal]).abs() > self.tolerance)
+            {
+                return None;
+            }
+
+            let start = plane.project(start);
+            let points: Vec<_> = points.iter().map(|point| plane.project(point)).collect();
+            let center = circumcenter(&start, &points[points.len() / 2], points.last()?)?;
+            let radius = (start - center).magnitude();
+            if radius > self.max_radius {
+                return None;
+            }
+
+            // Every move has to turn the same way around the center and stay close to the arc
+            let mut sweep = 0.0;
+            let mut previous = start;
+            for point in points.iter() {
+                let (a, b) = (previous - center, point - center);
+                let step = a.perp(&b).atan2(a.dot(&b));
+                let chord = (point - previous).magnitude();
+                let sagitta = radius - (radius.powi(2) - chord.powi(2) * 0.25).max(0.0).sqrt();
+                if step * sweep < 0.0
+                    || step.abs() > TAU / 8.0
+                    || ((point - center).magnitude() - radius).abs() > self.tolerance
+                    || sagitta > self.tolerance
+                {
+                    return None;
+                }
+                sweep += step;
+                previous = *point;
+            }
+            if sweep.abs() >= TAU - 1e-3 {
+                return None;
+            }
+
+            Some(Move::Arc {
+                to: *end,
+                plane: *plane,
+                center,
+                direction: if sweep > 0.0 {
+                    ArcDirection::CounterClockwise
+                } else {
+                    ArcDirection::Clockwise
+                },
+                feed,
+            })
+        })
+    }
+}
+
+/// The center of the circle through the points, None if they are (almost) collinear.
+fn circumcenter(a: &Vector2<f32>, b: &Vector2<f32>, c: &Vector2<f32>) -> Option<Vector2<f32>> {
+    let (ab, ac) = (b - a, c - a);
+    let d = 2.0 * ab.perp(&ac);
+    if d.abs() < 1e-9 * ab.magnitude_squared().max(ac.magnitude_squared()) {
+        return None;
+    }
+    let (ab2, ac2) = (ab.magnitude_squared(), ac.magnitude_squared());
+    Some(a + Vector2::new(ac.y * ab2 - ab.y * ac2, ab.x * ac2 - ac.x * ab2) / d)
+}
+
+/// Rebuilds the toolpath, replacing every run of feed moves with the same feed rate by the
+/// moves returned by the function. The function gets the position before the run, the targets
+/// of the moves and their feed rate. Runs never cross the boundaries of tags, the tags get
+/// adjusted to the new moves.
+fn rebuild<F>(toolpath: &Toolpath, mut replace: F) -> Toolpath
+where
+    F: FnMut(&Vector3<f32>, &[Vector3<f32>], f32) -> Vec<Move>,
+{
+    let count = toolpath.moves.len();
+    let mut bounds: Vec<usize> = (toolpath.tags.iter())
+        .flat_map(|tag| [tag.moves.start.min(count), tag.moves.end.min(count)])
+        .chain([0, count])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut moves = Vec::new();
+    let mut starts = Vec::new();
+    let mut position: Option<Vector3<f32>> = None;
+    for range in bounds.windows(2) {
+        starts.push(moves.len());
+        let chunk = &toolpath.moves[range[0]..range[1]];
+
+        let mut i = 0;
+        while i < chunk.len() {
+            let (Some(from), Move::Feed { feed, .. }) = (position, &chunk[i]) else {
+                position = chunk[i].to().copied().or(position);
+                moves.push(chunk[i].clone());
+                i += 1;
+                continue;
+            };
+
+            let mut points = Vec::new();
+            while let Some(Move::Feed { to, feed: f }) = chunk.get(i) {
+                if f != feed {
+                    break;
+                }
+                points.push(*to);
+                i += 1;
+            }
+            moves.extend(replace(&from, &points, *feed));
+            position = points.last().copied();
+        }
+    }
+    starts.push(moves.len());
+
+    let start = |index: usize| starts[bounds.binary_search(&index.min(count)).unwrap_or(0)];
+    let tags = (toolpath.tags.iter())
+        .map(|tag| Tag {
+            operation: tag.operation.clone(),
+            moves: start(tag.moves.start)..start(tag.moves.end),
+        })
+        .collect();
+    Toolpath { moves, tags }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed moves along a circle around the origin in the plane, from the start angle to the end
+    /// angle in steps.
+    fn circle(toolpath: &mut Toolpath, plane: ArcPlane, radius: f32, angles: (f32, f32, usize)) {
+        let (start, end, steps) = angles;
+        for i in 1..=steps {
+            let angle = start + (end - start) * i as f32 / steps as f32;
+            let point = Vector2::new(angle.cos(), angle.sin()) * radius;
+            toolpath.feed(plane.unproject(&point, 0.0), 100.0);
+        }
+    }
+
+    fn start(plane: ArcPlane, radius: f32) -> Toolpath {
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(plane.unproject(&Vector2::new(radius, 0.0), 0.0));
+        toolpath
+    }
+
+    #[test]
+    fn merge() {
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::zeros());
+        for x in 1..=5 {
+            // Slightly off the line, within the tolerance
+            let y = if x % 2 == 0 { 0.005 } else { 0.0 };
+            toolpath.feed(Vector3::new(x as f32, y, 0.0), 100.0);
+        }
+        toolpath.feed(Vector3::new(5.0, 5.0, 0.0), 100.0);
+        toolpath.feed(Vector3::new(5.0, 6.0, 0.0), 50.0);
+
+        let merged = merge_lines(&toolpath, 0.01);
+        assert_eq!(
+            merged.moves[1..],
+            [
+                Move::Feed {
+                    to: Vector3::new(5.0, 0.0, 0.0),
+                    feed: 100.0
+                },
+                Move::Feed {
+                    to: Vector3::new(5.0, 5.0, 0.0),
+                    feed: 100.0
+                },
+                Move::Feed {
+                    to: Vector3::new(5.0, 6.0, 0.0),
+                    feed: 50.0
+                },
+            ]
+        );
+        // Beyond the tolerance the moves are kept
+        assert_eq!(merge_lines(&toolpath, 0.001).moves.len(), 8);
+
+        // Turning back is not a straight line
+        let mut toolpath = Toolpath::new();
+        toolpath.rapid(Vector3::zeros());
+        for x in [2.0, 1.0, 3.0] {
+            toolpath.feed(Vector3::new(x, 0.0, 0.0), 100.0);
+        }
+        assert_eq!(merge_lines(&toolpath, 0.01).moves, toolpath.moves);
+    }
+
+    #[test]
+    fn arcs() {
+        let mut toolpath = start(ArcPlane::XY, 10.0);
+        circle(&mut toolpath, ArcPlane::XY, 10.0, (0.0, TAU / 4.0, 30));
+        circle(&mut toolpath, ArcPlane::XY, 10.0, (TAU / 4.0, 0.0, 30));
+
+        let fitted = ArcFitting::new(0.01).fit(&toolpath);
+        assert_eq!(fitted.moves.len(), 3);
+        for (m, direction) in fitted.moves[1..]
+            .iter()
+            .zip([ArcDirection::CounterClockwise, ArcDirection::Clockwise])
+        {
+            let Move::Arc {
+                plane,
+                center,
+                direction: d,
+                ..
+            } = m
+            else {
+                panic!("{:?}", m);
+            };
+            assert_eq!(*plane, ArcPlane::XY);
+            assert!(center.magnitude() < 1e-3);
+            assert_eq!(*d, direction);
+        }
+        assert_eq!(fitted.position(), toolpath.position());
+
+        // A full circle takes more than one arc
+        let mut toolpath = start(ArcPlane::XY, 10.0);
+        circle(&mut toolpath, ArcPlane::XY, 10.0, (0.0, TAU, 120));
+        let fitted = ArcFitting::new(0.01).fit(&toolpath);
+        assert!((3..6).contains(&fitted.moves.len()), "{:?}", fitted.moves);
+    }
+
+    #[test]
+    fn tolerance() {
+        // The chords of 10 degrees deviate by 0.038 from the arc
+        let mut toolpath = start(ArcPlane::XY, 10.0);
+        circle(&mut toolpath, ArcPlane::XY, 10.0, (0.0, TAU / 4.0, 9));
+        assert_eq!(ArcFitting::new(0.01).fit(&toolpath).moves.len(), 10);
+        assert_eq!(ArcFitting::new(0.05).fit(&toolpath).moves.len(), 2);
+
+        // Large arcs stay lines
+        let mut fitting = ArcFitting::new(0.05);
+        fitting.max_radius = 5.0;
+        assert_eq!(fitting.fit(&toolpath).moves.len(), 10);
+    }
+
+    #[test]
+    fn planes() {
+        let mut toolpath = start(ArcPlane::ZX, 10.0);
+        circle(&mut toolpath, ArcPlane::ZX, 10.0, (0.0, TAU / 4.0, 30));
+        let fitted = ArcFitting::new(0.01).fit(&toolpath);
+        assert!(matches!(
+            fitted.moves[1],
+            Move::Arc {
+                plane: ArcPlane::ZX,
+                ..
+            }
+        ));
+
+        let mut fitting = ArcFitting::new(0.01);
+        fitting.planes = vec![ArcPlane::XY];
+        assert_eq!(fitting.fit(&toolpath).moves.len(), 31);
+    }
+
+    #[test]
+    fn tags() {
+        let mut first = start(ArcPlane::XY, 10.0);
+        circle(&mut first, ArcPlane::XY, 10.0, (0.0, TAU / 4.0, 30));
+        let mut second = Toolpath::new();
+        circle(&mut second, ArcPlane::XY, 10.0, (TAU / 4.0, TAU / 2.0, 30));
+        let mut toolpath = first.tagged("Roughing");
+        toolpath.append(second.tagged("Finishing"));
+
+        // The runs end at the tags
+        let fitted = ArcFitting::new(0.01).fit(&toolpath);
+        assert_eq!(fitted.moves.len(), 3);
+        assert_eq!(fitted.tags[0].moves, 0..2);
+        assert_eq!(fitted.tags[1].moves, 2..3);
+        assert_eq!(fitted.operation(2), Some("Finishing"));
+
+        let merged = merge_lines(&toolpath, 0.01);
+        assert_eq!(merged.tags[1].moves.end, merged.moves.len());
+        assert_eq!(merged.moves.len(), toolpath.moves.len());
+    }
+}
@@ -0,0 +1,11 @@
+//! The toolpath intermediate representation shared by the toolpath generating operations, the
+//! editor preview and the post-processor. Positions are absolute and in millimeters, feed rates
+//! are in millimeters per minute.
+
+pub mod fitting;
+pub mod moves;
+pub mod toolpath;
+
+pub use fitting::{merge_lines, ArcFitting};
+pub use moves::{ArcDirection, ArcPlane, Coolant, Move, Spindle};
+pub use toolpath::{Tag, Toolpath};
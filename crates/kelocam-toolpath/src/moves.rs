@@ -0,0 +1,309 @@
+use std::f32::consts::TAU;
+
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// The direction of an arc, looking down onto the XY plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArcDirection {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// The plane of an arc. The center of an arc is given by its coordinates along the two axes of
+/// the plane, in the order of the plane. The order follows the right-hand rule, so counter
+/// clockwise arcs are G3 in all planes when looking against the normal of the plane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ArcPlane {
+    /// The XY plane (G17), looking down the Z axis.
+    #[default]
+    XY,
+    /// The XZ plane (G18), looking down the Y axis. Centers are given as (Z, X).
+    ZX,
+    /// The YZ plane (G19), looking down the X axis.
+    YZ,
+}
+
+impl ArcPlane {
+    pub const ALL: [Self; 3] = [Self::XY, Self::ZX, Self::YZ];
+
+    /// The indices of the first and second axis of the plane, followed by its normal.
+    pub fn axes(&self) -> [usize; 3] {
+        match self {
+            Self::XY => [0, 1, 2],
+            Self::ZX => [2, 0, 1],
+            Self::YZ => [1, 2, 0],
+        }
+    }
+
+    /// The coordinates of the point along the axes of the plane.
+    pub fn project(&self, point: &Vector3<f32>) -> Vector2<f32> {
+        let [a, b, _] = self.axes();
+        Vector2::new(point[a], point[b])
+    }
+
+    /// The point with the coordinates along the axes of the plane and along its normal.
+    pub fn unproject(&self, point: &Vector2<f32>, normal: f32) -> Vector3<f32> {
+        let [a, b, n] = self.axes();
+        let mut result = Vector3::zeros();
+        result[a] = point.x;
+        result[b] = point.y;
+        result[n] = normal;
+        result
+    }
+}
+
+/// The state of the spindle. Speeds are in revolutions per minute.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Spindle {
+    Clockwise(f32),
+    CounterClockwise(f32),
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Coolant {
+    Off,
+    Mist,
+    Flood,
+}
+
+/// A single instruction of a toolpath. Most instructions move the cutter, the others change the
+/// state of the machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Move {
+    /// Move to the position at maximum speed, without cutting.
+    Rapid(Vector3<f32>),
+    /// Move (mostly) vertically into the material with the specified feed rate.
+    Plunge {
+        to: Vector3<f32>,
+        feed: f32,
+    },
+    /// Move along a straight line, cutting at the specified feed rate.
+    Feed {
+        to: Vector3<f32>,
+        feed: f32,
+    },
+    /// Move along a circular arc around the center in the plane, cutting at the specified feed
+    /// rate. Moving along the normal of the plane turns the arc into a helix. An arc ending at
+    /// its starting point is a full circle.
+    Arc {
+        to: Vector3<f32>,
+        #[serde(default)]
+        plane: ArcPlane,
+        /// The center in the coordinates of the plane, see `ArcPlane`.
+        center: Vector2<f32>,
+        direction: ArcDirection,
+        feed: f32,
+    },
+    /// Wait for the specified number of seconds.
+    Dwell(f32),
+    /// Change to the tool with the specified number.
+    ToolChange(u32),
+    Spindle(Spindle),
+    Coolant(Coolant),
+    /// A comment for the operator, which has no effect on the machine.
+    Comment(String),
+}
+
+impl Move {
+    /// The position of the cutter after this move, or None if this instruction does not move
+    /// the cutter.
+    pub fn to(&self) -> Option<&Vector3<f32>> {
+        match self {
+            Self::Rapid(to) => Some(to),
+            Self::Plunge { to, .. } => Some(to),
+            Self::Feed { to, .. } => Some(to),
+            Self::Arc { to, .. } => Some(to),
+            _ => None,
+        }
+    }
+
+    /// The feed rate of this move, or None for rapid moves and instructions which do not move
+    /// the cutter.
+    pub fn feed(&self) -> Option<f32> {
+        match self {
+            Self::Plunge { feed, .. } | Self::Feed { feed, .. } | Self::Arc { feed, .. } => {
+                Some(*feed)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether this move cuts, i.e. moves the cutter at a feed rate.
+    pub fn is_cutting(&self) -> bool {
+        self.feed().is_some()
+    }
+
+    /// The signed angle an arc sweeps when starting at the specified position, positive when
+    /// counter clockwise. Arcs ending at their starting point sweep a full circle. None if this
+    /// move is not an arc.
+    pub fn sweep(&self, from: &Vector3<f32>) -> Option<f32> {
+        let Self::Arc {
+            to,
+            plane,
+            center,
+            direction,
+            ..
+        } = self
+        else {
+            return None;
+        };
+
+        let (start, end) = (plane.project(from) - center, plane.project(to) - center);
+        let (a0, a1) = (start.y.atan2(start.x), end.y.atan2(end.x));
+        let sweep = match direction {
+            ArcDirection::CounterClockwise => (a1 - a0).rem_euclid(TAU),
+            ArcDirection::Clockwise => (a0 - a1).rem_euclid(TAU),
+        };
+        // Ending at the starting point makes a full circle
+        let sweep = if sweep < 1e-6 { TAU } else { sweep };
+        Some(match direction {
+            ArcDirection::CounterClockwise => sweep,
+            ArcDirection::Clockwise => -sweep,
+        })
+    }
+
+    /// The positions along this move when starting at the specified position, ending with the
+    /// target of the move. Arcs are approximated by straight segments which deviate at most
+    /// `tolerance` from the arc. Empty if this instruction does not move the cutter.
+    pub fn interpolate(&self, from: &Vector3<f32>, tolerance: f32) -> Vec<Vector3<f32>> {
+        let (
+            Self::Arc {
+                to, plane, center, ..
+            },
+            Some(sweep),
+        ) = (self, self.sweep(from))
+        else {
+            return self.to().copied().into_iter().collect();
+        };
+
+        let start = plane.project(from) - center;
+        let end = plane.project(to) - center;
+        let normal = plane.axes()[2];
+        let a0 = start.y.atan2(start.x);
+
+        // The radius may differ slightly between the start and the end, blend between them
+        let (r0, r1) = (start.magnitude(), end.magnitude());
+        let radius = r0.max(r1);
+        let step = if radius > tolerance {
+            2.0 * (1.0 - tolerance / radius).acos()
+        } else {
+            TAU
+        };
+        let count = ((sweep.abs() / step.max(1e-3)).ceil() as usize).max(1);
+
+        (1..=count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                if i == count {
+                    return *to;
+                }
+                let (sin, cos) = (a0 + sweep * t).sin_cos();
+                let point = center + Vector2::new(cos, sin) * (r0 + (r1 - r0) * t);
+                plane.unproject(&point, from[normal] + (to[normal] - from[normal]) * t)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn arc(to: Vector3<f32>, center: Vector2<f32>, direction: ArcDirection) -> Move {
+        Move::Arc {
+            to,
+            plane: ArcPlane::XY,
+            center,
+            direction,
+            feed: 100.0,
+        }
+    }
+
+    #[test]
+    fn planes() {
+        let point = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(ArcPlane::XY.project(&point), Vector2::new(1.0, 2.0));
+        assert_eq!(ArcPlane::ZX.project(&point), Vector2::new(3.0, 1.0));
+        assert_eq!(ArcPlane::YZ.project(&point), Vector2::new(2.0, 3.0));
+        for plane in ArcPlane::ALL {
+            let [_, _, normal] = plane.axes();
+            assert_eq!(
+                plane.unproject(&plane.project(&point), point[normal]),
+                point
+            );
+        }
+    }
+
+    #[test]
+    fn sweep() {
+        let from = Vector3::new(1.0, 0.0, 0.0);
+        let center = Vector2::zeros();
+        let quarter = Vector3::new(0.0, 1.0, 0.0);
+        let ccw = arc(quarter, center, ArcDirection::CounterClockwise);
+        assert!((ccw.sweep(&from).unwrap() - PI / 2.0).abs() < 1e-5);
+        let cw = arc(quarter, center, ArcDirection::Clockwise);
+        assert!((cw.sweep(&from).unwrap() + PI * 1.5).abs() < 1e-5);
+
+        // Ending at the start makes a full circle
+        let full = arc(from, center, ArcDirection::Clockwise);
+        assert_eq!(full.sweep(&from), Some(-TAU));
+        assert_eq!(Move::Rapid(from).sweep(&from), None);
+    }
+
+    #[test]
+    fn interpolate() {
+        let from = Vector3::new(10.0, 0.0, 0.0);
+        let to = Vector3::new(-10.0, 0.0, -2.0);
+        let half = arc(to, Vector2::zeros(), ArcDirection::CounterClockwise);
+        let points = half.interpolate(&from, 0.01);
+        assert!(points.len() > 10);
+        assert_eq!(points.last(), Some(&to));
+
+        // The points stay on the left half of the helix and descend steadily
+        let mut z = from.z;
+        for point in points.iter() {
+            assert!((point.xy().magnitude() - 10.0).abs() < 1e-3);
+            assert!(point.y >= -1e-3);
+            assert!(point.z < z);
+            z = point.z;
+        }
+
+        // The chords deviate at most the tolerance from the arc
+        let mut previous = from;
+        for point in points.iter() {
+            let middle = ((previous + point) * 0.5).xy().magnitude();
+            assert!(10.0 - middle <= 0.01 + 1e-4);
+            previous = *point;
+        }
+
+        // Arcs in other planes keep the coordinate along the normal
+        let from = Vector3::new(0.0, 5.0, 10.0);
+        let arc = Move::Arc {
+            to: Vector3::new(0.0, 5.0, 10.0),
+            plane: ArcPlane::ZX,
+            center: Vector2::new(5.0, 0.0),
+            direction: ArcDirection::Clockwise,
+            feed: 100.0,
+        };
+        let points = arc.interpolate(&from, 0.01);
+        assert!(points.iter().all(|point| point.y == 5.0));
+        assert!(points.iter().any(|point| point.z < 1.0));
+
+        let line = Move::Feed { to, feed: 100.0 };
+        assert_eq!(line.interpolate(&from, 0.01), vec![to]);
+        assert!(Move::Dwell(1.0).interpolate(&from, 0.01).is_empty());
+    }
+
+    #[test]
+    fn feeds() {
+        let to = Vector3::zeros();
+        assert_eq!(Move::Rapid(to).feed(), None);
+        assert!(!Move::Rapid(to).is_cutting());
+        assert_eq!(Move::Plunge { to, feed: 50.0 }.feed(), Some(50.0));
+        assert!(Move::Feed { to, feed: 100.0 }.is_cutting());
+        assert_eq!(Move::Spindle(Spindle::Off).to(), None);
+    }
+}
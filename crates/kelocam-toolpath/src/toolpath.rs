@@ -0,0 +1,193 @@
+use std::ops::Range;
+
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{ArcDirection, ArcPlane, Coolant, Move, Spindle};
+
+/// Marks the moves generated by an operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tag {
+    /// The name of the operation, e.g. "Roughing".
+    pub operation: String,
+    /// The indices of the moves.
+    pub moves: Range<usize>,
+}
+
+/// A sequence of moves, tagged with the operations that generated them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Toolpath {
+    pub moves: Vec<Move>,
+    /// The tags of the moves. The tags do not overlap, but not every move has to be tagged.
+    pub tags: Vec<Tag>,
+}
+
+impl Toolpath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rapid move. Rapid moves to the current position are skipped.
+    pub fn rapid(&mut self, to: Vector3<f32>) {
+        if self.position() != Some(&to) {
+            self.moves.push(Move::Rapid(to));
+        }
+    }
+
+    pub fn plunge(&mut self, to: Vector3<f32>, feed: f32) {
+        self.moves.push(Move::Plunge { to, feed });
+    }
+
+    pub fn feed(&mut self, to: Vector3<f32>, feed: f32) {
+        self.moves.push(Move::Feed { to, feed });
+    }
+
+    /// Adds an arc in the XY plane.
+    pub fn arc(
+        &mut self,
+        to: Vector3<f32>,
+        center: Vector2<f32>,
+        direction: ArcDirection,
+        feed: f32,
+    ) {
+        self.arc_in(ArcPlane::XY, to, center, direction, feed);
+    }
+
+    /// Adds an arc in the plane, with the center in the coordinates of the plane.
+    pub fn arc_in(
+        &mut self,
+        plane: ArcPlane,
+        to: Vector3<f32>,
+        center: Vector2<f32>,
+        direction: ArcDirection,
+        feed: f32,
+    ) {
+        self.moves.push(Move::Arc {
+            to,
+            plane,
+            center,
+            direction,
+            feed,
+        });
+    }
+
+    pub fn dwell(&mut self, seconds: f32) {
+        self.moves.push(Move::Dwell(seconds));
+    }
+
+    pub fn tool_change(&mut self, tool: u32) {
+        self.moves.push(Move::ToolChange(tool));
+    }
+
+    pub fn spindle(&mut self, spindle: Spindle) {
+        self.moves.push(Move::Spindle(spindle));
+    }
+
+    pub fn coolant(&mut self, coolant: Coolant) {
+        self.moves.push(Move::Coolant(coolant));
+    }
+
+    pub fn comment<S: Into<String>>(&mut self, comment: S) {
+        self.moves.push(Move::Comment(comment.into()));
+    }
+
+    /// The position of the cutter after the last move (if any).
+    pub fn position(&self) -> Option<&Vector3<f32>> {
+        self.moves.iter().rev().find_map(Move::to)
+    }
+
+    /// Iterate over the positions the cutter moves to.
+    pub fn positions(&self) -> impl Iterator<Item = &Vector3<f32>> {
+        self.moves.iter().filter_map(Move::to)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Tags all moves with the operation, replacing the existing tags.
+    pub fn tagged<S: Into<String>>(mut self, operation: S) -> Self {
+        self.tags = vec![Tag {
+            operation: operation.into(),
+            moves: 0..self.moves.len(),
+        }];
+        self
+    }
+
+    /// Appends the moves of the other toolpath, keeping their tags.
+    pub fn append(&mut self, other: Toolpath) {
+        let offset = self.moves.len();
+        self.moves.extend(other.moves);
+        self.tags.extend(other.tags.into_iter().map(|tag| Tag {
+            operation: tag.operation,
+            moves: tag.moves.start + offset..tag.moves.end + offset,
+        }));
+    }
+
+    /// The operation that generated the move at the index (if tagged).
+    pub fn operation(&self, index: usize) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.moves.contains(&index))
+            .map(|tag| tag.operation.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let mut toolpath = Toolpath::new();
+        assert!(toolpath.is_empty());
+        assert_eq!(toolpath.position(), None);
+
+        let start = Vector3::new(0.0, 0.0, 5.0);
+        toolpath.rapid(start);
+        // Rapid moves to the current position are skipped
+        toolpath.rapid(start);
+        assert_eq!(toolpath.moves.len(), 1);
+
+        toolpath.spindle(Spindle::Clockwise(10000.0));
+        toolpath.plunge(Vector3::zeros(), 100.0);
+        toolpath.arc(
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            ArcDirection::Clockwise,
+            200.0,
+        );
+        toolpath.comment("done");
+        assert_eq!(toolpath.position(), Some(&Vector3::new(2.0, 0.0, 0.0)));
+        assert_eq!(toolpath.positions().count(), 3);
+
+        // Rapid moves to another position are kept
+        toolpath.rapid(start);
+        assert_eq!(toolpath.moves.len(), 6);
+    }
+
+    #[test]
+    fn tags() {
+        let mut first = Toolpath::new();
+        first.rapid(Vector3::new(0.0, 0.0, 5.0));
+        first.feed(Vector3::zeros(), 100.0);
+        let mut toolpath = first.tagged("Roughing");
+        assert_eq!(toolpath.operation(1), Some("Roughing"));
+        assert_eq!(toolpath.operation(2), None);
+
+        let mut second = Toolpath::new();
+        second.feed(Vector3::new(1.0, 0.0, 0.0), 100.0);
+        second.feed(Vector3::new(2.0, 0.0, 0.0), 100.0);
+        toolpath.append(second.tagged("Finishing"));
+        toolpath.append(Toolpath::new());
+        assert_eq!(toolpath.moves.len(), 4);
+        assert_eq!(toolpath.tags[1].moves, 2..4);
+        assert_eq!(toolpath.operation(1), Some("Roughing"));
+        assert_eq!(toolpath.operation(3), Some("Finishing"));
+
+        // Tagging again replaces the tags
+        let toolpath = toolpath.tagged("Job");
+        assert_eq!(toolpath.tags.len(), 1);
+        assert_eq!(toolpath.operation(3), Some("Job"));
+    }
+}
@@ -6,17 +6,31 @@ use std::pin::Pin;
 use std::task::Poll;
 use std::{future::Future, io::Cursor};
 
-use crate::view::{PrepareView, View};
+use crate::view::{MonitorView, PrepareView, View};
+use kelocam_core::cnc::Machine;
 use kelocam_core::primitives::{BoundingBox, Mesh};
 use kelocam_editor::{object::Object, Editor};
+use kelocam_postprocessor::Parser;
+
+/// The kind of file the file dialog was opened for.
+#[derive(Clone, Copy)]
+enum OpenFile {
+    Model,
+    Program,
+}
+
+/// The pending result of a file dialog.
+type PickFile = Pin<Box<dyn Future<Output = Option<FileHandle>>>>;
 
 pub struct KeloApp {
-    file_dialog: Option<Pin<Box<dyn Future<Output = Option<FileHandle>>>>>,
+    file_dialog: Option<(OpenFile, PickFile)>,
 
     view: View,
 
     prepare: PrepareView,
 
+    monitor: MonitorView,
+
     editor: Editor,
 }
 
@@ -26,38 +40,65 @@ impl KeloApp {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
-        let editor = Editor::new(cc).expect("Error while creating editor");
+        let mut editor = Editor::new(cc).expect("Error while creating editor");
+        editor.machine = Some(Machine::default());
 
         Self {
             file_dialog: None,
             view: View::Prepare,
             prepare: PrepareView::default(),
+            monitor: MonitorView::default(),
             editor,
         }
     }
 }
 
+impl KeloApp {
+    async fn open_model(&mut self, handle: &FileHandle) {
+        if let Ok(mut mesh) = Mesh::from_stl(&mut Cursor::new(&handle.read().await)) {
+            let (min, max) = mesh.bb_min_max();
+            let size = max - min;
+            // Don't center on z axis
+            let transform = -min - Vector3::new(size.x, size.y, 0.0).scale(0.5);
+            mesh.translate(&transform);
+            let mut message = self
+                .editor
+                .state
+                .insert_object(Object::new(mesh, handle.file_name()));
+            self.editor.state.apply(&mut message);
+            self.editor.log.push(message);
+        }
+    }
+
+    /// Loads a G-code program for previewing its toolpath.
+    async fn open_program(&mut self, handle: &FileHandle) {
+        let source = String::from_utf8_lossy(&handle.read().await).into_owned();
+        let name = handle.file_name();
+        match Parser::default().parse(&source) {
+            Ok(program) => {
+                for warning in program.warnings.iter() {
+                    self.editor.log.notify(format!("{}: {}", name, warning));
+                }
+                self.editor.toolpath = Some(program.toolpath);
+                self.editor.program = Some(source);
+            }
+            Err(e) => self.editor.log.notify(format!("{}: {}", name, e)),
+        }
+    }
+}
+
 impl eframe::App for KeloApp {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if let Some(file_dialog) = &mut self.file_dialog {
+        if let Some((kind, file_dialog)) = &mut self.file_dialog {
+            let kind = *kind;
             if let Poll::Ready(handle) = async { futures::poll!(file_dialog.as_mut()) }.block_on() {
                 self.file_dialog = None;
 
                 if let Some(handle) = handle {
                     async {
-                        if let Ok(mut mesh) = Mesh::from_stl(&mut Cursor::new(&handle.read().await))
-                        {
-                            let (min, max) = mesh.bb_min_max();
-                            let size = max - min;
-                            // Don't center on z axis
-                            let transform = -min - Vector3::new(size.x, size.y, 0.0).scale(0.5);
-                            mesh.translate(&transform);
-                            let mut message = self
-                                .editor
-                                .state
-                                .insert_object(Object::new(mesh, handle.file_name()));
-                            self.editor.state.apply(&mut message);
-                            self.editor.log.push(message);
+                        match kind {
+                            OpenFile::Model => self.open_model(&handle).await,
+                            OpenFile::Program => self.open_program(&handle).await,
                         }
                     }
                     .block_on();
@@ -69,11 +110,30 @@ impl eframe::App for KeloApp {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("Open").clicked() {
-                        self.file_dialog = Some(Box::pin(
-                            AsyncFileDialog::new()
-                                .add_filter("STL Files", &["stl"])
-                                .set_directory("/")
-                                .pick_file(),
+                        self.file_dialog = Some((
+                            OpenFile::Model,
+                            Box::pin(
+                                AsyncFileDialog::new()
+                                    .add_filter("STL Files", &["stl"])
+                                    .set_directory("/")
+                                    .pick_file(),
+                            ),
+                        ));
+
+                        ui.close_menu();
+                    }
+                    if ui.button("Open G-code").clicked() {
+                        self.file_dialog = Some((
+                            OpenFile::Program,
+                            Box::pin(
+                                AsyncFileDialog::new()
+                                    .add_filter(
+                                        "G-code Files",
+                                        &["nc", "ngc", "gcode", "tap", "cnc"],
+                                    )
+                                    .set_directory("/")
+                                    .pick_file(),
+                            ),
                         ));
 
                         ui.close_menu();
@@ -85,6 +145,11 @@ impl eframe::App for KeloApp {
                 ui.menu_button("View", |ui| {
                     if ui.button("Prepare").clicked() {
                         self.view = View::Prepare;
+                        ui.close_menu();
+                    }
+                    if ui.button("Monitor").clicked() {
+                        self.view = View::Monitor;
+                        ui.close_menu();
                     }
                 });
             });
@@ -92,11 +157,10 @@ impl eframe::App for KeloApp {
 
         // The central panel the region left after adding TopPanel's and SidePanel's
 
-        //match self.view {
-        //    View::Prepare => self.prepare.show(ctx, &mut self.editor),
-        //    _ => {}
-        //};
-
-        self.prepare.show(ctx, &mut self.editor)
+        match self.view {
+            View::Prepare => self.prepare.show(ctx, &mut self.editor),
+            View::Monitor => self.monitor.show(ctx, &mut self.editor),
+            View::None => {}
+        }
     }
 }
@@ -2,4 +2,5 @@
 
 mod app;
 pub use app::KeloApp;
+pub mod sender;
 pub mod view;
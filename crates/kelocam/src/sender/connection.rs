@@ -0,0 +1,393 @@
+//! Talking to a controller from the UI, without blocking it. The sender is polled by a
+//! background thread, which takes requests through a channel and shares what it learned about
+//! the machine.
+
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use nalgebra::Vector3;
+
+use super::grbl::{self, realtime, Event, Grbl, Override, STATUS_INTERVAL};
+use super::marlin::{self, Marlin};
+use super::probe::{ProbeSettings, Probing, Routine};
+use super::{Firmware, Progress, Sender, SenderError, Status};
+
+/// The number of lines kept in the log.
+const LOG_LINES: usize = 1000;
+
+enum Request {
+    Start(String),
+    Command(String),
+    Realtime(u8),
+    Pause,
+    Resume,
+    Reset,
+    Probe(Routine, ProbeSettings),
+}
+
+/// What is known about the machine, updated by the sender thread.
+#[derive(Debug, Clone, Default)]
+pub struct MachineState {
+    pub status: Option<Status>,
+    pub progress: Option<Progress>,
+    pub running: bool,
+    pub paused: bool,
+    pub alarm: Option<u8>,
+    /// The targets of override changes which no status report confirmed yet, indexed by
+    /// `Override`. The real-time commands of further changes start from them.
+    pub requested_overrides: [Option<u32>; 2],
+    /// Whether a probing routine is running.
+    pub probing: bool,
+    /// The position of the tool setter contact of the reference tool, in machine coordinates.
+    /// Set by the first tool length measurement.
+    pub tool_reference: Option<f32>,
+    /// The output of the controller and the errors of requests.
+    pub log: Vec<String>,
+    /// The error which closed the connection.
+    pub error: Option<String>,
+}
+
+impl MachineState {
+    /// The value of the override in percent, the requested one until it is confirmed.
+    pub fn override_value(&self, kind: Override) -> u32 {
+        self.requested_overrides[kind as usize]
+            .or_else(|| (self.status.as_ref()).map(|status| kind.value(&status.overrides)))
+            .unwrap_or(100)
+    }
+
+    fn log(&mut self, line: String) {
+        if self.log.len() >= LOG_LINES {
+            self.log.remove(0);
+        }
+        self.log.push(line);
+    }
+
+    fn record(&mut self, event: Event) {
+        match event {
+            Event::Error { line, code } => {
+                let location = line.map(|line| format!(" on line {}", line));
+                self.log(format!(
+                    "error:{}{}: {}",
+                    code,
+                    location.unwrap_or_default(),
+                    grbl::error_message(code)
+                ));
+            }
+            Event::Alarm(code) => {
+                self.log(format!("ALARM:{}: {}", code, grbl::alarm_message(code)))
+            }
+            Event::Reset(welcome) => {
+                // Resets restore the overrides to 100%
+                self.requested_overrides = [None; 2];
+                self.log(welcome)
+            }
+            Event::Finished => self.log("Job finished".to_string()),
+            Event::Status(_) => {}
+            Event::Probe(result) => {
+                let p = result.position;
+                match result.success {
+                    true => self.log(format!("Probe contact at X{} Y{} Z{}", p.x, p.y, p.z)),
+                    false => self.log("Probe made no contact".to_string()),
+                }
+            }
+            Event::Rejected { line, message } => {
+                let location = line.map(|line| format!(" on line {}", line));
+                self.log(format!(
+                    "Error{}: {}",
+                    location.unwrap_or_default(),
+                    message
+                ));
+            }
+            Event::Halted(message) => self.log(format!("Halted: {}", message)),
+            Event::Message(message) => self.log(message),
+        }
+    }
+}
+
+/// A connection to a controller.
+pub struct Connection {
+    /// The name of the serial port.
+    pub name: String,
+    pub firmware: Firmware,
+    requests: mpsc::Sender<Request>,
+    state: Arc<Mutex<MachineState>>,
+}
+
+impl Connection {
+    /// Connects to the controller with the firmware at the serial port.
+    pub fn open(path: &str, firmware: Firmware, baud_rate: u32) -> Result<Self, SenderError> {
+        match firmware {
+            Firmware::Grbl => Ok(Self::new(path, Grbl::new(super::open(path, baud_rate)?))),
+            Firmware::Marlin => Ok(Self::with_sender(path, Marlin::open(path, baud_rate)?)),
+        }
+    }
+
+    /// Starts a thread polling the Grbl sender, see `with_sender`.
+    pub fn new<P>(name: &str, mut grbl: Grbl<P>) -> Self
+    where
+        P: Read + Write + Send + 'static,
+    {
+        grbl.status_interval.get_or_insert(STATUS_INTERVAL);
+        Self::with_sender(name, grbl)
+    }
+
+    /// Starts a thread polling the sender. Reads from the port should wait a moment for data,
+    /// like serial ports do, so the thread does not spin. The thread stops when the connection
+    /// is dropped or the port fails.
+    pub fn with_sender<S: Sender + Send + 'static>(name: &str, sender: S) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let state = Arc::new(Mutex::new(MachineState::default()));
+
+        let firmware = sender.firmware();
+        let shared = state.clone();
+        thread::spawn(move || run(sender, receiver, shared));
+
+        Self {
+            name: name.to_string(),
+            firmware,
+            requests,
+            state,
+        }
+    }
+
+    /// The state of the machine. Keep the lock short, the sender thread waits for it.
+    pub fn state(&self) -> MutexGuard<'_, MachineState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the connection is still open.
+    pub fn is_open(&self) -> bool {
+        self.state().error.is_none()
+    }
+
+    /// Streams the program.
+    pub fn start(&self, program: String) {
+        self.send(Request::Start(program));
+    }
+
+    /// Sends a block, e.g. "$H" or "G0 X0".
+    pub fn command(&self, block: &str) {
+        self.send(Request::Command(block.to_string()));
+    }
+
+    /// Sends a real-time command.
+    pub fn realtime(&self, command: u8) {
+        self.send(Request::Realtime(command));
+    }
+
+    pub fn pause(&self) {
+        self.send(Request::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(Request::Resume);
+    }
+
+    pub fn reset(&self) {
+        self.send(Request::Reset);
+    }
+
+    /// Moves the machine by the distance at the feed rate, in millimeters.
+    pub fn jog(&self, distance: &Vector3<f32>, feed: f32) {
+        match self.firmware {
+            Firmware::Grbl => self.command(&grbl::jog(distance, feed)),
+            Firmware::Marlin => {
+                (marlin::jog(distance, feed).iter()).for_each(|block| self.command(block))
+            }
+        }
+    }
+
+    pub fn cancel_jog(&self) {
+        if self.supports_jog_cancel() {
+            self.realtime(realtime::JOG_CANCEL);
+        } else {
+            self.unsupported("cancelling jogs");
+        }
+    }
+
+    /// Whether jogs can be cancelled. Marlin has no jog mode, its jogs are ordinary moves.
+    pub fn supports_jog_cancel(&self) -> bool {
+        self.firmware == Firmware::Grbl
+    }
+
+    /// Runs the homing cycle.
+    pub fn home(&self) {
+        match self.firmware {
+            Firmware::Grbl => self.command("$H"),
+            Firmware::Marlin => self.command("G28"),
+        }
+    }
+
+    /// Leaves the alarm state without homing, or restarts Smoothieware after a halt.
+    pub fn unlock(&self) {
+        match self.firmware {
+            Firmware::Grbl => self.command("$X"),
+            Firmware::Marlin => self.command("M999"),
+        }
+    }
+
+    /// Sets the work position of the axes, see `grbl::set_work_position`.
+    pub fn set_work_position(&self, position: [Option<f32>; 3]) {
+        match self.firmware {
+            Firmware::Grbl => self.command(&grbl::set_work_position(position)),
+            Firmware::Marlin => self.command(&marlin::set_work_position(position)),
+        }
+    }
+
+    /// Moves by the distance until the probe makes contact, see `grbl::probe`.
+    pub fn probe(&self, distance: &Vector3<f32>, feed: f32) {
+        if !self.supports_probing() {
+            return;
+        }
+        for block in grbl::probe(distance, feed) {
+            self.command(&block);
+        }
+    }
+
+    /// Changes the override to the target value, in percent.
+    pub fn set_override(&self, kind: Override, target: u32) {
+        if !self.supports_override(kind) {
+            self.unsupported(&format!("the {:?} override", kind).to_lowercase());
+            return;
+        }
+        let target = target.clamp(10, 200);
+        let current = {
+            let mut state = self.state();
+            let current = state.override_value(kind);
+            state.requested_overrides[kind as usize] = Some(target);
+            current
+        };
+        for command in kind.adjust(current, target) {
+            self.realtime(command);
+        }
+    }
+
+    /// Whether the controller has the override, Marlin only has the feed override.
+    pub fn supports_override(&self, kind: Override) -> bool {
+        self.firmware == Firmware::Grbl || kind == Override::Feed
+    }
+
+    /// Runs the probing routine.
+    pub fn run_probing(&self, routine: Routine, settings: ProbeSettings) {
+        if self.supports_probing() {
+            self.send(Request::Probe(routine, settings));
+        }
+    }
+
+    /// Whether the controller reports the results of probing moves, only Grbl does. Logs an
+    /// error otherwise.
+    fn supports_probing(&self) -> bool {
+        if self.firmware != Firmware::Grbl {
+            self.unsupported("probing");
+        }
+        self.firmware == Firmware::Grbl
+    }
+
+    /// Logs that the firmware can't do what was requested.
+    fn unsupported(&self, what: &str) {
+        let error = SenderError::Unsupported(what.to_string());
+        self.state().log(error.to_string());
+    }
+
+    fn send(&self, request: Request) {
+        // The thread only stops after a failure, which is in the state
+        self.requests.send(request).ok();
+    }
+}
+
+/// Polls the sender until the connection is dropped or the port fails.
+fn run<S: Sender>(
+    mut sender: S,
+    receiver: mpsc::Receiver<Request>,
+    shared: Arc<Mutex<MachineState>>,
+) {
+    let mut probing: Option<Probing> = None;
+    loop {
+        let mut errors = Vec::new();
+        loop {
+            let result = match receiver.try_recv() {
+                Ok(Request::Start(_) | Request::Probe(..)) if probing.is_some() => {
+                    Err(SenderError::Busy)
+                }
+                Ok(Request::Start(program)) => sender.start(&program),
+                Ok(Request::Command(block)) => {
+                    sender.command(&block);
+                    Ok(())
+                }
+                Ok(Request::Realtime(command)) => sender.realtime(command),
+                Ok(Request::Pause) => sender.pause(),
+                Ok(Request::Resume) => sender.resume(),
+                Ok(Request::Reset) => sender.reset(),
+                Ok(Request::Probe(_, _)) if sender.is_running() => Err(SenderError::Busy),
+                Ok(Request::Probe(routine, settings)) => {
+                    probing = Some(Probing::new(routine, settings));
+                    Ok(())
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            };
+            errors.extend(result.err());
+        }
+
+        let result = sender.poll();
+
+        let Ok(mut state) = shared.lock() else {
+            return;
+        };
+        for error in errors {
+            state.log(error.to_string());
+        }
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                state.error = Some(e.to_string());
+                return;
+            }
+        };
+        for event in events {
+            match (&event, probing.as_mut()) {
+                (Event::Probe(result), Some(probing)) => probing.probed(result),
+                (Event::Alarm(_) | Event::Reset(_) | Event::Halted(_), Some(_)) => {
+                    probing = None;
+                    state.log("Probing aborted".to_string());
+                }
+                _ => {}
+            }
+            state.record(event);
+        }
+
+        // The next step of the probing routine follows when the previous one was executed
+        if let Some(routine) = probing.as_mut().filter(|_| sender.is_idle()) {
+            if !routine.is_ok() {
+                probing = None;
+                state.log("Probing failed, the probe made no contact".to_string());
+            } else if let Some(blocks) = routine.next_step() {
+                blocks.iter().for_each(|block| sender.command(block));
+            } else {
+                if let Routine::ToolLength { reference: None } = routine.routine() {
+                    state.tool_reference = routine.contacts().last().map(|contact| contact.z);
+                }
+                probing = None;
+                state.log("Probing finished".to_string());
+            }
+        }
+
+        state.status = sender.status().cloned();
+        if let Some(status) = &state.status {
+            let overrides = status.overrides;
+            for kind in [Override::Feed, Override::Spindle] {
+                let requested = &mut state.requested_overrides[kind as usize];
+                if *requested == Some(kind.value(&overrides)) {
+                    *requested = None;
+                }
+            }
+        }
+        state.progress = sender.progress();
+        state.running = sender.is_running();
+        state.paused = sender.is_paused();
+        state.alarm = sender.alarm();
+        state.probing = probing.is_some();
+    }
+}
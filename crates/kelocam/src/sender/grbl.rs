@@ -0,0 +1,845 @@
+//! Sender for Grbl controllers.
+//!
+//! Blocks are streamed with the character counting protocol: the sender keeps track of the
+//! bytes in the receive buffer of the controller and sends blocks as long as they fit. Every
+//! block gets answered by "ok" or "error:N" in order, which frees its bytes again. This keeps
+//! the planner of the controller filled, unlike waiting for the answer to every block.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector3;
+use serialport::SerialPort;
+
+use super::probe::ProbeResult;
+use super::status::{Overrides, State, Status};
+use super::{Firmware, Job, Progress, Sender, SenderError};
+
+/// The default baud rate of Grbl 1.1.
+pub const BAUD_RATE: u32 = 115200;
+
+/// The size of the receive buffer of Grbl on an Arduino Uno.
+pub const BUFFER_SIZE: usize = 128;
+
+/// The number of blocks in the planner buffer of Grbl on an Arduino Uno.
+pub const PLANNER_BLOCKS: usize = 15;
+
+/// How often status reports are requested. Grbl recommends at most 5 reports per second.
+pub const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Real-time commands, which are executed immediately and do not take up buffer space.
+pub mod realtime {
+    pub const STATUS: u8 = b'?';
+    pub const FEED_HOLD: u8 = b'!';
+    pub const CYCLE_START: u8 = b'~';
+    pub const SOFT_RESET: u8 = 0x18;
+    pub const JOG_CANCEL: u8 = 0x85;
+    pub const FEED_RESET: u8 = 0x90;
+    pub const FEED_PLUS_10: u8 = 0x91;
+    pub const FEED_MINUS_10: u8 = 0x92;
+    pub const FEED_PLUS_1: u8 = 0x93;
+    pub const FEED_MINUS_1: u8 = 0x94;
+    pub const RAPID_100: u8 = 0x95;
+    pub const RAPID_50: u8 = 0x96;
+    pub const RAPID_25: u8 = 0x97;
+    pub const SPINDLE_RESET: u8 = 0x99;
+    pub const SPINDLE_PLUS_10: u8 = 0x9A;
+    pub const SPINDLE_MINUS_10: u8 = 0x9B;
+    pub const SPINDLE_PLUS_1: u8 = 0x9C;
+    pub const SPINDLE_MINUS_1: u8 = 0x9D;
+    /// Stops and restarts the spindle during a feed hold.
+    pub const SPINDLE_STOP: u8 = 0x9E;
+}
+
+/// An override which is changed in steps of 10% and 1%, from 10% to 200%.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Override {
+    Feed,
+    Spindle,
+}
+
+impl Override {
+    /// The real-time commands resetting the override to 100% and changing it by +10%, -10%,
+    /// +1% and -1%.
+    pub fn commands(self) -> [u8; 5] {
+        use realtime::*;
+        match self {
+            Self::Feed => [
+                FEED_RESET,
+                FEED_PLUS_10,
+                FEED_MINUS_10,
+                FEED_PLUS_1,
+                FEED_MINUS_1,
+            ],
+            Self::Spindle => [
+                SPINDLE_RESET,
+                SPINDLE_PLUS_10,
+                SPINDLE_MINUS_10,
+                SPINDLE_PLUS_1,
+                SPINDLE_MINUS_1,
+            ],
+        }
+    }
+
+    /// The value of the override, in percent.
+    pub fn value(self, overrides: &Overrides) -> u32 {
+        match self {
+            Self::Feed => overrides.feed,
+            Self::Spindle => overrides.spindle,
+        }
+    }
+
+    /// The real-time commands changing the override from the current to the target value.
+    pub fn adjust(self, current: u32, target: u32) -> Vec<u8> {
+        let [reset, plus_10, minus_10, plus_1, minus_1] = self.commands();
+        let target = target.clamp(10, 200);
+        let mut current = current.clamp(10, 200);
+
+        let mut commands = Vec::new();
+        // Starting over from 100% can take fewer steps
+        if current.abs_diff(target) > 100u32.abs_diff(target) {
+            commands.push(reset);
+            current = 100;
+        }
+        while current + 10 <= target {
+            commands.push(plus_10);
+            current += 10;
+        }
+        while current >= target + 10 {
+            commands.push(minus_10);
+            current -= 10;
+        }
+        while current < target {
+            commands.push(plus_1);
+            current += 1;
+        }
+        while current > target {
+            commands.push(minus_1);
+            current -= 1;
+        }
+        commands
+    }
+}
+
+/// The real-time command setting the rapid override, which is either 100%, 50% or 25%.
+pub fn rapid_override(percent: u32) -> u8 {
+    match percent {
+        0..=37 => realtime::RAPID_25,
+        38..=75 => realtime::RAPID_50,
+        _ => realtime::RAPID_100,
+    }
+}
+
+const AXES: [char; 3] = ['X', 'Y', 'Z'];
+
+/// A jog moving the machine by the distance at the feed rate, in millimeters. Jogs do not change
+/// the modal state and can be cancelled with `realtime::JOG_CANCEL`.
+pub fn jog(distance: &Vector3<f32>, feed: f32) -> String {
+    format!("$J=G91 G21{} F{}", distance_words(distance), number(feed))
+}
+
+/// A block setting the work position of the axes in the active work coordinate system, e.g.
+/// `[Some(0.0), Some(0.0), None]` makes the current position the XY origin.
+pub fn set_work_position(position: [Option<f32>; 3]) -> String {
+    format!("G10 L20 P0{}", axis_words(position))
+}
+
+/// The blocks of a probing move by the distance, in millimeters. The machine stops when the
+/// probe makes contact and reports the position, it raises an alarm if it doesn't.
+pub fn probe(distance: &Vector3<f32>, feed: f32) -> Vec<String> {
+    vec![
+        format!(
+            "G38.2 G91 G21{} F{}",
+            distance_words(distance),
+            number(feed)
+        ),
+        // G91 is modal
+        "G90".to_string(),
+    ]
+}
+
+/// The words of the axes which move, e.g. " X10 Z-1".
+pub(super) fn distance_words(distance: &Vector3<f32>) -> String {
+    axis_words(distance.map(|value| (value != 0.0).then_some(value)).into())
+}
+
+/// The words of the axes with values, e.g. " X10 Z-1".
+pub(super) fn axis_words(values: [Option<f32>; 3]) -> String {
+    AXES.iter()
+        .zip(values)
+        .filter_map(|(axis, value)| Some(format!(" {}{}", axis, number(value?))))
+        .collect()
+}
+
+/// Formats the number with up to three decimal places.
+pub(super) fn number(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "-0" => "0".to_string(),
+        _ => text.to_string(),
+    }
+}
+
+/// Something the controller reported while polling.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A block was rejected. The line is the line number in the program for blocks of a job.
+    Error { line: Option<usize>, code: u8 },
+    /// The controller entered the alarm state and stopped. The running job was aborted.
+    Alarm(u8),
+    /// The controller started, e.g. after a soft reset. Contains the welcome message.
+    Reset(String),
+    /// All blocks of the job were executed.
+    Finished,
+    /// A status report.
+    Status(Status),
+    /// The result of a probing move.
+    Probe(ProbeResult),
+    /// A block was rejected by a controller describing its errors in words, like Marlin.
+    Rejected {
+        line: Option<usize>,
+        message: String,
+    },
+    /// The controller stopped because of an error or an emergency stop and has to be restarted.
+    /// The running job was aborted.
+    Halted(String),
+    /// Any other output, like feedback messages and settings.
+    Message(String),
+}
+
+/// A block sent to the controller which was not acknowledged yet.
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    /// The length of the block including the newline.
+    length: usize,
+    /// The line number in the program, for blocks of a job.
+    line: Option<usize>,
+    /// Whether the block unlocks the controller ($X or $H).
+    unlock: bool,
+}
+
+pub struct Grbl<P> {
+    port: P,
+    /// The size of the receive buffer of the controller in bytes.
+    pub buffer_size: usize,
+    /// Blocks waiting to be sent, before the blocks of the job.
+    commands: VecDeque<String>,
+    job: Option<Job>,
+    /// The blocks in the receive buffer of the controller, oldest first.
+    pending: VecDeque<Pending>,
+    /// Received bytes which do not form a complete line yet.
+    input: Vec<u8>,
+    paused: bool,
+    alarm: Option<u8>,
+    /// How often status reports are requested, never if None.
+    pub status_interval: Option<Duration>,
+    /// When the last status report was requested.
+    requested: Option<Instant>,
+    status: Option<Status>,
+}
+
+impl Grbl<Box<dyn SerialPort>> {
+    /// Connects to the controller at the serial port and polls its status.
+    pub fn open(path: &str) -> Result<Self, SenderError> {
+        let mut grbl = Self::new(super::open(path, BAUD_RATE)?);
+        grbl.status_interval = Some(STATUS_INTERVAL);
+        Ok(grbl)
+    }
+}
+
+impl<P: Read + Write> Grbl<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            buffer_size: BUFFER_SIZE,
+            commands: VecDeque::new(),
+            job: None,
+            pending: VecDeque::new(),
+            input: Vec::new(),
+            paused: false,
+            alarm: None,
+            status_interval: None,
+            requested: None,
+            status: None,
+        }
+    }
+
+    /// Starts streaming the program. Fails if another job is still running.
+    pub fn start(&mut self, program: &str) -> Result<(), SenderError> {
+        if self.is_running() {
+            return Err(SenderError::Busy);
+        }
+        self.job = Some(Job::new(program));
+        Ok(())
+    }
+
+    /// Queues a block, e.g. "$H" or "G0 X0 Y0". Commands are sent before the blocks of a job.
+    pub fn command(&mut self, block: &str) {
+        self.commands.push_back(block.trim().to_string());
+    }
+
+    /// Stops the motion of the machine with a controlled deceleration (feed hold).
+    pub fn pause(&mut self) -> Result<(), SenderError> {
+        self.realtime(realtime::FEED_HOLD)?;
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Resumes the motion after a feed hold.
+    pub fn resume(&mut self) -> Result<(), SenderError> {
+        self.realtime(realtime::CYCLE_START)?;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Resets the controller, which stops the machine immediately and clears its buffers. The
+    /// running job is aborted.
+    pub fn reset(&mut self) -> Result<(), SenderError> {
+        self.realtime(realtime::SOFT_RESET)?;
+        self.clear();
+        Ok(())
+    }
+
+    /// Sends a real-time command.
+    pub fn realtime(&mut self, command: u8) -> Result<(), SenderError> {
+        self.port.write_all(&[command])?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    pub fn job(&self) -> Option<&Job> {
+        self.job.as_ref()
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        // Blocks are acknowledged when they enter the planner buffer
+        let status = self.status.as_ref();
+        let planned = (status.and_then(|status| status.buffer))
+            .map_or(PLANNER_BLOCKS, |(free, _)| {
+                PLANNER_BLOCKS.saturating_sub(free as usize)
+            });
+        let number = status.and_then(|status| status.line);
+        self.job.as_ref().map(|job| job.progress(planned, number))
+    }
+
+    /// Whether a job is being streamed or executed.
+    pub fn is_running(&self) -> bool {
+        self.job.as_ref().map_or(false, |job| !job.is_done())
+    }
+
+    /// Whether the machine was paused with a feed hold.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The code of the alarm the controller is in, if any. Zero if the code is unknown, e.g.
+    /// when the controller reports being locked after a reset. Cleared once unlocking or homing
+    /// succeeds.
+    pub fn alarm(&self) -> Option<u8> {
+        self.alarm
+    }
+
+    /// The last status report.
+    pub fn status(&self) -> Option<&Status> {
+        self.status.as_ref()
+    }
+
+    /// Whether all blocks were sent and executed.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.commands.is_empty() && !self.is_running()
+    }
+
+    /// The number of bytes in the receive buffer of the controller.
+    pub fn buffered(&self) -> usize {
+        self.pending.iter().map(|pending| pending.length).sum()
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Handles the output of the controller and sends blocks as long as they fit into its
+    /// receive buffer. Has to be called regularly.
+    pub fn poll(&mut self) -> Result<Vec<Event>, SenderError> {
+        if let Some(interval) = self.status_interval {
+            if self
+                .requested
+                .map_or(true, |time| time.elapsed() >= interval)
+            {
+                self.realtime(realtime::STATUS)?;
+                self.requested = Some(Instant::now());
+            }
+        }
+
+        let mut buffer = [0; 256];
+        loop {
+            match self.port.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.input.extend_from_slice(&buffer[..n]);
+                    if n < buffer.len() {
+                        break;
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(end) = self.input.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() {
+                self.receive(line, &mut events);
+            }
+        }
+
+        self.stream()?;
+        Ok(events)
+    }
+
+    fn receive(&mut self, line: &str, events: &mut Vec<Event>) {
+        if line == "ok" {
+            self.acknowledge(None, events);
+        } else if let Some(code) = line.strip_prefix("error:") {
+            self.acknowledge(Some(code.parse().unwrap_or_default()), events);
+        } else if let Some(code) = line.strip_prefix("ALARM:") {
+            // Alarms reset the controller, which discards the blocks in its buffer
+            let code = code.parse().unwrap_or_default();
+            self.clear();
+            self.alarm = Some(code);
+            events.push(Event::Alarm(code));
+        } else if line.starts_with('<') {
+            match Status::parse(line, self.status.as_ref()) {
+                Some(status) => {
+                    self.paused = status.state == State::Hold;
+                    if status.state == State::Alarm && self.alarm.is_none() {
+                        self.alarm = Some(0);
+                    }
+                    self.status = Some(status.clone());
+                    events.push(Event::Status(status));
+                }
+                None => events.push(Event::Message(line.to_string())),
+            }
+        } else if let Some(result) = ProbeResult::parse(line) {
+            events.push(Event::Probe(result));
+        } else if line.starts_with("Grbl ") {
+            // The controller stays locked after a reset if it was in an alarm
+            self.clear();
+            events.push(Event::Reset(line.to_string()));
+        } else if line == "[MSG:'$H'|'$X' to unlock]" {
+            self.alarm = self.alarm.or(Some(0));
+            events.push(Event::Message(line.to_string()));
+        } else {
+            events.push(Event::Message(line.to_string()));
+        }
+    }
+
+    /// Handles the answer to the oldest block in the buffer of the controller.
+    fn acknowledge(&mut self, error: Option<u8>, events: &mut Vec<Event>) {
+        let Some(pending) = self.pending.pop_front() else {
+            return;
+        };
+        // Unlocking and homing leave the alarm state
+        if pending.unlock && error.is_none() {
+            self.alarm = None;
+        }
+        if let Some(code) = error {
+            events.push(Event::Error {
+                line: pending.line,
+                code,
+            });
+        }
+
+        let Some(job) = self.job.as_mut().filter(|_| pending.line.is_some()) else {
+            return;
+        };
+        job.acknowledged += 1;
+        // The blocks after an error would run in the wrong state
+        if error.is_some() {
+            job.stopped = true;
+        } else if job.is_done() && !job.stopped {
+            events.push(Event::Finished);
+        }
+    }
+
+    /// Sends blocks while they fit into the receive buffer of the controller.
+    fn stream(&mut self) -> Result<(), SenderError> {
+        loop {
+            let (block, line) = match self.commands.front() {
+                Some(block) => (block.clone(), None),
+                None => match self.job.as_ref().and_then(Job::next) {
+                    // Grbl rejects all blocks but settings during an alarm
+                    Some((line, block)) if self.alarm.is_none() => (block.clone(), Some(*line)),
+                    _ => return Ok(()),
+                },
+            };
+
+            let length = block.len() + 1;
+            // Blocks longer than the buffer are sent to an empty buffer, the controller rejects
+            // them
+            if self.buffered() + length > self.buffer_size && !self.pending.is_empty() {
+                return Ok(());
+            }
+            self.port.write_all(format!("{}\n", block).as_bytes())?;
+            let unlock = line.is_none() && (block == "$X" || block == "$H");
+            self.pending.push_back(Pending {
+                length,
+                line,
+                unlock,
+            });
+
+            match line {
+                None => {
+                    self.commands.pop_front();
+                }
+                Some(_) => {
+                    if let Some(job) = self.job.as_mut() {
+                        job.sent += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets the blocks in the buffer of the controller after a reset and aborts the job.
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.commands.clear();
+        self.paused = false;
+        if let Some(job) = self.job.as_mut() {
+            job.stopped = true;
+            job.acknowledged = job.sent;
+        }
+    }
+}
+
+impl<P: Read + Write> Sender for Grbl<P> {
+    fn firmware(&self) -> Firmware {
+        Firmware::Grbl
+    }
+
+    fn start(&mut self, program: &str) -> Result<(), SenderError> {
+        Grbl::start(self, program)
+    }
+
+    fn command(&mut self, block: &str) {
+        Grbl::command(self, block)
+    }
+
+    fn realtime(&mut self, command: u8) -> Result<(), SenderError> {
+        Grbl::realtime(self, command)
+    }
+
+    fn pause(&mut self) -> Result<(), SenderError> {
+        Grbl::pause(self)
+    }
+
+    fn resume(&mut self) -> Result<(), SenderError> {
+        Grbl::resume(self)
+    }
+
+    fn reset(&mut self) -> Result<(), SenderError> {
+        Grbl::reset(self)
+    }
+
+    fn poll(&mut self) -> Result<Vec<Event>, SenderError> {
+        Grbl::poll(self)
+    }
+
+    fn status(&self) -> Option<&Status> {
+        Grbl::status(self)
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        Grbl::progress(self)
+    }
+
+    fn is_running(&self) -> bool {
+        Grbl::is_running(self)
+    }
+
+    fn is_paused(&self) -> bool {
+        Grbl::is_paused(self)
+    }
+
+    fn is_idle(&self) -> bool {
+        Grbl::is_idle(self)
+    }
+
+    fn alarm(&self) -> Option<u8> {
+        Grbl::alarm(self)
+    }
+}
+
+/// A description of the error code.
+pub fn error_message(code: u8) -> &'static str {
+    match code {
+        1 => "Expected a command letter",
+        2 => "Bad number format",
+        3 => "Invalid '$' system command",
+        4 => "Negative value",
+        5 => "Homing is not enabled",
+        6 => "Step pulse time must be at least 3 microseconds",
+        7 => "EEPROM read failed, settings were restored",
+        8 => "'$' command only valid when idle",
+        9 => "G-code is locked during alarm or jog",
+        10 => "Soft limits require homing",
+        11 => "Line too long",
+        12 => "Setting exceeds the maximum step rate",
+        13 => "Safety door opened",
+        14 => "Line exceeds the EEPROM line length",
+        15 => "Jog target exceeds the machine travel",
+        16 => "Invalid jog command",
+        17 => "Laser mode requires PWM output",
+        20 => "Unsupported G-code command",
+        21 => "Multiple commands of the same modal group",
+        22 => "Undefined feed rate",
+        23 => "Command requires an integer value",
+        24 => "Multiple commands require axis words",
+        25 => "Repeated word",
+        26 => "Missing axis words",
+        27 => "Line number out of range",
+        28 => "Missing P or L value",
+        29 => "Unsupported work coordinate system",
+        30 => "G53 requires G0 or G1",
+        31 => "Unused axis words",
+        32 => "Arc without axis words in the plane",
+        33 => "Invalid motion target",
+        34 => "Invalid arc radius",
+        35 => "Arc without offsets in the plane",
+        36 => "Unused words",
+        37 => "Tool length offset is not assigned to the configured axis",
+        38 => "Tool number too large",
+        _ => "Unknown error",
+    }
+}
+
+/// A description of the alarm code.
+pub fn alarm_message(code: u8) -> &'static str {
+    match code {
+        0 => "Locked, unlock or home the machine",
+        1 => "Hard limit triggered, position was lost",
+        2 => "Motion target exceeds the machine travel",
+        3 => "Reset while in motion, position was lost",
+        4 => "Probe is not in the expected initial state",
+        5 => "Probe did not make contact",
+        6 => "Homing failed: reset during homing",
+        7 => "Homing failed: safety door opened",
+        8 => "Homing failed: could not clear the limit switch",
+        9 => "Homing failed: could not find the limit switch",
+        _ => "Unknown alarm",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sender::mock::Mock;
+
+    #[test]
+    fn character_counting() {
+        let mock = Mock::default();
+        let mut grbl = Grbl::new(mock.clone());
+        // 25 bytes per block with the newline
+        let program = "G1 X10.000 Y10.000 F1000\n".repeat(10);
+        grbl.start(&format!("(Contour)\n\n{}", program)).unwrap();
+        assert!(matches!(grbl.start("G0 X0"), Err(SenderError::Busy)));
+
+        grbl.poll().unwrap();
+        assert_eq!(mock.sent().lines().count(), 5);
+        assert_eq!(grbl.buffered(), 125);
+
+        mock.reply("ok\n");
+        grbl.poll().unwrap();
+        assert_eq!(mock.sent().lines().count(), 1);
+
+        mock.reply("ok\nok\nok\n");
+        grbl.poll().unwrap();
+        assert_eq!(mock.sent().lines().count(), 3);
+        let progress = grbl.progress().unwrap();
+        assert_eq!(
+            (progress.sent, progress.acknowledged, progress.total),
+            (9, 4, 10)
+        );
+        // After the comment and the empty line
+        assert_eq!(progress.line, Some(6));
+        // Without a status report, the planner buffer may hold all of them
+        assert_eq!(progress.executing, None);
+
+        mock.reply("<Run|MPos:0.000,0.000,0.000|Bf:13,50|FS:1000,0>\n");
+        grbl.poll().unwrap();
+        assert_eq!(grbl.progress().unwrap().executing, Some(4));
+        mock.sent();
+
+        mock.reply(&"ok\n".repeat(5));
+        assert_eq!(grbl.poll().unwrap(), vec![]);
+        assert_eq!(mock.sent().lines().count(), 1);
+        mock.reply("ok\n");
+        assert_eq!(grbl.poll().unwrap(), vec![Event::Finished]);
+        assert!(!grbl.is_running());
+        assert_eq!(grbl.progress().unwrap().fraction(), 1.0);
+    }
+
+    #[test]
+    fn executing_line() {
+        let mut job = Job::new("N10 G0 X0\n(Contour)\nN20 G1 X1\nN30 G1 X2\n");
+        (job.sent, job.acknowledged) = (3, 3);
+        // Programs with line numbers report the executing block
+        assert_eq!(job.progress(PLANNER_BLOCKS, Some(20)).executing, Some(3));
+        assert_eq!(job.progress(PLANNER_BLOCKS, None).executing, None);
+        assert_eq!(job.progress(1, Some(40)).executing, Some(3));
+    }
+
+    #[test]
+    fn errors_stop_the_job() {
+        let mock = Mock::default();
+        let mut grbl = Grbl::new(mock.clone());
+        grbl.start("G21 G90 ; setup\nG1 X10 F500\n\nG5 X20\nG1 X30\n")
+            .unwrap();
+        grbl.poll().unwrap();
+        assert_eq!(mock.sent(), "G21 G90\nG1 X10 F500\nG5 X20\nG1 X30\n");
+
+        mock.reply("ok\nok\nerror:20\nok\n");
+        let events = grbl.poll().unwrap();
+        assert_eq!(
+            events,
+            vec![Event::Error {
+                line: Some(4),
+                code: 20
+            }]
+        );
+        assert!(!grbl.is_running());
+        assert!(grbl.job().unwrap().is_stopped());
+        assert_eq!(grbl.buffered(), 0);
+    }
+
+    #[test]
+    fn alarms_and_realtime_commands() {
+        let mock = Mock::default();
+        let mut grbl = Grbl::new(mock.clone());
+        grbl.start(&"G1 X1 F100\n".repeat(100)).unwrap();
+        grbl.poll().unwrap();
+        grbl.pause().unwrap();
+        assert!(grbl.is_paused());
+        grbl.resume().unwrap();
+        assert!(mock.sent().ends_with("G1 X1 F100\n!~"));
+
+        mock.reply("ALARM:1\n[MSG:Reset to continue]\n");
+        let events = grbl.poll().unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Alarm(1),
+                Event::Message("[MSG:Reset to continue]".into())
+            ]
+        );
+        assert_eq!(grbl.alarm(), Some(1));
+        assert!(!grbl.is_running());
+        assert_eq!(mock.sent(), "");
+
+        grbl.reset().unwrap();
+        assert_eq!(mock.sent(), "\u{18}");
+        mock.reply("\r\nGrbl 1.1h ['$' for help]\r\n[MSG:'$H'|'$X' to unlock]\r\n");
+        let events = grbl.poll().unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event::Reset("Grbl 1.1h ['$' for help]".into()),
+                Event::Message("[MSG:'$H'|'$X' to unlock]".into())
+            ]
+        );
+        // The controller stays locked after the reset
+        assert_eq!(grbl.alarm(), Some(1));
+
+        // Jobs can be restarted once homing succeeded
+        grbl.start("G0 Z5").unwrap();
+        grbl.command("$H");
+        grbl.poll().unwrap();
+        assert_eq!(mock.sent(), "$H\n");
+        assert_eq!(grbl.alarm(), Some(1));
+        mock.reply("ok\n");
+        grbl.poll().unwrap();
+        assert_eq!(grbl.alarm(), None);
+        assert_eq!(mock.sent(), "G0 Z5\n");
+
+        // Controllers locked after starting are in an alarm with an unknown code
+        let mut grbl = Grbl::new(mock.clone());
+        mock.reply("Grbl 1.1h ['$' for help]\n[MSG:'$H'|'$X' to unlock]\n");
+        grbl.poll().unwrap();
+        assert_eq!(grbl.alarm(), Some(0));
+        grbl.command("$X");
+        grbl.poll().unwrap();
+        mock.reply("error:9\n");
+        grbl.poll().unwrap();
+        assert_eq!(grbl.alarm(), Some(0));
+    }
+
+    #[test]
+    fn status_polling() {
+        let mock = Mock::default();
+        let mut grbl = Grbl::new(mock.clone());
+        grbl.status_interval = Some(Duration::from_secs(60));
+        grbl.start("G1 X10 F100").unwrap();
+        grbl.poll().unwrap();
+        // Status requests bypass the buffer
+        assert_eq!(mock.sent(), "?G1 X10 F100\n");
+        assert_eq!(grbl.buffered(), 12);
+
+        mock.reply("<Hold:0|MPos:1.000,0.000,0.000|FS:0,0|WCO:1.000,0.000,0.000>\n");
+        let events = grbl.poll().unwrap();
+        assert!(matches!(events.as_slice(), [Event::Status(status)] if status.work.x == 0.0));
+        assert!(grbl.is_paused());
+        assert_eq!(grbl.status().unwrap().state, State::Hold);
+        // Not yet time for the next report
+        assert_eq!(mock.sent(), "");
+    }
+
+    #[test]
+    fn control_blocks() {
+        assert_eq!(
+            jog(&Vector3::new(10.0, 0.0, -0.1), 1000.0),
+            "$J=G91 G21 X10 Z-0.1 F1000"
+        );
+        assert_eq!(
+            set_work_position([Some(0.0), None, Some(-0.0)]),
+            "G10 L20 P0 X0 Z0"
+        );
+        assert_eq!(
+            probe(&Vector3::new(0.0, 0.0, -20.0), 50.0),
+            vec!["G38.2 G91 G21 Z-20 F50", "G90"]
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        use realtime::*;
+        assert_eq!(
+            Override::Feed.adjust(100, 121),
+            [FEED_PLUS_10, FEED_PLUS_10, FEED_PLUS_1]
+        );
+        assert_eq!(
+            Override::Spindle.adjust(150, 98),
+            [SPINDLE_RESET, SPINDLE_MINUS_1, SPINDLE_MINUS_1]
+        );
+        assert_eq!(Override::Feed.adjust(100, 0), [FEED_MINUS_10; 9]);
+        assert_eq!(Override::Feed.adjust(100, 100), []);
+        assert_eq!(rapid_override(50), RAPID_50);
+    }
+}
@@ -0,0 +1,812 @@
+//! Sender for Marlin, Smoothieware and other firmwares which came from 3D printers and also
+//! drive small routers and laser cutters.
+//!
+//! Blocks are streamed ping-pong: a line is only sent after the previous one was answered by
+//! "ok". Lines are numbered and carry a checksum, e.g. "N12 G1 X10*85". When the controller
+//! receives a corrupt line, it asks for it again with "Resend: 12" and the lines from there are
+//! sent again. The position is queried with M114. If an "ok" seems to have been lost, M105 asks
+//! for another one, and the "ok" of M105 itself is skipped if the first one was only late.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector3;
+use serialport::SerialPort;
+
+use super::grbl::{axis_words, distance_words, number, realtime, Event};
+use super::status::{Overrides, State, Status};
+use super::{simulator, Firmware, Job, Progress, Sender, SenderError};
+
+/// The default baud rate of Marlin. Smoothieware ignores the baud rate of its USB port.
+pub const BAUD_RATE: u32 = 250000;
+
+/// How often the position is queried. Queries are answered in turn with the other lines, so
+/// they slow down streaming a little.
+pub const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait for an "ok" before asking for another one. Marlin reports that it is busy
+/// while it executes long commands like dwells and homing, which restarts the wait.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of sent lines kept for resending.
+const HISTORY: usize = 64;
+
+/// The default size of the planner buffer of Marlin. Blocks are acknowledged when they enter it.
+const PLANNER_BLOCKS: usize = 16;
+
+/// The blocks of a jog moving the machine by the distance at the feed rate. Marlin executes
+/// one command per line and has no separate jog mode, the jog is an ordinary relative move.
+pub fn jog(distance: &Vector3<f32>, feed: f32) -> Vec<String> {
+    vec![
+        "G91".to_string(),
+        format!("G0{} F{}", distance_words(distance), number(feed)),
+        "G90".to_string(),
+    ]
+}
+
+/// A block setting the work position of the axes, e.g. `[Some(0.0), Some(0.0), None]` makes
+/// the current position the XY origin.
+pub fn set_work_position(position: [Option<f32>; 3]) -> String {
+    format!("G92{}", axis_words(position))
+}
+
+/// The line with its number and checksum, the XOR of all bytes before the "*".
+pub fn checksummed(number: usize, block: &str) -> String {
+    let line = format!("N{} {}", number, block);
+    let checksum = line.bytes().fold(0, |checksum, byte| checksum ^ byte);
+    format!("{}*{}", line, checksum)
+}
+
+/// Parses a position report, "X:10.00 Y:5.00 Z:-1.00 E:0.00 Count X:800 Y:400 Z:-80" from
+/// Marlin or "C: X:10.0000 Y:5.0000 Z:-1.0000 E:0.0000" from Smoothieware. The counts after
+/// the position are motor steps.
+pub fn parse_position(report: &str) -> Option<Vector3<f32>> {
+    let mut position = [None; 3];
+    for word in report
+        .split_whitespace()
+        .take_while(|word| *word != "Count")
+    {
+        let Some((axis, value)) = word.split_once(':') else {
+            continue;
+        };
+        if let Some(i) = ["X", "Y", "Z"].iter().position(|name| *name == axis) {
+            position[i] = value.parse().ok();
+        }
+    }
+    Some(Vector3::new(position[0]?, position[1]?, position[2]?))
+}
+
+/// The line asked for again by "Resend: 12" from Marlin or "rs N12" from other firmwares.
+fn resend_request(line: &str) -> Option<usize> {
+    let number = (line.strip_prefix("Resend:")).or_else(|| line.strip_prefix("rs "))?;
+    number.trim().trim_start_matches('N').parse().ok()
+}
+
+/// The message of an error report.
+fn error_message(line: &str) -> Option<&str> {
+    match line
+        .strip_prefix("Error:")
+        .or_else(|| line.strip_prefix("error:"))
+    {
+        Some(message) => Some(message.trim()),
+        None => {
+            (line.strip_prefix("echo:")).filter(|message| message.starts_with("Unknown command"))
+        }
+    }
+}
+
+/// Whether the error stopped the controller. Smoothieware answers every line with "!!" while it
+/// is halted.
+fn is_halted(line: &str, error: Option<&str>) -> bool {
+    line == "!!"
+        || error.map_or(false, |message| {
+            let message = message.to_lowercase();
+            ["halted", "kill", "stopped"]
+                .iter()
+                .any(|word| message.contains(word))
+        })
+}
+
+/// A line sent to the controller.
+#[derive(Debug, Clone)]
+struct Sent {
+    /// The line with its number and checksum.
+    text: String,
+    /// The line number in the program, for blocks of a job.
+    line: Option<usize>,
+}
+
+pub struct Marlin<P> {
+    port: P,
+    /// Blocks waiting to be sent, before the blocks of the job.
+    commands: VecDeque<String>,
+    job: Option<Job>,
+    /// The last lines sent, the last one has the number `number - 1`.
+    history: VecDeque<Sent>,
+    /// The number of the next new line.
+    number: usize,
+    /// The number of the line waiting for its "ok".
+    pending: Option<usize>,
+    /// The lines with smaller numbers were acknowledged.
+    acknowledged: usize,
+    /// The number of the next line to send again after the controller asked for it.
+    resend: Option<usize>,
+    /// Whether the controller asked for the pending line again, its "ok" does not accept it.
+    repeat: bool,
+    /// The error reported for the pending line.
+    error: Option<String>,
+    /// The number of M105 sent for lost answers whose "ok" is still expected. Nothing is sent
+    /// until they are answered, so that their "ok" does not accept the next line.
+    probes: usize,
+    /// Received bytes which do not form a complete line yet.
+    input: Vec<u8>,
+    paused: bool,
+    halted: bool,
+    overrides: Overrides,
+    /// How often the position is queried, never if None.
+    pub status_interval: Option<Duration>,
+    /// When the position was last queried.
+    requested: Option<Instant>,
+    /// Whether a position query was not answered yet.
+    querying: bool,
+    /// How long to wait for an "ok" before asking for another one.
+    pub timeout: Duration,
+    /// When the controller last sent something.
+    received: Instant,
+    status: Option<Status>,
+}
+
+impl Marlin<Box<dyn SerialPort>> {
+    /// Connects to the controller at the serial port and polls its position.
+    pub fn open(path: &str, baud_rate: u32) -> Result<Self, SenderError> {
+        if path == simulator::PORT_NAME {
+            return Err(SenderError::Unsupported("the Marlin protocol".to_string()));
+        }
+        let mut marlin = Self::new(super::open(path, baud_rate)?);
+        marlin.status_interval = Some(STATUS_INTERVAL);
+        Ok(marlin)
+    }
+}
+
+impl<P: Read + Write> Marlin<P> {
+    pub fn new(port: P) -> Self {
+        let mut marlin = Self {
+            port,
+            commands: VecDeque::new(),
+            job: None,
+            history: VecDeque::new(),
+            number: 0,
+            pending: None,
+            acknowledged: 0,
+            resend: None,
+            repeat: false,
+            error: None,
+            probes: 0,
+            input: Vec::new(),
+            paused: false,
+            halted: false,
+            overrides: Overrides::default(),
+            status_interval: None,
+            requested: None,
+            querying: false,
+            timeout: TIMEOUT,
+            received: Instant::now(),
+            status: None,
+        };
+        marlin.restart();
+        marlin
+    }
+
+    /// Starts streaming the program. Fails if another job is still running.
+    pub fn start(&mut self, program: &str) -> Result<(), SenderError> {
+        if self.is_running() {
+            return Err(SenderError::Busy);
+        }
+        self.job = Some(Job::new(program));
+        Ok(())
+    }
+
+    /// Queues a block, e.g. "G28" or "G0 X0 Y0". Commands are sent before the blocks of a job.
+    pub fn command(&mut self, block: &str) {
+        self.commands.push_back(block.trim().to_string());
+    }
+
+    /// Stops sending the blocks of the job. The machine only stops after the blocks it already
+    /// received, the firmware has no feed hold.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Stops the machine immediately with M112 and aborts the running job. Marlin has to be
+    /// restarted afterwards, Smoothieware leaves the halt with M999.
+    pub fn reset(&mut self) -> Result<(), SenderError> {
+        self.port.write_all(b"M112\n")?;
+        self.port.flush()?;
+        self.clear();
+        Ok(())
+    }
+
+    /// Handles the real-time commands of Grbl which have an equivalent: status reports, pausing,
+    /// resetting and the feed override, which is set with M220.
+    pub fn realtime(&mut self, command: u8) -> Result<(), SenderError> {
+        use realtime::*;
+        let feed = self.overrides.feed;
+        let feed = match command {
+            STATUS => {
+                self.query();
+                return Ok(());
+            }
+            FEED_HOLD => {
+                self.pause();
+                return Ok(());
+            }
+            CYCLE_START => {
+                self.resume();
+                return Ok(());
+            }
+            SOFT_RESET => return self.reset(),
+            FEED_RESET => 100,
+            FEED_PLUS_10 => feed + 10,
+            FEED_MINUS_10 => feed.saturating_sub(10),
+            FEED_PLUS_1 => feed + 1,
+            FEED_MINUS_1 => feed.saturating_sub(1),
+            _ => {
+                return Err(SenderError::Unsupported(format!(
+                    "the real-time command 0x{:02X}",
+                    command
+                )))
+            }
+        };
+        self.overrides.feed = feed.clamp(10, 200);
+        self.command(&format!("M220 S{}", self.overrides.feed));
+        Ok(())
+    }
+
+    pub fn job(&self) -> Option<&Job> {
+        self.job.as_ref()
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        // Marlin does not report its planner buffer, assume that it is full
+        self.job
+            .as_ref()
+            .map(|job| job.progress(PLANNER_BLOCKS, None))
+    }
+
+    /// Whether a job is being streamed or executed.
+    pub fn is_running(&self) -> bool {
+        self.job.as_ref().map_or(false, |job| !job.is_done())
+    }
+
+    /// Whether sending the job was paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Whether the controller stopped because of an error or an emergency stop.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The last position report. Marlin only reports the position in work coordinates, the
+    /// machine position is the same.
+    pub fn status(&self) -> Option<&Status> {
+        self.status.as_ref()
+    }
+
+    /// Whether all blocks were sent and acknowledged.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_none()
+            && self.probes == 0
+            && self.resend.is_none()
+            && self.commands.is_empty()
+            && !self.is_running()
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    /// Handles the output of the controller and sends the next line when the previous one was
+    /// answered. Has to be called regularly.
+    pub fn poll(&mut self) -> Result<Vec<Event>, SenderError> {
+        if let Some(interval) = self.status_interval {
+            if self
+                .requested
+                .map_or(true, |time| time.elapsed() >= interval)
+            {
+                self.query();
+            }
+        }
+
+        let mut buffer = [0; 256];
+        loop {
+            match self.port.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.input.extend_from_slice(&buffer[..n]);
+                    self.received = Instant::now();
+                    if n < buffer.len() {
+                        break;
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(end) = self.input.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() {
+                self.receive(line, &mut events);
+            }
+        }
+
+        // An "ok" lost to a transmission error would stop the stream for good. The answer to
+        // M105 also answers the pending line.
+        if self.received.elapsed() >= self.timeout {
+            if self.pending.is_some() {
+                self.port.write_all(b"M105\n")?;
+                self.probes += 1;
+                self.received = Instant::now();
+            } else if self.probes > 0 {
+                // No more answers came, the "ok" of the pending line was lost and the one of
+                // M105 accepted it
+                self.probes = 0;
+            }
+        }
+
+        let state = self.state();
+        if let Some(status) = self.status.as_mut() {
+            status.state = state;
+        }
+        self.stream(&mut events)?;
+        Ok(events)
+    }
+
+    fn receive(&mut self, line: &str, events: &mut Vec<Event>) {
+        let error = error_message(line);
+        if let Some(rest) = line.strip_prefix("ok") {
+            // Smoothieware answers M114 on the same line
+            if let Some(position) = parse_position(rest) {
+                self.report(position, events);
+            }
+            self.acknowledge(events);
+        } else if let Some(number) = resend_request(line) {
+            // The error was about the transmission, not the block
+            self.error = None;
+            self.resend = Some(number);
+            self.repeat = self.pending.is_some();
+        } else if is_halted(line, error) {
+            self.clear();
+            self.halted = true;
+            events.push(Event::Halted(error.unwrap_or(line).to_string()));
+        } else if let Some(message) = error {
+            self.error = Some(message.to_string());
+        } else if line == "start" || line.starts_with("Smoothie") {
+            self.clear();
+            self.restart();
+            events.push(Event::Reset(line.to_string()));
+        } else if let Some(position) = parse_position(line) {
+            self.report(position, events);
+        } else if let Some(feed) =
+            (line.strip_prefix("FR:")).and_then(|feed| feed.trim_end_matches('%').parse().ok())
+        {
+            self.overrides.feed = feed;
+        } else if line.starts_with("echo:busy") || line.starts_with("T:") || line == "wait" {
+            // Keep-alive messages and temperature reports
+        } else {
+            events.push(Event::Message(line.to_string()));
+        }
+    }
+
+    /// Handles the answer to the pending line.
+    fn acknowledge(&mut self, events: &mut Vec<Event>) {
+        let Some(number) = self.pending.take() else {
+            // The answer to M105 after the pending line was answered late
+            self.probes = self.probes.saturating_sub(1);
+            return;
+        };
+        let error = self.error.take();
+        // The line is sent again, or was accepted before
+        if std::mem::take(&mut self.repeat) || number < self.acknowledged {
+            return;
+        }
+        self.acknowledged = number + 1;
+
+        let line = self.sent(number).and_then(|sent| sent.line);
+        if let Some(message) = error.clone() {
+            events.push(Event::Rejected { line, message });
+        }
+        let Some(job) = self.job.as_mut().filter(|_| line.is_some()) else {
+            return;
+        };
+        job.acknowledged += 1;
+        // The blocks after an error would run in the wrong state
+        if error.is_some() {
+            job.stopped = true;
+        } else if job.is_done() && !job.stopped {
+            events.push(Event::Finished);
+        }
+    }
+
+    /// Sends the next line if the previous one was answered.
+    fn stream(&mut self, events: &mut Vec<Event>) -> Result<(), SenderError> {
+        if self.pending.is_some() || self.probes > 0 {
+            return Ok(());
+        }
+
+        if let Some(number) = self.resend.take() {
+            let Some(text) = self.sent(number).map(|sent| sent.text.clone()) else {
+                events.push(Event::Rejected {
+                    line: None,
+                    message: format!("line {} was asked for again, but it was not kept", number),
+                });
+                self.clear();
+                return Ok(());
+            };
+            self.port.write_all(format!("{}\n", text).as_bytes())?;
+            self.pending = Some(number);
+            self.resend = (number + 1 < self.number).then_some(number + 1);
+            return Ok(());
+        }
+
+        let (block, line) = match self.commands.pop_front() {
+            Some(block) => (block, None),
+            None => match self.job.as_ref().and_then(Job::next) {
+                Some((line, block)) if !self.paused && !self.halted => (block.clone(), Some(*line)),
+                _ => return Ok(()),
+            },
+        };
+
+        // The controller continues the line numbers after the one set by M110
+        if block == "M110 N0" {
+            self.history.clear();
+            (self.number, self.acknowledged) = (0, 0);
+        }
+        let text = checksummed(self.number, &block);
+        self.port.write_all(format!("{}\n", text).as_bytes())?;
+        self.history.push_back(Sent { text, line });
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        self.pending = Some(self.number);
+        self.number += 1;
+
+        match line {
+            // Restarting leaves the halt
+            None if block == "M999" => self.halted = false,
+            None => {}
+            Some(_) => {
+                if let Some(job) = self.job.as_mut() {
+                    job.sent += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The sent line with the number, if it was kept.
+    fn sent(&self, number: usize) -> Option<&Sent> {
+        let age = self.number.checked_sub(number)?;
+        self.history.get(self.history.len().checked_sub(age)?)
+    }
+
+    /// Queues a position query, unless one is waiting for its answer.
+    fn query(&mut self) {
+        self.requested = Some(Instant::now());
+        if !self.querying {
+            self.command("M114");
+            self.querying = true;
+        }
+    }
+
+    fn report(&mut self, position: Vector3<f32>, events: &mut Vec<Event>) {
+        self.querying = false;
+        let status = Status {
+            state: self.state(),
+            machine: position,
+            work: position,
+            offset: Vector3::zeros(),
+            feed: 0.0,
+            spindle: 0.0,
+            overrides: self.overrides,
+            buffer: None,
+            line: None,
+            pins: String::new(),
+            accessories: String::new(),
+        };
+        self.status = Some(status.clone());
+        events.push(Event::Status(status));
+    }
+
+    /// The state of the machine, as far as the sender knows it.
+    fn state(&self) -> State {
+        if self.halted {
+            State::Alarm
+        } else if self.is_running() && self.paused {
+            State::Hold
+        } else if self.is_running() {
+            State::Run
+        } else {
+            State::Idle
+        }
+    }
+
+    /// Starts the line numbers over, after connecting or a restart of the controller.
+    fn restart(&mut self) {
+        self.halted = false;
+        self.overrides = Overrides::default();
+        self.commands.push_front("M110 N0".to_string());
+    }
+
+    /// Forgets the lines the controller did not answer and aborts the job.
+    fn clear(&mut self) {
+        self.commands.clear();
+        self.pending = None;
+        self.resend = None;
+        self.repeat = false;
+        self.error = None;
+        self.probes = 0;
+        self.paused = false;
+        self.querying = false;
+        if let Some(job) = self.job.as_mut() {
+            job.stopped = true;
+            job.acknowledged = job.sent;
+        }
+    }
+}
+
+impl<P: Read + Write> Sender for Marlin<P> {
+    fn firmware(&self) -> Firmware {
+        Firmware::Marlin
+    }
+
+    fn start(&mut self, program: &str) -> Result<(), SenderError> {
+        Marlin::start(self, program)
+    }
+
+    fn command(&mut self, block: &str) {
+        Marlin::command(self, block)
+    }
+
+    fn realtime(&mut self, command: u8) -> Result<(), SenderError> {
+        Marlin::realtime(self, command)
+    }
+
+    fn pause(&mut self) -> Result<(), SenderError> {
+        Marlin::pause(self);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), SenderError> {
+        Marlin::resume(self);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), SenderError> {
+        Marlin::reset(self)
+    }
+
+    fn poll(&mut self) -> Result<Vec<Event>, SenderError> {
+        Marlin::poll(self)
+    }
+
+    fn status(&self) -> Option<&Status> {
+        Marlin::status(self)
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        Marlin::progress(self)
+    }
+
+    fn is_running(&self) -> bool {
+        Marlin::is_running(self)
+    }
+
+    fn is_paused(&self) -> bool {
+        Marlin::is_paused(self)
+    }
+
+    fn is_idle(&self) -> bool {
+        Marlin::is_idle(self)
+    }
+
+    fn alarm(&self) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sender::mock::Mock;
+
+    /// A sender which set the line numbers up.
+    fn connected(mock: &Mock) -> Marlin<Mock> {
+        let mut marlin = Marlin::new(mock.clone());
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N0 M110 N0*125\n");
+        mock.reply("ok\n");
+        marlin
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mock = Mock::default();
+        let mut marlin = connected(&mock);
+        marlin
+            .start("G21 ; setup\n(Contour)\nG1 X10 F500\n")
+            .unwrap();
+        assert!(matches!(marlin.start("G0 X0"), Err(SenderError::Busy)));
+
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N1 G21*27\n");
+        // Nothing is sent before the answer
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "");
+
+        mock.reply("ok\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N2 G1 X10 F500*0\n");
+        assert_eq!(marlin.progress().unwrap().line, Some(1));
+
+        mock.reply("echo:busy: processing\nok\n");
+        assert_eq!(marlin.poll().unwrap(), vec![Event::Finished]);
+        assert!(!marlin.is_running());
+        assert_eq!(marlin.progress().unwrap().line, Some(3));
+    }
+
+    #[test]
+    fn resends_and_errors() {
+        let mock = Mock::default();
+        let mut marlin = connected(&mock);
+        marlin.start("G21\nG1 X10 F500\nG5 X20\nG1 X30\n").unwrap();
+        marlin.poll().unwrap();
+        mock.reply("ok\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N1 G21*27\nN2 G1 X10 F500*0\n");
+
+        // The line got corrupted on the way
+        mock.reply("Error:checksum mismatch, Last Line: 1\nResend: 2\nok\n");
+        assert_eq!(marlin.poll().unwrap(), vec![]);
+        assert_eq!(mock.sent(), "N2 G1 X10 F500*0\n");
+        assert_eq!(marlin.progress().unwrap().acknowledged, 1);
+
+        mock.reply("ok\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N3 G5 X20*85\n");
+        assert_eq!(marlin.progress().unwrap().acknowledged, 2);
+
+        mock.reply("echo:Unknown command: \"G5 X20\"\nok\n");
+        assert_eq!(
+            marlin.poll().unwrap(),
+            vec![Event::Rejected {
+                line: Some(3),
+                message: "Unknown command: \"G5 X20\"".into()
+            }]
+        );
+        assert!(marlin.job().unwrap().is_stopped());
+        assert!(!marlin.is_running());
+        assert_eq!(mock.sent(), "");
+    }
+
+    #[test]
+    fn positions_and_feed_override() {
+        let mock = Mock::default();
+        let mut marlin = connected(&mock);
+        marlin.status_interval = Some(Duration::from_secs(60));
+        marlin.realtime(realtime::FEED_PLUS_10).unwrap();
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N1 M220 S110*97\n");
+
+        mock.reply("ok\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N2 M114*37\n");
+        mock.reply("X:10.00 Y:5.00 Z:-1.00 E:0.00 Count X:800 Y:400 Z:-80\nok\n");
+        let events = marlin.poll().unwrap();
+        assert!(matches!(events.as_slice(), [Event::Status(status)]
+            if status.work == Vector3::new(10.0, 5.0, -1.0) && status.overrides.feed == 110));
+        assert_eq!(marlin.status().unwrap().state, State::Idle);
+
+        assert_eq!(
+            parse_position("C: X:1.5000 Y:0.0000 Z:2.0000 E:0.0000"),
+            Some(Vector3::new(1.5, 0.0, 2.0))
+        );
+        assert_eq!(parse_position("T:21.3 /0.0 B:20.9 /0.0"), None);
+        assert!(matches!(
+            marlin.realtime(realtime::RAPID_50),
+            Err(SenderError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn halts_and_restarts() {
+        let mock = Mock::default();
+        let mut marlin = connected(&mock);
+        marlin.timeout = Duration::ZERO;
+        marlin.start(&"G1 X1 F100\n".repeat(10)).unwrap();
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N1 G1 X1 F100*55\n");
+        // The answer seems lost
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "M105\n");
+
+        mock.reply("Error:Printer halted. kill() called!\n");
+        assert_eq!(
+            marlin.poll().unwrap(),
+            vec![Event::Halted("Printer halted. kill() called!".into())]
+        );
+        assert!(marlin.is_halted());
+        assert!(!marlin.is_running());
+
+        mock.reply("start\n");
+        assert_eq!(marlin.poll().unwrap(), vec![Event::Reset("start".into())]);
+        assert!(!marlin.is_halted());
+        assert_eq!(mock.sent(), "N0 M110 N0*125\n");
+    }
+
+    #[test]
+    fn late_answers() {
+        let mock = Mock::default();
+        let mut marlin = connected(&mock);
+        marlin.start("G1 X1 F100\nG1 X2\n").unwrap();
+        marlin.timeout = Duration::ZERO;
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N1 G1 X1 F100*55\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "M105\n");
+        marlin.timeout = TIMEOUT;
+
+        // The answer was only late, the one to M105 must not accept the next line
+        mock.reply("ok\n");
+        assert_eq!(marlin.poll().unwrap(), vec![]);
+        assert_eq!(mock.sent(), "");
+        assert!(!marlin.is_idle());
+        mock.reply("ok\n");
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "N2 G1 X2*96\n");
+        assert_eq!(marlin.progress().unwrap().acknowledged, 1);
+
+        // The answer was lost, M105 brings the stream back after another wait
+        marlin.timeout = Duration::ZERO;
+        marlin.poll().unwrap();
+        assert_eq!(mock.sent(), "M105\n");
+        marlin.timeout = TIMEOUT;
+        mock.reply("ok\n");
+        assert_eq!(marlin.poll().unwrap(), vec![Event::Finished]);
+        marlin.timeout = Duration::ZERO;
+        marlin.poll().unwrap();
+        assert!(marlin.is_idle());
+    }
+
+    #[test]
+    fn control_blocks() {
+        assert_eq!(
+            jog(&Vector3::new(0.0, -1.0, 0.5), 600.0),
+            vec!["G91", "G0 Y-1 Z0.5 F600", "G90"]
+        );
+        assert_eq!(set_work_position([None, Some(0.0), None]), "G92 Y0");
+        assert_eq!(checksummed(0, "M110 N0"), "N0 M110 N0*125");
+    }
+}
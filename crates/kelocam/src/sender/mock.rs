@@ -0,0 +1,49 @@
+//! A serial port for testing senders without a controller.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// A serial port answering with prepared replies.
+#[derive(Clone, Default)]
+pub struct Mock {
+    input: Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Mock {
+    pub fn reply(&self, text: &str) {
+        self.input.borrow_mut().extend(text.bytes());
+    }
+
+    /// The bytes written since the last call.
+    pub fn sent(&self) -> String {
+        String::from_utf8(self.output.borrow_mut().split_off(0)).unwrap()
+    }
+}
+
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.borrow_mut();
+        if input.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n = buf.len().min(input.len());
+        for (byte, value) in buf.iter_mut().zip(input.drain(..n)) {
+            *byte = value;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! Streaming G-code programs to machine controllers over a serial connection.
+
+pub mod connection;
+pub mod grbl;
+pub mod marlin;
+#[cfg(test)]
+mod mock;
+pub mod probe;
+pub mod simulator;
+pub mod status;
+pub use connection::{Connection, MachineState};
+pub use grbl::Grbl;
+pub use marlin::Marlin;
+pub use probe::{ProbeSettings, Probing, Routine};
+pub use simulator::VirtualGrbl;
+pub use status::Status;
+
+use std::{fmt, io, time::Duration};
+
+use serialport::SerialPort;
+
+use grbl::Event;
+
+/// How long reads from a serial port wait for data. Senders are polled in a loop, so this
+/// should be short.
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Opens the serial port of a controller, or connects to a virtual one, see `simulator`.
+pub fn open(path: &str, baud_rate: u32) -> Result<Box<dyn SerialPort>, SenderError> {
+    if path == simulator::PORT_NAME {
+        return Ok(Box::new(VirtualGrbl::new(Default::default())));
+    }
+    Ok(serialport::new(path, baud_rate).timeout(TIMEOUT).open()?)
+}
+
+/// The names of the serial ports of the system, followed by the virtual controller.
+pub fn ports() -> Vec<String> {
+    let mut ports: Vec<String> = serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|port| port.port_name).collect())
+        .unwrap_or_default();
+    ports.push(simulator::PORT_NAME.to_string());
+    ports
+}
+
+/// The firmware of a controller, which decides how programs are streamed to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Firmware {
+    #[default]
+    Grbl,
+    /// Marlin, Smoothieware and other firmwares which acknowledge every line with "ok".
+    Marlin,
+}
+
+impl Firmware {
+    pub const ALL: [Self; 2] = [Self::Grbl, Self::Marlin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Grbl => "Grbl",
+            Self::Marlin => "Marlin / Smoothieware",
+        }
+    }
+
+    /// The usual baud rate of controllers with the firmware.
+    pub fn baud_rate(self) -> u32 {
+        match self {
+            Self::Grbl => grbl::BAUD_RATE,
+            Self::Marlin => marlin::BAUD_RATE,
+        }
+    }
+}
+
+/// Streams programs and commands to a controller with the protocol of its firmware. Polled by
+/// the thread of a `Connection`.
+pub trait Sender {
+    fn firmware(&self) -> Firmware;
+    /// Starts streaming the program. Fails if another job is still running.
+    fn start(&mut self, program: &str) -> Result<(), SenderError>;
+    /// Queues a block, commands are sent before the blocks of a job.
+    fn command(&mut self, block: &str);
+    /// Sends one of the real-time commands of Grbl, see `grbl::realtime`.
+    fn realtime(&mut self, command: u8) -> Result<(), SenderError>;
+    fn pause(&mut self) -> Result<(), SenderError>;
+    fn resume(&mut self) -> Result<(), SenderError>;
+    /// Stops the machine immediately and aborts the running job.
+    fn reset(&mut self) -> Result<(), SenderError>;
+    /// Handles the output of the controller and sends what it can take. Has to be called
+    /// regularly.
+    fn poll(&mut self) -> Result<Vec<Event>, SenderError>;
+    fn status(&self) -> Option<&Status>;
+    fn progress(&self) -> Option<Progress>;
+    /// Whether a job is being streamed or executed.
+    fn is_running(&self) -> bool;
+    fn is_paused(&self) -> bool;
+    /// Whether all blocks were sent and executed.
+    fn is_idle(&self) -> bool;
+    /// The code of the Grbl alarm the controller is in, if any.
+    fn alarm(&self) -> Option<u8>;
+}
+
+/// An error that occured while talking to a controller.
+#[derive(Debug)]
+pub enum SenderError {
+    Io(io::Error),
+    /// A job is already running.
+    Busy,
+    /// The firmware of the controller can't do what was requested.
+    Unsupported(String),
+}
+
+impl fmt::Display for SenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Busy => write!(f, "a job is already running"),
+            Self::Unsupported(what) => write!(f, "{} is not supported by the controller", what),
+        }
+    }
+}
+
+impl std::error::Error for SenderError {}
+
+impl From<io::Error> for SenderError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serialport::Error> for SenderError {
+    fn from(e: serialport::Error) -> Self {
+        Self::Io(e.into())
+    }
+}
+
+/// A program being streamed to a controller.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// The blocks of the program with their line numbers, starting at 1. Comments and empty
+    /// lines are left out, they would only take up space in the buffer of the controller.
+    lines: Vec<(usize, String)>,
+    /// The number of blocks sent to the controller.
+    sent: usize,
+    /// The number of blocks the controller acknowledged.
+    acknowledged: usize,
+    /// Whether the job was stopped before all blocks were sent, e.g. by an error.
+    stopped: bool,
+}
+
+impl Job {
+    pub fn new(program: &str) -> Self {
+        let lines = program
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, strip_comments(line)))
+            .filter(|(_, block)| !block.is_empty() && block != "%")
+            .collect();
+        Self {
+            lines,
+            sent: 0,
+            acknowledged: 0,
+            stopped: false,
+        }
+    }
+
+    /// How far the job has come. `planned` is the number of acknowledged blocks which may still
+    /// wait in the planner buffer of the controller, `number` the line number (N word) of the
+    /// executing block if the controller reports it.
+    pub fn progress(&self, planned: usize, number: Option<u32>) -> Progress {
+        let acknowledged = &self.lines[..self.acknowledged];
+        let numbered = number.and_then(|number| {
+            (acknowledged.iter().rev()).find(|(_, block)| line_number(block) == Some(number))
+        });
+        let executing = match numbered {
+            Some((line, _)) => Some(*line),
+            None => (self.acknowledged.checked_sub(planned + 1)).map(|i| self.lines[i].0),
+        };
+        Progress {
+            sent: self.sent,
+            acknowledged: self.acknowledged,
+            total: self.lines.len(),
+            line: self.acknowledged.checked_sub(1).map(|i| self.lines[i].0),
+            executing,
+        }
+    }
+
+    /// Whether all blocks were executed or the job was stopped.
+    pub fn is_done(&self) -> bool {
+        self.acknowledged == self.sent && (self.stopped || self.sent == self.lines.len())
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// The next block to send with its line number.
+    fn next(&self) -> Option<&(usize, String)> {
+        if self.stopped {
+            return None;
+        }
+        self.lines.get(self.sent)
+    }
+}
+
+/// How far a job has come, in blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub sent: usize,
+    pub acknowledged: usize,
+    pub total: usize,
+    /// The line number of the last block the controller acknowledged. Grbl acknowledges blocks
+    /// when they enter the planner buffer, the machine may not have got that far yet.
+    pub line: Option<usize>,
+    /// The line number of the block the machine executes (if known). Found by the line number
+    /// the controller reports if the program has them, otherwise estimated by assuming that
+    /// the planner buffer is full. The estimate is rather too early than too late.
+    pub executing: Option<usize>,
+}
+
+impl Progress {
+    /// The part of the job which was executed, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.acknowledged as f32 / self.total as f32
+    }
+}
+
+/// The line number (N word) at the start of the block (if any).
+fn line_number(block: &str) -> Option<u32> {
+    let number = block.strip_prefix(['N', 'n'])?;
+    let end = number
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(number.len());
+    number[..end].parse().ok()
+}
+
+/// Removes "(...)" and ";" comments from the line.
+fn strip_comments(line: &str) -> String {
+    let mut block = String::new();
+    let mut comment = false;
+    for c in line.chars() {
+        match c {
+            ';' if !comment => break,
+            '(' => comment = true,
+            ')' if comment => comment = false,
+            c if !comment => block.push(c),
+            _ => {}
+        }
+    }
+    block.trim().to_string()
+}
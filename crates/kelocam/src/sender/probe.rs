@@ -0,0 +1,286 @@
+//! Guided probing routines, which find the work origin and tool lengths by touching the stock,
+//! a touch plate or a tool setter with the tool.
+//!
+//! Every contact is made twice: quickly to find the surface and slowly after backing off, for
+//! precision. The work offsets are calculated from the positions the controller reports when
+//! the probe triggers, not from where the machine stopped afterwards.
+
+use nalgebra::Vector3;
+
+use super::grbl::{self, distance_words, number};
+
+/// The result of a probing move, reported as "[PRB:-10.000,5.000,-8.250:1]".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeResult {
+    /// The position where the probe triggered, in machine coordinates.
+    pub position: Vector3<f32>,
+    /// Whether the probe made contact.
+    pub success: bool,
+}
+
+impl ProbeResult {
+    pub fn parse(line: &str) -> Option<Self> {
+        let report = line.trim().strip_prefix("[PRB:")?.strip_suffix(']')?;
+        let (position, success) = report.rsplit_once(':')?;
+        let values: Vec<f32> = (position.split(','))
+            .map(|value| value.parse().ok())
+            .collect::<Option<_>>()?;
+        match values.as_slice() {
+            [x, y, z, ..] => Some(Self {
+                position: Vector3::new(*x, *y, *z),
+                success: success == "1",
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The distances and feed rates of probing moves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeSettings {
+    /// How far the probe moves at most to find a surface, in millimeters.
+    pub distance: f32,
+    /// The feed rate of finding a surface, in mm/min.
+    pub fast_feed: f32,
+    /// The feed rate of the precise second contact, in mm/min.
+    pub slow_feed: f32,
+    /// How far the probe backs off after a contact, in millimeters.
+    pub retract: f32,
+}
+
+impl Default for ProbeSettings {
+    fn default() -> Self {
+        Self {
+            distance: 20.0,
+            fast_feed: 200.0,
+            slow_feed: 25.0,
+            retract: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Routine {
+    /// Zeroes Z on the stock, with a touch plate of the thickness lying on top of it. Start with
+    /// the tool above the plate.
+    TouchPlate { thickness: f32 },
+    /// Zeroes X and Y at the front left corner of the stock. Start with the tool below the top
+    /// of the stock, in front of and left of the corner, less than `offset` away from both
+    /// sides. The tool touches the sides `offset` away from the corner.
+    Corner { tool_diameter: f32, offset: f32 },
+    /// Measures the length of the tool on a tool setter. Without a reference, the measured
+    /// position becomes the reference. Otherwise the difference to the reference is applied as
+    /// tool length offset, so the work origin stays valid after a tool change.
+    ToolLength { reference: Option<f32> },
+}
+
+/// A probing routine being run. The routine is made of steps, the steps are sent after the
+/// previous steps were executed. All but the last step end with a probing move.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probing {
+    routine: Routine,
+    settings: ProbeSettings,
+    /// The number of steps sent.
+    step: usize,
+    /// The positions of the contacts so far, in machine coordinates.
+    contacts: Vec<Vector3<f32>>,
+}
+
+impl Probing {
+    pub fn new(routine: Routine, settings: ProbeSettings) -> Self {
+        Self {
+            routine,
+            settings,
+            step: 0,
+            contacts: Vec::new(),
+        }
+    }
+
+    pub fn routine(&self) -> &Routine {
+        &self.routine
+    }
+
+    pub fn contacts(&self) -> &[Vector3<f32>] {
+        &self.contacts
+    }
+
+    /// Records the result of the probing move of the last step.
+    pub fn probed(&mut self, result: &ProbeResult) {
+        if result.success {
+            self.contacts.push(result.position);
+        }
+    }
+
+    /// Whether the probing moves of all steps sent so far made contact.
+    pub fn is_ok(&self) -> bool {
+        self.contacts.len() >= self.step.min(self.probes())
+    }
+
+    /// The blocks of the next step, None after the last step.
+    pub fn next_step(&mut self) -> Option<Vec<String>> {
+        let settings = &self.settings;
+        let down = -Vector3::z();
+        let blocks = match (self.routine, self.step) {
+            (Routine::TouchPlate { .. } | Routine::ToolLength { .. }, 0) => self.find(&down),
+            (Routine::TouchPlate { .. } | Routine::ToolLength { .. }, 1) => self.refine(&down),
+            (Routine::TouchPlate { thickness }, 2) => {
+                let z = self.contacts.get(1)?.z - thickness;
+                let mut blocks = vec![format!("G10 L2 P0 Z{}", number(z))];
+                blocks.extend(self.back_off(&down));
+                blocks
+            }
+            (Routine::ToolLength { reference }, 2) => {
+                let mut blocks = Vec::new();
+                if let Some(reference) = reference {
+                    let offset = self.contacts.get(1)?.z - reference;
+                    blocks.push(format!("G43.1 Z{}", number(offset)));
+                }
+                blocks.extend(self.back_off(&down));
+                blocks
+            }
+
+            (Routine::Corner { offset, .. }, 0) => {
+                let mut blocks = relative(&format!("G0 Y{}", number(offset)));
+                blocks.extend(self.find(&Vector3::x()));
+                blocks
+            }
+            (Routine::Corner { .. }, 1) => self.refine(&Vector3::x()),
+            (Routine::Corner { offset, .. }, 2) => {
+                // Around the corner to the front side
+                let mut blocks = self.back_off(&Vector3::x());
+                blocks.extend(relative(&format!("G0 Y{}", number(-offset))));
+                let x = settings.retract + offset;
+                blocks.extend(relative(&format!("G0 X{}", number(x))));
+                blocks.extend(self.find(&Vector3::y()));
+                blocks
+            }
+            (Routine::Corner { .. }, 3) => self.refine(&Vector3::y()),
+            (Routine::Corner { tool_diameter, .. }, 4) => {
+                // The sides are touched by the circumference of the tool
+                let radius = tool_diameter * 0.5;
+                let (x, y) = (self.contacts.get(1)?.x, self.contacts.get(3)?.y);
+                let (x, y) = (x + radius, y + radius);
+                let mut blocks = vec![format!("G10 L2 P0 X{} Y{}", number(x), number(y))];
+                blocks.extend(self.back_off(&Vector3::y()));
+                blocks
+            }
+            _ => return None,
+        };
+        self.step += 1;
+        Some(blocks)
+    }
+
+    /// The number of probing moves of the routine.
+    fn probes(&self) -> usize {
+        match self.routine {
+            Routine::TouchPlate { .. } | Routine::ToolLength { .. } => 2,
+            Routine::Corner { .. } => 4,
+        }
+    }
+
+    /// Moves in the direction until the probe makes contact.
+    fn find(&self, direction: &Vector3<f32>) -> Vec<String> {
+        let settings = &self.settings;
+        grbl::probe(&direction.scale(settings.distance), settings.fast_feed)
+    }
+
+    /// Backs off and slowly makes contact again.
+    fn refine(&self, direction: &Vector3<f32>) -> Vec<String> {
+        let settings = &self.settings;
+        let mut blocks = self.back_off(direction);
+        blocks.extend(grbl::probe(
+            &direction.scale(settings.retract * 2.0),
+            settings.slow_feed,
+        ));
+        blocks
+    }
+
+    /// Moves away from the surface in the direction.
+    fn back_off(&self, direction: &Vector3<f32>) -> Vec<String> {
+        let distance = direction.scale(-self.settings.retract);
+        relative(&format!("G0{}", distance_words(&distance)))
+    }
+}
+
+/// The blocks of a move by a distance, leaving the controller in absolute positioning.
+fn relative(block: &str) -> Vec<String> {
+    vec![format!("G91 {}", block), "G90".to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_results() {
+        let result = ProbeResult::parse("[PRB:-10.000,5.500,-8.250:1]").unwrap();
+        assert_eq!(result.position, Vector3::new(-10.0, 5.5, -8.25));
+        assert!(result.success);
+        assert!(
+            !ProbeResult::parse("[PRB:0.000,0.000,0.000:0]")
+                .unwrap()
+                .success
+        );
+        assert_eq!(ProbeResult::parse("[MSG:Pgm End]"), None);
+    }
+
+    #[test]
+    fn touch_plate() {
+        let mut probing = Probing::new(
+            Routine::TouchPlate { thickness: 10.0 },
+            ProbeSettings::default(),
+        );
+        assert_eq!(
+            probing.next_step().unwrap(),
+            ["G38.2 G91 G21 Z-20 F200", "G90"]
+        );
+        probing.probed(&ProbeResult::parse("[PRB:0.000,0.000,-30.100:1]").unwrap());
+        assert!(probing.is_ok());
+        assert_eq!(
+            probing.next_step().unwrap(),
+            ["G91 G0 Z2", "G90", "G38.2 G91 G21 Z-4 F25", "G90"]
+        );
+        probing.probed(&ProbeResult::parse("[PRB:0.000,0.000,-30.000:1]").unwrap());
+        assert_eq!(
+            probing.next_step().unwrap(),
+            ["G10 L2 P0 Z-40", "G91 G0 Z2", "G90"]
+        );
+        assert_eq!(probing.next_step(), None);
+    }
+
+    #[test]
+    fn corner_and_failures() {
+        let mut probing = Probing::new(
+            Routine::Corner {
+                tool_diameter: 6.0,
+                offset: 10.0,
+            },
+            ProbeSettings::default(),
+        );
+        assert_eq!(
+            probing.next_step().unwrap()[..3],
+            ["G91 G0 Y10", "G90", "G38.2 G91 G21 X20 F200"]
+        );
+        probing.probed(&ProbeResult::parse("[PRB:-100.000,-50.000,-30.000:1]").unwrap());
+        probing.next_step();
+        probing.probed(&ProbeResult::parse("[PRB:-100.100,-50.000,-30.000:1]").unwrap());
+        assert_eq!(
+            probing.next_step().unwrap(),
+            [
+                "G91 G0 X-2",
+                "G90",
+                "G91 G0 Y-10",
+                "G90",
+                "G91 G0 X12",
+                "G90",
+                "G38.2 G91 G21 Y20 F200",
+                "G90"
+            ]
+        );
+        probing.probed(&ProbeResult::parse("[PRB:-90.000,-45.000,-30.000:1]").unwrap());
+        probing.next_step();
+        // No contact
+        probing.probed(&ProbeResult::parse("[PRB:-90.000,-41.000,-30.000:0]").unwrap());
+        assert!(!probing.is_ok());
+    }
+}
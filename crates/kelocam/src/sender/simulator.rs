@@ -0,0 +1,1332 @@
+//! A virtual Grbl controller, for trying out the sender without a machine.
+//!
+//! The controller answers blocks with "ok" and "error:N", reports its status, fills its
+//! receive buffer and planner like Grbl does and raises alarms. Moves take as long as they
+//! would on a machine, at constant speed without acceleration. Arcs are split into short
+//! straight moves. Probing moves touch a horizontal surface at a configurable height.
+//!
+//! Time passes with the wall clock, or only when advanced for tests. Reads wait for output
+//! until the timeout, like serial ports do.
+
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector3;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use super::grbl::{realtime, BAUD_RATE, BUFFER_SIZE, PLANNER_BLOCKS};
+use super::status::Overrides;
+use super::TIMEOUT;
+
+/// The name of the serial port of the virtual controller.
+pub const PORT_NAME: &str = "Virtual Grbl";
+
+/// The maximum length of the straight moves arcs are split into, in millimeters.
+const ARC_SEGMENT: f32 = 0.5;
+
+/// How often reads check for output while waiting.
+const READ_INTERVAL: Duration = Duration::from_millis(1);
+
+const WELCOME: &str = "Grbl 1.1h ['$' for help]";
+
+/// The configuration of the virtual machine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// The maximum feed rate of the axes, used by rapid moves and homing, in mm/min.
+    pub max_rate: f32,
+    /// The travel of the axes, in millimeters. Like Grbl by default, homing moves to the
+    /// machine origin and the machine moves in negative coordinates. Moves beyond the travel
+    /// raise a soft limit alarm. Without travel, soft limits are disabled.
+    pub travel: Option<Vector3<f32>>,
+    /// The height of the surface probing moves touch, in machine coordinates. Without one,
+    /// probing moves never make contact.
+    pub probe_z: Option<f32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_rate: 3000.0,
+            travel: None,
+            probe_z: None,
+        }
+    }
+}
+
+/// A virtual Grbl controller behind a serial port. Clones are connected to the same controller.
+#[derive(Clone)]
+pub struct VirtualGrbl {
+    controller: Arc<Mutex<Controller>>,
+    timeout: Duration,
+}
+
+impl VirtualGrbl {
+    /// A controller whose time passes with the wall clock.
+    pub fn new(settings: Settings) -> Self {
+        let mut controller = Controller::new(settings);
+        controller.clock = Some(Instant::now());
+        Self {
+            controller: Arc::new(Mutex::new(controller)),
+            timeout: TIMEOUT,
+        }
+    }
+
+    /// A controller whose time only passes with `advance`, for tests. Reads do not wait.
+    pub fn stepped(settings: Settings) -> Self {
+        Self {
+            controller: Arc::new(Mutex::new(Controller::new(settings))),
+            timeout: Duration::ZERO,
+        }
+    }
+
+    /// Lets the time pass for a stepped controller.
+    pub fn advance(&self, duration: Duration) {
+        self.controller().step(duration.as_secs_f32());
+    }
+
+    /// The position of the machine, in machine coordinates.
+    pub fn position(&self) -> Vector3<f32> {
+        self.controller().position
+    }
+
+    /// Raises the alarm, e.g. 1 for a triggered limit switch.
+    pub fn trigger_alarm(&self, code: u8) {
+        self.controller().alarm(code);
+    }
+
+    fn controller(&self) -> MutexGuard<'_, Controller> {
+        self.controller.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Read for VirtualGrbl {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            {
+                let mut controller = self.controller();
+                controller.update();
+                if !controller.output.is_empty() {
+                    let n = buf.len().min(controller.output.len());
+                    for (byte, value) in buf.iter_mut().zip(controller.output.drain(..n)) {
+                        *byte = value;
+                    }
+                    return Ok(n);
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            thread::sleep(READ_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+impl Write for VirtualGrbl {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut controller = self.controller();
+        controller.update();
+        for byte in buf {
+            controller.receive(*byte);
+        }
+        controller.process();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for VirtualGrbl {
+    fn name(&self) -> Option<String> {
+        Some(PORT_NAME.to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(BAUD_RATE)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // The settings of the line do not matter to the virtual controller
+
+    fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.controller().output.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        let mut controller = self.controller();
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            controller.output.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Cut,
+    Jog,
+    Home,
+    /// A probing move, which raises an alarm without contact for G38.2.
+    Probe {
+        alarm: bool,
+    },
+}
+
+/// A block in the planner buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Planned {
+    /// A straight move to the position in machine coordinates, at the feed rate in mm/min or
+    /// at rapid speed.
+    Move {
+        to: Vector3<f32>,
+        feed: Option<f32>,
+        kind: Kind,
+    },
+    /// Waits for the remaining seconds.
+    Dwell(f32),
+    /// Holds, like a feed hold (M0).
+    Pause,
+    /// Answers a block which waits for the blocks before it to be executed, e.g. a dwell.
+    Ok,
+}
+
+/// How a block gets answered.
+enum Answer {
+    Now,
+    /// When the planner reaches `Planned::Ok`.
+    Later,
+}
+
+/// The modal state of the G-code parser.
+#[derive(Debug, Clone, Copy)]
+struct Modal {
+    /// The motion mode times ten, e.g. 382 for G38.2. None after G80.
+    motion: Option<u32>,
+    /// The axes of the arc plane and its normal, e.g. [0, 1, 2] for G17.
+    plane: [usize; 3],
+    inches: bool,
+    absolute: bool,
+    /// The index of the work coordinate system, 0 for G54.
+    coordinates: usize,
+    /// The feed rate in mm/min.
+    feed: Option<f32>,
+    speed: f32,
+    /// The M code of the spindle state: 3, 4 or 5.
+    spindle: u32,
+    mist: bool,
+    flood: bool,
+    tool: u32,
+}
+
+impl Default for Modal {
+    fn default() -> Self {
+        Self {
+            motion: Some(0),
+            plane: [0, 1, 2],
+            inches: false,
+            absolute: true,
+            coordinates: 0,
+            feed: None,
+            speed: 0.0,
+            spindle: 5,
+            mist: false,
+            flood: false,
+            tool: 0,
+        }
+    }
+}
+
+/// The state of the virtual controller.
+struct Controller {
+    settings: Settings,
+    /// The bytes of blocks which were received but not executed yet.
+    input: Vec<u8>,
+    /// The bytes the sender did not read yet.
+    output: Vec<u8>,
+    planner: VecDeque<Planned>,
+    modal: Modal,
+    /// The position of the machine, in machine coordinates.
+    position: Vector3<f32>,
+    /// The position at the end of the planned moves.
+    planned: Vector3<f32>,
+    /// The offsets of the work coordinate systems G54 to G59.
+    offsets: [Vector3<f32>; 6],
+    /// The tool length offset (G43.1).
+    tool_offset: f32,
+    overrides: Overrides,
+    alarm: Option<u8>,
+    hold: bool,
+    /// Whether the spindle was stopped during a feed hold.
+    spindle_stopped: bool,
+    /// Whether blocks are only checked, not executed ($C).
+    check: bool,
+    /// The number of status reports, the work offset and overrides are only reported in every
+    /// tenth report.
+    reports: usize,
+    /// When the time was last updated, None if time passes only with `step`.
+    clock: Option<Instant>,
+}
+
+impl Controller {
+    fn new(settings: Settings) -> Self {
+        let mut controller = Self {
+            settings,
+            input: Vec::new(),
+            output: Vec::new(),
+            planner: VecDeque::new(),
+            modal: Modal::default(),
+            position: Vector3::zeros(),
+            planned: Vector3::zeros(),
+            offsets: [Vector3::zeros(); 6],
+            tool_offset: 0.0,
+            overrides: Overrides::default(),
+            alarm: None,
+            hold: false,
+            spindle_stopped: false,
+            check: false,
+            reports: 0,
+            clock: None,
+        };
+        controller.send("");
+        controller.send(WELCOME);
+        controller
+    }
+
+    fn send(&mut self, line: &str) {
+        self.output.extend_from_slice(line.as_bytes());
+        self.output.extend_from_slice(b"\r\n");
+    }
+
+    /// Lets the time since the last update pass.
+    fn update(&mut self) {
+        if let Some(last) = self.clock {
+            let now = Instant::now();
+            self.step((now - last).as_secs_f32());
+            self.clock = Some(now);
+        }
+    }
+
+    /// Executes the planned blocks for the seconds.
+    fn step(&mut self, mut seconds: f32) {
+        while !self.hold && self.alarm.is_none() {
+            let Some(planned) = self.planner.front().copied() else {
+                break;
+            };
+            match planned {
+                Planned::Move { to, feed, kind } => {
+                    let speed = self.speed(feed) / 60.0;
+                    let distance = (to - self.position).magnitude();
+                    let available = speed * seconds;
+                    let next = if available >= distance {
+                        to
+                    } else {
+                        self.position + (to - self.position) * (available / distance)
+                    };
+                    if let (Kind::Probe { .. }, Some(z)) = (kind, self.settings.probe_z) {
+                        if self.position.z > z && next.z <= z {
+                            let t = (self.position.z - z) / (self.position.z - next.z);
+                            self.position += (next - self.position) * t;
+                            self.planned = self.position;
+                            self.planner.pop_front();
+                            self.send(&format!("[PRB:{}:1]", coordinates(&self.position)));
+                            seconds -= (distance * t / speed).min(seconds);
+                            continue;
+                        }
+                    }
+                    self.position = next;
+                    if available < distance {
+                        return;
+                    }
+                    seconds -= distance / speed;
+                    self.planner.pop_front();
+                    match kind {
+                        Kind::Probe { alarm: true } => self.alarm(5),
+                        Kind::Probe { alarm: false } => {
+                            self.send(&format!("[PRB:{}:0]", coordinates(&self.position)))
+                        }
+                        _ => {}
+                    }
+                }
+                Planned::Dwell(remaining) => {
+                    if remaining > seconds {
+                        self.planner[0] = Planned::Dwell(remaining - seconds);
+                        return;
+                    }
+                    seconds -= remaining;
+                    self.planner.pop_front();
+                }
+                Planned::Pause => {
+                    self.hold = true;
+                    self.planner.pop_front();
+                }
+                Planned::Ok => {
+                    self.send("ok");
+                    self.planner.pop_front();
+                }
+            }
+            // Blocks waiting for the planner
+            self.process();
+        }
+    }
+
+    /// The speed of a move with the feed rate or at rapid speed, in mm/min.
+    fn speed(&self, feed: Option<f32>) -> f32 {
+        let max_rate = self.settings.max_rate;
+        match feed {
+            Some(feed) => (feed * self.overrides.feed as f32 / 100.0).min(max_rate),
+            None => max_rate * self.overrides.rapid as f32 / 100.0,
+        }
+    }
+
+    /// Handles a received byte. Real-time commands are executed immediately, other bytes go to
+    /// the receive buffer, or get lost if it is full.
+    fn receive(&mut self, byte: u8) {
+        match byte {
+            realtime::STATUS => self.report(),
+            realtime::FEED_HOLD => match self.kind() {
+                Some(Kind::Jog) => self.cancel_jog(),
+                Some(_) => self.hold = true,
+                None => {}
+            },
+            realtime::CYCLE_START => {
+                self.hold = false;
+                self.spindle_stopped = false;
+            }
+            realtime::SOFT_RESET => self.reset(),
+            realtime::JOG_CANCEL => self.cancel_jog(),
+            realtime::SPINDLE_STOP if self.hold => self.spindle_stopped ^= true,
+            realtime::FEED_RESET => self.set_overrides(|o| o.feed = 100),
+            realtime::FEED_PLUS_10 => self.set_overrides(|o| o.feed = change(o.feed, 10)),
+            realtime::FEED_MINUS_10 => self.set_overrides(|o| o.feed = change(o.feed, -10)),
+            realtime::FEED_PLUS_1 => self.set_overrides(|o| o.feed = change(o.feed, 1)),
+            realtime::FEED_MINUS_1 => self.set_overrides(|o| o.feed = change(o.feed, -1)),
+            realtime::RAPID_100 => self.set_overrides(|o| o.rapid = 100),
+            realtime::RAPID_50 => self.set_overrides(|o| o.rapid = 50),
+            realtime::RAPID_25 => self.set_overrides(|o| o.rapid = 25),
+            realtime::SPINDLE_RESET => self.set_overrides(|o| o.spindle = 100),
+            realtime::SPINDLE_PLUS_10 => self.set_overrides(|o| o.spindle = change(o.spindle, 10)),
+            realtime::SPINDLE_MINUS_10 => {
+                self.set_overrides(|o| o.spindle = change(o.spindle, -10))
+            }
+            realtime::SPINDLE_PLUS_1 => self.set_overrides(|o| o.spindle = change(o.spindle, 1)),
+            realtime::SPINDLE_MINUS_1 => self.set_overrides(|o| o.spindle = change(o.spindle, -1)),
+            0x80.. => {}
+            byte if self.input.len() < BUFFER_SIZE => self.input.push(byte),
+            _ => {}
+        }
+    }
+
+    fn set_overrides<F: FnOnce(&mut Overrides)>(&mut self, f: F) {
+        f(&mut self.overrides);
+        // The next report includes the overrides
+        self.reports = 0;
+    }
+
+    /// Executes the received blocks while the planner has room.
+    fn process(&mut self) {
+        while self.planner.len() < PLANNER_BLOCKS
+            && !self.planner.contains(&Planned::Ok)
+            && self.input.contains(&b'\n')
+        {
+            let end = self
+                .input
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .unwrap_or(0);
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let block: String = super::strip_comments(&line)
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| c.to_ascii_uppercase())
+                .collect();
+            match self.execute(&block) {
+                Ok(Answer::Now) => self.send("ok"),
+                Ok(Answer::Later) => self.planner.push_back(Planned::Ok),
+                Err(code) => self.send(&format!("error:{}", code)),
+            }
+        }
+    }
+
+    fn execute(&mut self, block: &str) -> Result<Answer, u8> {
+        if block.is_empty() {
+            return Ok(Answer::Now);
+        }
+        if let Some(command) = block.strip_prefix('$') {
+            return self.system(command);
+        }
+        if self.alarm.is_some() || self.kind() == Some(Kind::Jog) {
+            return Err(9);
+        }
+        let words = words(block)?;
+        self.gcode(&words)
+    }
+
+    /// Executes a '$' system command.
+    fn system(&mut self, command: &str) -> Result<Answer, u8> {
+        let idle = self.planner.is_empty() && !self.hold;
+        if let Some(jog) = command.strip_prefix("J=") {
+            if self.alarm.is_some() || !(idle || self.kind() == Some(Kind::Jog)) {
+                return Err(8);
+            }
+            return self.jog(&words(jog)?);
+        }
+        match command {
+            "" => {
+                self.send("[HLP:$$ $# $G $I $N $x=val $Nx=line $J=line $SLP $C $X $H ~ ! ? ctrl-x]")
+            }
+            "G" => {
+                let modal = self.modal;
+                let coolant = match (modal.mist, modal.flood) {
+                    (false, false) => "M9",
+                    (true, false) => "M7",
+                    (false, true) => "M8",
+                    (true, true) => "M7 M8",
+                };
+                let report = format!(
+                    "[GC:G{} G{} G{} G{} G{} G94 M{} {} T{} F{} S{}]",
+                    modal.motion.map_or("80".to_string(), format_code),
+                    54 + modal.coordinates,
+                    [19, 18, 17][modal.plane[2]],
+                    if modal.inches { 20 } else { 21 },
+                    if modal.absolute { 90 } else { 91 },
+                    modal.spindle,
+                    coolant,
+                    modal.tool,
+                    number(modal.feed.unwrap_or(0.0)),
+                    number(modal.speed),
+                );
+                self.send(&report);
+            }
+            "#" => {
+                for (i, offset) in self.offsets.into_iter().enumerate() {
+                    self.send(&format!("[G{}:{}]", 54 + i, coordinates(&offset)));
+                }
+                self.send(&format!("[TLO:{}]", number(self.tool_offset)));
+            }
+            "I" => {
+                self.send("[VER:1.1h.20190830:]");
+                self.send(&format!("[OPT:V,{},{}]", PLANNER_BLOCKS, BUFFER_SIZE));
+            }
+            _ if !idle => return Err(8),
+            "$" => {
+                let settings = self.settings;
+                for (i, axis) in ['X', 'Y', 'Z'].into_iter().enumerate() {
+                    self.send(&format!("$11{}={}", i, number(settings.max_rate)));
+                    let travel = settings.travel.map_or(0.0, |travel| travel[i]);
+                    self.send(&format!("$13{}={} ({} travel)", i, number(travel), axis));
+                }
+            }
+            "X" => {
+                if self.alarm.take().is_some() {
+                    self.send("[MSG:Caution: Unlocked]");
+                }
+            }
+            "H" => {
+                self.alarm = None;
+                // Z first, to clear the stock
+                let home = Vector3::new(self.position.x, self.position.y, 0.0);
+                for to in [home, Vector3::zeros()] {
+                    self.planner.push_back(Planned::Move {
+                        to,
+                        feed: None,
+                        kind: Kind::Home,
+                    });
+                }
+                self.planned = Vector3::zeros();
+                return Ok(Answer::Later);
+            }
+            "C" => {
+                self.check = !self.check;
+                if self.check {
+                    self.send("[MSG:Enabled]");
+                } else {
+                    self.send("[MSG:Disabled]");
+                    self.reset();
+                }
+            }
+            // Settings and startup blocks are accepted but have no effect
+            command if command.contains('=') || command == "N" => {}
+            _ => return Err(3),
+        }
+        Ok(Answer::Now)
+    }
+
+    /// Plans a jog ("$J=G91 X10 F1000").
+    fn jog(&mut self, words: &[(char, f32)]) -> Result<Answer, u8> {
+        let mut modal = self.modal;
+        let mut axes = [None; 3];
+        let mut machine = false;
+        let mut feed = None;
+        for &(letter, value) in words {
+            match (letter, code(value)) {
+                ('G', 200) => modal.inches = true,
+                ('G', 210) => modal.inches = false,
+                ('G', 900) => modal.absolute = true,
+                ('G', 910) => modal.absolute = false,
+                ('G', 530) => machine = true,
+                ('X' | 'Y' | 'Z', _) => axes[axis(letter)] = Some(value),
+                ('F', _) => feed = Some(value * unit(&modal)),
+                ('N', _) => {}
+                _ => return Err(16),
+            }
+        }
+        let feed = feed.filter(|feed| *feed > 0.0).ok_or(22)?;
+        let to = self.target(&modal, &axes, machine);
+        if !self.within_travel(&to) {
+            return Err(15);
+        }
+        if !self.check {
+            self.planner.push_back(Planned::Move {
+                to,
+                feed: Some(feed),
+                kind: Kind::Jog,
+            });
+            self.planned = to;
+        }
+        Ok(Answer::Now)
+    }
+
+    /// Executes a block of G-code.
+    fn gcode(&mut self, words: &[(char, f32)]) -> Result<Answer, u8> {
+        let mut modal = self.modal;
+        let mut motion = None;
+        let (mut dwell, mut offsets, mut machine, mut tool_offset) = (false, false, false, None);
+        let (mut pause, mut end) = (false, false);
+        let mut feed = None;
+        let mut axes = [None; 3];
+        let mut centers = [None; 3];
+        let (mut radius, mut p, mut l) = (None, None, None);
+
+        for &(letter, value) in words {
+            match letter {
+                'G' => match code(value) {
+                    code @ (0 | 10 | 20 | 30 | 382 | 383) => motion = Some(code),
+                    800 => modal.motion = None,
+                    40 => dwell = true,
+                    100 => offsets = true,
+                    170 => modal.plane = [0, 1, 2],
+                    180 => modal.plane = [2, 0, 1],
+                    190 => modal.plane = [1, 2, 0],
+                    200 => modal.inches = true,
+                    210 => modal.inches = false,
+                    530 => machine = true,
+                    code @ (540 | 550 | 560 | 570 | 580 | 590) => {
+                        modal.coordinates = (code / 10 - 54) as usize
+                    }
+                    431 => tool_offset = Some(true),
+                    490 => tool_offset = Some(false),
+                    900 => modal.absolute = true,
+                    910 => modal.absolute = false,
+                    // Modes which are the only ones Grbl supports
+                    400 | 610 | 911 | 940 => {}
+                    _ => return Err(20),
+                },
+                'M' => match value as u32 {
+                    0 => pause = true,
+                    // Optional stops are disabled
+                    1 => {}
+                    2 | 30 => end = true,
+                    m @ 3..=5 => modal.spindle = m,
+                    7 => modal.mist = true,
+                    8 => modal.flood = true,
+                    9 => (modal.mist, modal.flood) = (false, false),
+                    _ => return Err(20),
+                },
+                'F' => feed = Some(value),
+                'S' => modal.speed = value,
+                'T' => modal.tool = value as u32,
+                'X' | 'Y' | 'Z' => axes[axis(letter)] = Some(value),
+                'I' | 'J' | 'K' => centers[(letter as u8 - b'I') as usize] = Some(value),
+                'R' => radius = Some(value),
+                'P' => p = Some(value),
+                'L' => l = Some(value),
+                'N' => {}
+                _ => return Err(20),
+            }
+        }
+        let unit = unit(&modal);
+        if let Some(feed) = feed {
+            modal.feed = Some(feed * unit);
+        }
+        if motion.is_some() {
+            modal.motion = motion;
+        }
+
+        // Blocks using the axis words for something else than moving
+        if offsets {
+            let (Some(l), Some(p)) = (l, p) else {
+                return Err(28);
+            };
+            let index = match p as usize {
+                0 => modal.coordinates,
+                p @ 1..=6 => p - 1,
+                _ => return Err(29),
+            };
+            for (i, value) in axes.into_iter().enumerate() {
+                let Some(value) = value else {
+                    continue;
+                };
+                let tool_offset = if i == 2 { self.tool_offset } else { 0.0 };
+                self.offsets[index][i] = match l as u32 {
+                    2 => value * unit,
+                    20 => self.planned[i] - value * unit - tool_offset,
+                    _ => return Err(20),
+                };
+            }
+            self.modal = modal;
+            // The next report includes the offset
+            self.reports = 0;
+            return Ok(Answer::Now);
+        }
+        if let Some(set) = tool_offset {
+            self.tool_offset = match set {
+                true => axes[2].ok_or(37)? * unit,
+                false => 0.0,
+            };
+            self.modal = modal;
+            self.reports = 0;
+            return Ok(Answer::Now);
+        }
+        if dwell {
+            let seconds = p.ok_or(28)?;
+            self.modal = modal;
+            if !self.check {
+                self.planner.push_back(Planned::Dwell(seconds));
+            }
+            return Ok(Answer::Later);
+        }
+
+        let mut answer = Answer::Now;
+        if motion.is_some() && axes.iter().all(Option::is_none) {
+            return Err(26);
+        }
+        if axes.iter().any(Option::is_some) {
+            let code = modal.motion.ok_or(31)?;
+            let to = self.target(&modal, &axes, machine);
+            let feed = match code {
+                0 => None,
+                _ => Some(modal.feed.filter(|feed| *feed > 0.0).ok_or(22)?),
+            };
+            let moves = match code {
+                20 | 30 => self.arc(&modal, &to, centers, radius.map(|r| r * unit), code == 20)?,
+                _ => vec![to],
+            };
+            let kind = match code {
+                382 => Kind::Probe { alarm: true },
+                383 => Kind::Probe { alarm: false },
+                _ => Kind::Cut,
+            };
+
+            self.modal = modal;
+            if !self.within_travel(&to) {
+                self.alarm(2);
+                return Ok(Answer::Now);
+            }
+            if !self.check {
+                for to in moves {
+                    self.planner.push_back(Planned::Move { to, feed, kind });
+                }
+            }
+            self.planned = to;
+            // Probing waits for the result
+            if let Kind::Probe { .. } = kind {
+                answer = Answer::Later;
+            }
+        }
+        self.modal = modal;
+
+        if pause && !self.check {
+            self.planner.push_back(Planned::Pause);
+        }
+        // The end of a program resets the modes, but not the units
+        if end {
+            self.modal = Modal {
+                motion: Some(10),
+                inches: modal.inches,
+                feed: modal.feed,
+                speed: modal.speed,
+                tool: modal.tool,
+                ..Modal::default()
+            };
+        }
+        Ok(answer)
+    }
+
+    /// The target of a move in machine coordinates.
+    fn target(&self, modal: &Modal, axes: &[Option<f32>; 3], machine: bool) -> Vector3<f32> {
+        let unit = unit(modal);
+        let mut offset = self.offsets[modal.coordinates];
+        offset.z += self.tool_offset;
+        let mut target = self.planned;
+        for (i, value) in axes.iter().enumerate() {
+            if let Some(value) = value {
+                target[i] = match (machine, modal.absolute) {
+                    (true, _) => value * unit,
+                    (false, true) => value * unit + offset[i],
+                    (false, false) => target[i] + value * unit,
+                };
+            }
+        }
+        target
+    }
+
+    /// Splits an arc from the planned position to the target into straight moves. The center is
+    /// given by its offsets from the start (IJK) or by the radius.
+    fn arc(
+        &self,
+        modal: &Modal,
+        to: &Vector3<f32>,
+        centers: [Option<f32>; 3],
+        radius: Option<f32>,
+        clockwise: bool,
+    ) -> Result<Vec<Vector3<f32>>, u8> {
+        let [a, b, normal] = modal.plane;
+        let from = self.planned;
+        let unit = unit(modal);
+        let (x, y) = (to[a] - from[a], to[b] - from[b]);
+
+        let (i, j) = match radius {
+            Some(radius) => {
+                // The center is on the bisector of the chord, see Grbl's gcode.c
+                let h = 4.0 * radius * radius - x * x - y * y;
+                if h < 0.0 || x * x + y * y == 0.0 {
+                    return Err(33);
+                }
+                let mut h = -h.sqrt() / x.hypot(y);
+                if !clockwise {
+                    h = -h;
+                }
+                if radius < 0.0 {
+                    h = -h;
+                }
+                (0.5 * (x - y * h), 0.5 * (y + x * h))
+            }
+            None => {
+                if centers[a].is_none() && centers[b].is_none() {
+                    return Err(35);
+                }
+                let center = |axis: usize| centers[axis].unwrap_or(0.0) * unit;
+                (center(a), center(b))
+            }
+        };
+
+        let (ca, cb) = (from[a] + i, from[b] + j);
+        let radius = i.hypot(j);
+        // The angle between the vectors from the center to the start and the end
+        let (start, end) = ((-i, -j), (to[a] - ca, to[b] - cb));
+        let mut sweep =
+            (start.0 * end.1 - start.1 * end.0).atan2(start.0 * end.0 + start.1 * end.1);
+        let start = start.1.atan2(start.0);
+        if clockwise && sweep >= -1e-6 {
+            sweep -= TAU;
+        } else if !clockwise && sweep <= 1e-6 {
+            sweep += TAU;
+        }
+
+        let segments = ((sweep.abs() * radius / ARC_SEGMENT).ceil() as usize).max(1);
+        let mut points: Vec<Vector3<f32>> = (1..segments)
+            .map(|k| {
+                let t = k as f32 / segments as f32;
+                let angle = start + sweep * t;
+                let mut point = Vector3::zeros();
+                point[a] = ca + radius * angle.cos();
+                point[b] = cb + radius * angle.sin();
+                point[normal] = from[normal] + (to[normal] - from[normal]) * t;
+                point
+            })
+            .collect();
+        points.push(*to);
+        Ok(points)
+    }
+
+    fn within_travel(&self, target: &Vector3<f32>) -> bool {
+        let Some(travel) = self.settings.travel else {
+            return true;
+        };
+        (0..3).all(|i| (-travel[i]..=0.0).contains(&target[i]))
+    }
+
+    /// The kind of the move being executed.
+    fn kind(&self) -> Option<Kind> {
+        match self.planner.front() {
+            Some(Planned::Move { kind, .. }) => Some(*kind),
+            _ => None,
+        }
+    }
+
+    fn cancel_jog(&mut self) {
+        (self.planner).retain(|planned| {
+            !matches!(
+                planned,
+                Planned::Move {
+                    kind: Kind::Jog,
+                    ..
+                }
+            )
+        });
+        if self.planner.is_empty() {
+            self.planned = self.position;
+        }
+    }
+
+    /// Stops the machine and locks it until it is unlocked or homed.
+    fn alarm(&mut self, code: u8) {
+        self.planner.clear();
+        self.input.clear();
+        self.planned = self.position;
+        self.hold = false;
+        self.alarm = Some(code);
+        self.send(&format!("ALARM:{}", code));
+    }
+
+    fn reset(&mut self) {
+        // The position is lost when the machine stops abruptly
+        if self.kind().is_some() && !self.hold {
+            self.alarm(3);
+        }
+        self.planner.clear();
+        self.input.clear();
+        self.planned = self.position;
+        self.hold = false;
+        self.spindle_stopped = false;
+        self.check = false;
+        self.modal = Modal::default();
+        self.send("");
+        self.send(WELCOME);
+        if self.alarm.is_some() {
+            self.send("[MSG:'$H'|'$X' to unlock]");
+        }
+    }
+
+    fn report(&mut self) {
+        let state = match (self.alarm, self.kind()) {
+            (Some(_), _) => "Alarm",
+            _ if self.check => "Check",
+            _ if self.hold => "Hold:0",
+            (_, Some(Kind::Jog)) => "Jog",
+            (_, Some(Kind::Home)) => "Home",
+            _ if !self.planner.is_empty() => "Run",
+            _ => "Idle",
+        };
+        let feed = match self.planner.front() {
+            Some(Planned::Move { feed, .. }) if !self.hold => self.speed(*feed),
+            _ => 0.0,
+        };
+        let modal = self.modal;
+        let spindle = match modal.spindle {
+            3 | 4 if !self.spindle_stopped => modal.speed * self.overrides.spindle as f32 / 100.0,
+            _ => 0.0,
+        };
+
+        let mut report = format!(
+            "<{}|MPos:{}|Bf:{},{}|FS:{},{}",
+            state,
+            coordinates(&self.position),
+            PLANNER_BLOCKS.saturating_sub(self.planner.len()),
+            BUFFER_SIZE - self.input.len(),
+            number(feed),
+            number(spindle),
+        );
+        if self.reports % 10 == 0 {
+            let mut offset = self.offsets[modal.coordinates];
+            offset.z += self.tool_offset;
+            let overrides = self.overrides;
+            report.push_str(&format!(
+                "|WCO:{}|Ov:{},{},{}",
+                coordinates(&offset),
+                overrides.feed,
+                overrides.rapid,
+                overrides.spindle
+            ));
+
+            let mut accessories = String::new();
+            match modal.spindle {
+                3 if spindle > 0.0 => accessories.push('S'),
+                4 if spindle > 0.0 => accessories.push('C'),
+                _ => {}
+            }
+            if modal.flood {
+                accessories.push('F');
+            }
+            if modal.mist {
+                accessories.push('M');
+            }
+            if !accessories.is_empty() {
+                report.push_str(&format!("|A:{}", accessories));
+            }
+        }
+        report.push('>');
+        self.reports += 1;
+        self.send(&report);
+    }
+}
+
+/// Splits a block without whitespace into its words.
+fn words(block: &str) -> Result<Vec<(char, f32)>, u8> {
+    let mut words = Vec::new();
+    let mut chars = block.chars().peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() {
+            return Err(1);
+        }
+        let mut number = String::new();
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+                break;
+            }
+            number.push(c);
+            chars.next();
+        }
+        words.push((letter, number.parse().map_err(|_| 2)?));
+    }
+    Ok(words)
+}
+
+/// Changes an override by the percentage, within 10% and 200%.
+fn change(value: u32, percent: i32) -> u32 {
+    (value as i32 + percent).clamp(10, 200) as u32
+}
+
+/// A G code times ten, e.g. 382 for G38.2.
+fn code(value: f32) -> u32 {
+    (value * 10.0).round() as u32
+}
+
+/// Formats a G code times ten.
+fn format_code(code: u32) -> String {
+    match code % 10 {
+        0 => format!("{}", code / 10),
+        fraction => format!("{}.{}", code / 10, fraction),
+    }
+}
+
+fn axis(letter: char) -> usize {
+    (letter as u8 - b'X') as usize
+}
+
+/// The length of a unit of the program, in millimeters.
+fn unit(modal: &Modal) -> f32 {
+    if modal.inches {
+        25.4
+    } else {
+        1.0
+    }
+}
+
+fn number(value: f32) -> String {
+    format!("{:.3}", value)
+}
+
+fn coordinates(position: &Vector3<f32>) -> String {
+    format!(
+        "{},{},{}",
+        number(position.x),
+        number(position.y),
+        number(position.z)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sender::grbl::{Event, Grbl, Override};
+    use crate::sender::status::State;
+    use crate::sender::Connection;
+
+    /// Polls the sender for the seconds, returning the events with the time they occured at.
+    fn run(grbl: &mut Grbl<VirtualGrbl>, seconds: f32) -> Vec<(f32, Event)> {
+        let step = Duration::from_millis(10);
+        let mut events = Vec::new();
+        for i in 0..(seconds / step.as_secs_f32()).round() as usize {
+            grbl.port().advance(step);
+            let time = (i + 1) as f32 * step.as_secs_f32();
+            events.extend(grbl.poll().unwrap().into_iter().map(|event| (time, event)));
+        }
+        events
+    }
+
+    fn stepped(settings: Settings) -> Grbl<VirtualGrbl> {
+        let mut grbl = Grbl::new(VirtualGrbl::stepped(settings));
+        grbl.status_interval = Some(Duration::ZERO);
+        grbl
+    }
+
+    /// The output of the controller since the last read.
+    fn output(port: &mut VirtualGrbl) -> String {
+        let mut output = Vec::new();
+        let mut buffer = [0; 256];
+        while let Ok(n) = port.read(&mut buffer) {
+            output.extend_from_slice(&buffer[..n]);
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn streams_programs() {
+        let mut grbl = stepped(Settings::default());
+        let events = run(&mut grbl, 0.1);
+        assert!(matches!(events[0], (_, Event::Reset(_))));
+
+        // 11.2 mm at rapid speed, 2 mm at 600 mm/min, a circle of 31.4 mm at 1200 mm/min and
+        // half a second of dwell take 2.5 seconds
+        let program = "G21 G90\nG10 L20 P1 X10 Y5 Z0\nG0 X0 Y0\nG1 Z-2 F600\n\
+                       G2 X0 Y0 I5 J0 F1200\nG4 P0.5\nM30\n";
+        grbl.start(program).unwrap();
+        let events = run(&mut grbl, 3.0);
+        let finished = events
+            .iter()
+            .find_map(|(time, event)| (*event == Event::Finished).then_some(*time))
+            .unwrap();
+        assert!(
+            (2.4..2.6).contains(&finished),
+            "finished after {}",
+            finished
+        );
+
+        let status = grbl.status().unwrap();
+        assert_eq!(status.state, State::Idle);
+        assert_eq!(status.machine, Vector3::new(-10.0, -5.0, -2.0));
+        assert_eq!(status.work, Vector3::new(0.0, 0.0, -2.0));
+        assert!(events
+            .iter()
+            .all(|(_, event)| !matches!(event, Event::Error { .. })));
+    }
+
+    #[test]
+    fn errors_holds_and_alarms() {
+        let mut grbl = stepped(Settings {
+            travel: Some(Vector3::new(100.0, 100.0, 50.0)),
+            ..Default::default()
+        });
+        run(&mut grbl, 0.1);
+        grbl.command("G1 X-10");
+        grbl.command("G5");
+        let events = run(&mut grbl, 0.1);
+        let errors: Vec<_> = (events.iter())
+            .filter_map(|(_, event)| match event {
+                Event::Error { code, .. } => Some(*code),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, [22, 20]);
+
+        // 50 mm at rapid speed take a second
+        grbl.command("G0 X-50");
+        run(&mut grbl, 0.2);
+        grbl.pause().unwrap();
+        run(&mut grbl, 0.5);
+        let status = grbl.status().unwrap();
+        assert_eq!(status.state, State::Hold);
+        assert!((status.machine.x + 10.0).abs() < 1.0);
+        grbl.resume().unwrap();
+        run(&mut grbl, 1.0);
+        assert_eq!(grbl.port().position().x, -50.0);
+
+        // Soft limits lock the machine until it is unlocked
+        grbl.command("G0 X10");
+        let events = run(&mut grbl, 0.1);
+        assert!(events.iter().any(|(_, event)| *event == Event::Alarm(2)));
+        grbl.command("G0 X-60");
+        let events = run(&mut grbl, 0.1);
+        assert!(events
+            .iter()
+            .any(|(_, event)| matches!(event, Event::Error { code: 9, .. })));
+        grbl.command("$X");
+        grbl.command("G0 X-80");
+        run(&mut grbl, 1.0);
+        assert_eq!(grbl.port().position().x, -80.0);
+
+        // Resets during motion lose the position
+        grbl.command("G0 X0");
+        run(&mut grbl, 0.1);
+        grbl.reset().unwrap();
+        let events: Vec<_> = run(&mut grbl, 0.1).into_iter().map(|(_, e)| e).collect();
+        assert_eq!(events[0], Event::Alarm(3));
+        assert!(matches!(events[1], Event::Reset(_)));
+        assert_eq!(grbl.status().unwrap().state, State::Alarm);
+
+        // The alarm outlasts the reset until unlocking succeeds
+        assert_eq!(grbl.alarm(), Some(3));
+        grbl.command("$X");
+        run(&mut grbl, 0.1);
+        assert_eq!(grbl.alarm(), None);
+    }
+
+    #[test]
+    fn buffers_and_probing() {
+        let mut grbl = stepped(Settings {
+            probe_z: Some(-30.0),
+            ..Default::default()
+        });
+        let port = grbl.port_mut();
+        assert!(output(port).contains(WELCOME));
+
+        // Bytes beyond the receive buffer are lost
+        port.write_all(&[b'G'; 200]).unwrap();
+        port.write_all(b"?").unwrap();
+        assert!(output(port).contains("|Bf:15,0|"));
+        port.write_all(&[realtime::SOFT_RESET]).unwrap();
+        assert!(output(port).contains(WELCOME));
+
+        grbl.command("G38.2 G91 Z-50 F600");
+        grbl.command("G90");
+        let events = run(&mut grbl, 4.0);
+        let probe = events.iter().find_map(|(_, event)| match event {
+            Event::Probe(result) => Some(*result),
+            _ => None,
+        });
+        assert!(probe.unwrap().success);
+        assert_eq!(probe.unwrap().position.z, -30.0);
+        assert!(grbl.is_idle());
+
+        // Without contact
+        grbl.command("G38.2 Z-40 F600");
+        let events = run(&mut grbl, 2.0);
+        assert!(events.iter().any(|(_, event)| *event == Event::Alarm(5)));
+    }
+
+    #[test]
+    fn connection() {
+        let grbl = Grbl::new(VirtualGrbl::new(Settings::default()));
+        let connection = Connection::new(PORT_NAME, grbl);
+
+        let start = Instant::now();
+        let mut sent = false;
+        while start.elapsed() < Duration::from_secs(5) {
+            let state = connection.state();
+            // Commands before the welcome message are discarded by the reset
+            if !sent && state.status.is_some() {
+                connection.command("G0 X-5 Y-5");
+                sent = true;
+            }
+            if state.status.as_ref().map_or(false, |status| {
+                status.state == State::Idle && status.work == Vector3::new(-5.0, -5.0, 0.0)
+            }) {
+                return;
+            }
+            drop(state);
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the machine did not move: {:?}", connection.state().status);
+    }
+
+    #[test]
+    fn override_requests() {
+        let grbl = Grbl::new(VirtualGrbl::new(Settings::default()));
+        let connection = Connection::new(PORT_NAME, grbl);
+        let start = Instant::now();
+        while connection.state().status.is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The second change starts from the first one, before any report confirms it
+        connection.set_override(Override::Feed, 150);
+        connection.set_override(Override::Feed, 120);
+        assert_eq!(connection.state().override_value(Override::Feed), 120);
+
+        while start.elapsed() < Duration::from_secs(5) {
+            let state = connection.state();
+            if state.requested_overrides == [None; 2] {
+                assert_eq!(state.status.as_ref().unwrap().overrides.feed, 120);
+                return;
+            }
+            drop(state);
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the override was not confirmed: {:?}", connection.state());
+    }
+}
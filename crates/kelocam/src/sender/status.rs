@@ -0,0 +1,184 @@
+//! Status reports of Grbl controllers, e.g.
+//! "<Run|MPos:10.000,5.000,-1.000|Bf:15,128|FS:500,8000|Ov:100,100,100>".
+
+use nalgebra::Vector3;
+
+/// The state of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Run,
+    /// Stopped by a feed hold.
+    Hold,
+    Jog,
+    Alarm,
+    /// Stopped by the safety door.
+    Door,
+    /// Checking a program without moving ($C).
+    Check,
+    Home,
+    Sleep,
+}
+
+impl State {
+    /// Parses the state, substates like "Hold:0" are ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let state = match text.split(':').next()? {
+            "Idle" => Self::Idle,
+            "Run" => Self::Run,
+            "Hold" => Self::Hold,
+            "Jog" => Self::Jog,
+            "Alarm" => Self::Alarm,
+            "Door" => Self::Door,
+            "Check" => Self::Check,
+            "Home" => Self::Home,
+            "Sleep" => Self::Sleep,
+            _ => return None,
+        };
+        Some(state)
+    }
+}
+
+/// Overrides of the programmed values, in percent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overrides {
+    pub feed: u32,
+    pub rapid: u32,
+    pub spindle: u32,
+}
+
+impl Default for Overrides {
+    fn default() -> Self {
+        Self {
+            feed: 100,
+            rapid: 100,
+            spindle: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub state: State,
+    /// The position in machine coordinates.
+    pub machine: Vector3<f32>,
+    /// The position in work coordinates.
+    pub work: Vector3<f32>,
+    /// The offset of the work coordinate system, the work position is the machine position
+    /// minus the offset.
+    pub offset: Vector3<f32>,
+    /// The current feed rate.
+    pub feed: f32,
+    /// The current spindle speed, in rpm.
+    pub spindle: f32,
+    pub overrides: Overrides,
+    /// The free blocks in the planner buffer and the free bytes in the receive buffer.
+    pub buffer: Option<(u32, u32)>,
+    /// The line number of the executed block, if the program has line numbers.
+    pub line: Option<u32>,
+    /// The letters of the triggered input pins, e.g. "XP" for the X limit switch and probe.
+    pub pins: String,
+    /// The letters of the active accessories: "S" and "C" for the spindle turning clockwise and
+    /// counter clockwise, "F" and "M" for flood and mist coolant.
+    pub accessories: String,
+}
+
+impl Status {
+    /// Parses a status report. Grbl reports the work offset and overrides only every few
+    /// reports, they are taken from the previous status when missing.
+    pub fn parse(report: &str, previous: Option<&Status>) -> Option<Self> {
+        let report = report.trim().strip_prefix('<')?.strip_suffix('>')?;
+        let mut fields = report.split('|');
+        let state = State::parse(fields.next()?)?;
+
+        let mut status = Self {
+            state,
+            machine: Vector3::zeros(),
+            work: Vector3::zeros(),
+            offset: previous.map_or_else(Vector3::zeros, |status| status.offset),
+            feed: 0.0,
+            spindle: 0.0,
+            overrides: previous.map_or_else(Overrides::default, |status| status.overrides),
+            buffer: None,
+            line: None,
+            pins: String::new(),
+            accessories: String::new(),
+        };
+
+        let (mut machine, mut work) = (None, None);
+        for field in fields {
+            let (name, value) = field.split_once(':')?;
+            let values: Vec<f32> = value.split(',').filter_map(|v| v.parse().ok()).collect();
+            match (name, values.as_slice()) {
+                ("MPos", [x, y, z, ..]) => machine = Some(Vector3::new(*x, *y, *z)),
+                ("WPos", [x, y, z, ..]) => work = Some(Vector3::new(*x, *y, *z)),
+                ("WCO", [x, y, z, ..]) => status.offset = Vector3::new(*x, *y, *z),
+                ("F", [feed]) => status.feed = *feed,
+                ("FS", [feed, spindle]) => (status.feed, status.spindle) = (*feed, *spindle),
+                ("Ov", [feed, rapid, spindle]) => {
+                    status.overrides = Overrides {
+                        feed: *feed as u32,
+                        rapid: *rapid as u32,
+                        spindle: *spindle as u32,
+                    }
+                }
+                ("Bf", [blocks, bytes]) => status.buffer = Some((*blocks as u32, *bytes as u32)),
+                ("Ln", [line]) => status.line = Some(*line as u32),
+                ("Pn", _) => status.pins = value.to_string(),
+                ("A", _) => status.accessories = value.to_string(),
+                _ => {}
+            }
+        }
+
+        // Grbl reports either the machine or the work position
+        match (machine, work) {
+            (Some(machine), _) => {
+                status.machine = machine;
+                status.work = machine - status.offset;
+            }
+            (None, Some(work)) => {
+                status.work = work;
+                status.machine = work + status.offset;
+            }
+            (None, None) => return None,
+        }
+        Some(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        let first = Status::parse(
+            "<Idle|MPos:10.000,5.000,-1.000|FS:0,0|WCO:5.000,5.000,-10.000>",
+            None,
+        )
+        .unwrap();
+        assert_eq!(first.state, State::Idle);
+        assert_eq!(first.work, Vector3::new(5.0, 0.0, 9.0));
+        assert_eq!(first.overrides, Overrides::default());
+
+        let second = Status::parse(
+            "<Hold:0|WPos:1.000,2.000,3.000|Bf:15,128|Ln:20|FS:500,8000|Ov:120,50,100|Pn:XP|A:SF>",
+            Some(&first),
+        )
+        .unwrap();
+        assert_eq!(second.state, State::Hold);
+        assert_eq!(second.machine, Vector3::new(6.0, 7.0, -7.0));
+        assert_eq!((second.feed, second.spindle), (500.0, 8000.0));
+        assert_eq!(second.overrides.feed, 120);
+        assert_eq!(second.overrides.rapid, 50);
+        assert_eq!(second.buffer, Some((15, 128)));
+        assert_eq!(second.line, Some(20));
+        assert_eq!(
+            (second.pins.as_str(), second.accessories.as_str()),
+            ("XP", "SF")
+        );
+
+        assert_eq!(Status::parse("<Idle|FS:0,0>", None), None);
+        assert_eq!(Status::parse("[MSG:Caution: Unlocked]", None), None);
+    }
+}
@@ -0,0 +1,222 @@
+use nalgebra::Vector3;
+
+use crate::sender::{Connection, ProbeSettings, Routine};
+
+/// The distances of a jog step to choose from, in millimeters.
+const STEPS: [f32; 5] = [0.01, 0.1, 1.0, 10.0, 100.0];
+
+/// Jogging, homing, zeroing and probing of a connected machine.
+pub struct ControlPanel {
+    /// The distance of a jog step, in millimeters.
+    step: f32,
+    /// The feed rate of jogs, in mm/min.
+    feed: f32,
+    probe: ProbeSettings,
+    /// The thickness of the touch plate, in millimeters.
+    plate_thickness: f32,
+    /// The diameter of the tool touching the sides of the stock, in millimeters.
+    tool_diameter: f32,
+    /// How far from the corner the sides of the stock are touched, in millimeters.
+    corner_offset: f32,
+}
+
+impl Default for ControlPanel {
+    fn default() -> Self {
+        Self {
+            step: 1.0,
+            feed: 1000.0,
+            probe: ProbeSettings::default(),
+            plate_thickness: 10.0,
+            tool_diameter: 6.0,
+            corner_offset: 10.0,
+        }
+    }
+}
+
+impl ControlPanel {
+    pub fn show(&mut self, ui: &mut egui::Ui, connection: &Connection) {
+        ui.heading("Control");
+
+        // Moving the machine or changing its offsets would spoil a running job
+        let busy = {
+            let state = connection.state();
+            state.running || state.probing
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Step");
+            for step in STEPS {
+                ui.selectable_value(&mut self.step, step, format!("{}", step));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Feed");
+            ui.add(
+                egui::DragValue::new(&mut self.feed)
+                    .clamp_range(1.0..=10000.0)
+                    .suffix(" mm/min"),
+            );
+        });
+
+        let mut jog = None;
+        ui.add_enabled_ui(!busy, |ui| {
+            egui::Grid::new("jog").show(ui, |ui| {
+                ui.label("");
+                if ui.button("Y+").clicked() {
+                    jog = Some(Vector3::y());
+                }
+                ui.label("");
+                if ui.button("Z+").clicked() {
+                    jog = Some(Vector3::z());
+                }
+                ui.end_row();
+
+                if ui.button("X-").clicked() {
+                    jog = Some(-Vector3::x());
+                }
+                if ui
+                    .add_enabled(connection.supports_jog_cancel(), egui::Button::new("Stop"))
+                    .on_disabled_hover_text("The firmware can't cancel jogs")
+                    .clicked()
+                {
+                    connection.cancel_jog();
+                }
+                if ui.button("X+").clicked() {
+                    jog = Some(Vector3::x());
+                }
+                ui.end_row();
+
+                ui.label("");
+                if ui.button("Y-").clicked() {
+                    jog = Some(-Vector3::y());
+                }
+                ui.label("");
+                if ui.button("Z-").clicked() {
+                    jog = Some(-Vector3::z());
+                }
+                ui.end_row();
+            });
+        });
+        if let Some(direction) = jog {
+            connection.jog(&direction.scale(self.step), self.feed);
+        }
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!busy, egui::Button::new("Home")).clicked() {
+                connection.home();
+            }
+            if ui.button("Unlock").clicked() {
+                connection.unlock();
+            }
+        });
+
+        ui.add_enabled_ui(!busy, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Zero");
+                for (i, axis) in ["X", "Y", "Z"].into_iter().enumerate() {
+                    if ui.button(axis).clicked() {
+                        let mut position = [None; 3];
+                        position[i] = Some(0.0);
+                        connection.set_work_position(position);
+                    }
+                }
+                if ui.button("All").clicked() {
+                    connection.set_work_position([Some(0.0); 3]);
+                }
+            });
+        });
+
+        self.probing(ui, connection);
+    }
+
+    fn probing(&mut self, ui: &mut egui::Ui, connection: &Connection) {
+        ui.heading("Probing");
+
+        let (busy, reference) = {
+            let state = connection.state();
+            (state.running || state.probing, state.tool_reference)
+        };
+
+        let probe = &mut self.probe;
+        egui::Grid::new("probe settings").show(ui, |ui| {
+            ui.label("Distance");
+            ui.add(length(&mut probe.distance, 1.0..=100.0));
+            ui.end_row();
+            ui.label("Retract");
+            ui.add(length(&mut probe.retract, 0.5..=10.0));
+            ui.end_row();
+            ui.label("Feed");
+            ui.horizontal(|ui| {
+                ui.add(feed(&mut probe.fast_feed));
+                ui.add(feed(&mut probe.slow_feed))
+                    .on_hover_text("Feed rate of the second, precise contact");
+            });
+            ui.end_row();
+        });
+
+        ui.add_enabled_ui(!busy, |ui| {
+            let mut routine = None;
+            ui.horizontal(|ui| {
+                if ui.button("Touch plate").clicked() {
+                    routine = Some(Routine::TouchPlate {
+                        thickness: self.plate_thickness,
+                    });
+                }
+                ui.add(length(&mut self.plate_thickness, 0.0..=50.0))
+                    .on_hover_text("Thickness of the touch plate");
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Corner")
+                    .on_hover_text("Start in front of and left of the front left corner")
+                    .clicked()
+                {
+                    routine = Some(Routine::Corner {
+                        tool_diameter: self.tool_diameter,
+                        offset: self.corner_offset,
+                    });
+                }
+                ui.add(length(&mut self.tool_diameter, 0.0..=50.0))
+                    .on_hover_text("Tool diameter");
+                ui.add(length(&mut self.corner_offset, 1.0..=100.0))
+                    .on_hover_text("Distance of the contacts from the corner");
+            });
+            ui.horizontal(|ui| {
+                let text = match reference {
+                    Some(_) => "Tool length",
+                    None => "Reference tool",
+                };
+                if ui
+                    .button(text)
+                    .on_hover_text("Measure the tool on the tool setter")
+                    .clicked()
+                {
+                    routine = Some(Routine::ToolLength { reference });
+                }
+                if reference.is_some() && ui.button("Forget reference").clicked() {
+                    connection.state().tool_reference = None;
+                }
+            });
+            if ui.button("Probe Z").clicked() {
+                connection.probe(&Vector3::new(0.0, 0.0, -probe.distance), probe.fast_feed);
+            }
+
+            if let Some(routine) = routine {
+                connection.run_probing(routine, *probe);
+            }
+        });
+    }
+}
+
+fn length(value: &mut f32, range: std::ops::RangeInclusive<f32>) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .clamp_range(range)
+        .speed(0.1)
+        .suffix(" mm")
+}
+
+fn feed(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .clamp_range(1.0..=1000.0)
+        .suffix(" mm/min")
+}
@@ -0,0 +1,205 @@
+use kelocam_core::operations::check_envelope;
+use kelocam_editor::Editor;
+use kelocam_postprocessor::{Dialect, Parser, PostProcessor, Resume};
+
+use crate::sender::{Connection, Firmware};
+
+/// Running the toolpath of the editor, from the start or part way through. A loaded program is
+/// run as it is, only generated toolpaths are posted.
+pub struct JobPanel {
+    resume: Resume,
+    /// The line of the program to resume at, starting at 1.
+    line: usize,
+    /// The index of the tag of the operation to resume at.
+    operation: usize,
+    /// What to add to the line numbers of the running job to get the line numbers of the whole
+    /// program. Unknown after resuming at an operation.
+    offset: Option<isize>,
+}
+
+impl Default for JobPanel {
+    fn default() -> Self {
+        Self {
+            resume: Resume::default(),
+            line: 1,
+            operation: 0,
+            offset: Some(0),
+        }
+    }
+}
+
+impl JobPanel {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        connection: &Connection,
+        editor: &Editor,
+    ) -> Result<(), String> {
+        ui.heading("Job");
+
+        let (busy, paused, progress) = {
+            let state = connection.state();
+            (state.running || state.probing, state.paused, state.progress)
+        };
+        let mut last_line = None;
+        if let Some(progress) = progress {
+            ui.add(egui::ProgressBar::new(progress.fraction()).show_percentage());
+            ui.label(format!(
+                "{} of {} lines",
+                progress.acknowledged, progress.total
+            ));
+            last_line = (progress.executing.zip(self.offset))
+                .map(|(line, offset)| (line as isize + offset).max(1) as usize);
+            if let Some(line) = last_line {
+                ui.label(format!("At line {} of the program", line));
+            }
+        }
+
+        let toolpath = editor.toolpath.as_ref().filter(|_| !busy);
+        let source = editor.program.as_deref();
+        // Marlin reads the P word of dwells in milliseconds
+        let dialect = match connection.firmware {
+            Firmware::Grbl => Dialect::grbl(),
+            Firmware::Marlin => Dialect::marlin(),
+        };
+        let post = match &editor.machine {
+            Some(machine) => PostProcessor::for_machine(machine, dialect),
+            None => PostProcessor::new(dialect),
+        };
+        // Generated toolpaths are checked against the machine before they are posted
+        let check = |toolpath| {
+            (editor.machine.as_ref())
+                .and_then(|machine| check_envelope(toolpath, machine))
+                .map_or(Ok(()), |violation| {
+                    Err(format!(
+                        "The toolpath leaves the work envelope: {}",
+                        violation
+                    ))
+                })
+        };
+        let program = |toolpath| match source {
+            Some(source) => Ok(source.to_string()),
+            None => {
+                check(toolpath)?;
+                post.post(toolpath).map_err(|e| e.to_string())
+            }
+        };
+
+        let mut result = Ok(());
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(toolpath.is_some(), egui::Button::new("Start"))
+                .on_disabled_hover_text("Generate a toolpath first")
+                .clicked()
+            {
+                if let Some(toolpath) = toolpath {
+                    result = program(toolpath).map(|program| connection.start(program));
+                    self.offset = Some(0);
+                }
+            }
+            if paused {
+                if ui.button("Resume").clicked() {
+                    connection.resume();
+                }
+            } else if ui.button("Pause").clicked() {
+                connection.pause();
+            }
+            if ui.button("Reset").clicked() {
+                connection.reset();
+            }
+        });
+
+        ui.collapsing("Start part way", |ui| {
+            let resume = &mut self.resume;
+            egui::Grid::new("resume").show(ui, |ui| {
+                ui.label("Safe Z");
+                ui.add(
+                    egui::DragValue::new(&mut resume.safe_z)
+                        .clamp_range(0.0..=100.0)
+                        .speed(0.1)
+                        .suffix(" mm"),
+                );
+                ui.end_row();
+
+                let mut plunge = resume.plunge_feed.is_some();
+                ui.checkbox(&mut plunge, "Plunge feed")
+                    .on_hover_text("Otherwise the feed rate of the program is used");
+                let mut feed = resume.plunge_feed.unwrap_or(300.0);
+                ui.add_enabled(
+                    plunge,
+                    egui::DragValue::new(&mut feed)
+                        .clamp_range(1.0..=10000.0)
+                        .suffix(" mm/min"),
+                );
+                resume.plunge_feed = plunge.then_some(feed);
+                ui.end_row();
+
+                ui.label("Spindle delay");
+                ui.add(
+                    egui::DragValue::new(&mut resume.spindle_delay)
+                        .clamp_range(0.0..=60.0)
+                        .speed(0.1)
+                        .suffix(" s"),
+                );
+                ui.end_row();
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Line");
+                ui.add(egui::DragValue::new(&mut self.line).clamp_range(1..=usize::MAX));
+                if ui
+                    .add_enabled(last_line.is_some(), egui::Button::new("Last"))
+                    .on_hover_text(
+                        "The line the machine executes, or an earlier one if the controller \
+                         does not report it",
+                    )
+                    .clicked()
+                {
+                    self.line = last_line.unwrap_or(1);
+                }
+                let start = ui.add_enabled(toolpath.is_some(), egui::Button::new("Start"));
+                if let Some(toolpath) = toolpath.filter(|_| start.clicked()) {
+                    let parser = Parser {
+                        dwell: post.dialect.dwell,
+                        ..Default::default()
+                    };
+                    let resumed = program(toolpath).and_then(|program| {
+                        let resumed = (self.resume.program(&program, self.line, &parser))
+                            .map_err(|e| e.to_string())?;
+                        let skipped =
+                            program.lines().count() as isize - resumed.lines().count() as isize;
+                        self.offset = Some(skipped);
+                        Ok(resumed)
+                    });
+                    result = resumed.map(|program| connection.start(program));
+                }
+            });
+
+            // Only generated toolpaths know their operations
+            let tags =
+                (toolpath.filter(|_| source.is_none())).map_or(&[][..], |toolpath| &toolpath.tags);
+            ui.horizontal(|ui| {
+                ui.label("Operation");
+                let selected = tags.get(self.operation).map(|tag| tag.operation.as_str());
+                egui::ComboBox::from_id_source("resume operation")
+                    .selected_text(selected.unwrap_or_default())
+                    .show_ui(ui, |ui| {
+                        for (i, tag) in tags.iter().enumerate() {
+                            ui.selectable_value(&mut self.operation, i, &tag.operation);
+                        }
+                    });
+                let start = ui.add_enabled(selected.is_some(), egui::Button::new("Start"));
+                if let (Some(toolpath), true) = (toolpath, start.clicked()) {
+                    let index = tags[self.operation].moves.start;
+                    result = check(toolpath)
+                        .and_then(|_| {
+                            (post.resume(toolpath, index, &self.resume)).map_err(|e| e.to_string())
+                        })
+                        .map(|program| connection.start(program));
+                    self.offset = None;
+                }
+            });
+        });
+        result
+    }
+}
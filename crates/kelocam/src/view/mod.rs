@@ -1,7 +1,16 @@
+pub mod control;
+pub mod job;
+pub mod monitor;
+pub mod overrides;
 pub mod prepare;
+pub use control::ControlPanel;
+pub use job::JobPanel;
+pub use monitor::MonitorView;
+pub use overrides::OverridePanel;
 pub use prepare::PrepareView;
 
 pub enum View {
     None,
     Prepare,
+    Monitor,
 }
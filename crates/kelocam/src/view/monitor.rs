@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use kelocam_editor::Editor;
+
+use super::{ControlPanel, JobPanel, OverridePanel};
+use crate::sender::{self, grbl, Connection, Firmware};
+
+/// How often the view is repainted while connected, to show the status reports.
+const REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The baud rates to choose from.
+const BAUD_RATES: [u32; 4] = [57600, 115200, 250000, 500000];
+
+#[derive(Default)]
+pub struct MonitorView {
+    /// The serial ports to choose from.
+    ports: Vec<String>,
+    port: String,
+    firmware: Firmware,
+    baud_rate: Option<u32>,
+    connection: Option<Connection>,
+    control: ControlPanel,
+    job: JobPanel,
+    overrides: OverridePanel,
+    /// The block typed into the console.
+    command: String,
+    error: Option<String>,
+}
+
+impl MonitorView {
+    pub fn show(&mut self, ctx: &egui::Context, editor: &mut Editor) {
+        let mut messages = Vec::new();
+
+        egui::SidePanel::left("monitor")
+            .resizable(true)
+            .default_width(250.0)
+            .width_range(200.0..=350.0)
+            .show_separator_line(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::new([false, true]).show(ui, |ui| {
+                    self.connection_ui(ui);
+                    if let Some(connection) = &self.connection {
+                        ui.separator();
+                        status(ui, connection);
+                        ui.separator();
+                        self.control.show(ui, connection);
+                        ui.separator();
+                        if let Err(e) = self.job.show(ui, connection, editor) {
+                            self.error = Some(e);
+                        }
+                        ui.separator();
+                        self.overrides.show(ui, connection);
+                        ui.separator();
+                        console(ui, connection, &mut self.command);
+                    }
+                });
+            });
+
+        editor.tool_position = (self.connection.as_ref())
+            .and_then(|connection| connection.state().status.as_ref().map(|status| status.work));
+
+        egui::CentralPanel::default()
+            .frame(egui::Frame::default())
+            .show(ctx, |ui| editor.ui(ui, &mut messages));
+
+        for mut message in messages {
+            editor.state.apply(&mut message);
+            editor.log.push(message);
+        }
+
+        if self.connection.is_some() {
+            ctx.request_repaint_after(REPAINT_INTERVAL);
+        }
+    }
+
+    fn connection_ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Connection");
+
+        match &self.connection {
+            Some(connection) => {
+                ui.label(format!("Connected to {}", connection.name));
+                if let Some(error) = connection.state().error.clone() {
+                    self.error = Some(error);
+                }
+                if ui.button("Disconnect").clicked() || !connection.is_open() {
+                    self.connection = None;
+                }
+            }
+            None => {
+                if self.ports.is_empty() {
+                    self.ports = sender::ports();
+                }
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("port")
+                        .selected_text(self.port.as_str())
+                        .show_ui(ui, |ui| {
+                            for port in self.ports.iter() {
+                                ui.selectable_value(&mut self.port, port.clone(), port);
+                            }
+                        });
+                    if ui.button("Refresh").clicked() {
+                        self.ports = sender::ports();
+                    }
+                });
+                egui::Grid::new("serial settings").show(ui, |ui| {
+                    ui.label("Firmware");
+                    egui::ComboBox::from_id_source("firmware")
+                        .selected_text(self.firmware.name())
+                        .show_ui(ui, |ui| {
+                            for firmware in Firmware::ALL {
+                                let name = firmware.name();
+                                if ui
+                                    .selectable_value(&mut self.firmware, firmware, name)
+                                    .changed()
+                                {
+                                    // The usual baud rate of the firmware
+                                    self.baud_rate = None;
+                                }
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Baud rate");
+                    let default = self.firmware.baud_rate();
+                    let baud_rate = self.baud_rate.get_or_insert(default);
+                    egui::ComboBox::from_id_source("baud rate")
+                        .selected_text(baud_rate.to_string())
+                        .show_ui(ui, |ui| {
+                            for rate in BAUD_RATES {
+                                ui.selectable_value(baud_rate, rate, rate.to_string());
+                            }
+                        });
+                    ui.end_row();
+                });
+
+                let enabled = !self.port.is_empty();
+                if ui
+                    .add_enabled(enabled, egui::Button::new("Connect"))
+                    .clicked()
+                {
+                    let baud_rate = self.baud_rate.unwrap_or(self.firmware.baud_rate());
+                    match Connection::open(&self.port, self.firmware, baud_rate) {
+                        Ok(connection) => {
+                            self.connection = Some(connection);
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e.to_string()),
+                    }
+                }
+            }
+        }
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+}
+
+fn status(ui: &mut egui::Ui, connection: &Connection) {
+    let state = connection.state();
+    let Some(status) = &state.status else {
+        ui.label("Waiting for status...");
+        return;
+    };
+
+    egui::Grid::new("status").num_columns(3).show(ui, |ui| {
+        ui.label("State");
+        ui.strong(format!("{:?}", status.state));
+        ui.end_row();
+
+        ui.label("");
+        ui.label("Work");
+        ui.label("Machine");
+        ui.end_row();
+        for (i, axis) in ["X", "Y", "Z"].into_iter().enumerate() {
+            ui.label(axis);
+            ui.monospace(format!("{:9.3}", status.work[i]));
+            ui.monospace(format!("{:9.3}", status.machine[i]));
+            ui.end_row();
+        }
+
+        ui.label("Feed");
+        ui.label(format!("{:.0} mm/min", status.feed));
+        ui.end_row();
+        ui.label("Spindle");
+        ui.label(format!("{:.0} rpm", status.spindle));
+        ui.end_row();
+
+        let overrides = status.overrides;
+        ui.label("Overrides");
+        ui.label(format!(
+            "F {}%  R {}%  S {}%",
+            overrides.feed, overrides.rapid, overrides.spindle
+        ));
+        ui.end_row();
+    });
+
+    if let Some(alarm) = state.alarm {
+        ui.colored_label(
+            ui.visuals().error_fg_color,
+            format!("Alarm {}: {}", alarm, grbl::alarm_message(alarm)),
+        );
+    }
+}
+
+fn console(ui: &mut egui::Ui, connection: &Connection, command: &mut String) {
+    ui.heading("Console");
+
+    egui::ScrollArea::vertical()
+        .id_source("console")
+        .max_height(200.0)
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            for line in connection.state().log.iter() {
+                ui.monospace(line);
+            }
+        });
+
+    ui.horizontal(|ui| {
+        let response = ui.text_edit_singleline(command);
+        let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        if (ui.button("Send").clicked() || entered) && !command.trim().is_empty() {
+            connection.command(command);
+            command.clear();
+        }
+    });
+}
@@ -0,0 +1,107 @@
+use crate::sender::grbl::{self, realtime, Override};
+use crate::sender::{Connection, Firmware};
+
+/// Feed, rapid and spindle overrides of a running job.
+#[derive(Default)]
+pub struct OverridePanel {
+    /// The values of the sliders being dragged, the overrides change when they are released.
+    feed: Option<u32>,
+    spindle: Option<u32>,
+}
+
+impl OverridePanel {
+    pub fn show(&mut self, ui: &mut egui::Ui, connection: &Connection) {
+        ui.heading("Overrides");
+
+        let (overrides, feed, spindle) = {
+            let state = connection.state();
+            let overrides = (state.status.as_ref())
+                .map(|status| status.overrides)
+                .unwrap_or_default();
+            // Changes show up before the controller confirms them
+            let feed = state.override_value(Override::Feed);
+            (overrides, feed, state.override_value(Override::Spindle))
+        };
+
+        egui::Grid::new("overrides").show(ui, |ui| {
+            ui.label("Feed");
+            adjustable(ui, connection, Override::Feed, feed, &mut self.feed);
+            ui.end_row();
+
+            ui.label("Spindle");
+            ui.add_enabled_ui(connection.supports_override(Override::Spindle), |ui| {
+                adjustable(
+                    ui,
+                    connection,
+                    Override::Spindle,
+                    spindle,
+                    &mut self.spindle,
+                );
+            });
+            ui.end_row();
+
+            // Marlin has no rapid override either
+            if connection.firmware != Firmware::Grbl {
+                return;
+            }
+
+            ui.label("Rapid");
+            ui.horizontal(|ui| {
+                for percent in [25, 50, 100] {
+                    let selected = overrides.rapid == percent;
+                    if ui
+                        .selectable_label(selected, format!("{}%", percent))
+                        .clicked()
+                    {
+                        connection.realtime(grbl::rapid_override(percent));
+                    }
+                }
+            });
+            ui.end_row();
+        });
+
+        if connection.firmware == Firmware::Grbl
+            && ui
+                .button("Toggle spindle")
+                .on_hover_text("Stops and restarts the spindle while paused")
+                .clicked()
+        {
+            connection.realtime(realtime::SPINDLE_STOP);
+        }
+    }
+}
+
+/// A slider and buttons changing the override in steps.
+fn adjustable(
+    ui: &mut egui::Ui,
+    connection: &Connection,
+    kind: Override,
+    current: u32,
+    dragged: &mut Option<u32>,
+) {
+    ui.vertical(|ui| {
+        let mut value = dragged.unwrap_or(current);
+        let response = ui.add(egui::Slider::new(&mut value, 10..=200).suffix("%"));
+        if response.dragged() {
+            *dragged = Some(value);
+        } else if response.changed() || dragged.is_some() {
+            dragged.take();
+            connection.set_override(kind, value);
+        }
+
+        ui.horizontal(|ui| {
+            // The steps go through the requested value, so quick clicks add up
+            for (text, target) in [
+                ("-10", current.saturating_sub(10)),
+                ("-1", current.saturating_sub(1)),
+                ("100%", 100),
+                ("+1", current + 1),
+                ("+10", current + 10),
+            ] {
+                if ui.small_button(text).clicked() {
+                    connection.set_override(kind, target);
+                }
+            }
+        });
+    });
+}
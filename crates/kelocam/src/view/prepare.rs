@@ -1,9 +1,23 @@
+use kelocam_core::cnc::{BallNose, Bit, FlatEndMill};
 use kelocam_editor::icons::Icons;
 use kelocam_editor::Editor;
 
-#[derive(Default)]
 pub struct PrepareView {
     icons: Option<Icons>,
+    /// The diameter of the bit simulating the toolpath, in millimeters.
+    diameter: f32,
+    /// Whether the simulated bit is a ball nose instead of a flat end mill.
+    ball: bool,
+}
+
+impl Default for PrepareView {
+    fn default() -> Self {
+        Self {
+            icons: None,
+            diameter: 6.0,
+            ball: false,
+        }
+    }
 }
 
 impl PrepareView {
@@ -22,6 +36,57 @@ impl PrepareView {
                 });
             });
 
+        egui::TopBottomPanel::bottom("simulation").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong("Simulation");
+                ui.add(
+                    egui::DragValue::new(&mut self.diameter)
+                        .clamp_range(0.1..=50.0)
+                        .speed(0.1)
+                        .suffix(" mm"),
+                )
+                .on_hover_text("Bit diameter");
+                ui.selectable_value(&mut self.ball, false, "Flat end");
+                ui.selectable_value(&mut self.ball, true, "Ball nose");
+                if ui
+                    .add_enabled(editor.toolpath.is_some(), egui::Button::new("Simulate"))
+                    .on_hover_text("Cut the stock with the toolpath")
+                    .on_disabled_hover_text("Open a G-code program first")
+                    .clicked()
+                {
+                    let bit: Bit = match self.ball {
+                        true => BallNose::new(self.diameter, 50.0, self.diameter, 2).into(),
+                        false => FlatEndMill::new(self.diameter, 50.0, self.diameter, 2).into(),
+                    };
+                    editor.simulate(&bit);
+                }
+                if editor.stock.is_some() && ui.button("Clear").clicked() {
+                    editor.stock = None;
+                }
+            });
+        });
+
+        if !editor.log.notices.is_empty() {
+            egui::TopBottomPanel::bottom("notices").show(ctx, |ui| {
+                let mut clear = false;
+                ui.horizontal(|ui| {
+                    ui.strong("Messages");
+                    clear = ui.button("Clear").clicked();
+                });
+                egui::ScrollArea::vertical()
+                    .max_height(100.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for notice in editor.log.notices.iter() {
+                            ui.label(notice);
+                        }
+                    });
+                if clear {
+                    editor.log.notices.clear();
+                }
+            });
+        }
+
         egui::CentralPanel::default()
             .frame(egui::Frame::default())
             .show(ctx, |ui| editor.ui(ui, &mut messages));